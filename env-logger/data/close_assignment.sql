@@ -0,0 +1,3 @@
+update DEVICE_ASSIGNMENT_TABLE
+  set valid_until = :valid_until
+  where device_id = :device_id and valid_until is NULL;
@@ -0,0 +1,16 @@
+create table if not exists DEVICE_ASSIGNMENT_TABLE (
+  /* デバイス固有のID */
+  device_id TEXT not NULL,
+
+  /* 設置場所名 */
+  location TEXT not NULL,
+
+  /* 有効期間の開始時刻(ミリ秒単位のUNIX時刻、この時刻を含む) */
+  valid_from INTEGER not NULL,
+
+  /* 有効期間の終了時刻(ミリ秒単位のUNIX時刻、この時刻を含まない) */
+  valid_until INTEGER,
+
+  /* プライマリーキー設定 */
+  primary key(device_id, valid_from)
+);
@@ -0,0 +1,28 @@
+create table if not exists DEVICE_STATUS_TABLE (
+  /* デバイス固有のID */
+  device_id TEXT not NULL,
+
+  /* 受信時刻(ミリ秒単位のUNIX時刻) */
+  timestamp INTEGER not NULL,
+
+  /* 受信時のデバイスの設置場所名 */
+  location TEXT,
+
+  /* ファームウェアのバージョン */
+  firmware TEXT,
+
+  /* 受信信号強度(dBm) */
+  rssi INTEGER,
+
+  /* 電池残量(%) */
+  battery REAL,
+
+  /* 起動からの経過時間(秒) */
+  uptime INTEGER,
+
+  /* 直前のリセットの理由 */
+  reset_reason TEXT,
+
+  /* プライマリーキー設定 */
+  primary key(device_id, timestamp)
+);
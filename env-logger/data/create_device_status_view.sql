@@ -0,0 +1,50 @@
+create view if not exists DEVICE_STATUS_VIEW as
+  with
+    /* 経過時間の減少(再起動)の検出 */
+    history as (
+      select
+          device_id,
+          timestamp,
+          uptime < lag(uptime) over (
+            partition by device_id order by timestamp
+          ) as rebooted
+        from DEVICE_STATUS_TABLE
+        where uptime is not NULL
+    ),
+
+    /* デバイス毎の最終受信時刻 */
+    latest as (
+      select
+          device_id,
+          max(timestamp) as last_seen
+        from DEVICE_STATUS_TABLE
+        group by device_id
+    )
+
+  select
+      l.device_id,
+      l.last_seen,
+      (select firmware from DEVICE_STATUS_TABLE as s
+        where s.device_id = l.device_id and firmware is not NULL
+        order by timestamp desc limit 1) as firmware,
+      (select rssi from DEVICE_STATUS_TABLE as s
+        where s.device_id = l.device_id and rssi is not NULL
+        order by timestamp desc limit 1) as rssi,
+      (select avg(rssi) from DEVICE_STATUS_TABLE as s
+        where s.device_id = l.device_id
+          and timestamp > l.last_seen - 3600000) as rssi_1h,
+      (select avg(rssi) from DEVICE_STATUS_TABLE as s
+        where s.device_id = l.device_id
+          and timestamp > l.last_seen - 86400000) as rssi_24h,
+      (select battery from DEVICE_STATUS_TABLE as s
+        where s.device_id = l.device_id and battery is not NULL
+        order by timestamp desc limit 1) as battery,
+      (select uptime from DEVICE_STATUS_TABLE as s
+        where s.device_id = l.device_id and uptime is not NULL
+        order by timestamp desc limit 1) as uptime,
+      (select reset_reason from DEVICE_STATUS_TABLE as s
+        where s.device_id = l.device_id and reset_reason is not NULL
+        order by timestamp desc limit 1) as reset_reason,
+      (select count(*) from history as h
+        where h.device_id = l.device_id and h.rebooted) as reboot_count
+    from latest as l;
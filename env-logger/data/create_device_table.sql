@@ -0,0 +1,13 @@
+create table if not exists DEVICE_TABLE (
+  /* デバイス固有のID */
+  device_id TEXT primary key,
+
+  /* 説明 */
+  description TEXT,
+
+  /* 登録時刻(ミリ秒単位のUNIX時刻) */
+  registered INTEGER not NULL,
+
+  /* 退役時刻(ミリ秒単位のUNIX時刻、稼働中はNULL) */
+  retired INTEGER
+);
@@ -0,0 +1,13 @@
+create table if not exists FORWARD_QUEUE_TABLE (
+  /* 転送先の識別名 */
+  sink TEXT not NULL,
+
+  /* 転送対象レコードの設置場所名 */
+  location TEXT not NULL,
+
+  /* 転送対象レコードの登録時刻(ミリ秒単位のUNIX時刻) */
+  timestamp INTEGER not NULL,
+
+  /* プライマリーキー設定 */
+  primary key(sink, location, timestamp)
+);
@@ -0,0 +1,22 @@
+create table if not exists LOCATION_TABLE (
+  /* 設置場所名(レコードのlocationと同じ表記) */
+  name TEXT primary key,
+
+  /* 表示名 */
+  display_name TEXT,
+
+  /* 階 */
+  floor TEXT,
+
+  /* 部屋 */
+  room TEXT,
+
+  /* 屋外か否か(0:屋内, 1:屋外) */
+  outdoor INTEGER not NULL default 0,
+
+  /* 標高(m) */
+  altitude REAL,
+
+  /* タグ(カンマ区切り) */
+  tags TEXT
+);
@@ -0,0 +1,16 @@
+create table if not exists MEASUREMENT_TABLE (
+  /* デバイスの設置場所名 */
+  location TEXT not NULL,
+
+  /* 登録時刻(ミリ秒単位のUNIX時刻) */
+  timestamp INTEGER not NULL,
+
+  /* 計測種別の名前 */
+  name TEXT not NULL,
+
+  /* 計測値 */
+  value REAL not NULL,
+
+  /* プライマリーキー設定 */
+  primary key(location, timestamp, name)
+);
@@ -0,0 +1,19 @@
+create table if not exists RELOCATE_LOG_TABLE (
+  /* 処理単位の識別番号 */
+  batch_id INTEGER primary key autoincrement,
+
+  /* 実行時刻(ミリ秒単位のUNIX時刻) */
+  executed INTEGER not NULL,
+
+  /* 移動元の設置場所名 */
+  src TEXT not NULL,
+
+  /* 移動先の設置場所名 */
+  dst TEXT not NULL,
+
+  /* 移動したレコード数 */
+  moved INTEGER not NULL,
+
+  /* 取り消した時刻(ミリ秒単位のUNIX時刻、取り消していない場合はNULL) */
+  undone INTEGER
+);
@@ -0,0 +1,13 @@
+create table if not exists RELOCATE_UNDO_TABLE (
+  /* 処理単位の識別番号 */
+  batch_id INTEGER not NULL,
+
+  /* 更新したテーブル名 */
+  target TEXT not NULL,
+
+  /* 更新した行のタイムスタンプ(割り当ての場合は有効期間の開始時刻) */
+  timestamp INTEGER not NULL,
+
+  /* 更新した行のデバイスID(割り当ての場合のみ) */
+  device_id TEXT
+);
@@ -17,6 +17,48 @@ create table if not exists SENSOR_RESULT_TABLE (
   /* 気圧(hpa) */
   air_pressure REAL,
 
+  /* 較正前の気温(較正を適用した場合のみ) */
+  raw_temperature REAL,
+
+  /* 較正前の湿度(較正を適用した場合のみ) */
+  raw_humidity REAL,
+
+  /* 較正前の気圧(較正を適用した場合のみ) */
+  raw_air_pressure REAL,
+
+  /* 品質フラグ(妥当性検査で問題が見つかった場合のみ) */
+  quality TEXT,
+
+  /* 露点温度(摂氏、気温と湿度から計算) */
+  dew_point REAL,
+
+  /* 絶対湿度(g/m3、気温と湿度から計算) */
+  absolute_humidity REAL,
+
+  /* ヒューミデックス(気温と湿度から計算) */
+  humidex REAL,
+
+  /* 暑さ指数(摂氏、気温と湿度から計算) */
+  heat_index REAL,
+
+  /* 飽差(kPa、気温と湿度から計算) */
+  vpd REAL,
+
+  /* 海面気圧(hpa、標高が指定されている設置場所のみ) */
+  sea_level_pressure REAL,
+
+  /* 3時間あたりの気圧の変化量(hpa) */
+  pressure_tendency REAL,
+
+  /* 気圧傾向の区分 */
+  pressure_trend TEXT,
+
+  /* Zambretti方式の簡易予報 */
+  forecast TEXT,
+
+  /* 未知のプロパティ(JSONオブジェクト、存在する場合のみ) */
+  extras TEXT,
+
   /* プライマリーキー設定 */
   primary key(location, timestamp)
 );
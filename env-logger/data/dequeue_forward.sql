@@ -0,0 +1,2 @@
+delete from FORWARD_QUEUE_TABLE
+  where sink = :sink and location = :location and timestamp = :timestamp;
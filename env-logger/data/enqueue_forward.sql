@@ -0,0 +1,5 @@
+insert or ignore into FORWARD_QUEUE_TABLE values (
+    :sink,
+    :location,
+    :timestamp
+);
@@ -0,0 +1,6 @@
+insert into DEVICE_ASSIGNMENT_TABLE values (
+    :device_id,
+    :location,
+    :valid_from,
+    NULL
+);
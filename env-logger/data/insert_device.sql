@@ -0,0 +1,6 @@
+insert into DEVICE_TABLE values (
+    :device_id,
+    :description,
+    :registered,
+    NULL
+);
@@ -0,0 +1,10 @@
+insert or replace into DEVICE_STATUS_TABLE values (
+    :device_id,
+    :timestamp,
+    :location,
+    :firmware,
+    :rssi,
+    :battery,
+    :uptime,
+    :reset_reason
+);
@@ -0,0 +1,6 @@
+insert into MEASUREMENT_TABLE values (
+    :location,
+    :timestamp,
+    :name,
+    :value
+);
@@ -0,0 +1,19 @@
+insert into QUARANTINE_TABLE (
+    location,
+    device_id,
+    timestamp,
+    temperature,
+    humidity,
+    air_pressure,
+    quality,
+    extras
+) values (
+    :location,
+    :device_id,
+    :timestamp,
+    :temperature,
+    :humidity,
+    :air_pressure,
+    :quality,
+    :extras
+);
@@ -1,8 +1,43 @@
-insert into SENSOR_RESULT_TABLE values (
+insert into SENSOR_RESULT_TABLE (
+    location,
+    device_id,
+    timestamp,
+    temperature,
+    humidity,
+    air_pressure,
+    raw_temperature,
+    raw_humidity,
+    raw_air_pressure,
+    quality,
+    dew_point,
+    absolute_humidity,
+    humidex,
+    heat_index,
+    vpd,
+    sea_level_pressure,
+    pressure_tendency,
+    pressure_trend,
+    forecast,
+    extras
+) values (
     :location,
     :device_id,
     :timestamp,
     :temperature,
     :humidity,
-    :air_pressure
+    :air_pressure,
+    :raw_temperature,
+    :raw_humidity,
+    :raw_air_pressure,
+    :quality,
+    :dew_point,
+    :absolute_humidity,
+    :humidex,
+    :heat_index,
+    :vpd,
+    :sea_level_pressure,
+    :pressure_tendency,
+    :pressure_trend,
+    :forecast,
+    :extras
 );
@@ -0,0 +1,6 @@
+insert into RELOCATE_LOG_TABLE (executed, src, dst, moved) values (
+    :executed,
+    :src,
+    :dst,
+    :moved
+);
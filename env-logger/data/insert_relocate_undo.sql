@@ -0,0 +1,6 @@
+insert into RELOCATE_UNDO_TABLE values (
+    :batch_id,
+    :target,
+    :timestamp,
+    :device_id
+);
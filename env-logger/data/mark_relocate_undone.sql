@@ -0,0 +1,3 @@
+update RELOCATE_LOG_TABLE
+  set undone = :undone
+  where batch_id = :batch_id;
@@ -0,0 +1,4 @@
+update DEVICE_ASSIGNMENT_TABLE
+  set location = :dst
+  where location = :src
+    and device_id = :device_id and valid_from = :timestamp;
@@ -0,0 +1,3 @@
+update FORWARD_QUEUE_TABLE
+  set location = :dst
+  where location = :src and timestamp = :timestamp;
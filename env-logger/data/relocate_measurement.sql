@@ -0,0 +1,3 @@
+update MEASUREMENT_TABLE
+  set location = :dst
+  where location = :src and timestamp = :timestamp;
@@ -0,0 +1,3 @@
+update SENSOR_RESULT_TABLE
+  set location = :dst
+  where location = :src and timestamp = :timestamp;
@@ -0,0 +1,3 @@
+update DEVICE_TABLE
+  set retired = :retired
+  where device_id = :device_id;
@@ -0,0 +1,7 @@
+select
+    device_id,
+    location,
+    valid_from,
+    valid_until
+  from DEVICE_ASSIGNMENT_TABLE
+  order by device_id, valid_from;
@@ -0,0 +1,14 @@
+select
+    location,
+    device_id,
+    timestamp,
+    temperature,
+    humidity,
+    air_pressure,
+    raw_temperature,
+    raw_humidity,
+    raw_air_pressure
+  from SENSOR_RESULT_TABLE
+  where device_id is not NULL
+    and timestamp >= :from and timestamp < :until
+  order by timestamp;
@@ -0,0 +1,7 @@
+select
+    device_id,
+    location,
+    valid_from,
+    valid_until
+  from DEVICE_ASSIGNMENT_TABLE
+  where device_id = :device_id and valid_until is NULL;
@@ -0,0 +1,13 @@
+select
+    location,
+    timestamp,
+    temperature,
+    humidity,
+    dew_point,
+    absolute_humidity,
+    humidex,
+    heat_index,
+    vpd
+  from SENSOR_RESULT_TABLE
+  where timestamp >= :from and timestamp < :until
+  order by timestamp;
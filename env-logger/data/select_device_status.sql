@@ -0,0 +1,14 @@
+select
+    device_id,
+    last_seen,
+    firmware,
+    rssi,
+    rssi_1h,
+    rssi_24h,
+    battery,
+    uptime,
+    reset_reason,
+    reboot_count
+  from DEVICE_STATUS_VIEW
+  where :device_id is NULL or device_id = :device_id
+  order by device_id;
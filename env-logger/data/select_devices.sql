@@ -0,0 +1,7 @@
+select
+    device_id,
+    description,
+    registered,
+    retired
+  from DEVICE_TABLE
+  order by device_id;
@@ -0,0 +1,29 @@
+select
+    q.location,
+    q.timestamp,
+    r.device_id,
+    r.temperature,
+    r.humidity,
+    r.air_pressure,
+    r.location is not NULL,
+    r.sea_level_pressure,
+    r.pressure_tendency,
+    r.forecast,
+    r.extras,
+    r.raw_temperature,
+    r.raw_humidity,
+    r.raw_air_pressure,
+    r.quality,
+    s.firmware,
+    s.rssi,
+    s.battery,
+    s.uptime,
+    s.reset_reason
+  from FORWARD_QUEUE_TABLE as q
+  left join SENSOR_RESULT_TABLE as r
+    on r.location = q.location and r.timestamp = q.timestamp
+  left join DEVICE_STATUS_TABLE as s
+    on s.device_id = r.device_id and s.timestamp = r.timestamp
+  where q.sink = :sink
+  order by q.timestamp
+  limit :limit;
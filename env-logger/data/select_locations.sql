@@ -0,0 +1,10 @@
+select
+    name,
+    display_name,
+    floor,
+    room,
+    outdoor,
+    altitude,
+    tags
+  from LOCATION_TABLE
+  order by name;
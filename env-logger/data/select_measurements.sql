@@ -0,0 +1,6 @@
+select
+    name,
+    value
+  from MEASUREMENT_TABLE
+  where location = :location and timestamp = :timestamp
+  order by name;
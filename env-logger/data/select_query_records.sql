@@ -0,0 +1,17 @@
+select
+    location,
+    device_id,
+    timestamp,
+    temperature,
+    humidity,
+    air_pressure,
+    quality,
+    extras
+  from SENSOR_RESULT_TABLE
+  where timestamp >= :from and timestamp < :until
+    and (:location is NULL or location = :location)
+    and (:device is NULL or device_id = :device)
+    /* --whereで指定された条件式(未指定時は常に真) */
+    and ({condition})
+  order by timestamp, location
+  limit :limit;
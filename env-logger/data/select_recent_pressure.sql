@@ -0,0 +1,7 @@
+select
+    location,
+    timestamp,
+    air_pressure
+  from SENSOR_RESULT_TABLE
+  where timestamp >= :since and air_pressure is not NULL
+  order by timestamp;
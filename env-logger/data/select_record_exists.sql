@@ -0,0 +1,4 @@
+select
+    count(*)
+  from SENSOR_RESULT_TABLE
+  where location = :location and timestamp = :timestamp;
@@ -0,0 +1,6 @@
+select
+    device_id,
+    valid_from
+  from DEVICE_ASSIGNMENT_TABLE
+  where location = :src
+  order by device_id, valid_from;
@@ -0,0 +1,9 @@
+select
+    batch_id,
+    executed,
+    src,
+    dst,
+    moved,
+    undone
+  from RELOCATE_LOG_TABLE
+  order by batch_id;
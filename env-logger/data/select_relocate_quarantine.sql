@@ -0,0 +1,7 @@
+select distinct
+    timestamp
+  from QUARANTINE_TABLE
+  where location = :src
+    and timestamp >= :from and timestamp < :until
+    and (:device_id is NULL or device_id = :device_id)
+  order by timestamp;
@@ -0,0 +1,11 @@
+select
+    s.timestamp,
+    s.device_id,
+    d.timestamp is not NULL
+  from SENSOR_RESULT_TABLE as s
+    left join SENSOR_RESULT_TABLE as d
+      on d.location = :dst and d.timestamp = s.timestamp
+  where s.location = :src
+    and s.timestamp >= :from and s.timestamp < :until
+    and (:device_id is NULL or s.device_id = :device_id)
+  order by s.timestamp;
@@ -0,0 +1,6 @@
+select
+    target,
+    timestamp,
+    device_id
+  from RELOCATE_UNDO_TABLE
+  where batch_id = :batch_id;
@@ -0,0 +1,14 @@
+update SENSOR_RESULT_TABLE
+  set
+    temperature = :temperature,
+    humidity = :humidity,
+    air_pressure = :air_pressure,
+    raw_temperature = :raw_temperature,
+    raw_humidity = :raw_humidity,
+    raw_air_pressure = :raw_air_pressure,
+    dew_point = :dew_point,
+    absolute_humidity = :absolute_humidity,
+    humidex = :humidex,
+    heat_index = :heat_index,
+    vpd = :vpd
+  where location = :location and timestamp = :timestamp;
@@ -0,0 +1,8 @@
+update SENSOR_RESULT_TABLE
+  set
+    dew_point = :dew_point,
+    absolute_humidity = :absolute_humidity,
+    humidex = :humidex,
+    heat_index = :heat_index,
+    vpd = :vpd
+  where location = :location and timestamp = :timestamp;
@@ -0,0 +1,17 @@
+insert into LOCATION_TABLE values (
+    :name,
+    :display_name,
+    :floor,
+    :room,
+    :outdoor,
+    :altitude,
+    :tags
+)
+  on conflict(name) do update
+    set
+      display_name = excluded.display_name,
+      floor = excluded.floor,
+      room = excluded.room,
+      outdoor = excluded.outdoor,
+      altitude = excluded.altitude,
+      tags = excluded.tags;
@@ -0,0 +1,118 @@
+#!/usr/bin/env python3
+#
+# systemd連携の確認用スクリプト
+#
+# 通知用のUNIXドメインソケット(NOTIFY_SOCKET)を用意してenv-loggerを起動し、
+# 受け取った通知(READY=1, STATUS=, WATCHDOG=1, STOPPING=1)を表示する。
+# Ctrl-Cでenv-loggerにSIGTERMを送る。
+#
+# 使い方
+#   fake-systemd.py [--watchdog 秒] [--listen ポート] -- env-loggerの引数...
+#
+#   --watchdog  WATCHDOG_USECを設定する(指定した秒数の間に通知が無い場合は
+#               警告を表示する)
+#   --listen    TCPとUDPの待ち受けソケットを作成し、LISTEN_FDSで引き渡す
+#
+# 例
+#   fake-systemd.py --watchdog 4 --listen 2342 -- \
+#       ./target/debug/env-logger -L log.txt test.db
+#
+
+import argparse
+import os
+import select
+import signal
+import socket
+import sys
+import tempfile
+import time
+
+SD_LISTEN_FDS_START = 3
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--watchdog", type=float)
+    parser.add_argument("--listen", type=int)
+    parser.add_argument("command", nargs=argparse.REMAINDER)
+    args = parser.parse_args()
+
+    command = args.command[1:] if args.command[:1] == ["--"] else args.command
+    if not command:
+        parser.error("command is required")
+
+    # 通知用ソケットの作成
+    tmpdir = tempfile.mkdtemp()
+    path = os.path.join(tmpdir, "notify")
+    notify = socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM)
+    notify.bind(path)
+
+    # 待ち受けソケットの作成
+    listeners = []
+    if args.listen:
+        tcp = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+        tcp.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+        tcp.bind(("0.0.0.0", args.listen))
+        tcp.listen(128)
+
+        udp = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        udp.bind(("0.0.0.0", args.listen))
+
+        listeners = [tcp, udp]
+
+    pid = os.fork()
+    if pid == 0:
+        env = dict(os.environ)
+        env["NOTIFY_SOCKET"] = path
+
+        if args.watchdog:
+            env["WATCHDOG_USEC"] = str(int(args.watchdog * 1000000))
+            env["WATCHDOG_PID"] = str(os.getpid())
+
+        if listeners:
+            for i, sock in enumerate(listeners):
+                os.dup2(sock.fileno(), SD_LISTEN_FDS_START + i)
+            env["LISTEN_FDS"] = str(len(listeners))
+            env["LISTEN_PID"] = str(os.getpid())
+
+        os.execvpe(command[0], command, env)
+
+    for sock in listeners:
+        sock.close()
+
+    signal.signal(signal.SIGINT, lambda *_: os.kill(pid, signal.SIGTERM))
+
+    start = time.monotonic()
+    last_watchdog = start
+
+    while True:
+        try:
+            ready, _, _ = select.select([notify], [], [], 0.5)
+        except InterruptedError:
+            continue
+
+        now = time.monotonic()
+
+        if ready:
+            msg = notify.recv(4096).decode(errors="replace")
+            for line in filter(None, msg.split("\n")):
+                print("[%8.3f] %s" % (now - start, line), flush=True)
+                if line == "WATCHDOG=1":
+                    last_watchdog = now
+
+        if args.watchdog and now - last_watchdog > args.watchdog:
+            print("[%8.3f] ** watchdog timeout **" % (now - start), flush=True)
+            last_watchdog = now
+
+        done, status = os.waitpid(pid, os.WNOHANG)
+        if done:
+            print("exit status: %d" % os.waitstatus_to_exitcode(status))
+            break
+
+    notify.close()
+    os.unlink(path)
+    os.rmdir(tmpdir)
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -0,0 +1,463 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 気圧に関する派生値(海面気圧、気圧傾向、簡易予報)の計算をまとめたモジュー
+//! ル
+//!
+//! 海面気圧(QNH)の計算には設置場所の標高が必要なので、設定ファイルの
+//! `[[location]]`テーブルで設置場所毎に標高を指定する。気圧傾向は設置場所毎
+//! の3時間前の現地気圧との差から求めるので、起動直後の3時間はデータベースに
+//! 記録済みのレコードを参照する。
+//!
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Local, TimeZone};
+use serde::Deserialize;
+
+use crate::record::{round_value, SensorRecord};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 気圧傾向を評価する時間幅(ミリ秒)
+pub(crate) const TENDENCY_PERIOD: u64 = 3 * 60 * 60 * 1000;
+
+/// 3時間前のレコードとみなす時刻の許容幅(ミリ秒)
+pub(crate) const TENDENCY_TOLERANCE: u64 = 30 * 60 * 1000;
+
+/// Zambretti予報で気圧が上昇または下降しているとみなす変化量(hPa/3h)
+const ZAMBRETTI_THRESHOLD: f32 = 1.6;
+
+/// Zambretti予報で扱う気圧の下限(hPa)
+const ZAMBRETTI_BOTTOM: f32 = 950.0;
+
+/// Zambretti予報で扱う気圧の上限(hPa)
+const ZAMBRETTI_TOP: f32 = 1050.0;
+
+/// Zambretti予報の予報文(A〜Z)
+const ZAMBRETTI_FORECASTS: [&str; 26] = [
+    "Settled fine",
+    "Fine weather",
+    "Becoming fine",
+    "Fine, becoming less settled",
+    "Fine, possible showers",
+    "Fairly fine, improving",
+    "Fairly fine, possible showers early",
+    "Fairly fine, showery later",
+    "Showery early, improving",
+    "Changeable, mending",
+    "Fairly fine, showers likely",
+    "Rather unsettled clearing later",
+    "Unsettled, probably improving",
+    "Showery, bright intervals",
+    "Showery, becoming less settled",
+    "Changeable, some rain",
+    "Unsettled, short fine intervals",
+    "Unsettled, rain later",
+    "Unsettled, some rain",
+    "Mostly very unsettled",
+    "Occasional rain, worsening",
+    "Rain at times, very unsettled",
+    "Rain at frequent intervals",
+    "Rain, very unsettled",
+    "Stormy, may improve",
+    "Stormy, much rain",
+];
+
+/// 気圧上昇時の気圧の区分毎の予報文の番号
+const ZAMBRETTI_RISING: [usize; 22] = [
+    25, 25, 25, 24, 24, 19, 16, 12, 11, 9, 8, 6, 5, 2, 1, 1, 0, 0, 0, 0, 0, 0,
+];
+
+/// 気圧安定時の気圧の区分毎の予報文の番号
+const ZAMBRETTI_STEADY: [usize; 22] = [
+    25, 25, 25, 25, 25, 25, 23, 23, 22, 18, 15, 13, 10, 4, 1, 1, 0, 0, 0, 0, 0, 0,
+];
+
+/// 気圧下降時の気圧の区分毎の予報文の番号
+const ZAMBRETTI_FALLING: [usize; 22] = [
+    25, 25, 25, 25, 25, 25, 25, 25, 23, 23, 21, 20, 17, 14, 7, 3, 1, 1, 1, 0, 0, 0,
+];
+
+///
+/// 設定ファイル上の設置場所の定義を表す構造体
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LocationConfig {
+    /// 設置場所名
+    name: String,
+
+    /// 標高(m)
+    altitude: f32,
+}
+
+impl LocationConfig {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `name` - 設置場所名
+    /// * `altitude` - 標高(m)
+    ///
+    pub(crate) fn new(name: &str, altitude: f32) -> Self {
+        Self {name: name.to_string(), altitude}
+    }
+
+    ///
+    /// 設置場所名へのアクセサ
+    ///
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+///
+/// 気圧傾向の区分を指し示す列挙子
+///
+/// # 注記
+/// 3時間あたりの変化量による区分で、WMOの気圧傾向の表現に従う。
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Trend {
+    /// ほとんど変化なし(0.1hPa未満)
+    Steady,
+
+    /// ゆっくり上昇(1.5hPa以下)
+    RisingSlowly,
+
+    /// 上昇(3.5hPa以下)
+    Rising,
+
+    /// 急上昇(6.0hPa以下)
+    RisingQuickly,
+
+    /// 非常に急な上昇(6.0hPa超)
+    RisingVeryRapidly,
+
+    /// ゆっくり下降(1.5hPa以下)
+    FallingSlowly,
+
+    /// 下降(3.5hPa以下)
+    Falling,
+
+    /// 急下降(6.0hPa以下)
+    FallingQuickly,
+
+    /// 非常に急な下降(6.0hPa超)
+    FallingVeryRapidly,
+}
+
+impl Trend {
+    ///
+    /// 3時間あたりの変化量からの区分の決定
+    ///
+    pub(crate) fn from_tendency(tendency: f32) -> Self {
+        let amount = tendency.abs();
+
+        if amount < 0.1 {
+            Self::Steady
+        } else if tendency > 0.0 {
+            match amount {
+                a if a <= 1.5 => Self::RisingSlowly,
+                a if a <= 3.5 => Self::Rising,
+                a if a <= 6.0 => Self::RisingQuickly,
+                _ => Self::RisingVeryRapidly,
+            }
+        } else {
+            match amount {
+                a if a <= 1.5 => Self::FallingSlowly,
+                a if a <= 3.5 => Self::Falling,
+                a if a <= 6.0 => Self::FallingQuickly,
+                _ => Self::FallingVeryRapidly,
+            }
+        }
+    }
+}
+
+// Displayトレイトの実装
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Steady => "steady",
+            Self::RisingSlowly => "rising slowly",
+            Self::Rising => "rising",
+            Self::RisingQuickly => "rising quickly",
+            Self::RisingVeryRapidly => "rising very rapidly",
+            Self::FallingSlowly => "falling slowly",
+            Self::Falling => "falling",
+            Self::FallingQuickly => "falling quickly",
+            Self::FallingVeryRapidly => "falling very rapidly",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+///
+/// 気圧に関する派生値を保持する構造体
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Barometric {
+    /// 海面気圧(hPa、標高が指定されている設置場所のみ)
+    pub(crate) sea_level_pressure: Option<f32>,
+
+    /// 3時間あたりの気圧の変化量(hPa)
+    pub(crate) tendency: Option<f32>,
+
+    /// 気圧傾向の区分
+    pub(crate) trend: Option<Trend>,
+
+    /// Zambretti方式の簡易予報
+    pub(crate) forecast: Option<String>,
+}
+
+///
+/// 設置場所毎の標高を表す構造体
+///
+/// # 注記
+/// 設置場所の定義を検証した上で保持する。設定の再読み込み時は、他の設定と
+/// 共に全ての検証を終えてから`Barometer::set_altitudes()`で入れ替える。
+///
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Altitudes(HashMap<String, f32>);
+
+impl Altitudes {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `locations` - 設置場所の定義のリスト
+    ///
+    /// # 戻り値
+    /// 生成に成功した場合はオブジェクトを`Ok()`でラップして返す。定義に不備
+    /// があった場合はエラー情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn new(locations: &[LocationConfig]) -> Result<Self> {
+        let mut altitudes = HashMap::new();
+
+        for location in locations {
+            if !location.altitude.is_finite()
+                || !(-500.0..=9000.0).contains(&location.altitude)
+            {
+                return Err(anyhow!(
+                    "location {}: invalid altitude {}",
+                    location.name,
+                    location.altitude
+                ));
+            }
+
+            if altitudes.insert(location.name.clone(), location.altitude).is_some() {
+                return Err(anyhow!("location {}: duplicated", location.name));
+            }
+        }
+
+        Ok(Self(altitudes))
+    }
+}
+
+///
+/// 気圧に関する派生値を計算する構造体
+///
+pub(crate) struct Barometer {
+    /// 設置場所毎の標高
+    altitudes: Altitudes,
+
+    /// 設置場所毎の直近の現地気圧(タイムスタンプと気圧の組)
+    history: HashMap<String, VecDeque<(u64, f32)>>,
+}
+
+impl Barometer {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `altitudes` - 設置場所毎の標高
+    ///
+    pub(crate) fn new(altitudes: Altitudes) -> Self {
+        Self {altitudes, history: HashMap::new()}
+    }
+
+    ///
+    /// 設置場所毎の標高の入れ替え
+    ///
+    /// # 引数
+    /// * `altitudes` - 設置場所毎の標高
+    ///
+    /// # 注記
+    /// 設定ファイルの再読み込み時に使用する。記録済みの気圧の履歴は保持する。
+    ///
+    pub(crate) fn set_altitudes(&mut self, altitudes: Altitudes) {
+        self.altitudes = altitudes;
+    }
+
+    ///
+    /// 気圧の履歴の追加
+    ///
+    /// # 引数
+    /// * `location` - 設置場所名
+    /// * `timestamp` - タイムスタンプ
+    /// * `pressure` - 現地気圧
+    ///
+    /// # 注記
+    /// 起動時にデータベースに記録済みのレコードを読み込む際に使用する。
+    ///
+    pub(crate) fn push_history(
+        &mut self,
+        location: &str,
+        timestamp: u64,
+        pressure: f32
+    ) {
+        let history = self.history.entry(location.to_string()).or_default();
+
+        history.push_back((timestamp, pressure));
+
+        // 評価に使用しない古い履歴は捨てる
+        let latest = history.iter().map(|(tm, _)| *tm).max().unwrap_or(0);
+        let limit = latest.saturating_sub(TENDENCY_PERIOD + TENDENCY_TOLERANCE);
+
+        history.retain(|(tm, _)| *tm >= limit);
+    }
+
+    ///
+    /// 派生値の計算と設定
+    ///
+    /// # 引数
+    /// * `record` - 対象のレコード
+    ///
+    pub(crate) fn apply(&mut self, record: &mut SensorRecord) {
+        let Some(pressure) = record.air_pressure() else {
+            return;
+        };
+
+        let location = record.location();
+        let timestamp = record.timestamp();
+
+        /*
+         * 海面気圧
+         */
+        let sea_level_pressure = self.altitudes.0.get(&location)
+            .map(|altitude| {
+                sea_level_pressure(pressure, *altitude, record.temperature())
+            });
+
+        /*
+         * 気圧傾向(3時間前に最も近いレコードとの差を3時間あたりに換算)
+         */
+        let tendency = self.history.get(&location)
+            .and_then(|history| {
+                let target = timestamp.checked_sub(TENDENCY_PERIOD)?;
+
+                history.iter()
+                    .filter(|(tm, _)| tm.abs_diff(target) <= TENDENCY_TOLERANCE)
+                    .min_by_key(|(tm, _)| tm.abs_diff(target))
+                    .copied()
+            })
+            .map(|(tm, prev)| {
+                let scale = TENDENCY_PERIOD as f32 / (timestamp - tm) as f32;
+                round_value(((pressure - prev) * scale).into(), 2) as f32
+            });
+
+        /*
+         * 簡易予報
+         */
+        let forecast = match (sea_level_pressure, tendency) {
+            (Some(slp), Some(tendency)) => {
+                Some(zambretti(slp, tendency, timestamp).to_string())
+            }
+
+            _ => None,
+        };
+
+        record.set_barometric(Barometric {
+            sea_level_pressure,
+            tendency,
+            trend: tendency.map(Trend::from_tendency),
+            forecast,
+        });
+
+        self.push_history(&location, timestamp, pressure);
+    }
+}
+
+///
+/// 海面気圧の計算
+///
+/// # 引数
+/// * `pressure` - 現地気圧(hPa)
+/// * `altitude` - 標高(m)
+/// * `temperature` - 気温(摂氏)
+///
+/// # 戻り値
+/// 海面気圧(hPa)を返す
+///
+/// # 注記
+/// 気象庁の海面更正の式を用いる。気温が無い場合は標準大気の気温減率から求め
+/// た気温(海面で15℃)を用いる。
+///
+pub(crate) fn sea_level_pressure(
+    pressure: f32,
+    altitude: f32,
+    temperature: Option<f32>
+) -> f32 {
+    let h = altitude as f64;
+    let t = temperature.map_or(15.0 - 0.0065 * h, |t| t as f64);
+    let slp = pressure as f64
+        * (1.0 - 0.0065 * h / (t + 0.0065 * h + 273.15)).powf(-5.257);
+
+    round_value(slp, 2) as f32
+}
+
+///
+/// Zambretti方式の簡易予報
+///
+/// # 引数
+/// * `slp` - 海面気圧(hPa)
+/// * `tendency` - 3時間あたりの気圧の変化量(hPa)
+/// * `timestamp` - レコードのタイムスタンプ(季節の判定に使用する)
+///
+/// # 戻り値
+/// 予報文を返す
+///
+/// # 注記
+/// 風向による補正は行わない。季節は北半球を前提とし、4月から9月を夏とする。
+///
+fn zambretti(slp: f32, tendency: f32, timestamp: u64) -> &'static str {
+    let range = ZAMBRETTI_TOP - ZAMBRETTI_BOTTOM;
+    let summer = Local.timestamp_millis_opt(timestamp as i64)
+        .single()
+        .is_some_and(|tm| (4..=9).contains(&tm.month()));
+
+    let mut slp = slp;
+
+    let table = if tendency >= ZAMBRETTI_THRESHOLD {
+        if summer {
+            slp += 0.07 * range;
+        }
+
+        &ZAMBRETTI_RISING
+
+    } else if tendency <= -ZAMBRETTI_THRESHOLD {
+        if !summer {
+            slp -= 0.07 * range;
+        }
+
+        &ZAMBRETTI_FALLING
+
+    } else {
+        &ZAMBRETTI_STEADY
+    };
+
+    let step = range / table.len() as f32;
+    let index = ((slp - ZAMBRETTI_BOTTOM) / step).floor()
+        .clamp(0.0, (table.len() - 1) as f32) as usize;
+
+    ZAMBRETTI_FORECASTS[table[index]]
+}
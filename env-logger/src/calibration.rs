@@ -0,0 +1,310 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! デバイス毎の計測値の較正処理をまとめたモジュール
+//!
+//! 較正情報は設定ファイルの`[[calibration]]`テーブルにデバイスID毎に記述
+//! し、計測値毎のオフセットとゲインで補正を行う(補正後の値 = 較正前の値 ×
+//! ゲイン + オフセット)。較正は計測種別の取り込みの直後、処理ステージを通
+//! 過する前のレコードに適用するので、較正情報はデバイスが送信する単位で記述
+//! する。処理ステージ(丸めや妥当性の確認など)には較正後の計測値が渡され、
+//! 隔離されたレコードも較正後の計測値で記録される。
+//!
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::record::{parse_time_string, RawValues, SensorRecord};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+///
+/// 設定ファイル上の日時の表記を表す列挙子
+///
+/// # 注記
+/// TOMLの日時リテラルと文字列の双方を受け付ける。
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum TimeSpec {
+    /// TOMLの日時リテラル
+    Datetime(toml::value::Datetime),
+
+    /// 文字列
+    Text(String),
+}
+
+impl TimeSpec {
+    ///
+    /// ミリ秒単位のUNIX時刻への変換
+    ///
+    fn to_millis(&self) -> Result<u64> {
+        match self {
+            Self::Datetime(tm) => parse_time_string(&tm.to_string()),
+            Self::Text(tm) => parse_time_string(tm),
+        }
+    }
+}
+
+///
+/// 計測値の補正内容を表す構造体
+///
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Correction {
+    /// オフセット
+    #[serde(default)]
+    offset: f64,
+
+    /// ゲイン
+    #[serde(default = "default_gain")]
+    gain: f64,
+}
+
+impl Correction {
+    ///
+    /// 補正内容の確認
+    ///
+    fn validate(&self) -> Result<()> {
+        if !self.offset.is_finite() || !self.gain.is_finite() || self.gain == 0.0 {
+            return Err(anyhow!(
+                "invalid correction (offset={}, gain={})",
+                self.offset,
+                self.gain
+            ));
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// 補正の適用
+    ///
+    fn apply(&self, val: f32) -> f32 {
+        (val as f64 * self.gain + self.offset) as f32
+    }
+}
+
+///
+/// ゲインのデフォルト値の取得(serde用)
+///
+fn default_gain() -> f64 {
+    1.0
+}
+
+///
+/// 設定ファイル上の較正情報の定義を表す構造体
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CalibrationConfig {
+    /// 対象のデバイスID
+    device_id: String,
+
+    /// 有効期間の開始日時(この日時を含む、省略時は無期限)
+    valid_from: Option<TimeSpec>,
+
+    /// 有効期間の終了日時(この日時を含まない、省略時は無期限)
+    valid_until: Option<TimeSpec>,
+
+    /// 気温の補正内容
+    temperature: Option<Correction>,
+
+    /// 湿度の補正内容
+    humidity: Option<Correction>,
+
+    /// 気圧の補正内容
+    air_pressure: Option<Correction>,
+}
+
+///
+/// 有効期間を解決済みの較正情報を表す構造体
+///
+#[derive(Debug, Clone)]
+struct Entry {
+    /// 有効期間の開始時刻(ミリ秒単位のUNIX時刻)
+    from: Option<u64>,
+
+    /// 有効期間の終了時刻(ミリ秒単位のUNIX時刻)
+    until: Option<u64>,
+
+    /// 気温の補正内容
+    temperature: Option<Correction>,
+
+    /// 湿度の補正内容
+    humidity: Option<Correction>,
+
+    /// 気圧の補正内容
+    air_pressure: Option<Correction>,
+}
+
+impl Entry {
+    ///
+    /// 有効期間の判定
+    ///
+    fn covers(&self, timestamp: u64) -> bool {
+        self.from.is_none_or(|from| timestamp >= from)
+            && self.until.is_none_or(|until| timestamp < until)
+    }
+}
+
+///
+/// 較正情報の登録簿を表す構造体
+///
+pub(crate) struct Calibration {
+    /// デバイスID毎の較正情報のリスト(記述順)
+    entries: HashMap<String, Vec<Entry>>,
+}
+
+impl Calibration {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `configs` - 較正情報の定義のリスト
+    ///
+    /// # 戻り値
+    /// 生成に成功した場合はCalibrationオブジェクトを`Ok()`でラップして返す。
+    /// 定義に不備があった場合はエラー情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn new(configs: &[CalibrationConfig]) -> Result<Self> {
+        let mut entries: HashMap<String, Vec<Entry>> = HashMap::new();
+
+        for config in configs {
+            let from = config.valid_from.as_ref()
+                .map(|tm| tm.to_millis())
+                .transpose()?;
+
+            let until = config.valid_until.as_ref()
+                .map(|tm| tm.to_millis())
+                .transpose()?;
+
+            if let (Some(from), Some(until)) = (from, until) {
+                if from >= until {
+                    return Err(anyhow!(
+                        "calibration for {}: empty validity period",
+                        config.device_id
+                    ));
+                }
+            }
+
+            let corrections = [
+                ("temperature", config.temperature),
+                ("humidity", config.humidity),
+                ("air_pressure", config.air_pressure),
+            ];
+
+            for (name, correction) in corrections {
+                if let Some(correction) = correction {
+                    if let Err(err) = correction.validate() {
+                        return Err(anyhow!(
+                            "calibration for {} ({}): {}",
+                            config.device_id,
+                            name,
+                            err
+                        ));
+                    }
+                }
+            }
+
+            entries.entry(config.device_id.clone())
+                .or_default()
+                .push(Entry {
+                    from,
+                    until,
+                    temperature: config.temperature,
+                    humidity: config.humidity,
+                    air_pressure: config.air_pressure,
+                });
+        }
+
+        for (device_id, list) in &entries {
+            info!("calibration enabled: {} ({} entries)", device_id, list.len());
+        }
+
+        Ok(Self {entries})
+    }
+
+    ///
+    /// 較正の適用
+    ///
+    /// # 引数
+    /// * `record` - 適用対象のレコード
+    ///
+    /// # 戻り値
+    /// レコードの内容が変化した場合は`true`を返す。
+    ///
+    /// # 注記
+    /// レコードが較正前の計測値を保持している場合は、その値を起点に較正をや
+    /// り直す。デバイスIDとタイムスタンプに合致する較正情報が無い計測値は較
+    /// 正前の値に戻す。合致する較正情報が複数ある場合は記述順で先のものを使
+    /// 用する。
+    ///
+    pub(crate) fn apply(&self, record: &mut SensorRecord) -> bool {
+        let entry = record.device_id()
+            .and_then(|id| self.entries.get(&id))
+            .and_then(|list| list.iter().find(|e| e.covers(record.timestamp())));
+
+        let raw = record.raw();
+
+        let (temperature, raw_temperature) = correct(
+            entry.and_then(|e| e.temperature),
+            raw.temperature.or(record.temperature())
+        );
+
+        let (humidity, raw_humidity) = correct(
+            entry.and_then(|e| e.humidity),
+            raw.humidity.or(record.humidity())
+        );
+
+        let (air_pressure, raw_air_pressure) = correct(
+            entry.and_then(|e| e.air_pressure),
+            raw.air_pressure.or(record.air_pressure())
+        );
+
+        let raw = RawValues {
+            temperature: raw_temperature,
+            humidity: raw_humidity,
+            air_pressure: raw_air_pressure,
+        };
+
+        let changed = record.temperature() != temperature
+            || record.humidity() != humidity
+            || record.air_pressure() != air_pressure
+            || record.raw() != raw;
+
+        record.set_temperature(temperature);
+        record.set_humidity(humidity);
+        record.set_air_pressure(air_pressure);
+        record.set_raw(raw);
+
+        changed
+    }
+}
+
+///
+/// 計測値の補正
+///
+/// # 引数
+/// * `correction` - 補正内容
+/// * `raw` - 較正前の計測値
+///
+/// # 戻り値
+/// 補正後の計測値と、記録する較正前の計測値(補正しなかった場合はNone)の組
+/// を返す。
+///
+fn correct(correction: Option<Correction>, raw: Option<f32>)
+    -> (Option<f32>, Option<f32>)
+{
+    match (correction, raw) {
+        (Some(correction), Some(raw)) => (Some(correction.apply(raw)), Some(raw)),
+        (_, raw) => (raw, None),
+    }
+}
@@ -89,7 +89,7 @@ fn format(writer: &mut dyn Write, now: &mut DeferredNow, record: &Record)
         now.format("%Y-%m-%d %H:%M:%S"),
         record.level(),
         record.args(),
-        source_info(&record),
+        source_info(record),
     )
 }
 
@@ -165,7 +165,7 @@ where
 
     if !path.exists() {
         // 指定されたパスに何もなければファイルを作成
-        File::create(&path)?;
+        File::create(path)?;
     }
 
     let path = std::fs::canonicalize(path)?;
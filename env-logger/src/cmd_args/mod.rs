@@ -14,11 +14,24 @@ use std::sync::Arc;
 use std::path::PathBuf;
 
 use anyhow::{anyhow, Result};
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
+use tokio::sync::Semaphore;
+
+use crate::line_protocol::Precision;
+use crate::record::parse_time_string;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 
+/// MQTTブローカーのデフォルトのポート番号
+const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// 購読するトピックフィルタのデフォルト値
+const DEFAULT_MQTT_TOPIC: &str = "envlog/{location}/{device_id}";
+
+/// 中継先のデフォルトのポート番号(`--port`のデフォルト値と同じ)
+const DEFAULT_RELAY_PORT: u16 = 2342;
+
 ///
 /// ログレベルを指し示す列挙子
 ///
@@ -44,16 +57,16 @@ enum LogLevel {
     Trace,
 }
 
-// Intoトレイトの実装
-impl Into<log::LevelFilter> for LogLevel {
-    fn into(self) -> log::LevelFilter {
-        match self {
-            Self::Off => log::LevelFilter::Off,
-            Self::Error => log::LevelFilter::Error,
-            Self::Warn => log::LevelFilter::Warn,
-            Self::Info => log::LevelFilter::Info,
-            Self::Debug => log::LevelFilter::Debug,
-            Self::Trace => log::LevelFilter::Trace,
+// Fromトレイトの実装
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => Self::Off,
+            LogLevel::Error => Self::Error,
+            LogLevel::Warn => Self::Warn,
+            LogLevel::Info => Self::Info,
+            LogLevel::Debug => Self::Debug,
+            LogLevel::Trace => Self::Trace,
         }
     }
 }
@@ -72,6 +85,279 @@ impl AsRef<str> for LogLevel {
     }
 }
 
+///
+/// サブコマンドを指し示す列挙子
+///
+/// # 注記
+/// サブコマンドを指定した場合は、レコードの受信は行わずにサブコマンドの処理
+/// のみを行って終了する。
+///
+#[derive(Subcommand, Debug, Clone)]
+pub(crate) enum Command {
+    /// 設定ファイルの較正情報を記録済みのレコードに再適用する
+    Recalibrate {
+        /// 対象期間の開始日時(この日時を含む、未指定時は最古のレコードから)
+        #[arg(long = "from", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        from: Option<u64>,
+
+        /// 対象期間の終了日時(この日時を含まない、未指定時は最新のレコードまで)
+        #[arg(long = "until", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        until: Option<u64>,
+
+        /// 対象とするデバイスID(複数指定可、未指定時は全てのデバイス)
+        #[arg(long = "device", value_name = "ID")]
+        devices: Vec<String>,
+
+        /// 変更内容の表示のみを行い、データベースを更新しない
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// 記録済みのレコードの派生値(露点温度など)を計算し直す
+    Rederive {
+        /// 対象期間の開始日時(この日時を含む、未指定時は最古のレコードから)
+        #[arg(long = "from", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        from: Option<u64>,
+
+        /// 対象期間の終了日時(この日時を含まない、未指定時は最新のレコードまで)
+        #[arg(long = "until", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        until: Option<u64>,
+    },
+
+    /// 設置場所の登録簿を操作する
+    Location {
+        #[command(subcommand)]
+        action: LocationAction,
+    },
+
+    /// デバイスの登録簿を操作する
+    Device {
+        #[command(subcommand)]
+        action: DeviceAction,
+    },
+
+    /// 記録済みのレコードを別の設置場所に移す(設置場所名の変更や統合)
+    Relocate {
+        /// 移動元の設置場所名
+        src: String,
+
+        /// 移動先の設置場所名
+        dst: String,
+
+        /// 対象とするデバイスID(未指定時は全てのデバイス)
+        #[arg(long = "device", value_name = "ID")]
+        device: Option<String>,
+
+        /// 対象期間の開始日時(この日時を含む、未指定時は最古のレコードから)
+        #[arg(long = "from", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        from: Option<u64>,
+
+        /// 対象期間の終了日時(この日時を含まない、未指定時は最新のレコードまで)
+        #[arg(long = "until", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        until: Option<u64>,
+
+        /// 移動先に同じタイムスタンプのレコードが存在する場合の扱い
+        #[arg(long = "on-conflict", value_name = "POLICY",
+            default_value = "fail")]
+        on_conflict: ConflictPolicy,
+
+        /// 変更内容の要約の表示のみを行い、データベースを更新しない
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// relocateによる変更を取り消す
+    UndoRelocate {
+        /// 取り消す処理の識別番号(未指定時は取り消していない最新の処理)
+        batch_id: Option<i64>,
+
+        /// 取り消しの対象となる処理の一覧を表示する
+        #[arg(long = "list", conflicts_with = "batch_id")]
+        list: bool,
+    },
+
+    /// 記録済みのレコードを検索して出力する
+    Query {
+        /// 対象とする設置場所名(未指定時は全ての設置場所)
+        #[arg(long = "location", value_name = "NAME")]
+        location: Option<String>,
+
+        /// 対象とするデバイスID(未指定時は全てのデバイス)
+        #[arg(long = "device", value_name = "ID")]
+        device: Option<String>,
+
+        /// 対象期間の開始日時(この日時を含む、未指定時は最古のレコードから)
+        #[arg(long = "from", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        from: Option<u64>,
+
+        /// 対象期間の終了日時(この日時を含まない、未指定時は最新のレコードまで)
+        #[arg(long = "until", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        until: Option<u64>,
+
+        /// 出力する計測種別(複数指定可、未指定時は全ての計測種別)
+        #[arg(long = "metric", value_name = "NAME")]
+        metrics: Vec<String>,
+
+        /// 出力するレコード数の上限
+        #[arg(long = "limit", value_name = "NUMBER")]
+        limit: Option<usize>,
+
+        /// 追加の条件式(SQLの式、extrasカラムはJSON関数で参照できる)
+        #[arg(long = "where", value_name = "EXPR")]
+        condition: Option<String>,
+
+        /// 出力形式
+        #[arg(long = "format", value_name = "FORMAT", default_value = "text")]
+        format: QueryFormat,
+    },
+}
+
+///
+/// 検索結果の出力形式を指し示す列挙子
+///
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum QueryFormat {
+    /// 1レコード1行のテキスト
+    Text,
+
+    /// CSV(計測種別毎に列を設ける)
+    Csv,
+
+    /// JSON Lines
+    Json,
+}
+
+///
+/// 移動先のレコードとの衝突時の扱いを指し示す列挙子
+///
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum ConflictPolicy {
+    /// 衝突がある場合は何も変更せずにエラーとする
+    Fail,
+
+    /// 衝突するレコードは移動せずに移動元に残す
+    Skip,
+}
+
+///
+/// 設置場所の登録簿に対する操作を指し示す列挙子
+///
+#[derive(Subcommand, Debug, Clone)]
+pub(crate) enum LocationAction {
+    /// 登録済みの設置場所を一覧表示する
+    List,
+
+    /// 設置場所を登録する(登録済みの場合は内容を置き換える)
+    Set {
+        /// 設置場所名(レコードのlocationと同じ表記)
+        name: String,
+
+        /// 表示名
+        #[arg(long = "display-name", value_name = "NAME")]
+        display_name: Option<String>,
+
+        /// 階
+        #[arg(long = "floor", value_name = "FLOOR")]
+        floor: Option<String>,
+
+        /// 部屋
+        #[arg(long = "room", value_name = "ROOM")]
+        room: Option<String>,
+
+        /// 屋外の設置場所として登録する
+        #[arg(long = "outdoor")]
+        outdoor: bool,
+
+        /// 標高(m)
+        #[arg(long = "altitude", value_name = "METER",
+            allow_negative_numbers = true)]
+        altitude: Option<f32>,
+
+        /// タグ(複数指定可)
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+    },
+}
+
+///
+/// デバイスの登録簿に対する操作を指し示す列挙子
+///
+/// # 注記
+/// 時刻を省略した場合は現在時刻を用いる。
+///
+#[derive(Subcommand, Debug, Clone)]
+pub(crate) enum DeviceAction {
+    /// 登録済みのデバイスと設置場所の履歴を一覧表示する
+    List,
+
+    /// デバイスを登録する
+    Add {
+        /// デバイスID
+        device_id: String,
+
+        /// 設置場所名
+        location: String,
+
+        /// 設置した日時
+        #[arg(long = "since", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        since: Option<u64>,
+
+        /// 説明
+        #[arg(long = "description", value_name = "TEXT")]
+        description: Option<String>,
+    },
+
+    /// デバイスを別の設置場所に移設する
+    Move {
+        /// デバイスID
+        device_id: String,
+
+        /// 移設先の設置場所名
+        location: String,
+
+        /// 移設した日時
+        #[arg(long = "since", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        since: Option<u64>,
+    },
+
+    /// デバイスを退役させる
+    Retire {
+        /// デバイスID
+        device_id: String,
+
+        /// 退役した日時
+        #[arg(long = "since", value_name = "DATETIME",
+            value_parser = parse_datetime)]
+        since: Option<u64>,
+    },
+
+    /// デバイスの稼働状態(ファームウェア、電波強度、電池残量など)を表示する
+    Status {
+        /// デバイスID(未指定時は全てのデバイス)
+        device_id: Option<String>,
+    },
+}
+
+///
+/// コマンドラインオプションで指定された日時のパース
+///
+/// # 注記
+/// 受け付ける書式は`record::parse_time_string()`を参照。
+///
+fn parse_datetime(s: &str) -> Result<u64> {
+    parse_time_string(s)
+}
+
 ///
 /// コマンドラインオプションをまとめた構造体
 ///
@@ -94,6 +380,10 @@ pub(crate) struct Options {
     #[arg(short = 'L', long = "log-output", value_name = "PATH")]
     log_output: Option<PathBuf>,
 
+    /// 設定ファイル(TOML)のパス
+    #[arg(short = 'c', long = "config", value_name = "PATH")]
+    config_file: Option<PathBuf>,
+
     /// 待受けを行うIPアドレス
     #[arg(short = 'b', long = "bind", default_value = "0.0.0.0")]
     bind: String,
@@ -102,9 +392,214 @@ pub(crate) struct Options {
     #[arg(short = 'p', long = "port", default_value = "2342")]
     port: usize,
 
+    /// TCPの待受けでTLSを使用する場合のサーバ証明書(PEM)のパス
+    #[arg(long = "tls-cert", value_name = "PATH", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// TCPの待受けでTLSを使用する場合の秘密鍵(PEM)のパス
+    #[arg(long = "tls-key", value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// クライアント証明書の検証に用いるCA証明書(PEM)のパス(指定時は相互TLS
+    /// 認証を行う)
+    #[arg(long = "tls-client-ca", value_name = "PATH", requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// TCPの同時接続数の上限
+    ///
+    /// 現在の接続数は稼働状況のログに`connections`として出力する。
+    #[arg(long = "tcp-max-connections", value_name = "NUMBER",
+        default_value = "256")]
+    tcp_max_connections: usize,
+
+    /// TCPで受信する1行(1レコード)の長さの上限(バイト)
+    #[arg(long = "tcp-max-line-length", value_name = "BYTES",
+        default_value = "16384")]
+    tcp_max_line_length: usize,
+
+    /// TCPの接続から1行を受信し終えるまでの期限(秒)
+    ///
+    /// ラインプロトコルの接続では、各行の先頭を受信してから受信し終えるまで
+    /// の期限として扱う。
+    #[arg(long = "tcp-read-timeout", value_name = "SECONDS",
+        default_value = "10")]
+    tcp_read_timeout: u64,
+
+    /// UDP、CoAPおよびラインプロトコルの待ち受け毎に受信したデータを処理す
+    /// るワーカーの数
+    #[arg(long = "udp-workers", value_name = "NUMBER", default_value = "4")]
+    udp_workers: usize,
+
+    /// UDPで受信するデータグラムの大きさの上限(バイト)
+    ///
+    /// 上限を超えるデータグラムは切り詰められるため、破棄して稼働状況に計上
+    /// する。
+    #[arg(long = "udp-max-datagram-size", value_name = "BYTES",
+        default_value = "8192")]
+    udp_max_datagram_size: usize,
+
+    /// 終了時に処理中のTCPセッションの完了を待つ期限(秒)
+    #[arg(long = "shutdown-timeout", value_name = "SECONDS",
+        default_value = "10")]
+    shutdown_timeout: u64,
+
+    /// CoAPの待受けを行うUDPポート番号(未指定時は待受けを行わない)
+    #[arg(long = "coap-port", value_name = "PORT")]
+    coap_port: Option<usize>,
+
+    /// InfluxDBラインプロトコルの待受けを行うTCP/UDPポート番号
+    #[arg(long = "influx-port", value_name = "PORT")]
+    influx_port: Option<usize>,
+
+    /// InfluxDB v2互換の書き込みAPIの待受けを行うHTTPポート番号
+    #[arg(long = "influx-http-port", value_name = "PORT")]
+    influx_http_port: Option<usize>,
+
+    /// ラインプロトコルのタイムスタンプの精度
+    ///
+    /// HTTPの場合はクエリーパラメータ"precision"の指定が優先される。
+    #[arg(long = "influx-precision", value_name = "PRECISION",
+        default_value = "ns", ignore_case = true)]
+    influx_precision: Precision,
+
+    /// ラインプロトコルで設置場所として扱うタグのキー
+    #[arg(long = "influx-location-tag", value_name = "KEY",
+        default_value = "location")]
+    influx_location_tag: String,
+
+    /// ラインプロトコルでデバイスIDとして扱うタグのキー
+    #[arg(long = "influx-device-tag", value_name = "KEY",
+        default_value = "device_id")]
+    influx_device_tag: String,
+
+    /// ラインプロトコルのタイムスタンプを受信時刻の前後に丸める
+    ///
+    /// 未指定時はポイントのタイムスタンプをそのまま採用する。指定した場合は
+    /// JSONで受信したレコードと同様に、受信時刻との差が許容範囲を超えない
+    /// ように丸める(溜め込んだポイントをまとめて送るクライアントでは、古い
+    /// ポイントの時刻が失われる点に注意)。
+    #[arg(long = "influx-clamp-timestamps")]
+    influx_clamp_timestamps: bool,
+
+    /// 接続するMQTTブローカーのアドレス(購読と再配信で共用する)
+    ///
+    /// `--mqtt-topic`を指定した場合と、`--mqtt-publish`を指定していない場合
+    /// に購読を行う。IPv6アドレスは角括弧で括る(例: "[::1]:1883")。
+    #[arg(long = "mqtt-broker", value_name = "HOST[:PORT]")]
+    mqtt_broker: Option<String>,
+
+    /// MQTTブローカー接続時に使用するクライアントID
+    #[arg(long = "mqtt-client-id", value_name = "ID",
+        default_value = "env-logger")]
+    mqtt_client_id: String,
+
+    /// MQTTブローカー接続時に使用するユーザ名
+    #[arg(long = "mqtt-user", value_name = "USER")]
+    mqtt_user: Option<String>,
+
+    /// MQTTブローカー接続時に使用するパスワード
+    #[arg(long = "mqtt-password", value_name = "PASSWORD",
+        requires = "mqtt_user")]
+    mqtt_password: Option<String>,
+
+    /// 購読するトピックフィルタ(複数指定可)
+    ///
+    /// セグメントに"{location}"または"{device_id}"を指定すると、そのセグメ
+    /// ントをワイルドカードとして購読し、ペイロードに該当する値が含まれてい
+    /// ない場合の補完に用いる。未指定時は"envlog/{location}/{device_id}"を
+    /// 購読する(`--mqtt-publish`を指定した場合は購読しない)。
+    #[arg(long = "mqtt-topic", value_name = "FILTER", requires = "mqtt_broker")]
+    mqtt_topics: Vec<String>,
+
+    /// 記録したレコードのMQTTブローカーへの再配信を行う
+    ///
+    /// 再配信と共に購読も行う場合は`--mqtt-topic`を指定する。
+    #[arg(long = "mqtt-publish", requires = "mqtt_broker")]
+    mqtt_publish: bool,
+
+    /// 再配信時に使用するステートトピックのプレフィックス
+    #[arg(long = "mqtt-state-prefix", value_name = "PREFIX",
+        default_value = "env-logger")]
+    mqtt_state_prefix: String,
+
+    /// Home Assistantのディスカバリートピックのプレフィックス
+    #[arg(long = "mqtt-discovery-prefix", value_name = "PREFIX",
+        default_value = "homeassistant")]
+    mqtt_discovery_prefix: String,
+
+    /// 受信したレコードをCSVファイルとして出力するディレクトリ
+    ///
+    /// 日付毎に"YYYY-MM-DD.csv"のファイルを作成して追記する(未指定時は出力
+    /// しない)。
+    #[arg(long = "csv-dir", value_name = "DIR")]
+    csv_dir: Option<PathBuf>,
+
+    /// 受信したレコードをJSONでPOSTするURL(複数指定可)
+    #[arg(long = "webhook-url", value_name = "URL")]
+    webhook_urls: Vec<String>,
+
+    /// 各出力先(シンク)のキューの長さ
+    ///
+    /// データベース以外のシンクはキューが満杯になった場合にレコードを破棄す
+    /// る。
+    #[arg(long = "sink-queue-size", value_name = "NUMBER",
+        default_value = "64")]
+    sink_queue_size: usize,
+
+    /// シンクと受信側の稼働状況をログに出力する間隔(秒、0の場合は終了時のみ出力)
+    #[arg(long = "sink-stats-interval", value_name = "SECONDS",
+        default_value = "600")]
+    sink_stats_interval: u64,
+
+    /// 記録したレコードの転送先となる書き込みAPIのURL(未指定時は転送しない)
+    ///
+    /// InfluxDB v2の場合は"http://HOST:8086/api/v2/write?org=ORG&bucket=BUCKET"、
+    /// VictoriaMetricsの場合は"http://HOST:8428/write"の様に指定する。
+    #[arg(long = "tsdb-url", value_name = "URL")]
+    tsdb_url: Option<String>,
+
+    /// 書き込みAPIの認証に使用するトークン
+    #[arg(long = "tsdb-token", value_name = "TOKEN", requires = "tsdb_url")]
+    tsdb_token: Option<String>,
+
+    /// 転送時に使用するメジャーメント名
+    #[arg(long = "tsdb-measurement", value_name = "NAME",
+        default_value = "environment")]
+    tsdb_measurement: String,
+
+    /// 1回のリクエストで転送するレコードの最大数
+    #[arg(long = "tsdb-batch-size", value_name = "NUMBER",
+        default_value = "500")]
+    tsdb_batch_size: usize,
+
+    /// 記録したレコードの中継先となる上流のenv-loggerのアドレス
+    ///
+    /// 指定した場合は、ローカルに記録したレコードを上流のTCPポートへ転送す
+    /// る(未指定時は中継しない)。転送するレコードは`--relay-name`と
+    /// `--relay-secret`で署名するので、上流側の設定ファイルの`[[auth.relay]]`
+    /// に同じ名前と共有鍵を登録しておく。IPv6アドレスは角括弧で括る(例:
+    /// "[::1]:2342")。
+    #[arg(long = "relay-upstream", value_name = "HOST[:PORT]",
+        requires_all = ["relay_name", "relay_secret"])]
+    relay_upstream: Option<String>,
+
+    /// 上流に対して名乗る中継元の名前
+    #[arg(long = "relay-name", value_name = "NAME",
+        requires = "relay_upstream")]
+    relay_name: Option<String>,
+
+    /// 中継するレコードの署名に使用する共有鍵
+    #[arg(long = "relay-secret", value_name = "SECRET",
+        requires = "relay_upstream")]
+    relay_secret: Option<String>,
+
     /// データベースファイルのパス
     #[arg(default_value = "database.db")]
     db_file: PathBuf,
+
+    /// 実行するサブコマンド(未指定時はレコードの受信と記録を行う)
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
 impl Options {
@@ -127,6 +622,17 @@ impl Options {
         self.log_output.clone()
     }
 
+    ///
+    /// 設定ファイルへのアクセサ
+    ///
+    /// # 戻り値
+    /// 設定ファイルが指定されている場合は、パス情報を`Some()`でラップして返
+    /// す。
+    ///
+    pub(crate) fn config_file(&self) -> Option<PathBuf> {
+        self.config_file.clone()
+    }
+
     /// 
     /// 待ち受けを行うエンドポイントへのアクセサ
     ///
@@ -137,6 +643,353 @@ impl Options {
         format!("{}:{}", self.bind, self.port)
     } 
 
+    ///
+    /// TLSのサーバ証明書と秘密鍵へのアクセサ
+    ///
+    /// # 戻り値
+    /// TLSを使用する場合は、サーバ証明書と秘密鍵のパスをパックしたタプルを
+    /// `Some()`でラップして返す。
+    ///
+    pub(crate) fn tls_identity(&self) -> Option<(PathBuf, PathBuf)> {
+        self.tls_cert.clone().zip(self.tls_key.clone())
+    }
+
+    ///
+    /// クライアント証明書の検証に用いるCA証明書へのアクセサ
+    ///
+    /// # 戻り値
+    /// 相互TLS認証を行う場合は、CA証明書のパスを`Some()`でラップして返す。
+    ///
+    pub(crate) fn tls_client_ca(&self) -> Option<PathBuf> {
+        self.tls_client_ca.clone()
+    }
+
+    ///
+    /// TCPの同時接続数の上限へのアクセサ
+    ///
+    pub(crate) fn tcp_max_connections(&self) -> usize {
+        self.tcp_max_connections
+    }
+
+    ///
+    /// TCPで受信する1行の長さの上限へのアクセサ
+    ///
+    /// # 戻り値
+    /// 上限をバイト数で返す(改行を含まない)。
+    ///
+    pub(crate) fn tcp_max_line_length(&self) -> usize {
+        self.tcp_max_line_length
+    }
+
+    ///
+    /// TCPの接続毎の受信期限へのアクセサ
+    ///
+    /// # 戻り値
+    /// 接続の受け付けから1行を受信し終えるまでの期限を秒単位で返す。
+    ///
+    pub(crate) fn tcp_read_timeout(&self) -> u64 {
+        self.tcp_read_timeout
+    }
+
+    ///
+    /// UDPで受信したデータを処理するワーカーの数へのアクセサ
+    ///
+    pub(crate) fn udp_workers(&self) -> usize {
+        self.udp_workers
+    }
+
+    ///
+    /// UDPで受信するデータグラムの大きさの上限へのアクセサ
+    ///
+    /// # 戻り値
+    /// 上限をバイト数で返す。
+    ///
+    pub(crate) fn udp_max_datagram_size(&self) -> usize {
+        self.udp_max_datagram_size
+    }
+
+    ///
+    /// 終了時に処理中のTCPセッションの完了を待つ期限へのアクセサ
+    ///
+    /// # 戻り値
+    /// 期限を秒単位で返す。期限を過ぎたセッションは打ち切る。
+    ///
+    pub(crate) fn shutdown_timeout(&self) -> u64 {
+        self.shutdown_timeout
+    }
+
+    ///
+    /// CoAPの待ち受けを行うエンドポイントへのアクセサ
+    ///
+    /// # 戻り値
+    /// CoAPのポート番号が指定されている場合は、待ち受けアドレスを`Some()`で
+    /// ラップして返す。
+    ///
+    pub(crate) fn coap_endpoint(&self) -> Option<String> {
+        self.coap_port.map(|port| format!("{}:{}", self.bind, port))
+    }
+
+    ///
+    /// ラインプロトコルの待ち受けを行うエンドポイントへのアクセサ
+    ///
+    /// # 戻り値
+    /// ポート番号が指定されている場合は、待ち受けアドレスを`Some()`でラップ
+    /// して返す。
+    ///
+    pub(crate) fn influx_endpoint(&self) -> Option<String> {
+        self.influx_port.map(|port| format!("{}:{}", self.bind, port))
+    }
+
+    ///
+    /// 書き込みAPIの待ち受けを行うエンドポイントへのアクセサ
+    ///
+    /// # 戻り値
+    /// ポート番号が指定されている場合は、待ち受けアドレスを`Some()`でラップ
+    /// して返す。
+    ///
+    pub(crate) fn influx_http_endpoint(&self) -> Option<String> {
+        self.influx_http_port.map(|port| format!("{}:{}", self.bind, port))
+    }
+
+    ///
+    /// ラインプロトコルのタイムスタンプの精度へのアクセサ
+    ///
+    /// # 戻り値
+    /// デフォルトで使用するタイムスタンプの精度を返す
+    ///
+    pub(crate) fn influx_precision(&self) -> Precision {
+        self.influx_precision
+    }
+
+    ///
+    /// 設置場所として扱うタグのキーへのアクセサ
+    ///
+    /// # 戻り値
+    /// 設置場所として扱うタグのキーを返す
+    ///
+    pub(crate) fn influx_location_tag(&self) -> String {
+        self.influx_location_tag.clone()
+    }
+
+    ///
+    /// デバイスIDとして扱うタグのキーへのアクセサ
+    ///
+    /// # 戻り値
+    /// デバイスIDとして扱うタグのキーを返す
+    ///
+    pub(crate) fn influx_device_tag(&self) -> String {
+        self.influx_device_tag.clone()
+    }
+
+    ///
+    /// ラインプロトコルのタイムスタンプの丸めの有無へのアクセサ
+    ///
+    /// # 戻り値
+    /// タイムスタンプを受信時刻の前後に丸める場合は`true`を返す。
+    ///
+    pub(crate) fn influx_clamp_timestamps(&self) -> bool {
+        self.influx_clamp_timestamps
+    }
+
+    ///
+    /// MQTTブローカーのアドレスへのアクセサ
+    ///
+    /// # 戻り値
+    /// MQTTブローカーが指定されている場合は、ホスト名とポート番号のタプルを
+    /// `Some()`でラップして返す(ポート番号省略時は1883を補う)。
+    ///
+    pub(crate) fn mqtt_broker(&self) -> Option<(String, u16)> {
+        self.mqtt_broker.as_deref()
+            .and_then(|broker| split_host_port(broker, DEFAULT_MQTT_PORT))
+    }
+
+    ///
+    /// MQTTクライアントIDへのアクセサ
+    ///
+    /// # 戻り値
+    /// MQTTブローカー接続時に使用するクライアントIDを返す
+    ///
+    pub(crate) fn mqtt_client_id(&self) -> String {
+        self.mqtt_client_id.clone()
+    }
+
+    ///
+    /// MQTTの認証情報へのアクセサ
+    ///
+    /// # 戻り値
+    /// ユーザ名が指定されている場合は、ユーザ名とパスワードのタプルを`Some()`
+    /// でラップして返す(パスワード省略時は空文字列を補う)。
+    ///
+    pub(crate) fn mqtt_credentials(&self) -> Option<(String, String)> {
+        self.mqtt_user.as_ref().map(|user| {
+            (user.clone(), self.mqtt_password.clone().unwrap_or_default())
+        })
+    }
+
+    ///
+    /// MQTTの購読の有無へのアクセサ
+    ///
+    /// # 戻り値
+    /// ブローカーが指定されており、トピックフィルタが指定されているか再配信
+    /// のみを行う指定でない場合に`true`を返す。
+    ///
+    pub(crate) fn mqtt_subscribe(&self) -> bool {
+        self.mqtt_broker.is_some()
+            && (!self.mqtt_topics.is_empty() || !self.mqtt_publish)
+    }
+
+    ///
+    /// 購読するトピックフィルタへのアクセサ
+    ///
+    /// # 戻り値
+    /// 購読するトピックフィルタのリストを返す(未指定時はデフォルトのトピッ
+    /// クフィルタのみ)。
+    ///
+    pub(crate) fn mqtt_topics(&self) -> Vec<String> {
+        if self.mqtt_topics.is_empty() {
+            vec![DEFAULT_MQTT_TOPIC.to_string()]
+        } else {
+            self.mqtt_topics.clone()
+        }
+    }
+
+    ///
+    /// MQTTへの再配信の有無へのアクセサ
+    ///
+    /// # 戻り値
+    /// 記録したレコードをMQTTブローカーへ再配信する場合は`true`を返す
+    ///
+    pub(crate) fn mqtt_publish(&self) -> bool {
+        self.mqtt_publish
+    }
+
+    ///
+    /// ステートトピックのプレフィックスへのアクセサ
+    ///
+    /// # 戻り値
+    /// 再配信時に使用するステートトピックのプレフィックスを返す
+    ///
+    pub(crate) fn mqtt_state_prefix(&self) -> String {
+        self.mqtt_state_prefix.clone()
+    }
+
+    ///
+    /// ディスカバリートピックのプレフィックスへのアクセサ
+    ///
+    /// # 戻り値
+    /// Home Assistantのディスカバリートピックのプレフィックスを返す
+    ///
+    pub(crate) fn mqtt_discovery_prefix(&self) -> String {
+        self.mqtt_discovery_prefix.clone()
+    }
+
+    ///
+    /// CSVファイルの出力先へのアクセサ
+    ///
+    /// # 戻り値
+    /// 出力先が指定されている場合は、ディレクトリのパスを`Some()`でラップし
+    /// て返す。
+    ///
+    pub(crate) fn csv_dir(&self) -> Option<PathBuf> {
+        self.csv_dir.clone()
+    }
+
+    ///
+    /// Webhookの通知先へのアクセサ
+    ///
+    /// # 戻り値
+    /// 通知先のURLのリストを返す
+    ///
+    pub(crate) fn webhook_urls(&self) -> Vec<String> {
+        self.webhook_urls.clone()
+    }
+
+    ///
+    /// シンクのキューの長さへのアクセサ
+    ///
+    /// # 戻り値
+    /// 各シンクのキューの長さを返す
+    ///
+    pub(crate) fn sink_queue_size(&self) -> usize {
+        self.sink_queue_size
+    }
+
+    ///
+    /// シンクの稼働状況の出力間隔へのアクセサ
+    ///
+    /// # 戻り値
+    /// 定期出力を行う場合は、出力間隔(秒)を`Some()`でラップして返す。
+    ///
+    pub(crate) fn sink_stats_interval(&self) -> Option<u64> {
+        (self.sink_stats_interval > 0).then_some(self.sink_stats_interval)
+    }
+
+    ///
+    /// 転送先の書き込みAPIのURLへのアクセサ
+    ///
+    /// # 戻り値
+    /// 転送先が指定されている場合は、URLを`Some()`でラップして返す。
+    ///
+    pub(crate) fn tsdb_url(&self) -> Option<String> {
+        self.tsdb_url.clone()
+    }
+
+    ///
+    /// 書き込みAPIの認証トークンへのアクセサ
+    ///
+    /// # 戻り値
+    /// トークンが指定されている場合は、トークンを`Some()`でラップして返す。
+    ///
+    pub(crate) fn tsdb_token(&self) -> Option<String> {
+        self.tsdb_token.clone()
+    }
+
+    ///
+    /// 転送時のメジャーメント名へのアクセサ
+    ///
+    /// # 戻り値
+    /// 転送時に使用するメジャーメント名を返す
+    ///
+    pub(crate) fn tsdb_measurement(&self) -> String {
+        self.tsdb_measurement.clone()
+    }
+
+    ///
+    /// 転送時のバッチサイズへのアクセサ
+    ///
+    /// # 戻り値
+    /// 1回のリクエストで転送するレコードの最大数を返す
+    ///
+    pub(crate) fn tsdb_batch_size(&self) -> usize {
+        self.tsdb_batch_size
+    }
+
+    ///
+    /// 中継先のアドレスへのアクセサ
+    ///
+    /// # 戻り値
+    /// 中継先が指定されている場合は、ホスト名とポート番号のタプルを`Some()`
+    /// でラップして返す。
+    ///
+    pub(crate) fn relay_upstream(&self) -> Option<(String, u16)> {
+        self.relay_upstream.as_deref()
+            .and_then(|upstream| split_host_port(upstream, DEFAULT_RELAY_PORT))
+    }
+
+    ///
+    /// 中継元の共有鍵へのアクセサ
+    ///
+    /// # 戻り値
+    /// 中継元の名前と共有鍵が指定されている場合は、そのタプルを`Some()`で
+    /// ラップして返す。
+    ///
+    pub(crate) fn relay_key(&self) -> Option<(String, String)> {
+        match (&self.relay_name, &self.relay_secret) {
+            (Some(name), Some(secret)) => Some((name.clone(), secret.clone())),
+            _ => None,
+        }
+    }
+
     ///
     /// データベースファイルへのアクセサ
     ///
@@ -147,6 +1000,17 @@ impl Options {
         self.db_file.clone()
     }
 
+    ///
+    /// サブコマンドへのアクセサ
+    ///
+    /// # 戻り値
+    /// サブコマンドが指定されている場合は、その内容を`Some()`でラップして返
+    /// す。
+    ///
+    pub(crate) fn command(&self) -> Option<Command> {
+        self.command.clone()
+    }
+
     ///
     /// 設定情報のバリデーション
     ///
@@ -159,10 +1023,162 @@ impl Options {
             return Err(anyhow!("待ち受けポート番号が範囲外です。"));
         }
 
+        // 追加の待ち受けポート番号の範囲の確認
+        let ports = [
+            ("CoAP", self.coap_port),
+            ("ラインプロトコル", self.influx_port),
+            ("書き込みAPI", self.influx_http_port),
+        ];
+
+        for (name, port) in ports {
+            if let Some(port) = port {
+                if !(1024..=65535).contains(&port) {
+                    return Err(anyhow!("{}の待ち受けポート番号が範囲外です。", name));
+                }
+            }
+        }
+
+        // TCPの受信の制限の確認
+        if self.tcp_max_connections == 0
+            || self.tcp_max_connections > Semaphore::MAX_PERMITS
+        {
+            return Err(anyhow!("TCPの同時接続数の上限が不正です。"));
+        }
+
+        if self.tcp_max_line_length == 0 {
+            return Err(anyhow!("TCPで受信する行の長さの上限が不正です。"));
+        }
+
+        if self.tcp_read_timeout == 0 {
+            return Err(anyhow!("TCPの受信期限が不正です。"));
+        }
+
+        // UDPの受信の設定の確認
+        if self.udp_workers == 0 {
+            return Err(anyhow!("UDPのワーカーの数が不正です。"));
+        }
+
+        if !(1..=65507).contains(&self.udp_max_datagram_size) {
+            return Err(anyhow!("UDPのデータグラムの上限が範囲外です。"));
+        }
+
+        // MQTTブローカーのアドレスの確認
+        if let Some(broker) = &self.mqtt_broker {
+            if split_host_port(broker, DEFAULT_MQTT_PORT).is_none() {
+                return Err(anyhow!("MQTTブローカーのアドレスが不正です。"));
+            }
+        }
+
+        // 再配信時のトピックプレフィックスの確認
+        let prefixes = [&self.mqtt_state_prefix, &self.mqtt_discovery_prefix];
+
+        if prefixes.iter().any(|s| s.is_empty() || s.contains(['+', '#'])) {
+            return Err(anyhow!("MQTTのトピックプレフィックスが不正です。"));
+        }
+
+        // シンクのキューの長さの確認
+        if self.sink_queue_size == 0 {
+            return Err(anyhow!("シンクのキューの長さが不正です。"));
+        }
+
+        // 転送先および通知先のURLの確認
+        let urls = self.tsdb_url.iter().chain(self.webhook_urls.iter());
+
+        for url in urls {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => return Err(anyhow!("URLが不正です: {}", url)),
+            }
+        }
+
+        // 転送時の設定の確認
+        if self.tsdb_measurement.is_empty() {
+            return Err(anyhow!("転送時のメジャーメント名が空です。"));
+        }
+
+        if self.tsdb_batch_size == 0 {
+            return Err(anyhow!("転送時のバッチサイズが不正です。"));
+        }
+
+        // 中継先のアドレスの確認
+        if let Some(upstream) = &self.relay_upstream {
+            if split_host_port(upstream, DEFAULT_RELAY_PORT).is_none() {
+                return Err(anyhow!("中継先のアドレスが不正です。"));
+            }
+        }
+
+        // 中継元の共有鍵の確認
+        let relay_key = [&self.relay_name, &self.relay_secret];
+
+        if relay_key.iter().any(|val| val.as_deref() == Some("")) {
+            return Err(anyhow!("中継元の名前または共有鍵が空です。"));
+        }
+
+        // サブコマンドの対象期間の確認
+        let range = match &self.command {
+            Some(Command::Recalibrate {from, until, ..}) => (*from, *until),
+            Some(Command::Rederive {from, until}) => (*from, *until),
+            Some(Command::Relocate {from, until, ..}) => (*from, *until),
+            Some(Command::Query {from, until, ..}) => (*from, *until),
+            _ => (None, None),
+        };
+
+        if let (Some(from), Some(until)) = range {
+            if from >= until {
+                return Err(anyhow!("対象期間の指定が不正です。"));
+            }
+        }
+
         Ok(())
     }
 }
 
+///
+/// "ホスト名[:ポート番号]"形式のアドレスの分解
+///
+/// # 引数
+/// * `src` - 分解するアドレス
+/// * `default_port` - ポート番号が省略された場合に補うポート番号
+///
+/// # 戻り値
+/// ホスト名とポート番号のタプルを`Some()`でラップして返す。書式が不正な場合
+/// は`None`を返す。
+///
+/// # 注記
+/// IPv6アドレスは"[::1]:1883"のように角括弧で括る(ポート番号を省略する場合
+/// は括らなくてもよい)。返すホスト名には角括弧を含めない。
+///
+fn split_host_port(src: &str, default_port: u16) -> Option<(String, u16)> {
+    let (host, port) = if let Some(rest) = src.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+
+        match rest {
+            "" => (host, None),
+            _ => (host, Some(rest.strip_prefix(':')?)),
+        }
+
+    } else if src.matches(':').count() > 1 {
+        (src, None)
+
+    } else {
+        match src.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (src, None),
+        }
+    };
+
+    if host.is_empty() {
+        return None;
+    }
+
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port,
+    };
+
+    Some((host.to_string(), port))
+}
+
 ///
 /// コマンドラインオプションのパース
 ///
@@ -188,3 +1204,111 @@ pub(super) fn parse() -> Result<Arc<Options>> {
      */
     Ok(Arc::new(opts))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_host_name() {
+        assert_eq!(
+            split_host_port("broker.local:1884", 1883),
+            Some(("broker.local".to_string(), 1884))
+        );
+        assert_eq!(
+            split_host_port("broker.local", 1883),
+            Some(("broker.local".to_string(), 1883))
+        );
+    }
+
+    #[test]
+    fn split_ipv6_address() {
+        assert_eq!(
+            split_host_port("[::1]:1884", 1883),
+            Some(("::1".to_string(), 1884))
+        );
+        assert_eq!(
+            split_host_port("[fe80::1]", 1883),
+            Some(("fe80::1".to_string(), 1883))
+        );
+        assert_eq!(
+            split_host_port("fe80::1", 1883),
+            Some(("fe80::1".to_string(), 1883))
+        );
+    }
+
+    #[test]
+    fn reject_malformed_address() {
+        assert_eq!(split_host_port("", 1883), None);
+        assert_eq!(split_host_port(":1883", 1883), None);
+        assert_eq!(split_host_port("host:port", 1883), None);
+        assert_eq!(split_host_port("[::1", 1883), None);
+        assert_eq!(split_host_port("[::1]1883", 1883), None);
+        assert_eq!(split_host_port("host:70000", 1883), None);
+    }
+
+    #[test]
+    fn validate_max_connections() {
+        let opts = |n: &str| {
+            Options::try_parse_from(["env-logger", "--tcp-max-connections", n])
+                .unwrap()
+        };
+
+        assert!(opts("256").validate().is_ok());
+        assert!(opts("0").validate().is_err());
+
+        let max = Semaphore::MAX_PERMITS;
+        assert!(opts(&max.to_string()).validate().is_ok());
+        assert!(opts(&(max + 1).to_string()).validate().is_err());
+    }
+
+    #[test]
+    fn publish_without_subscribe() {
+        let opts = |args: &[&str]| {
+            let args = ["env-logger", "--mqtt-broker", "localhost"].iter()
+                .chain(args);
+            Options::try_parse_from(args).unwrap()
+        };
+
+        let only_broker = opts(&[]);
+        assert!(only_broker.mqtt_subscribe());
+        assert_eq!(only_broker.mqtt_topics(), [DEFAULT_MQTT_TOPIC]);
+
+        assert!(!opts(&["--mqtt-publish"]).mqtt_subscribe());
+
+        let both = opts(&["--mqtt-publish", "--mqtt-topic", "sensors/#"]);
+        assert!(both.mqtt_subscribe());
+        assert_eq!(both.mqtt_topics(), ["sensors/#"]);
+    }
+
+    #[test]
+    fn parse_relay_upstream() {
+        let opts = |upstream: &str| {
+            let args = [
+                "env-logger",
+                "--relay-upstream", upstream,
+                "--relay-name", "cabin",
+                "--relay-secret", "secret",
+            ];
+            Options::try_parse_from(args).unwrap()
+        };
+
+        let upstream = opts("[::1]:2400");
+        assert!(upstream.validate().is_ok());
+        assert_eq!(upstream.relay_upstream(), Some(("::1".to_string(), 2400)));
+
+        let upstream = opts("hub.local");
+        assert!(upstream.validate().is_ok());
+        assert_eq!(
+            upstream.relay_upstream(),
+            Some(("hub.local".to_string(), DEFAULT_RELAY_PORT))
+        );
+
+        assert!(opts("[::1]2400").validate().is_err());
+        assert!(opts("hub.local:port").validate().is_err());
+
+        // 中継元の名前と共有鍵は必須
+        let args = ["env-logger", "--relay-upstream", "hub.local"];
+        assert!(Options::try_parse_from(args).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! デバイスの登録簿を操作するサブコマンドの処理をまとめたモジュール
+//!
+//! 稼働中のプロセスに変更を反映するにはSIGHUPを送る。
+//!
+
+use anyhow::Result;
+use chrono::Utc;
+
+use crate::cmd_args::{DeviceAction, Options};
+use crate::database::open_database;
+use crate::record::local_time_string;
+use crate::registry;
+use crate::telemetry::{self, StatusSummary};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+///
+/// デバイスの登録簿の操作
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+/// * `action` - 実行する操作
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+pub(super) fn run(opts: &Options, action: DeviceAction) -> Result<()> {
+    let conn = open_database(opts.db_file())?;
+    let now = Utc::now().timestamp_millis() as u64;
+
+    match action {
+        DeviceAction::List => {
+            let assignments = registry::select_assignments(&conn)?;
+
+            for device in registry::select_devices(&conn)? {
+                let mut line = device.device_id.clone();
+
+                if let Some(description) = &device.description {
+                    line.push_str(&format!(" \"{}\"", description));
+                }
+
+                line.push_str(&format!(
+                    " registered {}",
+                    local_time_string(device.registered)
+                ));
+
+                if let Some(retired) = device.retired {
+                    line.push_str(&format!(
+                        " (retired {})",
+                        local_time_string(retired)
+                    ));
+                }
+
+                println!("{}", line);
+
+                let history = assignments.iter()
+                    .filter(|a| a.device_id == device.device_id);
+
+                for a in history {
+                    println!(
+                        "    {} .. {} {}",
+                        local_time_string(a.valid_from),
+                        a.valid_until.map(local_time_string).unwrap_or_default(),
+                        a.location
+                    );
+                }
+            }
+        }
+
+        DeviceAction::Add {device_id, location, since, description} => {
+            let since = since.unwrap_or(now);
+
+            registry::add_device(
+                &conn,
+                &device_id,
+                &location,
+                since,
+                description
+            )?;
+
+            info!("device registered: {} at {}", device_id, location);
+            println!("{} registered at {}", device_id, location);
+        }
+
+        DeviceAction::Move {device_id, location, since} => {
+            let since = since.unwrap_or(now);
+
+            registry::move_device(&conn, &device_id, &location, since)?;
+
+            info!("device moved: {} to {}", device_id, location);
+            println!("{} moved to {}", device_id, location);
+        }
+
+        DeviceAction::Retire {device_id, since} => {
+            let since = since.unwrap_or(now);
+
+            registry::retire_device(&conn, &device_id, since)?;
+
+            info!("device retired: {}", device_id);
+            println!("{} retired", device_id);
+        }
+
+        DeviceAction::Status {device_id} => {
+            let list = telemetry::select_status(&conn, device_id.as_deref())?;
+
+            for status in list {
+                println!("{}", describe_status(&status));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// 稼働状態の要約の表示用文字列の生成
+///
+fn describe_status(status: &StatusSummary) -> String {
+    let mut ret = format!(
+        "{} last_seen={}",
+        status.device_id,
+        local_time_string(status.last_seen)
+    );
+
+    let rssi = status.rssi.map(|rssi| {
+        match (status.rssi_trend(), status.rssi_24h) {
+            (Some(trend), Some(avg)) => {
+                format!("{}dBm({}, 24h avg {:.1}dBm)", rssi, trend, avg)
+            }
+            _ => format!("{}dBm", rssi),
+        }
+    });
+
+    let attrs = [
+        ("firmware", status.firmware.clone()),
+        ("rssi", rssi),
+        ("battery", status.battery.map(|val| format!("{}%", val))),
+        ("uptime", status.uptime.map(|val| format!("{}s", val))),
+        ("reset_reason", status.reset_reason.clone()),
+    ];
+
+    for (key, val) in attrs {
+        if let Some(val) = val {
+            ret.push_str(&format!(" {}={}", key, val));
+        }
+    }
+
+    ret.push_str(&format!(" reboots={}", status.reboot_count));
+
+    ret
+}
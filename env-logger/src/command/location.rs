@@ -0,0 +1,106 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 設置場所の登録簿を操作するサブコマンドの処理をまとめたモジュール
+//!
+
+use anyhow::{anyhow, Result};
+
+use crate::cmd_args::{LocationAction, Options};
+use crate::database::open_database;
+use crate::registry::{self, LocationInfo};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+///
+/// 設置場所の登録簿の操作
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+/// * `action` - 実行する操作
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+pub(super) fn run(opts: &Options, action: LocationAction) -> Result<()> {
+    let conn = open_database(opts.db_file())?;
+
+    match action {
+        LocationAction::List => {
+            for info in registry::select_locations(&conn)? {
+                println!("{}", describe(&info));
+            }
+        }
+
+        LocationAction::Set {
+            name,
+            display_name,
+            floor,
+            room,
+            outdoor,
+            altitude,
+            tags,
+        } => {
+            if name.is_empty() {
+                return Err(anyhow!("location name is empty"));
+            }
+
+            if altitude.is_some_and(|alt| !alt.is_finite()) {
+                return Err(anyhow!("invalid altitude"));
+            }
+
+            let info = LocationInfo {
+                name,
+                display_name,
+                floor,
+                room,
+                outdoor,
+                altitude,
+                tags: tags.iter()
+                    .flat_map(|tag| registry::split_tags(tag))
+                    .collect(),
+            };
+
+            registry::upsert_location(&conn, &info)?;
+
+            info!("location registered: {}", info.name);
+            println!("{}", describe(&info));
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// 設置場所の情報の表示用文字列の生成
+///
+fn describe(info: &LocationInfo) -> String {
+    let mut ret = info.name.clone();
+
+    if let Some(display_name) = &info.display_name {
+        ret.push_str(&format!(" \"{}\"", display_name));
+    }
+
+    let attrs = [
+        ("floor", info.floor.clone()),
+        ("room", info.room.clone()),
+        ("altitude", info.altitude.map(|alt| format!("{}m", alt))),
+        ("tags", (!info.tags.is_empty()).then(|| info.tags.join(","))),
+    ];
+
+    ret.push_str(if info.outdoor {" outdoor"} else {" indoor"});
+
+    for (key, val) in attrs {
+        if let Some(val) = val {
+            ret.push_str(&format!(" {}={}", key, val));
+        }
+    }
+
+    ret
+}
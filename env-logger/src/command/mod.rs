@@ -0,0 +1,94 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! サブコマンドの処理をまとめたモジュール
+//!
+//! サブコマンドは記録済みのデータベースに対する保守作業を行うためのもので、
+//! レコードの受信は行わない。
+//!
+
+mod device;
+mod location;
+mod query;
+mod recalibrate;
+mod rederive;
+mod relocate;
+
+use anyhow::Result;
+
+use crate::cmd_args::{Command, Options};
+
+///
+/// サブコマンドの実行
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+/// * `command` - 実行するサブコマンド
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+pub(crate) fn run(opts: &Options, command: Command) -> Result<()> {
+    match command {
+        Command::Recalibrate {from, until, devices, dry_run} => {
+            recalibrate::run(opts, from, until, &devices, dry_run)
+        }
+
+        Command::Rederive {from, until} => {
+            rederive::run(opts, from, until)
+        }
+
+        Command::Location {action} => {
+            location::run(opts, action)
+        }
+
+        Command::Device {action} => {
+            device::run(opts, action)
+        }
+
+        Command::Relocate {
+            src,
+            dst,
+            device,
+            from,
+            until,
+            on_conflict,
+            dry_run,
+        } => {
+            let range = relocate::Range {device, from, until};
+            relocate::run(opts, src, dst, range, on_conflict, dry_run)
+        }
+
+        Command::UndoRelocate {batch_id, list} => {
+            relocate::undo(opts, batch_id, list)
+        }
+
+        Command::Query {
+            location,
+            device,
+            from,
+            until,
+            metrics,
+            limit,
+            condition,
+            format,
+        } => {
+            let filter = query::Filter {
+                location,
+                device,
+                from,
+                until,
+                metrics,
+                limit,
+                condition,
+            };
+
+            query::run(opts, filter, format)
+        }
+    }
+}
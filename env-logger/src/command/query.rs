@@ -0,0 +1,334 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 記録済みのレコードの検索を行うサブコマンドの処理をまとめたモジュール
+//!
+//! 検索結果は標準出力に書き出す。出力形式はテキスト(1レコード1行)、CSV、
+//! JSON Lines(1レコード1行のJSON)から選択できる。CSVでは計測種別毎に列を
+//! 設けるため、検索結果に含まれる計測種別によって列の構成が変わる。
+//!
+//! 未知のプロパティ(`extras`カラムのJSONオブジェクト)は、`--where`で指定
+//! する条件式の中でSQLiteのJSON関数を用いて参照できる。
+//!
+//! ```text
+//! env-logger database.db query --where "json_extract(extras, '$.rssi') < -80"
+//! ```
+//!
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+use rusqlite::{named_params, Connection};
+use serde_json::{Map, Value};
+
+use crate::cmd_args::{Options, QueryFormat};
+use crate::database::open_database;
+use crate::record::local_time_string;
+use crate::sink::csv::{quote, value_string};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 検索対象のレコードの取得クエリー
+const SELECT_RECORDS_QUERY: &str =
+    include_str!("../../data/select_query_records.sql");
+
+/// 計測値の取得クエリー
+const SELECT_MEASUREMENTS_QUERY: &str =
+    include_str!("../../data/select_measurements.sql");
+
+///
+/// 検索条件を表す構造体
+///
+pub(super) struct Filter {
+    /// 設置場所名
+    pub(super) location: Option<String>,
+
+    /// デバイスID
+    pub(super) device: Option<String>,
+
+    /// 対象期間の開始時刻(ミリ秒単位のUNIX時刻)
+    pub(super) from: Option<u64>,
+
+    /// 対象期間の終了時刻(ミリ秒単位のUNIX時刻)
+    pub(super) until: Option<u64>,
+
+    /// 出力する計測種別(空の場合は全ての計測種別)
+    pub(super) metrics: Vec<String>,
+
+    /// 出力するレコード数の上限
+    pub(super) limit: Option<usize>,
+
+    /// 追加の条件式(SQLの式)
+    pub(super) condition: Option<String>,
+}
+
+///
+/// 検索結果のレコードを表す構造体
+///
+struct Row {
+    /// 設置場所名
+    location: String,
+
+    /// デバイスID
+    device_id: Option<String>,
+
+    /// タイムスタンプ
+    timestamp: u64,
+
+    /// 気温
+    temperature: Option<f32>,
+
+    /// 湿度
+    humidity: Option<f32>,
+
+    /// 気圧
+    air_pressure: Option<f32>,
+
+    /// 品質フラグ
+    quality: Option<String>,
+
+    /// 追加の計測値
+    metrics: BTreeMap<String, f32>,
+
+    /// 未知のプロパティ
+    extras: Map<String, Value>,
+}
+
+///
+/// 記録済みのレコードの検索
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+/// * `filter` - 検索条件
+/// * `format` - 出力形式
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+/// # 注記
+/// 計測種別を指定した場合は、指定した計測値のいずれかを持つレコードのみを
+/// 出力する。
+///
+pub(super) fn run(opts: &Options, filter: Filter, format: QueryFormat)
+    -> Result<()>
+{
+    let conn = open_database(opts.db_file())?;
+    let rows = match select_rows(&conn, &filter) {
+        Ok(rows) => rows,
+        Err(err) => return Err(anyhow!("select records failed: {}", err)),
+    };
+
+    match format {
+        QueryFormat::Text => {
+            for row in &rows {
+                println!("{}", text_line(row));
+            }
+        }
+
+        QueryFormat::Csv => {
+            let names = rows.iter()
+                .flat_map(|row| row.metrics.keys().cloned())
+                .collect::<BTreeSet<_>>();
+
+            println!("{}", csv_header(&names));
+
+            for row in &rows {
+                println!("{}", csv_line(row, &names));
+            }
+        }
+
+        QueryFormat::Json => {
+            for row in &rows {
+                println!("{}", json_line(row));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// 検索対象のレコードの読み出し
+///
+fn select_rows(conn: &Connection, filter: &Filter)
+    -> rusqlite::Result<Vec<Row>>
+{
+    let query = SELECT_RECORDS_QUERY.replace(
+        "{condition}",
+        filter.condition.as_deref().unwrap_or("1")
+    );
+
+    let mut stmt = conn.prepare(&query)?;
+    let mut measurements = conn.prepare(SELECT_MEASUREMENTS_QUERY)?;
+    let rows = stmt.query_map(
+        named_params! {
+            ":from": filter.from.unwrap_or(0),
+            ":until": filter.until.unwrap_or(i64::MAX as u64),
+            ":location": filter.location,
+            ":device": filter.device,
+            ":limit": filter.limit.map(|n| n as i64).unwrap_or(-1),
+        },
+        |row| Ok(Row {
+            location: row.get(0)?,
+            device_id: row.get(1)?,
+            timestamp: row.get(2)?,
+            temperature: row.get(3)?,
+            humidity: row.get(4)?,
+            air_pressure: row.get(5)?,
+            quality: row.get(6)?,
+            metrics: BTreeMap::new(),
+            extras: row.get::<_, Option<String>>(7)?
+                .and_then(|s| serde_json::from_str(&s).ok())
+                .unwrap_or_default(),
+        })
+    )?;
+
+    let mut ret = vec![];
+
+    for row in rows {
+        let mut row = row?;
+        let metrics = measurements.query_map(
+            named_params! {
+                ":location": row.location,
+                ":timestamp": row.timestamp,
+            },
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, f32>(1)?))
+        )?;
+
+        for metric in metrics {
+            let (name, val) = metric?;
+
+            if filter.metrics.is_empty() || filter.metrics.contains(&name) {
+                row.metrics.insert(name, val);
+            }
+        }
+
+        if filter.metrics.is_empty() || !row.metrics.is_empty() {
+            ret.push(row);
+        }
+    }
+
+    Ok(ret)
+}
+
+///
+/// テキスト形式の行の生成
+///
+fn text_line(row: &Row) -> String {
+    let mut ret = format!(
+        "{} {} {}",
+        local_time_string(row.timestamp),
+        row.location,
+        row.device_id.as_deref().unwrap_or("-"),
+    );
+
+    let values = [
+        ("temperature", row.temperature),
+        ("humidity", row.humidity),
+        ("air_pressure", row.air_pressure),
+    ];
+
+    for (name, val) in values {
+        if let Some(val) = val {
+            ret.push_str(&format!(" {}={}", name, val));
+        }
+    }
+
+    for (name, val) in &row.metrics {
+        ret.push_str(&format!(" {}={}", name, val));
+    }
+
+    for (key, val) in &row.extras {
+        ret.push_str(&format!(" {}={}", key, val));
+    }
+
+    if let Some(quality) = &row.quality {
+        ret.push_str(&format!(" quality={}", quality));
+    }
+
+    ret
+}
+
+///
+/// CSVのヘッダ行の生成
+///
+fn csv_header(names: &BTreeSet<String>) -> String {
+    let mut ret = concat!(
+        "timestamp,location,device_id,",
+        "temperature,humidity,air_pressure,quality,extras"
+    ).to_string();
+
+    for name in names {
+        ret.push(',');
+        ret.push_str(name);
+    }
+
+    ret
+}
+
+///
+/// CSVの行の生成
+///
+fn csv_line(row: &Row, names: &BTreeSet<String>) -> String {
+    let mut fields = vec![
+        local_time_string(row.timestamp),
+        quote(&row.location),
+        quote(row.device_id.as_deref().unwrap_or_default()),
+        value_string(row.temperature),
+        value_string(row.humidity),
+        value_string(row.air_pressure),
+        quote(row.quality.as_deref().unwrap_or_default()),
+        quote(&extras_string(&row.extras)),
+    ];
+
+    for name in names {
+        fields.push(value_string(row.metrics.get(name).copied()));
+    }
+
+    fields.join(",")
+}
+
+///
+/// JSON Lines形式の行の生成
+///
+fn json_line(row: &Row) -> String {
+    let mut map = Map::new();
+
+    map.insert("location".into(), row.location.clone().into());
+    map.insert("device_id".into(), row.device_id.clone().into());
+    map.insert("timestamp".into(), row.timestamp.into());
+    map.insert("temperature".into(), row.temperature.into());
+    map.insert("humidity".into(), row.humidity.into());
+    map.insert("air_pressure".into(), row.air_pressure.into());
+
+    for (name, val) in &row.metrics {
+        map.insert(name.clone(), (*val).into());
+    }
+
+    for (key, val) in &row.extras {
+        map.insert(key.clone(), val.clone());
+    }
+
+    if let Some(quality) = &row.quality {
+        map.insert("quality".into(), quality.clone().into());
+    }
+
+    Value::Object(map).to_string()
+}
+
+///
+/// 未知のプロパティの文字列化(プロパティが無い場合は空文字列)
+///
+fn extras_string(extras: &Map<String, Value>) -> String {
+    if extras.is_empty() {
+        String::new()
+    } else {
+        Value::Object(extras.clone()).to_string()
+    }
+}
@@ -0,0 +1,195 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 較正情報の再適用を行うサブコマンドの処理をまとめたモジュール
+//!
+
+use anyhow::{anyhow, Result};
+use rusqlite::{named_params, Connection, Row};
+
+use crate::calibration::Calibration;
+use crate::cmd_args::Options;
+use crate::config::Config;
+use crate::database::open_database;
+use crate::record::{RawValues, SensorRecord};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 再適用対象のレコードの取得クエリー
+const SELECT_TARGET_QUERY: &str =
+    include_str!("../../data/select_calibration_target.sql");
+
+/// 較正結果の更新クエリー
+const UPDATE_CALIBRATION_QUERY: &str =
+    include_str!("../../data/update_calibration.sql");
+
+///
+/// 較正情報の再適用
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+/// * `from` - 対象期間の開始時刻(ミリ秒単位のUNIX時刻)
+/// * `until` - 対象期間の終了時刻(ミリ秒単位のUNIX時刻)
+/// * `devices` - 対象とするデバイスIDのリスト(空の場合は全てのデバイス)
+/// * `dry_run` - 変更内容の表示のみを行うか否か
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+/// # 注記
+/// 記録済みのレコードを現在の設定ファイルの較正情報で較正し直す。較正前の
+/// 計測値が記録されていないレコードは、記録されている計測値を較正前の値とし
+/// て扱う。計測値を更新したレコードは派生値も計算し直す。なお、転送済みの
+/// レコードを転送先で更新することは行わない。処理ステージは実行し直さない
+/// ので、単位の変換や丸めを行う処理ステージを使用している場合は、その変換
+/// は再適用の結果には反映されない。
+///
+pub(super) fn run(
+    opts: &Options,
+    from: Option<u64>,
+    until: Option<u64>,
+    devices: &[String],
+    dry_run: bool,
+) -> Result<()>
+{
+    /*
+     * 較正情報の読み込み
+     */
+    let config = Config::load(opts.config_file())?;
+    let calibration = Calibration::new(&config.calibrations())?;
+
+    /*
+     * 対象レコードの読み出し
+     */
+    let conn = open_database(opts.db_file())?;
+    let records = match select_target(&conn, from, until) {
+        Ok(records) => records,
+        Err(err) => return Err(anyhow!("select records failed: {}", err)),
+    };
+
+    /*
+     * 較正の再適用
+     */
+    let mut updated = vec![];
+    let mut examined = 0;
+
+    for mut record in records {
+        if !devices.is_empty()
+            && !record.device_id().is_some_and(|id| devices.contains(&id))
+        {
+            continue;
+        }
+
+        examined += 1;
+
+        let before = record.clone();
+
+        if calibration.apply(&mut record) {
+            if dry_run {
+                println!("{} -> {}", before, record);
+            }
+
+            updated.push(record);
+        }
+    }
+
+    /*
+     * 較正結果の書き込み
+     */
+    if !dry_run {
+        if let Err(err) = update_records(&conn, &updated) {
+            return Err(anyhow!("update records failed: {}", err));
+        }
+
+        info!("recalibrate {} of {} records", updated.len(), examined);
+    }
+
+    println!(
+        "{} of {} records {}",
+        updated.len(),
+        examined,
+        if dry_run {"would be updated"} else {"updated"}
+    );
+
+    Ok(())
+}
+
+///
+/// 再適用対象のレコードの読み出し
+///
+fn select_target(conn: &Connection, from: Option<u64>, until: Option<u64>)
+    -> rusqlite::Result<Vec<SensorRecord>>
+{
+    let mut stmt = conn.prepare(SELECT_TARGET_QUERY)?;
+    let rows = stmt.query_map(
+        named_params! {
+            ":from": from.unwrap_or(0),
+            ":until": until.unwrap_or(i64::MAX as u64),
+        },
+        to_record
+    )?;
+
+    rows.collect()
+}
+
+///
+/// 読み出した行からのレコードの生成
+///
+fn to_record(row: &Row) -> rusqlite::Result<SensorRecord> {
+    let mut record = SensorRecord::new(
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+    );
+
+    record.set_raw(RawValues {
+        temperature: row.get(6)?,
+        humidity: row.get(7)?,
+        air_pressure: row.get(8)?,
+    });
+
+    Ok(record)
+}
+
+///
+/// 較正結果の書き込み(全てのレコードを同一トランザクションで更新する)
+///
+fn update_records(conn: &Connection, records: &[SensorRecord])
+    -> rusqlite::Result<()>
+{
+    let tx = conn.unchecked_transaction()?;
+
+    for record in records {
+        let derived = record.derived();
+
+        tx.execute(
+            UPDATE_CALIBRATION_QUERY,
+            named_params! {
+                ":location" : record.location(),
+                ":timestamp" : record.timestamp(),
+                ":temperature" : record.temperature(),
+                ":humidity" : record.humidity(),
+                ":air_pressure" : record.air_pressure(),
+                ":raw_temperature" : record.raw().temperature,
+                ":raw_humidity" : record.raw().humidity,
+                ":raw_air_pressure" : record.raw().air_pressure,
+                ":dew_point" : derived.map(|d| d.dew_point),
+                ":absolute_humidity" : derived.map(|d| d.absolute_humidity),
+                ":humidex" : derived.map(|d| d.humidex),
+                ":heat_index" : derived.map(|d| d.heat_index),
+                ":vpd" : derived.map(|d| d.vpd),
+            },
+        )?;
+    }
+
+    tx.commit()
+}
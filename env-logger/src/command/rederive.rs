@@ -0,0 +1,179 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 派生値の再計算を行うサブコマンドの処理をまとめたモジュール
+//!
+
+use anyhow::{anyhow, Result};
+use rusqlite::{named_params, Connection, Row};
+
+use crate::cmd_args::Options;
+use crate::database::open_database;
+use crate::psychrometrics::Derived;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 再計算対象のレコードの取得クエリー
+const SELECT_TARGET_QUERY: &str =
+    include_str!("../../data/select_derive_target.sql");
+
+/// 派生値の更新クエリー
+const UPDATE_DERIVED_QUERY: &str =
+    include_str!("../../data/update_derived.sql");
+
+///
+/// 再計算対象のレコードを表す構造体
+///
+struct Target {
+    /// 設置場所名
+    location: String,
+
+    /// タイムスタンプ
+    timestamp: u64,
+
+    /// 再計算した派生値
+    derived: Option<Derived>,
+}
+
+///
+/// 派生値の再計算
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+/// * `from` - 対象期間の開始時刻(ミリ秒単位のUNIX時刻)
+/// * `until` - 対象期間の終了時刻(ミリ秒単位のUNIX時刻)
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+/// # 注記
+/// 記録済みの気温と湿度から派生値を計算し直し、記録されている値と異なるレ
+/// コードのみを更新する。計算式を変更した場合や、以前のバージョンで記録した
+/// レコードに派生値を補う場合に使用する。
+///
+pub(super) fn run(opts: &Options, from: Option<u64>, until: Option<u64>)
+    -> Result<()>
+{
+    /*
+     * 対象レコードの読み出し
+     */
+    let conn = open_database(opts.db_file())?;
+    let (targets, examined) = match select_target(&conn, from, until) {
+        Ok(ret) => ret,
+        Err(err) => return Err(anyhow!("select records failed: {}", err)),
+    };
+
+    /*
+     * 派生値の書き込み
+     */
+    if let Err(err) = update_records(&conn, &targets) {
+        return Err(anyhow!("update records failed: {}", err));
+    }
+
+    info!("rederive {} of {} records", targets.len(), examined);
+    println!("{} of {} records updated", targets.len(), examined);
+
+    Ok(())
+}
+
+///
+/// 再計算対象のレコードの読み出し
+///
+/// # 戻り値
+/// 派生値が変化するレコードのリストと、読み出したレコード数の組を返す。
+///
+fn select_target(conn: &Connection, from: Option<u64>, until: Option<u64>)
+    -> rusqlite::Result<(Vec<Target>, usize)>
+{
+    let mut stmt = conn.prepare(SELECT_TARGET_QUERY)?;
+    let rows = stmt.query_map(
+        named_params! {
+            ":from": from.unwrap_or(0),
+            ":until": until.unwrap_or(i64::MAX as u64),
+        },
+        to_target
+    )?;
+
+    let mut targets = vec![];
+    let mut examined = 0;
+
+    for row in rows {
+        if let Some(target) = row? {
+            targets.push(target);
+        }
+
+        examined += 1;
+    }
+
+    Ok((targets, examined))
+}
+
+///
+/// 読み出した行からの再計算対象の生成
+///
+/// # 戻り値
+/// 派生値が変化する場合は再計算対象を`Some()`でラップして返す。
+///
+fn to_target(row: &Row) -> rusqlite::Result<Option<Target>> {
+    let derived = Derived::compute(row.get(2)?, row.get(3)?);
+    let current = [
+        row.get::<_, Option<f32>>(4)?,
+        row.get::<_, Option<f32>>(5)?,
+        row.get::<_, Option<f32>>(6)?,
+        row.get::<_, Option<f32>>(7)?,
+        row.get::<_, Option<f32>>(8)?,
+    ];
+
+    let changed = match derived {
+        Some(derived) => derived.fields()
+            .iter()
+            .zip(current)
+            .any(|((_, val), cur)| cur != Some(*val)),
+
+        None => current.iter().any(|cur| cur.is_some()),
+    };
+
+    if !changed {
+        return Ok(None);
+    }
+
+    Ok(Some(Target {
+        location: row.get(0)?,
+        timestamp: row.get(1)?,
+        derived,
+    }))
+}
+
+///
+/// 派生値の書き込み(全てのレコードを同一トランザクションで更新する)
+///
+fn update_records(conn: &Connection, targets: &[Target])
+    -> rusqlite::Result<()>
+{
+    let tx = conn.unchecked_transaction()?;
+
+    for target in targets {
+        let derived = target.derived;
+
+        tx.execute(
+            UPDATE_DERIVED_QUERY,
+            named_params! {
+                ":location" : target.location,
+                ":timestamp" : target.timestamp,
+                ":dew_point" : derived.map(|d| d.dew_point),
+                ":absolute_humidity" : derived.map(|d| d.absolute_humidity),
+                ":humidex" : derived.map(|d| d.humidex),
+                ":heat_index" : derived.map(|d| d.heat_index),
+                ":vpd" : derived.map(|d| d.vpd),
+            },
+        )?;
+    }
+
+    tx.commit()
+}
@@ -0,0 +1,669 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 記録済みのレコードの設置場所を変更するサブコマンドの処理をまとめたモジュー
+//! ル
+//!
+//! 設置場所名の変更(移動元の全てのレコードを移す)と、デバイスや期間を限定
+//! したレコードの移動を扱う。移動は以下のテーブルに対して行う。
+//!
+//! * `SENSOR_RESULT_TABLE` - 計測結果
+//! * `MEASUREMENT_TABLE` - 移動したレコードの追加の計測値
+//! * `FORWARD_QUEUE_TABLE` - 移動したレコードの未転送分
+//! * `QUARANTINE_TABLE` - 隔離したレコード
+//! * `DEVICE_ASSIGNMENT_TABLE` - デバイスの設置場所の割り当て(設置場所名の
+//!   変更の場合のみ)
+//!
+//! 更新した行は処理単位毎に取り消し用のログに記録し、`undo-relocate`で元に
+//! 戻せるようにする。なお、転送済みのレコードを転送先で更新することは行わな
+//! い。
+//!
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use rusqlite::{named_params, Connection, Transaction};
+
+use crate::cmd_args::{ConflictPolicy, Options};
+use crate::database::open_database;
+use crate::record::local_time_string;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 移動対象のレコードの取得クエリー
+const SELECT_TARGET_QUERY: &str =
+    include_str!("../../data/select_relocate_target.sql");
+
+/// 移動対象の隔離レコードの取得クエリー
+const SELECT_QUARANTINE_QUERY: &str =
+    include_str!("../../data/select_relocate_quarantine.sql");
+
+/// 移動対象の割り当ての取得クエリー
+const SELECT_ASSIGNMENT_QUERY: &str =
+    include_str!("../../data/select_relocate_assignment.sql");
+
+/// 計測結果の移動クエリー
+const RELOCATE_RECORD_QUERY: &str =
+    include_str!("../../data/relocate_record.sql");
+
+/// 追加の計測値の移動クエリー
+const RELOCATE_MEASUREMENT_QUERY: &str =
+    include_str!("../../data/relocate_measurement.sql");
+
+/// 転送キューの移動クエリー
+const RELOCATE_FORWARD_QUERY: &str =
+    include_str!("../../data/relocate_forward.sql");
+
+/// 隔離レコードの移動クエリー
+const RELOCATE_QUARANTINE_QUERY: &str =
+    include_str!("../../data/relocate_quarantine.sql");
+
+/// 割り当ての移動クエリー
+const RELOCATE_ASSIGNMENT_QUERY: &str =
+    include_str!("../../data/relocate_assignment.sql");
+
+/// 処理ログの登録クエリー
+const INSERT_LOG_QUERY: &str =
+    include_str!("../../data/insert_relocate_log.sql");
+
+/// 取り消し用ログの登録クエリー
+const INSERT_UNDO_QUERY: &str =
+    include_str!("../../data/insert_relocate_undo.sql");
+
+/// 処理ログの一覧の取得クエリー
+const SELECT_LOGS_QUERY: &str =
+    include_str!("../../data/select_relocate_logs.sql");
+
+/// 取り消し用ログの取得クエリー
+const SELECT_UNDO_QUERY: &str =
+    include_str!("../../data/select_relocate_undo.sql");
+
+/// 取り消し済みの記録クエリー
+const MARK_UNDONE_QUERY: &str =
+    include_str!("../../data/mark_relocate_undone.sql");
+
+/// 計測結果の存在確認クエリー
+const SELECT_EXISTS_QUERY: &str =
+    include_str!("../../data/select_record_exists.sql");
+
+/// 計測結果のテーブル名
+const RECORD_TABLE: &str = "SENSOR_RESULT_TABLE";
+
+/// 隔離テーブルのテーブル名
+const QUARANTINE_TABLE: &str = "QUARANTINE_TABLE";
+
+/// 割り当てテーブルのテーブル名
+const ASSIGNMENT_TABLE: &str = "DEVICE_ASSIGNMENT_TABLE";
+
+/// 要約で表示する衝突の件数の上限
+const MAX_CONFLICT_REPORT: usize = 10;
+
+///
+/// 移動するレコードの範囲を表す構造体
+///
+pub(super) struct Range {
+    /// 対象とするデバイスID(Noneの場合は全てのデバイス)
+    pub(super) device: Option<String>,
+
+    /// 対象期間の開始時刻(ミリ秒単位のUNIX時刻)
+    pub(super) from: Option<u64>,
+
+    /// 対象期間の終了時刻(ミリ秒単位のUNIX時刻)
+    pub(super) until: Option<u64>,
+}
+
+impl Range {
+    ///
+    /// 設置場所名の変更か否かの判定(デバイスと期間のいずれも指定しない場合)
+    ///
+    fn is_rename(&self) -> bool {
+        self.device.is_none() && self.from.is_none() && self.until.is_none()
+    }
+}
+
+///
+/// 移動の対象を表す構造体
+///
+#[derive(Default)]
+struct Plan {
+    /// 移動するレコードのタイムスタンプとデバイスIDの組のリスト
+    records: Vec<(u64, Option<String>)>,
+
+    /// 移動先と衝突するレコードのタイムスタンプのリスト
+    conflicts: Vec<u64>,
+
+    /// 移動する隔離レコードのタイムスタンプのリスト
+    quarantines: Vec<u64>,
+
+    /// 移動する割り当てのデバイスIDと開始時刻の組のリスト
+    assignments: Vec<(String, u64)>,
+}
+
+///
+/// レコードの移動
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+/// * `src` - 移動元の設置場所名
+/// * `dst` - 移動先の設置場所名
+/// * `range` - 移動するレコードの範囲
+/// * `policy` - 移動先との衝突時の扱い
+/// * `dry_run` - 変更内容の要約の表示のみを行うか否か
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+/// # 注記
+/// デバイスと期間のいずれも指定しなかった場合は設置場所名の変更として扱い、
+/// デバイスの設置場所の割り当ても移す。
+///
+pub(super) fn run(
+    opts: &Options,
+    src: String,
+    dst: String,
+    range: Range,
+    policy: ConflictPolicy,
+    dry_run: bool,
+) -> Result<()>
+{
+    if src.is_empty() || dst.is_empty() || src == dst {
+        return Err(anyhow!("invalid location (src={}, dst={})", src, dst));
+    }
+
+    /*
+     * 対象の読み出し
+     */
+    let conn = open_database(opts.db_file())?;
+    let plan = match select_plan(&conn, &src, &dst, &range) {
+        Ok(plan) => plan,
+        Err(err) => return Err(anyhow!("select records failed: {}", err)),
+    };
+
+    /*
+     * 要約の表示
+     */
+    print_summary(&src, &dst, &plan, policy);
+
+    if !plan.conflicts.is_empty() && policy == ConflictPolicy::Fail {
+        return Err(anyhow!(
+            "{} records conflict at {} (use --on-conflict skip to leave them)",
+            plan.conflicts.len(),
+            dst
+        ));
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    /*
+     * 移動の実行
+     */
+    let batch_id = match execute(&conn, &src, &dst, &plan) {
+        Ok(batch_id) => batch_id,
+        Err(err) => return Err(anyhow!("relocate failed: {}", err)),
+    };
+
+    info!(
+        "relocate {} records from {} to {} (batch {})",
+        plan.records.len(),
+        src,
+        dst,
+        batch_id
+    );
+
+    println!("done (batch {0}, undo with `undo-relocate {0}`)", batch_id);
+
+    Ok(())
+}
+
+///
+/// 移動対象の読み出し
+///
+fn select_plan(conn: &Connection, src: &str, dst: &str, range: &Range)
+    -> rusqlite::Result<Plan>
+{
+    let mut plan = Plan::default();
+    let from = range.from.unwrap_or(0);
+    let until = range.until.unwrap_or(i64::MAX as u64);
+
+    /*
+     * 計測結果
+     */
+    let mut stmt = conn.prepare(SELECT_TARGET_QUERY)?;
+    let mut rows = stmt.query(named_params! {
+        ":src": src,
+        ":dst": dst,
+        ":from": from,
+        ":until": until,
+        ":device_id": range.device,
+    })?;
+
+    while let Some(row) = rows.next()? {
+        let timestamp: u64 = row.get(0)?;
+
+        if row.get::<_, bool>(2)? {
+            plan.conflicts.push(timestamp);
+        } else {
+            plan.records.push((timestamp, row.get(1)?));
+        }
+    }
+
+    /*
+     * 隔離レコード
+     */
+    let mut stmt = conn.prepare(SELECT_QUARANTINE_QUERY)?;
+    let rows = stmt.query_map(
+        named_params! {
+            ":src": src,
+            ":from": from,
+            ":until": until,
+            ":device_id": range.device,
+        },
+        |row| row.get(0)
+    )?;
+
+    plan.quarantines = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    /*
+     * 割り当て(設置場所名の変更の場合のみ)
+     */
+    if range.is_rename() {
+        let mut stmt = conn.prepare(SELECT_ASSIGNMENT_QUERY)?;
+        let rows = stmt.query_map(
+            named_params! {":src": src},
+            |row| Ok((row.get(0)?, row.get(1)?))
+        )?;
+
+        plan.assignments = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+    }
+
+    Ok(plan)
+}
+
+///
+/// 変更内容の要約の表示
+///
+fn print_summary(src: &str, dst: &str, plan: &Plan, policy: ConflictPolicy) {
+    println!("{} -> {}", src, dst);
+
+    /*
+     * 計測結果(デバイス毎の件数と期間)
+     */
+    let mut devices: BTreeMap<String, (usize, u64, u64)> = BTreeMap::new();
+
+    for (timestamp, device_id) in &plan.records {
+        let key = device_id.clone().unwrap_or_else(|| "(none)".to_string());
+        let entry = devices.entry(key).or_insert((0, *timestamp, *timestamp));
+
+        entry.0 += 1;
+        entry.1 = entry.1.min(*timestamp);
+        entry.2 = entry.2.max(*timestamp);
+    }
+
+    println!("  {}: {} records", RECORD_TABLE, plan.records.len());
+
+    for (device_id, (count, first, last)) in &devices {
+        println!(
+            "    {}: {} records ({} .. {})",
+            device_id,
+            count,
+            local_time_string(*first),
+            local_time_string(*last)
+        );
+    }
+
+    /*
+     * 衝突
+     */
+    if !plan.conflicts.is_empty() {
+        println!(
+            "  conflicts: {} records ({})",
+            plan.conflicts.len(),
+            match policy {
+                ConflictPolicy::Fail => "abort",
+                ConflictPolicy::Skip => "left at source",
+            }
+        );
+
+        for timestamp in plan.conflicts.iter().take(MAX_CONFLICT_REPORT) {
+            println!("    {}", local_time_string(*timestamp));
+        }
+
+        if plan.conflicts.len() > MAX_CONFLICT_REPORT {
+            println!(
+                "    ... and {} more",
+                plan.conflicts.len() - MAX_CONFLICT_REPORT
+            );
+        }
+    }
+
+    /*
+     * 隔離レコードと割り当て
+     */
+    println!("  {}: {} records", QUARANTINE_TABLE, plan.quarantines.len());
+
+    if !plan.assignments.is_empty() {
+        println!(
+            "  {}: {} assignments",
+            ASSIGNMENT_TABLE,
+            plan.assignments.len()
+        );
+    }
+}
+
+///
+/// 移動の実行(全ての更新を同一トランザクションで行う)
+///
+/// # 戻り値
+/// 処理単位の識別番号を返す。
+///
+fn execute(conn: &Connection, src: &str, dst: &str, plan: &Plan)
+    -> rusqlite::Result<i64>
+{
+    let tx = conn.unchecked_transaction()?;
+
+    tx.execute(
+        INSERT_LOG_QUERY,
+        named_params! {
+            ":executed": Utc::now().timestamp_millis(),
+            ":src": src,
+            ":dst": dst,
+            ":moved": plan.records.len(),
+        },
+    )?;
+
+    let batch_id = tx.last_insert_rowid();
+
+    for (timestamp, _) in &plan.records {
+        let params = named_params! {
+            ":src": src,
+            ":dst": dst,
+            ":timestamp": timestamp,
+        };
+
+        tx.execute(RELOCATE_RECORD_QUERY, params)?;
+        tx.execute(RELOCATE_MEASUREMENT_QUERY, params)?;
+        tx.execute(RELOCATE_FORWARD_QUERY, params)?;
+        log_undo(&tx, batch_id, RECORD_TABLE, *timestamp, None)?;
+    }
+
+    for timestamp in &plan.quarantines {
+        tx.execute(
+            RELOCATE_QUARANTINE_QUERY,
+            named_params! {
+                ":src": src,
+                ":dst": dst,
+                ":timestamp": timestamp,
+            },
+        )?;
+
+        log_undo(&tx, batch_id, QUARANTINE_TABLE, *timestamp, None)?;
+    }
+
+    for (device_id, valid_from) in &plan.assignments {
+        tx.execute(
+            RELOCATE_ASSIGNMENT_QUERY,
+            named_params! {
+                ":src": src,
+                ":dst": dst,
+                ":device_id": device_id,
+                ":timestamp": valid_from,
+            },
+        )?;
+
+        log_undo(
+            &tx,
+            batch_id,
+            ASSIGNMENT_TABLE,
+            *valid_from,
+            Some(device_id)
+        )?;
+    }
+
+    tx.commit()?;
+
+    Ok(batch_id)
+}
+
+///
+/// 取り消し用ログの記録
+///
+fn log_undo(
+    tx: &Transaction,
+    batch_id: i64,
+    target: &str,
+    timestamp: u64,
+    device_id: Option<&str>,
+) -> rusqlite::Result<()>
+{
+    tx.execute(
+        INSERT_UNDO_QUERY,
+        named_params! {
+            ":batch_id": batch_id,
+            ":target": target,
+            ":timestamp": timestamp,
+            ":device_id": device_id,
+        },
+    )?;
+
+    Ok(())
+}
+
+///
+/// 処理ログを表す構造体
+///
+struct Log {
+    /// 処理単位の識別番号
+    batch_id: i64,
+
+    /// 実行時刻
+    executed: u64,
+
+    /// 移動元の設置場所名
+    src: String,
+
+    /// 移動先の設置場所名
+    dst: String,
+
+    /// 移動したレコード数
+    moved: u64,
+
+    /// 取り消した時刻
+    undone: Option<u64>,
+}
+
+///
+/// レコードの移動の取り消し
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+/// * `batch_id` - 取り消す処理の識別番号
+/// * `list` - 処理ログの一覧の表示のみを行うか否か
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+/// # 注記
+/// 移動元に同じタイムスタンプのレコードが記録されている場合(移動後に移動元
+/// の名前で受信した場合など)は何も変更せずにエラーとする。
+///
+pub(super) fn undo(opts: &Options, batch_id: Option<i64>, list: bool)
+    -> Result<()>
+{
+    let conn = open_database(opts.db_file())?;
+    let logs = select_logs(&conn)?;
+
+    /*
+     * 処理ログの一覧の表示
+     */
+    if list {
+        for log in &logs {
+            println!(
+                "{}: {} {} -> {} ({} records){}",
+                log.batch_id,
+                local_time_string(log.executed),
+                log.src,
+                log.dst,
+                log.moved,
+                log.undone
+                    .map(|tm| format!(" undone {}", local_time_string(tm)))
+                    .unwrap_or_default()
+            );
+        }
+
+        return Ok(());
+    }
+
+    /*
+     * 対象の処理の決定
+     */
+    let log = match batch_id {
+        Some(id) => logs.iter().find(|log| log.batch_id == id),
+        None => logs.iter().rev().find(|log| log.undone.is_none()),
+    };
+
+    let Some(log) = log else {
+        return Err(anyhow!("no relocation to undo"));
+    };
+
+    if log.undone.is_some() {
+        return Err(anyhow!("batch {} is already undone", log.batch_id));
+    }
+
+    /*
+     * 取り消しの実行
+     */
+    let restored = match revert(&conn, log) {
+        Ok(restored) => restored,
+        Err(err) => return Err(anyhow!("undo failed: {}", err)),
+    };
+
+    info!("undo relocate batch {} ({} rows)", log.batch_id, restored);
+    println!(
+        "batch {} undone: {} -> {} ({} rows)",
+        log.batch_id,
+        log.dst,
+        log.src,
+        restored
+    );
+
+    Ok(())
+}
+
+///
+/// 処理ログの一覧の読み出し
+///
+fn select_logs(conn: &Connection) -> rusqlite::Result<Vec<Log>> {
+    let mut stmt = conn.prepare(SELECT_LOGS_QUERY)?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Log {
+            batch_id: row.get(0)?,
+            executed: row.get(1)?,
+            src: row.get(2)?,
+            dst: row.get(3)?,
+            moved: row.get(4)?,
+            undone: row.get(5)?,
+        })
+    })?;
+
+    rows.collect()
+}
+
+///
+/// 取り消し用ログに従った移動の取り消し(全ての更新を同一トランザクション
+/// で行う)
+///
+/// # 戻り値
+/// 元に戻した行数を`Ok()`でラップして返す。
+///
+fn revert(conn: &Connection, log: &Log) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+
+    let entries = {
+        let mut stmt = tx.prepare(SELECT_UNDO_QUERY)?;
+        let rows = stmt.query_map(
+            named_params! {":batch_id": log.batch_id},
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u64>(1)?,
+                    row.get::<_, Option<String>>(2)?
+                ))
+            }
+        )?;
+
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+
+    for (target, timestamp, device_id) in &entries {
+        let params = named_params! {
+            ":src": log.dst,
+            ":dst": log.src,
+            ":timestamp": timestamp,
+        };
+
+        match target.as_str() {
+            RECORD_TABLE => {
+                let exists: i64 = tx.query_row(
+                    SELECT_EXISTS_QUERY,
+                    named_params! {
+                        ":location": log.src,
+                        ":timestamp": timestamp,
+                    },
+                    |row| row.get(0)
+                )?;
+
+                if exists > 0 {
+                    return Err(anyhow!(
+                        "{} already has a record at {}",
+                        log.src,
+                        local_time_string(*timestamp)
+                    ));
+                }
+
+                tx.execute(RELOCATE_RECORD_QUERY, params)?;
+                tx.execute(RELOCATE_MEASUREMENT_QUERY, params)?;
+                tx.execute(RELOCATE_FORWARD_QUERY, params)?;
+            }
+
+            QUARANTINE_TABLE => {
+                tx.execute(RELOCATE_QUARANTINE_QUERY, params)?;
+            }
+
+            ASSIGNMENT_TABLE => {
+                tx.execute(
+                    RELOCATE_ASSIGNMENT_QUERY,
+                    named_params! {
+                        ":src": log.dst,
+                        ":dst": log.src,
+                        ":device_id": device_id,
+                        ":timestamp": timestamp,
+                    },
+                )?;
+            }
+
+            _ => return Err(anyhow!("unknown target {}", target)),
+        }
+    }
+
+    tx.execute(
+        MARK_UNDONE_QUERY,
+        named_params! {
+            ":batch_id": log.batch_id,
+            ":undone": Utc::now().timestamp_millis(),
+        },
+    )?;
+
+    tx.commit()?;
+
+    Ok(entries.len())
+}
@@ -0,0 +1,159 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 設定ファイル関連の処理をまとめたモジュール
+//!
+//! 設定ファイルはTOML形式で、コマンドラインオプションでは表現しづらい構造
+//! を持つ設定(処理ステージの定義など)を記述する。
+//!
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::barometer::LocationConfig;
+use crate::calibration::CalibrationConfig;
+use crate::measurement::MeasurementConfig;
+use crate::receiver::access::AccessSection;
+use crate::receiver::auth::AuthConfig;
+use crate::stage::StageConfig;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+///
+/// 設定ファイルの内容を投影する構造体
+///
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    /// 処理ステージの定義(記述順に適用する)
+    #[serde(default, rename = "stage")]
+    stages: Vec<StageConfig>,
+
+    /// デバイス毎の較正情報
+    #[serde(default, rename = "calibration")]
+    calibrations: Vec<CalibrationConfig>,
+
+    /// 設置場所毎の情報
+    #[serde(default, rename = "location")]
+    locations: Vec<LocationConfig>,
+
+    /// 計測種別の定義(組み込みの計測種別への追加)
+    #[serde(default, rename = "measurement")]
+    measurements: Vec<MeasurementConfig>,
+
+    /// 受信レコードの認証の設定
+    #[serde(default)]
+    auth: AuthConfig,
+
+    /// 待ち受け毎の受信の制限
+    #[serde(default)]
+    access: AccessSection,
+}
+
+impl Config {
+    ///
+    /// 設定ファイルの読み込み
+    ///
+    /// # 引数
+    /// * `path` - 設定ファイルのパス(未指定の場合はNone)
+    ///
+    /// # 戻り値
+    /// 読み込みに成功した場合は設定内容を`Ok()`でラップして返す。設定ファイル
+    /// が指定されていない場合はデフォルトの設定を返す。失敗した場合はエラー情
+    /// 報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn load<P>(path: Option<P>) -> Result<Self>
+    where
+        P: AsRef<Path>
+    {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+
+        let src = match std::fs::read_to_string(path.as_ref()) {
+            Ok(src) => src,
+            Err(err) => return Err(anyhow!(
+                "read {} failed: {}",
+                path.as_ref().display(),
+                err
+            )),
+        };
+
+        match toml::from_str::<Self>(&src) {
+            Ok(config) => Ok(config),
+            Err(err) => Err(anyhow!(
+                "parse {} failed: {}",
+                path.as_ref().display(),
+                err
+            )),
+        }
+    }
+
+    ///
+    /// 処理ステージの定義へのアクセサ
+    ///
+    /// # 戻り値
+    /// 処理ステージの定義のリストを返す
+    ///
+    pub(crate) fn stages(&self) -> Vec<StageConfig> {
+        self.stages.clone()
+    }
+
+    ///
+    /// 較正情報へのアクセサ
+    ///
+    /// # 戻り値
+    /// デバイス毎の較正情報の定義のリストを返す
+    ///
+    pub(crate) fn calibrations(&self) -> Vec<CalibrationConfig> {
+        self.calibrations.clone()
+    }
+
+    ///
+    /// 設置場所の情報へのアクセサ
+    ///
+    /// # 戻り値
+    /// 設置場所の定義のリストを返す
+    ///
+    pub(crate) fn locations(&self) -> Vec<LocationConfig> {
+        self.locations.clone()
+    }
+
+    ///
+    /// 計測種別の定義へのアクセサ
+    ///
+    /// # 戻り値
+    /// 計測種別の定義のリストを返す
+    ///
+    pub(crate) fn measurements(&self) -> Vec<MeasurementConfig> {
+        self.measurements.clone()
+    }
+
+    ///
+    /// 認証の設定へのアクセサ
+    ///
+    /// # 戻り値
+    /// 受信レコードの認証の設定を返す
+    ///
+    pub(crate) fn auth(&self) -> AuthConfig {
+        self.auth.clone()
+    }
+
+    ///
+    /// 受信の制限へのアクセサ
+    ///
+    /// # 戻り値
+    /// 待ち受け毎の受信の制限の設定を返す
+    ///
+    pub(crate) fn access(&self) -> AccessSection {
+        self.access.clone()
+    }
+}
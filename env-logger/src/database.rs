@@ -13,14 +13,17 @@ use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use rusqlite::{named_params, Connection};
 use tokio::task::JoinHandle;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::Receiver;
 
 use crate::cmd_args::Options;
 use crate::record::SensorRecord;
+use crate::sink::{self, SinkStats};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
@@ -34,6 +37,92 @@ const VACUUM_QUERY: &str = include_str!("../data/vacuum.sql");
 /// レコード挿入クエリー
 const INSERT_RECORD_QUERY: &str = include_str!("../data/insert_record.sql");
 
+/// 転送キューテーブル作成のクエリー
+const CREATE_FORWARD_QUEUE_QUERY: &str =
+    include_str!("../data/create_forward_queue.sql");
+
+/// 転送キュー登録クエリー
+const ENQUEUE_FORWARD_QUERY: &str = include_str!("../data/enqueue_forward.sql");
+
+/// 直近の気圧の取得クエリー
+const SELECT_RECENT_PRESSURE_QUERY: &str =
+    include_str!("../data/select_recent_pressure.sql");
+
+/// 隔離テーブル作成のクエリー
+const CREATE_QUARANTINE_TABLE_QUERY: &str =
+    include_str!("../data/create_quarantine_table.sql");
+
+/// 隔離レコード挿入クエリー
+const INSERT_QUARANTINE_QUERY: &str =
+    include_str!("../data/insert_quarantine.sql");
+
+/// 設置場所テーブル作成のクエリー
+const CREATE_LOCATION_TABLE_QUERY: &str =
+    include_str!("../data/create_location_table.sql");
+
+/// デバイステーブル作成のクエリー
+const CREATE_DEVICE_TABLE_QUERY: &str =
+    include_str!("../data/create_device_table.sql");
+
+/// 設置場所の割り当てテーブル作成のクエリー
+const CREATE_DEVICE_ASSIGNMENT_TABLE_QUERY: &str =
+    include_str!("../data/create_device_assignment_table.sql");
+
+/// レコード移動の処理ログテーブル作成のクエリー
+const CREATE_RELOCATE_LOG_TABLE_QUERY: &str =
+    include_str!("../data/create_relocate_log_table.sql");
+
+/// レコード移動の取り消し用ログテーブル作成のクエリー
+const CREATE_RELOCATE_UNDO_TABLE_QUERY: &str =
+    include_str!("../data/create_relocate_undo_table.sql");
+
+/// 計測値テーブル作成のクエリー
+const CREATE_MEASUREMENT_TABLE_QUERY: &str =
+    include_str!("../data/create_measurement_table.sql");
+
+/// 計測値挿入クエリー
+const INSERT_MEASUREMENT_QUERY: &str =
+    include_str!("../data/insert_measurement.sql");
+
+/// 稼働状態テーブル作成のクエリー
+const CREATE_DEVICE_STATUS_TABLE_QUERY: &str =
+    include_str!("../data/create_device_status_table.sql");
+
+/// 稼働状態の要約ビュー作成のクエリー
+const CREATE_DEVICE_STATUS_VIEW_QUERY: &str =
+    include_str!("../data/create_device_status_view.sql");
+
+/// 稼働状態挿入クエリー
+const INSERT_DEVICE_STATUS_QUERY: &str =
+    include_str!("../data/insert_device_status.sql");
+
+/// 既存のデータベースに追加するカラム(テーブル名, カラム名, 型)
+///
+/// テーブル作成のクエリーにも同じカラムを記述すること。
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("SENSOR_RESULT_TABLE", "raw_temperature", "REAL"),
+    ("SENSOR_RESULT_TABLE", "raw_humidity", "REAL"),
+    ("SENSOR_RESULT_TABLE", "raw_air_pressure", "REAL"),
+    ("SENSOR_RESULT_TABLE", "quality", "TEXT"),
+    ("SENSOR_RESULT_TABLE", "dew_point", "REAL"),
+    ("SENSOR_RESULT_TABLE", "absolute_humidity", "REAL"),
+    ("SENSOR_RESULT_TABLE", "humidex", "REAL"),
+    ("SENSOR_RESULT_TABLE", "heat_index", "REAL"),
+    ("SENSOR_RESULT_TABLE", "vpd", "REAL"),
+    ("SENSOR_RESULT_TABLE", "sea_level_pressure", "REAL"),
+    ("SENSOR_RESULT_TABLE", "pressure_tendency", "REAL"),
+    ("SENSOR_RESULT_TABLE", "pressure_trend", "TEXT"),
+    ("SENSOR_RESULT_TABLE", "forecast", "TEXT"),
+    ("SENSOR_RESULT_TABLE", "extras", "TEXT"),
+    ("QUARANTINE_TABLE", "extras", "TEXT"),
+];
+
+/// ロック待ちのタイムアウト
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// ファンアウトに登録するシンクの名前
+pub(crate) const SINK_NAME: &str = "database";
+
 ///
 /// データベース処理タスクをラップする構造体
 ///
@@ -48,7 +137,9 @@ impl DatabaseTask {
     ///
     /// # 引数
     /// * `opts` - オプション情報をパックしたオブジェクト
-    /// * `rx` - 受信レコード受信用チャネルオブジェクト
+    /// * `pipeline_rx` - 受信レコード受信用チャネルオブジェクト
+    /// * `stats` - シンクの稼働状況を集計するオブジェクト
+    /// * `stored_tx` - 記録済みレコード通知用チャネルオブジェクト
     ///
     /// # 戻り値
     /// タスクの開始に成功した場合は、タスクにバインドされたDatabaseTaskのオブ
@@ -56,7 +147,9 @@ impl DatabaseTask {
     /// 失敗した場合はエラー情報を `Err()`でラップして返す。
     pub(crate) async fn start(
         opts: Arc<Options>,
-        pipeline_rx: Receiver<SensorRecord>
+        pipeline_rx: Receiver<SensorRecord>,
+        stats: Arc<SinkStats>,
+        stored_tx: broadcast::Sender<SensorRecord>,
     ) -> Result<Self>
     {
         /*
@@ -64,12 +157,30 @@ impl DatabaseTask {
          */
         let conn = open_database(opts.db_file())?;
 
+        /*
+         * データベースの最適化
+         */
+        if let Err(err) = conn.execute(VACUUM_QUERY, []) {
+            return Err(anyhow!("vacuum failed: {}", err))
+        }
+
         info!("success open {}", opts.db_file().display());
 
+        /*
+         * 転送キューに登録する転送先の決定
+         */
+        let sinks = forward_sinks(&opts);
+
         /*
          * データベースタスクの起動
          */
-        let handle = tokio::spawn(database_task(conn, pipeline_rx));
+        let handle = tokio::spawn(database_task(
+            conn,
+            sinks,
+            pipeline_rx,
+            stats,
+            stored_tx
+        ));
 
         /*
          * 戻り値の生成
@@ -97,7 +208,11 @@ impl Future for DatabaseTask {
 /// データベースのオープンに成功した場合は、接続オブジェクトを`Ok()`でラップし
 /// て返す。
 ///
-fn open_database(path: impl AsRef<Path>) -> Result<Connection> {
+/// # 注記
+/// 必要なテーブルが存在しない場合は作成し、以前のバージョンで作成されたテー
+/// ブルには不足しているカラムを追加する。
+///
+pub(crate) fn open_database(path: impl AsRef<Path>) -> Result<Connection> {
     /*
      * データベースのオープン
      */
@@ -113,11 +228,48 @@ fn open_database(path: impl AsRef<Path>) -> Result<Connection> {
         return Err(anyhow!("create table failed: {}", err))
     }
 
+    if let Err(err) = conn.execute(CREATE_FORWARD_QUEUE_QUERY, []) {
+        return Err(anyhow!("create table failed: {}", err))
+    }
+
+    if let Err(err) = conn.execute(CREATE_QUARANTINE_TABLE_QUERY, []) {
+        return Err(anyhow!("create table failed: {}", err))
+    }
+
+    if let Err(err) = conn.execute(CREATE_MEASUREMENT_TABLE_QUERY, []) {
+        return Err(anyhow!("create table failed: {}", err))
+    }
+
+    let maintenance_tables = [
+        CREATE_LOCATION_TABLE_QUERY,
+        CREATE_DEVICE_TABLE_QUERY,
+        CREATE_DEVICE_ASSIGNMENT_TABLE_QUERY,
+        CREATE_RELOCATE_LOG_TABLE_QUERY,
+        CREATE_RELOCATE_UNDO_TABLE_QUERY,
+        CREATE_DEVICE_STATUS_TABLE_QUERY,
+        CREATE_DEVICE_STATUS_VIEW_QUERY,
+    ];
+
+    for query in maintenance_tables {
+        if let Err(err) = conn.execute(query, []) {
+            return Err(anyhow!("create table failed: {}", err))
+        }
+    }
+
+    /*
+     * カラムの追加
+     */
+    for (table, column, decl) in ADDED_COLUMNS {
+        if let Err(err) = add_column(&conn, table, column, decl) {
+            return Err(anyhow!("add column {} failed: {}", column, err))
+        }
+    }
+
     /*
-     * データベースの最適化
+     * ロック待ちの設定(他のプロセスと同時にアクセスする場合に備える)
      */
-    if let Err(err) = conn.execute(VACUUM_QUERY, []) {
-        return Err(anyhow!("vacuum failed: {}", err))
+    if let Err(err) = conn.busy_timeout(BUSY_TIMEOUT) {
+        return Err(anyhow!("set busy timeout failed: {}", err))
     }
 
     /*
@@ -126,27 +278,152 @@ fn open_database(path: impl AsRef<Path>) -> Result<Connection> {
     Ok(conn)
 }
 
+///
+/// テーブルへのカラムの追加
+///
+/// # 引数
+/// * `conn` - データベース接続オブジェクト
+/// * `table` - テーブル名
+/// * `column` - カラム名
+/// * `decl` - カラムの型
+///
+/// # 注記
+/// 既にカラムが存在する場合は何もしない。
+///
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str)
+    -> rusqlite::Result<()>
+{
+    let mut stmt = conn.prepare(&format!("pragma table_info({})", table))?;
+    let exists = stmt.query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(
+            &format!("alter table {} add column {} {}", table, column, decl),
+            []
+        )?;
+
+        info!("add column {}.{}", table, column);
+    }
+
+    Ok(())
+}
+
+///
+/// 直近の気圧の読み出し
+///
+/// # 引数
+/// * `path` - データベースファイルへのパス
+/// * `since` - 読み出す期間の開始時刻(ミリ秒単位のUNIX時刻)
+///
+/// # 戻り値
+/// 指定時刻以降に記録した気圧を、設置場所とタイムスタンプと気圧の組のリスト
+/// にして`Ok()`でラップして返す(タイムスタンプの古い順)。
+///
+/// # 注記
+/// 起動時に気圧傾向の計算に必要な履歴を復元するために使用する。
+///
+pub(crate) fn load_recent_pressures(path: impl AsRef<Path>, since: u64)
+    -> Result<Vec<(String, u64, f32)>>
+{
+    let conn = open_database(path)?;
+    let mut stmt = conn.prepare(SELECT_RECENT_PRESSURE_QUERY)?;
+    let rows = stmt.query_map(
+        named_params! {":since": since},
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    )?;
+
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+///
+/// 転送キューに登録する転送先の一覧の生成
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+///
+/// # 戻り値
+/// 有効になっている転送先の識別名のリストを返す。
+///
+fn forward_sinks(opts: &Options) -> Vec<&'static str> {
+    let mut ret = vec![];
+
+    if opts.tsdb_url().is_some() {
+        ret.push(sink::tsdb::SINK_NAME);
+    }
+
+    if opts.relay_upstream().is_some() {
+        ret.push(sink::relay::SINK_NAME);
+    }
+
+    ret
+}
+
 ///
 /// データベース処理タスク
 ///
 /// # 引数
 /// * `conn` - データベース接続オブジェクト
+/// * `sinks` - 転送キューに登録する転送先の識別名のリスト
 /// * `pipeline_rx` - 受信レコード受信チャネルオブジェクト
+/// * `stats` - シンクの稼働状況を集計するオブジェクト
+/// * `stored_tx` - 記録済みレコード通知用チャネルオブジェクト
+///
+/// # 注記
+/// 記録済みレコードの通知はブロードキャストチャネルで行うため、通知先の処理
+/// が滞っても本タスクがブロックされることはない。
 ///
 async fn database_task(
     conn: Connection,
-    mut pipeline_rx: Receiver<SensorRecord>
+    sinks: Vec<&'static str>,
+    mut pipeline_rx: Receiver<SensorRecord>,
+    stats: Arc<SinkStats>,
+    stored_tx: broadcast::Sender<SensorRecord>,
 )
 {
     info!("start database task");
 
     while let Some(record) = pipeline_rx.recv().await {
-        if let Err(err) = insert_record(&conn, &record) {
+        /*
+         * 稼働状態の記録
+         *
+         * 処理ステージで隔離したレコードも記録するが、認証に失敗したレコード
+         * はデバイスを騙っている可能性があるので記録しない。
+         */
+        if !record.is_unauthenticated() {
+            if let Err(err) = insert_device_status(&conn, &record) {
+                error!("insert device status failed: {}", err);
+            }
+        }
+
+        /*
+         * 隔離対象のレコードの記録(転送や通知は行わない)
+         */
+        if record.is_quarantined() {
+            if let Err(err) = insert_quarantine(&conn, &record) {
+                error!("insert quarantined record failed: {}", err);
+                stats.count_failed(1, err);
+            } else {
+                info!("insert quarantined record: {}", record);
+                stats.count_delivered(1);
+            }
+
+            continue;
+        }
+
+        if let Err(err) = insert_record(&conn, &sinks, &record) {
             error!("insert record failed: {}", err);
+            stats.count_failed(1, err);
             continue;
         } 
 
-        info!("insert record: {}", record.to_string());
+        info!("insert record: {}", record);
+        stats.count_delivered(1);
+
+        // 通知先が存在しない場合もエラーになるので結果は無視する
+        let _ = stored_tx.send(record);
     }
 
     info!("shutdown database task");
@@ -157,16 +434,25 @@ async fn database_task(
 ///
 /// # 引数
 /// * `conn` - データベース接続オブジェクト
+/// * `sinks` - 転送キューに登録する転送先の識別名のリスト
 /// * `record` -  受信レコード
 ///
 /// # 戻り値
 /// レコードのインサートに成功した場合は`Ok(())`を返す。失敗した場合はエラー情
 /// 報を`Err()`でラップして返す。
 ///
-fn insert_record(conn: &Connection, record: &SensorRecord)
+/// # 注記
+/// レコードの挿入と転送キューへの登録は同一トランザクションで行う。派生値は
+/// 挿入時に計算して記録する。
+///
+fn insert_record(conn: &Connection, sinks: &[&str], record: &SensorRecord)
     -> rusqlite::Result<()>
 {
-    conn.execute(
+    let tx = conn.unchecked_transaction()?;
+    let derived = record.derived();
+    let barometric = record.barometric().unwrap_or_default();
+
+    tx.execute(
         INSERT_RECORD_QUERY,
         named_params! {
             ":location" : record.location(),
@@ -175,6 +461,119 @@ fn insert_record(conn: &Connection, record: &SensorRecord)
             ":temperature" : record.temperature(),
             ":humidity" : record.humidity(),
             ":air_pressure" : record.air_pressure(),
+            ":raw_temperature" : record.raw().temperature,
+            ":raw_humidity" : record.raw().humidity,
+            ":raw_air_pressure" : record.raw().air_pressure,
+            ":quality" : record.quality(),
+            ":dew_point" : derived.map(|d| d.dew_point),
+            ":absolute_humidity" : derived.map(|d| d.absolute_humidity),
+            ":humidex" : derived.map(|d| d.humidex),
+            ":heat_index" : derived.map(|d| d.heat_index),
+            ":vpd" : derived.map(|d| d.vpd),
+            ":sea_level_pressure" : barometric.sea_level_pressure,
+            ":pressure_tendency" : barometric.tendency,
+            ":pressure_trend" : barometric.trend.map(|t| t.to_string()),
+            ":forecast" : barometric.forecast,
+            ":extras" : record.extras_json(),
+        },
+    )?;
+
+    for (name, value) in record.metrics() {
+        tx.execute(
+            INSERT_MEASUREMENT_QUERY,
+            named_params! {
+                ":location" : record.location(),
+                ":timestamp" : record.timestamp(),
+                ":name" : name,
+                ":value" : value,
+            },
+        )?;
+    }
+
+    for sink in sinks {
+        tx.execute(
+            ENQUEUE_FORWARD_QUERY,
+            named_params! {
+                ":sink" : sink,
+                ":location" : record.location(),
+                ":timestamp" : record.timestamp(),
+            },
+        )?;
+    }
+
+    tx.commit()
+}
+
+///
+/// 隔離対象のレコードのインサート手続きをまとめた関数
+///
+/// # 引数
+/// * `conn` - データベース接続オブジェクト
+/// * `record` -  隔離対象のレコード
+///
+/// # 戻り値
+/// レコードのインサートに成功した場合は`Ok(())`を返す。失敗した場合はエラー情
+/// 報を`Err()`でラップして返す。
+///
+fn insert_quarantine(conn: &Connection, record: &SensorRecord)
+    -> rusqlite::Result<()>
+{
+    conn.execute(
+        INSERT_QUARANTINE_QUERY,
+        named_params! {
+            ":location" : record.location(),
+            ":device_id" : record.device_id(),
+            ":timestamp" : record.timestamp(),
+            ":temperature" : record.temperature(),
+            ":humidity" : record.humidity(),
+            ":air_pressure" : record.air_pressure(),
+            ":quality" : record.quality(),
+            ":extras" : record.extras_json(),
+        },
+    )?;
+
+    Ok(())
+}
+
+///
+/// 稼働状態のインサート手続きをまとめた関数
+///
+/// # 引数
+/// * `conn` - データベース接続オブジェクト
+/// * `record` - 稼働状態を含むレコード
+///
+/// # 戻り値
+/// 処理に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+/// # 注記
+/// デバイスIDを持たないレコードや、稼働状態を含まないレコードの場合は何も
+/// しない。
+///
+fn insert_device_status(conn: &Connection, record: &SensorRecord)
+    -> rusqlite::Result<()>
+{
+    let telemetry = record.telemetry();
+
+    let Some(device_id) = record.device_id() else {
+        return Ok(());
+    };
+
+    if telemetry.is_empty() {
+        return Ok(());
+    }
+
+    conn.execute(
+        INSERT_DEVICE_STATUS_QUERY,
+        named_params! {
+            ":device_id" : device_id,
+            ":timestamp" : record.timestamp(),
+            ":location" : record.location(),
+            ":firmware" : telemetry.firmware,
+            ":rssi" : telemetry.rssi,
+            ":battery" : telemetry.battery,
+            ":uptime" : telemetry.uptime,
+            ":reset_reason" : telemetry.reset_reason,
         },
     )?;
 
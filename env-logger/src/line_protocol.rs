@@ -0,0 +1,630 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! InfluxDBのラインプロトコルの処理をまとめたモジュール
+//!
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// メジャーメント名でエスケープが必要な文字
+const MEASUREMENT_ESCAPES: &[char] = &[',', ' '];
+
+/// タグキー、タグ値およびフィールドキーでエスケープが必要な文字
+const KEY_ESCAPES: &[char] = &[',', '=', ' '];
+
+/// 文字列のフィールド値でエスケープが必要な文字
+const STRING_ESCAPES: &[char] = &['"', '\\'];
+
+///
+/// タイムスタンプの精度を指し示す列挙子
+///
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Precision {
+    /// ナノ秒
+    Ns,
+
+    /// マイクロ秒
+    Us,
+
+    /// ミリ秒
+    Ms,
+
+    /// 秒
+    S,
+}
+
+impl Precision {
+    ///
+    /// 文字列からの変換
+    ///
+    /// # 引数
+    /// * `s` - 精度を表す文字列("ns", "us", "ms", "s")
+    ///
+    /// # 戻り値
+    /// 変換に成功した場合は精度を`Some()`でラップして返す。
+    ///
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        <Self as ValueEnum>::from_str(s, true).ok()
+    }
+
+    ///
+    /// タイムスタンプのミリ秒単位への変換
+    ///
+    /// # 引数
+    /// * `tm` - 本精度で表されたUNIX時刻
+    ///
+    /// # 戻り値
+    /// ミリ秒単位のUNIX時刻を返す
+    ///
+    pub(crate) fn to_millis(self, tm: i64) -> i64 {
+        match self {
+            Self::Ns => tm / 1_000_000,
+            Self::Us => tm / 1_000,
+            Self::Ms => tm,
+            Self::S => tm.saturating_mul(1_000),
+        }
+    }
+}
+
+///
+/// フィールド値を表す列挙子
+///
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FieldValue {
+    /// 浮動小数点数
+    Float(f64),
+
+    /// 符号付き整数
+    Integer(i64),
+
+    /// 符号無し整数
+    UInteger(u64),
+
+    /// 真偽値
+    Boolean(bool),
+
+    /// 文字列
+    String(String),
+}
+
+impl FieldValue {
+    ///
+    /// 数値としての取得
+    ///
+    /// # 戻り値
+    /// 数値型のフィールドであれば値を`Some()`でラップして返す。
+    ///
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Float(val) => Some(*val),
+            Self::Integer(val) => Some(*val as f64),
+            Self::UInteger(val) => Some(*val as f64),
+            _ => None,
+        }
+    }
+}
+
+///
+/// ラインプロトコルの1行を表す構造体
+///
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Point {
+    /// メジャーメント名
+    measurement: String,
+
+    /// タグセット
+    tags: Vec<(String, String)>,
+
+    /// フィールドセット
+    fields: Vec<(String, FieldValue)>,
+
+    /// タイムスタンプ(精度はパース時の指定に依存)
+    timestamp: Option<i64>,
+}
+
+impl Point {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `measurement` - メジャーメント名
+    /// * `timestamp` - タイムスタンプ
+    ///
+    /// # 注記
+    /// タグとフィールドは`add_tag()`と`add_field()`で追加する。
+    ///
+    pub(crate) fn new(measurement: &str, timestamp: Option<i64>) -> Self {
+        Self {
+            measurement: measurement.to_string(),
+            tags: vec![],
+            fields: vec![],
+            timestamp,
+        }
+    }
+
+    ///
+    /// タグの追加
+    ///
+    /// # 引数
+    /// * `key` - タグキー
+    /// * `val` - タグ値
+    ///
+    pub(crate) fn add_tag(&mut self, key: &str, val: &str) {
+        self.tags.push((key.to_string(), val.to_string()));
+    }
+
+    ///
+    /// フィールドの追加
+    ///
+    /// # 引数
+    /// * `key` - フィールドキー
+    /// * `val` - フィールド値
+    ///
+    pub(crate) fn add_field(&mut self, key: &str, val: FieldValue) {
+        self.fields.push((key.to_string(), val));
+    }
+
+    ///
+    /// フィールドを持っているか否か
+    ///
+    pub(crate) fn has_fields(&self) -> bool {
+        !self.fields.is_empty()
+    }
+
+    ///
+    /// 1行分のパース
+    ///
+    /// # 引数
+    /// * `line` - パースする行(改行を含まない)
+    ///
+    /// # 戻り値
+    /// パースに成功した場合は、Pointオブジェクトを`Ok(Some())`でラップして返
+    /// す。空行およびコメント行の場合は`Ok(None)`を返す。失敗した場合はエラー
+    /// 情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn parse(line: &str) -> Result<Option<Self>> {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        /*
+         * 空白でセクション(シリーズ, フィールドセット, タイムスタンプ)に分割
+         *
+         * ダブルクォートが意味を持つのはフィールドセットの文字列値のみなので、
+         * シリーズの区切りではクォートを考慮しない。
+         */
+        let series = split_unescaped(line, ' ', false)[0];
+        let rest = line[series.len()..].trim_start();
+
+        let sections: Vec<&str> = split_unescaped(rest, ' ', true)
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let (fields, timestamp) = match sections.as_slice() {
+            [fields] => (*fields, None),
+            [fields, tm] => (*fields, Some(*tm)),
+            _ => return Err(anyhow!("malformed line: {}", line)),
+        };
+
+        /*
+         * メジャーメント名とタグセット
+         */
+        let mut items = split_unescaped(series, ',', false).into_iter();
+        let measurement = unescape(
+            items.next().unwrap_or_default(),
+            MEASUREMENT_ESCAPES
+        );
+
+        if measurement.is_empty() {
+            return Err(anyhow!("missing measurement: {}", line));
+        }
+
+        let mut tags = vec![];
+
+        for item in items {
+            let (key, val) = split_pair(item)
+                .ok_or_else(|| anyhow!("malformed tag: {}", item))?;
+
+            tags.push((
+                unescape(key, KEY_ESCAPES),
+                unescape(val, KEY_ESCAPES)
+            ));
+        }
+
+        /*
+         * フィールドセット
+         */
+        let mut field_set = vec![];
+
+        for item in split_unescaped(fields, ',', true) {
+            let (key, val) = split_pair(item)
+                .ok_or_else(|| anyhow!("malformed field: {}", item))?;
+
+            field_set.push((
+                unescape(key, KEY_ESCAPES),
+                parse_field_value(val)?
+            ));
+        }
+
+        if field_set.is_empty() {
+            return Err(anyhow!("missing field: {}", line));
+        }
+
+        /*
+         * タイムスタンプ
+         */
+        let timestamp = match timestamp {
+            Some(tm) => match tm.parse::<i64>() {
+                Ok(tm) => Some(tm),
+                Err(_) => return Err(anyhow!("invalid timestamp: {}", tm)),
+            },
+
+            None => None,
+        };
+
+        Ok(Some(Self {measurement, tags, fields: field_set, timestamp}))
+    }
+
+    ///
+    /// メジャーメント名へのアクセサ
+    ///
+    pub(crate) fn measurement(&self) -> &str {
+        &self.measurement
+    }
+
+    ///
+    /// タグ値の取得
+    ///
+    /// # 引数
+    /// * `key` - タグキー
+    ///
+    /// # 戻り値
+    /// タグが存在する場合は値を`Some()`でラップして返す。
+    ///
+    pub(crate) fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    ///
+    /// フィールド値の取得
+    ///
+    /// # 引数
+    /// * `key` - フィールドキー
+    ///
+    /// # 戻り値
+    /// フィールドが存在する場合は値を`Some()`でラップして返す。
+    ///
+    pub(crate) fn field(&self, key: &str) -> Option<&FieldValue> {
+        self.fields.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    ///
+    /// フィールドの列挙
+    ///
+    /// # 戻り値
+    /// フィールドキーと値の組を記述順に返すイテレータ
+    ///
+    pub(crate) fn fields(&self) -> impl Iterator<Item = (&str, &FieldValue)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    ///
+    /// タイムスタンプへのアクセサ
+    ///
+    pub(crate) fn timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+}
+
+// Displayトレイトの実装(ラインプロトコルの1行として出力する)
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", escape(&self.measurement, MEASUREMENT_ESCAPES))?;
+
+        for (key, val) in &self.tags {
+            write!(
+                f,
+                ",{}={}",
+                escape(key, KEY_ESCAPES),
+                escape(val, KEY_ESCAPES)
+            )?;
+        }
+
+        for (i, (key, val)) in self.fields.iter().enumerate() {
+            let val = match val {
+                FieldValue::Float(val) => val.to_string(),
+                FieldValue::Integer(val) => format!("{}i", val),
+                FieldValue::UInteger(val) => format!("{}u", val),
+                FieldValue::Boolean(val) => val.to_string(),
+                FieldValue::String(val) => {
+                    format!("\"{}\"", escape(val, STRING_ESCAPES))
+                }
+            };
+
+            write!(
+                f,
+                "{}{}={}",
+                if i == 0 { ' ' } else { ',' },
+                escape(key, KEY_ESCAPES),
+                val
+            )?;
+        }
+
+        if let Some(tm) = self.timestamp {
+            write!(f, " {}", tm)?;
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// エスケープの付与
+///
+/// # 引数
+/// * `src` - 対象の文字列
+/// * `targets` - エスケープが必要な文字
+///
+fn escape(src: &str, targets: &[char]) -> String {
+    let mut ret = String::with_capacity(src.len());
+
+    for ch in src.chars() {
+        if targets.contains(&ch) {
+            ret.push('\\');
+        }
+
+        ret.push(ch);
+    }
+
+    ret
+}
+
+///
+/// エスケープされていない区切り文字での分割
+///
+/// # 引数
+/// * `src` - 分割対象の文字列
+/// * `delim` - 区切り文字
+/// * `quote` - ダブルクォートで囲まれた範囲を分割対象外とするか否か
+///
+fn split_unescaped(src: &str, delim: char, quote: bool) -> Vec<&str> {
+    let mut ret = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+
+    for (i, ch) in src.char_indices() {
+        if escaped {
+            escaped = false;
+
+        } else if ch == '\\' {
+            escaped = true;
+
+        } else if quote && ch == '"' {
+            quoted = !quoted;
+
+        } else if ch == delim && !quoted {
+            ret.push(&src[start..i]);
+            start = i + ch.len_utf8();
+        }
+    }
+
+    ret.push(&src[start..]);
+    ret
+}
+
+///
+/// "キー=値"形式の分割
+///
+fn split_pair(src: &str) -> Option<(&str, &str)> {
+    let mut escaped = false;
+
+    for (i, ch) in src.char_indices() {
+        if escaped {
+            escaped = false;
+
+        } else if ch == '\\' {
+            escaped = true;
+
+        } else if ch == '=' {
+            let (key, val) = (&src[..i], &src[i + 1..]);
+            return if key.is_empty() { None } else { Some((key, val)) };
+        }
+    }
+
+    None
+}
+
+///
+/// エスケープの除去
+///
+/// # 引数
+/// * `src` - 対象の文字列
+/// * `targets` - エスケープされている文字
+///
+/// # 注記
+/// 対象外の文字の前のバックスラッシュはそのまま残す。
+///
+fn unescape(src: &str, targets: &[char]) -> String {
+    let mut ret = String::with_capacity(src.len());
+    let mut chars = src.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            if let Some(next) = chars.peek() {
+                if targets.contains(next) {
+                    ret.push(*next);
+                    chars.next();
+                    continue;
+                }
+            }
+        }
+
+        ret.push(ch);
+    }
+
+    ret
+}
+
+///
+/// フィールド値のパース
+///
+fn parse_field_value(src: &str) -> Result<FieldValue> {
+    if src.len() >= 2 && src.starts_with('"') && src.ends_with('"') {
+        let val = unescape(&src[1..src.len() - 1], STRING_ESCAPES);
+        return Ok(FieldValue::String(val));
+    }
+
+    match src {
+        "t" | "T" | "true" | "True" | "TRUE" => {
+            return Ok(FieldValue::Boolean(true));
+        }
+
+        "f" | "F" | "false" | "False" | "FALSE" => {
+            return Ok(FieldValue::Boolean(false));
+        }
+
+        _ => {}
+    }
+
+    if let Some(val) = src.strip_suffix('i') {
+        if let Ok(val) = val.parse::<i64>() {
+            return Ok(FieldValue::Integer(val));
+        }
+    }
+
+    if let Some(val) = src.strip_suffix('u') {
+        if let Ok(val) = val.parse::<u64>() {
+            return Ok(FieldValue::UInteger(val));
+        }
+    }
+
+    match src.parse::<f64>() {
+        Ok(val) if val.is_finite() => Ok(FieldValue::Float(val)),
+        _ => Err(anyhow!("invalid field value: {}", src)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Point {
+        Point::parse(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parse_full_line() {
+        let point = parse("weather,room=living temp=21.5,hum=40i 1700000000");
+
+        assert_eq!(point.measurement(), "weather");
+        assert_eq!(point.tag("room"), Some("living"));
+        assert_eq!(point.field("temp"), Some(&FieldValue::Float(21.5)));
+        assert_eq!(point.field("hum"), Some(&FieldValue::Integer(40)));
+        assert_eq!(point.timestamp(), Some(1700000000));
+    }
+
+    #[test]
+    fn skip_blank_and_comment() {
+        assert!(Point::parse("").unwrap().is_none());
+        assert!(Point::parse("   ").unwrap().is_none());
+        assert!(Point::parse("# comment").unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_escapes() {
+        let point = parse(r"my\ meas\,x,tag\ key=a\,b\=c\ d f\=k=1");
+
+        assert_eq!(point.measurement(), "my meas,x");
+        assert_eq!(point.tag("tag key"), Some("a,b=c d"));
+        assert_eq!(point.field("f=k"), Some(&FieldValue::Float(1.0)));
+
+        /* メジャーメント名では'='はエスケープ対象外 */
+        let point = parse(r"a\=b f=1");
+        assert_eq!(point.measurement(), r"a\=b");
+    }
+
+    #[test]
+    fn parse_quoted_string() {
+        let point = parse(r#"m s="a b,c=d \"q\" \\",n=2 10"#);
+
+        assert_eq!(
+            point.field("s"),
+            Some(&FieldValue::String(r#"a b,c=d "q" \"#.to_string()))
+        );
+        assert_eq!(point.field("n"), Some(&FieldValue::Float(2.0)));
+        assert_eq!(point.timestamp(), Some(10));
+    }
+
+    #[test]
+    fn quote_in_series_is_literal() {
+        let point = parse(r#"m"x,tag=a"b s="x y""#);
+
+        assert_eq!(point.measurement(), r#"m"x"#);
+        assert_eq!(point.tag("tag"), Some(r#"a"b"#));
+        assert_eq!(
+            point.field("s"),
+            Some(&FieldValue::String("x y".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_field_types() {
+        let point = parse("m a=-3i,b=4u,c=t,d=FALSE,e=1e3");
+
+        assert_eq!(point.field("a"), Some(&FieldValue::Integer(-3)));
+        assert_eq!(point.field("b"), Some(&FieldValue::UInteger(4)));
+        assert_eq!(point.field("c"), Some(&FieldValue::Boolean(true)));
+        assert_eq!(point.field("d"), Some(&FieldValue::Boolean(false)));
+        assert_eq!(point.field("e"), Some(&FieldValue::Float(1000.0)));
+    }
+
+    #[test]
+    fn reject_malformed_line() {
+        assert!(Point::parse("weather").is_err());
+        assert!(Point::parse(",tag=a f=1").is_err());
+        assert!(Point::parse("m,tag f=1").is_err());
+        assert!(Point::parse("m f").is_err());
+        assert!(Point::parse("m f=abc").is_err());
+        assert!(Point::parse("m f=NaN").is_err());
+        assert!(Point::parse("m f=1 12x").is_err());
+        assert!(Point::parse("m f=1 10 extra").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let line = r#"m\ x,t\,k=v\=1 s="a \"b\"",i=3i,u=4u,b=true 5"#;
+        assert_eq!(parse(line).to_string(), line);
+    }
+
+    #[test]
+    fn timestamp_precision() {
+        let tm = 1_700_000_000_123_456_789i64;
+
+        assert_eq!(Precision::Ns.to_millis(tm), 1_700_000_000_123);
+        assert_eq!(Precision::Us.to_millis(tm / 1_000), 1_700_000_000_123);
+        assert_eq!(Precision::Ms.to_millis(tm / 1_000_000), 1_700_000_000_123);
+        assert_eq!(Precision::S.to_millis(1_700_000_000), 1_700_000_000_000);
+        assert_eq!(Precision::S.to_millis(i64::MAX), i64::MAX);
+
+        assert_eq!(Precision::parse("US"), Some(Precision::Us));
+        assert_eq!(Precision::parse("min"), None);
+    }
+}
@@ -8,25 +8,62 @@
 //! プログラムのエントリーポイント
 //!
 
+mod barometer;
+mod calibration;
 mod cmd_args;
+mod command;
+mod config;
 mod database;
+mod line_protocol;
+mod measurement;
+mod psychrometrics;
 mod receiver;
 mod record;
+mod registry;
+mod sink;
+mod stage;
+mod systemd;
+mod telemetry;
 
+use std::future::Future;
 use std::sync::Arc;
 
 use anyhow::Result;
 use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 
+use barometer::{Altitudes, Barometer};
+use calibration::Calibration;
 use cmd_args::Options;
+use config::Config;
 use database::DatabaseTask;
-use receiver::tcp::{TcpReceiveTask, TcpReceiverHandle};
-use receiver::udp::{UdpReceiveTask, UdpReceiverHandle};
+use measurement::Measurements;
+use receiver::{OptionalReceiver, ReceiverHandle};
+use receiver::access::{AccessControls, AccessPolicies};
+use receiver::auth::{AuthPolicy, Authenticator};
+use receiver::coap::CoapReceiveTask;
+use receiver::influx::InfluxReceiveTask;
+use receiver::mqtt::MqttReceiveTask;
+use receiver::tcp::TcpReceiveTask;
+use receiver::tls::TlsServer;
+use receiver::udp::UdpReceiveTask;
+use registry::Registry;
+use sink::{FanOut, Overflow, SinkStats};
+use sink::csv::CsvWriteTask;
+use sink::mqtt::MqttPublishTask;
+use sink::relay::RelayForwardTask;
+use sink::tsdb::TsdbForwardTask;
+use sink::webhook::WebhookTask;
+use stage::StageChain;
+use systemd::ListenSockets;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 
+/// 記録済みレコード通知用チャネルの容量
+const STORED_CAPACITY: usize = 32;
+
 ///
 /// 同じシグネチャを持つ複数のFutureの何れかが完了するまで待つマクロ
 ///
@@ -60,9 +97,14 @@ async fn main() {
     };
 
     /*
-     * 実行関数の呼び出し
+     * 実行関数の呼び出し(サブコマンドが指定されている場合はその処理のみ)
      */
-    if let Err(err) = run(opts).await {
+    let result = match opts.command() {
+        Some(command) => command::run(&opts, command),
+        None => run(opts).await,
+    };
+
+    if let Err(err) = result {
         eprintln!("error: {}", err);
         std::process::exit(1);
     }
@@ -82,89 +124,571 @@ async fn run(opts: Arc<Options>) -> Result<()> {
     info!("start env-logger {}", env!("CARGO_PKG_VERSION"));
 
     /*
-     * 連絡用チャネルの生成 
+     * 設定ファイルと登録簿の読み込みと処理ステージおよび較正情報の生成
+     */
+    let Pipeline {
+        mut stage_chain,
+        mut calibration,
+        mut registry,
+        mut measurements,
+        altitudes,
+        auth,
+        access,
+    } = load_pipeline(&opts)?;
+
+    let mut barometer = Barometer::new(altitudes);
+    let authenticator = Authenticator::new(auth);
+    let access = AccessControls::new(access);
+    let receiver_stats = [vec![authenticator.stats()], access.stats()].concat();
+
+    /*
+     * systemdから渡された待ち受けソケットの受け取り(ソケットアクティベーシ
+     * ョン時のみ)
      */
-    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    let mut sockets = ListenSockets::take()?;
 
     /*
-     * TCPレシーバタスクの起動
+     * TCPレシーバタスクの起動(証明書が指定されている場合はTLSで待ち受ける)
      */
-    let (tcp_task, mut tcp_rx) = TcpReceiveTask::start(opts.clone()).await?;
+    let tls = TlsServer::new(&opts)?;
+    let (tcp_task, tcp_rx) = TcpReceiveTask::start(
+        opts.clone(),
+        authenticator.clone(),
+        tls.clone(),
+        access.tcp(),
+        sockets.take_tcp()
+    ).await?;
+
+    let mut tcp_rx = OptionalReceiver::new(Some(tcp_rx));
 
     /*
      * UDPレシーバタスクの起動
      */
-    let (udp_task, mut udp_rx) = UdpReceiveTask::start(opts.clone()).await?;
+    let (udp_task, udp_rx) = UdpReceiveTask::start(
+        opts.clone(),
+        authenticator.clone(),
+        access.udp(),
+        sockets.take_udp()
+    ).await?;
+
+    let mut udp_rx = OptionalReceiver::new(Some(udp_rx));
+
+    /*
+     * CoAPレシーバタスクの起動(ポートが指定されている場合のみ)
+     */
+    let (coap_task, mut coap_rx) = if opts.coap_endpoint().is_some() {
+        let (task, rx) = CoapReceiveTask::start(
+            opts.clone(),
+            authenticator.clone(),
+            access.coap()
+        ).await?;
+
+        (Some(task), OptionalReceiver::new(Some(rx)))
+    } else {
+        (None, OptionalReceiver::new(None))
+    };
+
+    /*
+     * ラインプロトコルレシーバタスクの起動(ポートが指定されている場合のみ)
+     */
+    let (influx_task, mut influx_rx) = if opts.influx_endpoint().is_some()
+        || opts.influx_http_endpoint().is_some()
+    {
+        let (task, rx) = InfluxReceiveTask::start(
+            opts.clone(),
+            authenticator.clone(),
+            access.influx(),
+            access.influx_http()
+        ).await?;
+
+        (Some(task), OptionalReceiver::new(Some(rx)))
+    } else {
+        (None, OptionalReceiver::new(None))
+    };
+
+    /*
+     * MQTTレシーバタスクの起動(購読を行う場合のみ)
+     */
+    let (mqtt_task, mut mqtt_rx) = if opts.mqtt_subscribe() {
+        let (task, rx) = MqttReceiveTask::start(
+            opts.clone(),
+            authenticator.clone(),
+            access.mqtt()
+        ).await?;
+
+        (Some(task), OptionalReceiver::new(Some(rx)))
+    } else {
+        (None, OptionalReceiver::new(None))
+    };
+
+    /*
+     * ファンアウトの生成
+     */
+    let mut fanout = FanOut::new(opts.sink_queue_size());
+    let mut stats = vec![];
+
+    /*
+     * 記録済みレコード通知用チャネルの生成
+     */
+    let (stored_tx, _) = tokio::sync::broadcast::channel(STORED_CAPACITY);
+
+    /*
+     * 転送タスク用の通知の受信側の生成(転送先が指定されている場合のみ)
+     */
+    let tsdb_rx = opts.tsdb_url().map(|_| stored_tx.subscribe());
+    let relay_rx = opts.relay_upstream().map(|_| stored_tx.subscribe());
 
     /*
      * データベースタスクの起動
+     *
+     * データベースは記録の正本なので、キューが満杯の場合もレコードを破棄せず
+     * に空きを待つ。
+     */
+    let (rx, st) = fanout.add(database::SINK_NAME, Overflow::Block);
+    stats.push(st.clone());
+
+    let database_stats = st.clone();
+
+    let database_task = DatabaseTask::start(
+        opts.clone(),
+        rx,
+        st,
+        stored_tx
+    ).await?;
+
+    /*
+     * 気圧傾向の計算に必要な履歴の復元
+     */
+    let since = (chrono::Utc::now().timestamp_millis() as u64)
+        .saturating_sub(barometer::TENDENCY_PERIOD)
+        .saturating_sub(barometer::TENDENCY_TOLERANCE);
+
+    for (location, timestamp, pressure) in
+        database::load_recent_pressures(opts.db_file(), since)?
+    {
+        barometer.push_history(&location, timestamp, pressure);
+    }
+
+    /*
+     * MQTT再配信タスクの起動(指定されている場合のみ)
+     */
+    let mqtt_publish_task = if opts.mqtt_publish() {
+        let (rx, st) = fanout.add("mqtt", Overflow::Drop);
+        stats.push(st.clone());
+        Some(MqttPublishTask::start(opts.clone(), rx, st).await?)
+    } else {
+        None
+    };
+
+    /*
+     * CSV出力タスクの起動(指定されている場合のみ)
+     */
+    let csv_task = if opts.csv_dir().is_some() {
+        let (rx, st) = fanout.add("csv", Overflow::Drop);
+        stats.push(st.clone());
+        Some(CsvWriteTask::start(opts.clone(), rx, st).await?)
+    } else {
+        None
+    };
+
+    /*
+     * Webhook通知タスクの起動(指定されている通知先毎)
      */
-    let database_task = DatabaseTask::start(opts.clone(), rx).await?;
+    let mut webhook_tasks = vec![];
+
+    for (i, url) in opts.webhook_urls().into_iter().enumerate() {
+        let (rx, st) = fanout.add(&format!("webhook#{}", i + 1), Overflow::Drop);
+        stats.push(st.clone());
+        webhook_tasks.push(WebhookTask::start(url, rx, st).await?);
+    }
+
+    /*
+     * 時系列データベースへの転送タスクの起動(転送キューの生成後に行う)
+     */
+    let tsdb_task = match tsdb_rx {
+        Some(rx) => {
+            let st = SinkStats::new("tsdb");
+            stats.push(st.clone());
+            Some(TsdbForwardTask::start(opts.clone(), rx, st).await?)
+        }
+
+        None => None,
+    };
+
+    /*
+     * 上流のenv-loggerへの中継タスクの起動(転送キューの生成後に行う)
+     */
+    let relay_forward_task = match relay_rx {
+        Some(rx) => {
+            let st = SinkStats::new("relay");
+            stats.push(st.clone());
+            Some(RelayForwardTask::start(opts.clone(), rx, st).await?)
+        }
+
+        None => None,
+    };
+
+    /*
+     * シンクの稼働状況の定期出力タスクの起動(指定されている場合のみ)
+     */
+    let stats_task = opts.sink_stats_interval().map(|period| {
+        let receivers = receiver_stats.clone();
+        sink::spawn_stats_reporter(stats.clone(), receivers, period)
+    });
 
     /*
      * シグナルトラップタスクの起動
      */
-    let signal_trap_task = signal_trap(tcp_task.handle(), udp_task.handle())?;
+    let mut handles = vec![
+        ReceiverHandle::Tcp(tcp_task.handle()),
+        ReceiverHandle::Udp(udp_task.handle()),
+    ];
+
+    if let Some(task) = &coap_task {
+        handles.push(ReceiverHandle::Coap(task.handle()));
+    }
+
+    if let Some(task) = &influx_task {
+        handles.push(ReceiverHandle::Influx(task.handle()));
+    }
+
+    if let Some(task) = &mqtt_task {
+        handles.push(ReceiverHandle::Mqtt(task.handle()));
+    }
+
+    let (reload_tx, mut reload_rx) = mpsc::channel(1);
+    let signal_trap_task = signal_trap(handles, reload_tx)?;
+
+    /*
+     * ウォッチドッグタスクの起動(systemd配下で起動されている場合のみ)
+     */
+    let (probe_tx, mut probe_rx) = mpsc::channel(1);
+    let watchdog_task = systemd::spawn_watchdog(probe_tx, database_stats);
 
     /*
-     * 中継処理タスクの起動
+     * 中継処理タスクの起動(処理ステージを通過したレコードを較正し、気圧に関す
+     * る派生値を付与してファンアウトへ渡す)
+     *
+     * 設置場所を持たないレコードは登録簿から設置場所を補い、補えなかった場合
+     * は破棄する。続いて計測種別の登録簿に従って追加の計測値を取り込む。処理
+     * ステージで隔離対象となったレコードはデータベースにのみ渡す。
+     *
+     * 設定の再読み込みが要求された場合は処理ステージと較正情報、登録簿、計測
+     * 種別、設置場所の定義、認証の設定、受信の制限を作り直す。全ての設定の
+     * 検証を終えてから一斉に入れ替えるので、再読み込みに失敗した場合は全て
+     * の設定について従前のものを使い続ける。TLSの証明書と鍵は設定とは独立し
+     * て読み込み直す。読み込み(登録簿の読み出しやスクリプトのコンパイル)は
+     * ブロッキング処理用のスレッドで行い、その間も従前の設定でレコードの処
+     * 理を続ける。読み込み中に届いた再読み込みの要求は、読み込みの完了後に
+     * 処理する。
+     *
+     * ウォッチドッグタスクからの確認要求にはループの先頭で応答する(ファンア
+     * ウトで待たされ続けている場合は応答できない)。
      */
+    let relay_opts = opts.clone();
+
     let relay_task = tokio::spawn(async move {
+        // 読み込み中の設定(完了するまで次の再読み込みの要求は受け付けない)
+        let mut reloading: Option<JoinHandle<Result<Pipeline>>> = None;
+
         loop {
-            match select_receive!(tcp_rx, udp_rx) {
-                Some(record) => {
-                    if let Err(err) = tx.send(record).await {
-                        error!("record send faild: {}", err);
+            tokio::select! {
+                Some(reply_tx) = probe_rx.recv() => {
+                    let _ = reply_tx.send(());
+                }
+
+                Some(()) = reload_rx.recv(), if reloading.is_none() => {
+                    let opts = relay_opts.clone();
+                    let tls = tls.clone();
+
+                    reloading = Some(tokio::task::spawn_blocking(move || {
+                        if let Some(tls) = &tls {
+                            match tls.reload() {
+                                Ok(()) => info!("TLS certificate reloaded"),
+                                Err(err) => error!(
+                                    "reload TLS certificate failed: {}",
+                                    err
+                                ),
+                            }
+                        }
+
+                        load_pipeline(&opts)
+                    }));
+                }
+
+                result = async { reloading.as_mut().unwrap().await },
+                    if reloading.is_some() =>
+                {
+                    reloading = None;
+
+                    match result {
+                        Ok(Ok(pipeline)) => {
+                            stage_chain = pipeline.stage_chain;
+                            calibration = pipeline.calibration;
+                            registry = pipeline.registry;
+                            measurements = pipeline.measurements;
+                            barometer.set_altitudes(pipeline.altitudes);
+                            authenticator.update(pipeline.auth);
+                            access.update(pipeline.access);
+                            info!("configuration reloaded");
+                        }
+
+                        Ok(Err(err)) => {
+                            error!("reload configuration failed: {}", err);
+                        }
+
+                        Err(err) => {
+                            error!("reload configuration failed: {}", err);
+                        }
                     }
                 }
 
-                None => break,
+                result = async {
+                    select_receive!(tcp_rx, udp_rx, coap_rx, influx_rx, mqtt_rx)
+                } => {
+                    // 全てのレシーバのチャネルが閉じられるまで受信を続ける
+                    let Some(mut record) = result else {
+                        let receivers = [
+                            &tcp_rx, &udp_rx, &coap_rx, &influx_rx, &mqtt_rx
+                        ];
+
+                        if receivers.iter().all(|rx| rx.is_closed()) {
+                            break;
+                        }
+
+                        continue;
+                    };
+
+                    record.split_telemetry();
+
+                    if !registry.resolve(&mut record) {
+                        warn!("location unknown, record dropped: {}", record);
+                        continue;
+                    }
+
+                    measurements.apply(&mut record);
+
+                    // 中継済みのレコードは中継元で較正と処理ステージを適用
+                    // 済みなので、そのまま記録する
+                    let records = if record.is_relayed() {
+                        vec![record]
+                    } else {
+                        calibration.apply(&mut record);
+                        stage_chain.process(record)
+                    };
+
+                    for mut record in records {
+                        if record.is_quarantined() {
+                            fanout.dispatch_to(database::SINK_NAME, record).await;
+                            continue;
+                        }
+
+                        barometer.apply(&mut record);
+                        fanout.dispatch(record).await;
+                    }
+                }
             }
         }
     });
 
+    /*
+     * 起動完了の通知(データベースと全ての待ち受けの準備が整った時点)
+     */
+    systemd::notify_ready();
+
     /*
      * タスクの終了待ち
+     *
+     * 中継処理タスクは全てのレシーバのチャネルが閉じられた時点(受信済みのレ
+     * コードを全てファンアウトに渡し終えた時点)で終了する。その後、各シンク
+     * はキューに残ったレコードを処理し終えてから終了する。
      */
-    if let Err(err) = relay_task.await {
-        warn!("relay task has been troubled: {}", err);
+    let mut joined = 0;
+    let mut troubled = 0;
+
+    let mut tally = |ok: bool| {
+        joined += 1;
+
+        if !ok {
+            troubled += 1;
+        }
+    };
+
+    tally(join_task("relay", relay_task).await);
+    tally(join_task("TCP receiver", tcp_task).await);
+    tally(join_task("UDP receiver", udp_task).await);
+
+    if let Some(coap_task) = coap_task {
+        tally(join_task("CoAP receiver", coap_task).await);
     }
 
-    if let Err(err) = tcp_task.await {
-        warn!("TCP receiver task has been troubled: {}", err);
+    if let Some(influx_task) = influx_task {
+        tally(join_task("line protocol receiver", influx_task).await);
     }
 
-    if let Err(err) = database_task.await {
-        warn!("database task has been troubled: {}", err);
+    if let Some(mqtt_task) = mqtt_task {
+        tally(join_task("MQTT receiver", mqtt_task).await);
+    }
+
+    tally(join_task("database", database_task).await);
+
+    if let Some(csv_task) = csv_task {
+        tally(join_task("CSV write", csv_task).await);
     }
 
-    if let Err(err) = signal_trap_task.await {
-        warn!("signal-trap task has been troubled: {}", err);
+    for webhook_task in webhook_tasks {
+        tally(join_task("webhook", webhook_task).await);
+    }
+
+    if let Some(mqtt_publish_task) = mqtt_publish_task {
+        tally(join_task("MQTT publish", mqtt_publish_task).await);
+    }
+
+    if let Some(tsdb_task) = tsdb_task {
+        tally(join_task("TSDB forward", tsdb_task).await);
+    }
+
+    if let Some(relay_forward_task) = relay_forward_task {
+        tally(join_task("relay forward", relay_forward_task).await);
+    }
+
+    /*
+     * シグナルトラップタスクの停止(二度目のシグナルの待ち受けを終える)と
+     * ウォッチドッグタスクの停止
+     */
+    signal_trap_task.abort();
+
+    if let Some(watchdog_task) = watchdog_task {
+        watchdog_task.abort();
     }
 
+    /*
+     * シンクの稼働状況の最終出力
+     */
+    if let Some(stats_task) = stats_task {
+        stats_task.abort();
+    }
+
+    sink::log_stats(&stats, &receiver_stats);
+
     /*
      * 終了
      */
+    info!(
+        "shutdown completed: {} tasks joined ({} troubled)",
+        joined,
+        troubled
+    );
     info!("exit env-logger process");
 
     Ok(())
 }
 
+///
+/// タスクの終了待ち
+///
+/// # 引数
+/// * `name` - タスクの名前(ログ出力用)
+/// * `task` - 終了を待つタスク
+///
+/// # 戻り値
+/// タスクが正常に終了した場合は`true`を、異常終了した場合は`false`を返す。
+///
+async fn join_task<F>(name: &str, task: F) -> bool
+where
+    F: Future<Output = std::result::Result<(), tokio::task::JoinError>>,
+{
+    match task.await {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("{} task has been troubled: {}", name, err);
+            false
+        }
+    }
+}
+
+///
+/// 中継処理で用いる設定をまとめた構造体
+///
+struct Pipeline {
+    /// 処理ステージ
+    stage_chain: StageChain,
+
+    /// 較正情報
+    calibration: Calibration,
+
+    /// 設置場所とデバイスの登録簿
+    registry: Registry,
+
+    /// 計測種別の登録簿
+    measurements: Measurements,
+
+    /// 気圧に関する派生値の計算に用いる設置場所毎の標高
+    altitudes: Altitudes,
+
+    /// 受信レコードの認証の設定
+    auth: AuthPolicy,
+
+    /// 待ち受け毎の受信の制限
+    access: AccessPolicies,
+}
+
+///
+/// 中継処理で用いる設定の生成
+///
+/// # 引数
+/// * `opts` - オプション情報をパックしたオブジェクト
+///
+/// # 戻り値
+/// 設定ファイルと登録簿を読み込んで生成した中継処理の設定を`Ok()`でラップし
+/// て返す。
+///
+/// # 注記
+/// 設置場所の標高は登録簿と設定ファイルの双方で指定できる。双方で指定され
+/// ている場合は設定ファイルの値を用いる。全ての設定の検証は本関数で行うの
+/// で、戻り値の設定は失敗すること無く適用できる。
+///
+fn load_pipeline(opts: &Options) -> Result<Pipeline> {
+    let config = Config::load(opts.config_file())?;
+    let registry = Registry::load(opts.db_file())?;
+
+    let mut locations = config.locations();
+    let mut altitudes = registry.altitudes();
+
+    altitudes.retain(|loc| locations.iter().all(|l| l.name() != loc.name()));
+    locations.append(&mut altitudes);
+
+    Ok(Pipeline {
+        stage_chain: StageChain::new(&config.stages())?,
+        calibration: Calibration::new(&config.calibrations())?,
+        registry,
+        measurements: Measurements::new(&config.measurements())?,
+        altitudes: Altitudes::new(&locations)?,
+        auth: AuthPolicy::new(&config.auth())?,
+        access: AccessPolicies::new(&config.access())?,
+    })
+}
+
 ///
 /// シグナルトラップ処理を実行するタスク
 ///
 /// # 引数
-/// * `handle` - TCPレシーバタスクの制御を行うためのハンドルオブジェクト
+/// * `handles` - 各レシーバタスクの制御を行うためのハンドルオブジェクト
+/// * `reload_tx` - 設定の再読み込み要求を通知するチャネル
 ///
 /// # 戻り値
 /// シグナルトラップタスクのジョインハンドルを返す。
 ///
 /// # 注記
 /// 本タスクでは、SIGINTと SIGTERMをトラップしする。両シグナルとも、プログラム
-/// の正常終了をキックする(TCPレシーバタスクの終了を要求し、連鎖的に他のタスク
-/// を終了させる)。
+/// の正常終了をキックする(各レシーバタスクの終了を要求し、連鎖的に他のタスク
+/// を終了させる)。終了処理中に再度SIGINTまたはSIGTERMをトラップした場合は、
+/// 終了処理の完了を待たずにプロセスを終了する。
+/// また、SIGHUPをトラップした場合は設定(処理ステージと較正情報)の再読み込み
+/// を要求する。
+/// 終了処理の開始はsystemdにも通知する。
 ///
-fn signal_trap(tcp_handle: TcpReceiverHandle, udp_handle: UdpReceiverHandle)
+fn signal_trap(handles: Vec<ReceiverHandle>, reload_tx: mpsc::Sender<()>)
     -> Result<JoinHandle<()>>
 {
     /*
@@ -172,17 +696,52 @@ fn signal_trap(tcp_handle: TcpReceiverHandle, udp_handle: UdpReceiverHandle)
      */
     let mut sigterm = signal(SignalKind::terminate())?;
     let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sighup = signal(SignalKind::hangup())?;
 
     /*
      * タスクを起動
      */
     Ok(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = sigint.recv() => {
+                    info!("caught SIGINT");
+                    break;
+                }
+
+                _ = sigterm.recv() => {
+                    info!("caught SIGTERM");
+                    break;
+                }
+
+                _ = sighup.recv() => {
+                    info!("caught SIGHUP");
+
+                    // 再読み込みが未処理の場合は要求をまとめる
+                    let _ = reload_tx.try_send(());
+                }
+            }
+        }
+
+        /*
+         * 各レシーバタスクへの終了要求(以降は再読み込みを受け付けない)
+         */
+        systemd::notify_stopping();
+        drop(reload_tx);
+
+        for handle in handles {
+            handle.shutdown().await;
+        }
+
+        /*
+         * 二度目のシグナルによる強制終了
+         */
         tokio::select! {
-            _ = sigint.recv() => info!("caught SIGINT"),
-            _ = sigterm.recv() => info!("caught SIGTERM"),
+            _ = sigint.recv() => {}
+            _ = sigterm.recv() => {}
         }
 
-        tcp_handle.shutdown().await;
-        udp_handle.shutdown().await;
+        warn!("caught signal again, exit immediately");
+        std::process::exit(1);
     }))
 }
@@ -0,0 +1,205 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 計測種別の登録簿をまとめたモジュール
+//!
+//! 気温、湿度、気圧以外の計測値(CO2濃度や照度など)は、登録簿に定義された
+//! 計測種別のみを受け付ける。計測種別は組み込みのものに加えて、設定ファイル
+//! の`[[measurement]]`テーブルで追加(または組み込みのものを上書き)できる。
+//! 受け付けた計測値はデータベースの`MEASUREMENT_TABLE`に1計測値1行の形式で
+//! 記録する。
+//!
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 組み込みの計測種別(名前, 単位, 下限値, 上限値, device_class)
+const BUILTIN_TYPES: [(&str, &str, f64, f64, Option<&str>); 8] = [
+    ("co2", "ppm", 0.0, 40000.0, Some("carbon_dioxide")),
+    ("pm1", "µg/m³", 0.0, 1000.0, Some("pm1")),
+    ("pm2_5", "µg/m³", 0.0, 1000.0, Some("pm25")),
+    ("pm10", "µg/m³", 0.0, 1000.0, Some("pm10")),
+    ("lux", "lx", 0.0, 200000.0, Some("illuminance")),
+    ("tvoc", "ppb", 0.0, 60000.0, Some("volatile_organic_compounds_parts")),
+    ("voc_index", "", 0.0, 500.0, None),
+    ("nox_index", "", 0.0, 500.0, None),
+];
+
+/// 計測種別の名前として使用できない名前(レコードの固定のプロパティと稼働
+/// 状態のプロパティ)
+const RESERVED_NAMES: [&str; 11] = [
+    "location",
+    "device_id",
+    "timestamp",
+    "temperature",
+    "humidity",
+    "air_pressure",
+    "firmware",
+    "rssi",
+    "battery",
+    "uptime",
+    "reset_reason",
+];
+
+///
+/// 計測種別の定義を表す構造体
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MeasurementConfig {
+    /// 計測種別の名前(JSONのプロパティ名)
+    pub(crate) name: String,
+
+    /// 単位
+    #[serde(default)]
+    pub(crate) unit: String,
+
+    /// 下限値
+    pub(crate) min: Option<f64>,
+
+    /// 上限値
+    pub(crate) max: Option<f64>,
+
+    /// Home Assistantのdevice_class
+    pub(crate) device_class: Option<String>,
+}
+
+impl MeasurementConfig {
+    ///
+    /// 定義の確認
+    ///
+    fn validate(&self) -> Result<()> {
+        let valid_name = !self.name.is_empty()
+            && self.name.chars().all(|ch| {
+                ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '_'
+            });
+
+        if !valid_name {
+            return Err(anyhow!("measurement {}: invalid name", self.name));
+        }
+
+        if RESERVED_NAMES.contains(&self.name.as_str()) {
+            return Err(anyhow!("measurement {}: reserved name", self.name));
+        }
+
+        if [self.min, self.max].iter().flatten().any(|v| !v.is_finite()) {
+            return Err(anyhow!("measurement {}: invalid range", self.name));
+        }
+
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(anyhow!("measurement {}: min > max", self.name));
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// 値が範囲内か否かの判定
+    ///
+    fn contains(&self, val: f64) -> bool {
+        val.is_finite()
+            && self.min.is_none_or(|min| val >= min)
+            && self.max.is_none_or(|max| val <= max)
+    }
+}
+
+///
+/// 計測種別の登録簿を表す構造体
+///
+pub(crate) struct Measurements {
+    /// 名前毎の計測種別の定義
+    types: BTreeMap<String, MeasurementConfig>,
+}
+
+impl Measurements {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `configs` - 設定ファイルで定義された計測種別のリスト
+    ///
+    /// # 戻り値
+    /// 生成に成功した場合はMeasurementsオブジェクトを`Ok()`でラップして返す。
+    /// 定義に不備があった場合はエラー情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn new(configs: &[MeasurementConfig]) -> Result<Self> {
+        let mut types = BTreeMap::new();
+
+        for (name, unit, min, max, class) in BUILTIN_TYPES {
+            types.insert(name.to_string(), MeasurementConfig {
+                name: name.to_string(),
+                unit: unit.to_string(),
+                min: Some(min),
+                max: Some(max),
+                device_class: class.map(|s| s.to_string()),
+            });
+        }
+
+        for config in configs {
+            config.validate()?;
+            types.insert(config.name.clone(), config.clone());
+        }
+
+        Ok(Self {types})
+    }
+
+    ///
+    /// 計測種別の定義の取得
+    ///
+    /// # 引数
+    /// * `name` - 計測種別の名前
+    ///
+    /// # 戻り値
+    /// 登録されている場合は定義を`Some()`でラップして返す。
+    ///
+    pub(crate) fn get(&self, name: &str) -> Option<&MeasurementConfig> {
+        self.types.get(name)
+    }
+
+    ///
+    /// 計測値の取り込み
+    ///
+    /// # 引数
+    /// * `record` - 対象のレコード
+    ///
+    /// # 注記
+    /// レコードが持つ未知のプロパティのうち、登録されている計測種別の数値を
+    /// 計測値として取り込む。範囲外の値は取り込まずに品質フラグを付ける。数
+    /// 値以外の値は未知のプロパティのまま残す。
+    ///
+    pub(crate) fn apply(&self, record: &mut SensorRecord) {
+        let names = record.extras()
+            .keys()
+            .filter(|key| self.types.contains_key(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for name in names {
+            let Some(val) = record.extras().get(&name).and_then(|v| v.as_f64())
+            else {
+                continue;
+            };
+
+            record.remove_extra(&name);
+
+            if self.types[&name].contains(val) {
+                record.set_metric(&name, val as f32);
+            } else {
+                record.add_quality_flag(format!("{}:range:{}", name, val));
+            }
+        }
+    }
+}
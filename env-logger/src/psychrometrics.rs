@@ -0,0 +1,178 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 気温と相対湿度から求める派生値(湿り空気の状態量)の計算をまとめたモジュー
+//! ル
+//!
+//! 飽和水蒸気圧はMagnusの式(Sonntag 1990の係数)で求める。
+//!
+
+use crate::record::round_value;
+
+/// Magnusの式の係数(無次元)
+const MAGNUS_B: f64 = 17.62;
+
+/// Magnusの式の係数(摂氏)
+const MAGNUS_C: f64 = 243.12;
+
+/// 0℃における飽和水蒸気圧(hPa)
+const MAGNUS_E0: f64 = 6.112;
+
+/// 派生値の項目名のリスト(データベースのカラム名と同じ)
+pub(crate) const FIELD_NAMES: [&str; 5] = [
+    "dew_point",
+    "absolute_humidity",
+    "humidex",
+    "heat_index",
+    "vpd",
+];
+
+///
+/// 気温と相対湿度から求めた派生値を保持する構造体
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Derived {
+    /// 露点温度(摂氏)
+    pub(crate) dew_point: f32,
+
+    /// 絶対湿度(g/m³)
+    pub(crate) absolute_humidity: f32,
+
+    /// ヒューミデックス(体感温度の指標、摂氏相当)
+    pub(crate) humidex: f32,
+
+    /// 暑さ指数(NOAAのheat index、摂氏)
+    pub(crate) heat_index: f32,
+
+    /// 飽差(kPa)
+    pub(crate) vpd: f32,
+}
+
+impl Derived {
+    ///
+    /// 派生値の計算
+    ///
+    /// # 引数
+    /// * `temperature` - 気温(摂氏)
+    /// * `humidity` - 相対湿度(%)
+    ///
+    /// # 戻り値
+    /// 計算できた場合は派生値を`Some()`でラップして返す。気温または湿度が無
+    /// い場合や、湿度が0%以下の場合はNoneを返す。
+    ///
+    /// # 注記
+    /// 100%を超える相対湿度は100%として扱う。各値は小数点以下2桁に丸める。
+    ///
+    pub(crate) fn compute(temperature: Option<f32>, humidity: Option<f32>)
+        -> Option<Self>
+    {
+        let t = temperature? as f64;
+        let rh = (humidity? as f64).min(100.0);
+
+        if rh <= 0.0 || t <= -MAGNUS_C {
+            return None;
+        }
+
+        /*
+         * 飽和水蒸気圧と水蒸気圧(hPa)
+         */
+        let es = MAGNUS_E0 * (MAGNUS_B * t / (MAGNUS_C + t)).exp();
+        let e = es * rh / 100.0;
+
+        /*
+         * 露点温度
+         */
+        let gamma = (rh / 100.0).ln() + MAGNUS_B * t / (MAGNUS_C + t);
+        let dew_point = MAGNUS_C * gamma / (MAGNUS_B - gamma);
+
+        /*
+         * 絶対湿度(水蒸気の状態方程式から求める)
+         */
+        let absolute_humidity = 216.7 * e / (273.15 + t);
+
+        /*
+         * ヒューミデックス(Environment Canadaの定義)
+         */
+        let humidex = t + 0.5555 * (e - 10.0);
+
+        /*
+         * 飽差(hPaからkPaに変換)
+         */
+        let vpd = (es - e) / 10.0;
+
+        Some(Self {
+            dew_point: round_value(dew_point, 2) as f32,
+            absolute_humidity: round_value(absolute_humidity, 2) as f32,
+            humidex: round_value(humidex, 2) as f32,
+            heat_index: round_value(heat_index(t, rh), 2) as f32,
+            vpd: round_value(vpd, 2) as f32,
+        })
+    }
+
+    ///
+    /// 派生値のリストへの変換
+    ///
+    /// # 戻り値
+    /// 項目名と値の組のリストを返す(項目の並びは`FIELD_NAMES`と同じ)。
+    ///
+    pub(crate) fn fields(&self) -> [(&'static str, f32); 5] {
+        [
+            (FIELD_NAMES[0], self.dew_point),
+            (FIELD_NAMES[1], self.absolute_humidity),
+            (FIELD_NAMES[2], self.humidex),
+            (FIELD_NAMES[3], self.heat_index),
+            (FIELD_NAMES[4], self.vpd),
+        ]
+    }
+}
+
+///
+/// 暑さ指数(heat index)の計算
+///
+/// # 引数
+/// * `t` - 気温(摂氏)
+/// * `rh` - 相対湿度(%)
+///
+/// # 戻り値
+/// 暑さ指数(摂氏)を返す
+///
+/// # 注記
+/// 米国気象局(NOAA/NWS)の手順に従い、Steadmanの簡易式で求めた値が80°F未満
+/// の場合はその値を、それ以外の場合はRothfuszの回帰式(補正付き)の値を用い
+/// る。
+///
+fn heat_index(t: f64, rh: f64) -> f64 {
+    let f = t * 9.0 / 5.0 + 32.0;
+
+    let simple = 0.5 * (f + 61.0 + (f - 68.0) * 1.2 + rh * 0.094);
+
+    let hi = if (simple + f) / 2.0 < 80.0 {
+        simple
+
+    } else {
+        let mut hi = -42.379
+            + 2.049_015_23 * f
+            + 10.143_331_27 * rh
+            - 0.224_755_41 * f * rh
+            - 0.006_837_83 * f * f
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * f * f * rh
+            + 0.000_852_82 * f * rh * rh
+            - 0.000_001_99 * f * f * rh * rh;
+
+        if rh < 13.0 && (80.0..=112.0).contains(&f) {
+            hi -= ((13.0 - rh) / 4.0) * ((17.0 - (f - 95.0).abs()) / 17.0).sqrt();
+
+        } else if rh > 85.0 && (80.0..=87.0).contains(&f) {
+            hi += ((rh - 85.0) / 10.0) * ((87.0 - f) / 5.0);
+        }
+
+        hi
+    };
+
+    (hi - 32.0) * 5.0 / 9.0
+}
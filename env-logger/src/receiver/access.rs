@@ -0,0 +1,784 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 受信の可否の判定(送信元の制限と流量の制限)をまとめたモジュール
+//!
+//! 待ち受け毎(TCP、UDP、CoAP、ラインプロトコル、書き込みAPI、MQTT)に、以
+//! 下の制限を設定できる。
+//!
+//! * 送信元アドレスの許可リストと拒否リスト(CIDR表記またはアドレス)
+//! * 送信元アドレス毎の流量の制限(トークンバケット)
+//! * デバイスID毎の流量の制限(トークンバケット)
+//!
+//! MQTTはブローカーを経由して受信するため送信元アドレスを特定できない。こ
+//! のためMQTTではデバイスID毎の流量の制限のみを設定できる。
+//!
+//! 拒否リストは許可リストに優先する。許可リストが空の場合は、拒否リストに
+//! 該当しない全ての送信元を許可する。流量は1秒あたりのレコード数(`rate`)と
+//! 連続して受け付けるレコード数の上限(`burst`)で指定する。
+//!
+//! 制限により受け付けなかったレコードはパイプラインに渡さずに破棄し、待ち
+//! 受け毎の稼働状況として計上する。
+//!
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use super::ReceiverStats;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 保持するトークンバケットの数の上限
+const MAX_BUCKETS: usize = 4096;
+
+/// 上限に達した場合にトークンバケットの破棄を試みる間隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+///
+/// 待ち受け毎の制限の設定をまとめた構造体
+///
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AccessSection {
+    /// TCPの待ち受けの制限
+    #[serde(default)]
+    tcp: AccessConfig,
+
+    /// UDPの待ち受けの制限
+    #[serde(default)]
+    udp: AccessConfig,
+
+    /// CoAPの待ち受けの制限
+    #[serde(default)]
+    coap: AccessConfig,
+
+    /// ラインプロトコルの待ち受け(TCP/UDP)の制限
+    #[serde(default)]
+    influx: AccessConfig,
+
+    /// 書き込みAPIの待ち受けの制限
+    #[serde(default)]
+    influx_http: AccessConfig,
+
+    /// MQTTによる受信の制限(デバイスID毎の流量のみ)
+    #[serde(default)]
+    mqtt: AccessConfig,
+}
+
+///
+/// 受信の制限の設定を表す構造体
+///
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AccessConfig {
+    /// 受信を許可する送信元(空の場合は全て)
+    #[serde(default)]
+    allow: Vec<String>,
+
+    /// 受信を拒否する送信元
+    #[serde(default)]
+    deny: Vec<String>,
+
+    /// 送信元アドレス毎の流量(レコード/秒)
+    source_rate: Option<f64>,
+
+    /// 送信元アドレス毎に連続して受け付けるレコード数
+    source_burst: Option<u32>,
+
+    /// デバイスID毎の流量(レコード/秒)
+    device_rate: Option<f64>,
+
+    /// デバイスID毎に連続して受け付けるレコード数
+    device_burst: Option<u32>,
+}
+
+///
+/// 流量の制限を表す構造体
+///
+#[derive(Debug, Clone, Copy)]
+struct Rate {
+    /// 1秒あたりに補充するトークン数
+    rate: f64,
+
+    /// バケットの容量
+    burst: f64,
+}
+
+impl Rate {
+    ///
+    /// 設定の展開
+    ///
+    /// # 注記
+    /// `burst`を省略した場合は1秒分(最低1)とする。
+    ///
+    fn new(name: &str, rate: Option<f64>, burst: Option<u32>)
+        -> Result<Option<Self>>
+    {
+        let Some(rate) = rate else {
+            if burst.is_some() {
+                return Err(anyhow!("access: {}_burst without rate", name));
+            }
+
+            return Ok(None);
+        };
+
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(anyhow!("access: {}_rate must be positive", name));
+        }
+
+        let burst = burst.map(|n| n as f64).unwrap_or(rate.ceil());
+
+        if burst < 1.0 {
+            return Err(anyhow!("access: {}_burst must be positive", name));
+        }
+
+        Ok(Some(Self {rate, burst}))
+    }
+}
+
+///
+/// トークンバケットを表す構造体
+///
+struct Bucket {
+    /// 残りのトークン数
+    tokens: f64,
+
+    /// 最後に補充した時刻
+    last: Instant,
+}
+
+impl Bucket {
+    ///
+    /// 満杯のバケットの生成
+    ///
+    fn new(rate: &Rate, now: Instant) -> Self {
+        Self {tokens: rate.burst, last: now}
+    }
+
+    ///
+    /// トークンの補充
+    ///
+    fn refill(&mut self, rate: &Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate.rate).min(rate.burst);
+        self.last = now;
+    }
+}
+
+///
+/// 受信の制限の設定を展開した構造体
+///
+/// # 注記
+/// 設定の再読み込み時は、他の設定と共に全ての検証を終えてから
+/// `AccessControl::update()`で入れ替える。
+///
+pub(crate) struct AccessPolicy {
+    /// 受信を許可する送信元
+    allow: Vec<IpNet>,
+
+    /// 受信を拒否する送信元
+    deny: Vec<IpNet>,
+
+    /// 送信元アドレス毎の流量
+    source: Option<Rate>,
+
+    /// デバイスID毎の流量
+    device: Option<Rate>,
+}
+
+impl AccessPolicy {
+    ///
+    /// 設定の展開
+    ///
+    /// # 引数
+    /// * `config` - 受信の制限の設定
+    ///
+    /// # 戻り値
+    /// 展開に成功した場合はオブジェクトを`Ok()`でラップして返す。設定に不備
+    /// があった場合はエラー情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn new(config: &AccessConfig) -> Result<Self> {
+        Ok(Self {
+            allow: parse_networks(&config.allow)?,
+            deny: parse_networks(&config.deny)?,
+            source: Rate::new(
+                "source",
+                config.source_rate,
+                config.source_burst
+            )?,
+            device: Rate::new(
+                "device",
+                config.device_rate,
+                config.device_burst
+            )?,
+        })
+    }
+
+    ///
+    /// デバイスID毎の流量のみの設定の展開
+    ///
+    /// # 引数
+    /// * `name` - 待ち受けの名前(エラーメッセージに用いる)
+    /// * `config` - 受信の制限の設定
+    ///
+    /// # 戻り値
+    /// 展開に成功した場合はオブジェクトを`Ok()`でラップして返す。送信元に関
+    /// する制限が設定されていた場合はエラー情報を`Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// 送信元アドレスを特定できない待ち受け(MQTT)に用いる。
+    ///
+    fn for_devices(name: &str, config: &AccessConfig) -> Result<Self> {
+        if !config.allow.is_empty()
+            || !config.deny.is_empty()
+            || config.source_rate.is_some()
+            || config.source_burst.is_some()
+        {
+            return Err(anyhow!(
+                "access: {} accepts only device_rate and device_burst",
+                name
+            ));
+        }
+
+        Self::new(config)
+    }
+
+    ///
+    /// 送信元アドレスの可否の判定
+    ///
+    fn permits(&self, addr: &IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(addr)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(addr))
+    }
+}
+
+///
+/// 送信元の定義のリストの展開
+///
+/// # 注記
+/// CIDR表記の他、アドレスのみの記述(単一のホスト)も受け付ける。
+///
+fn parse_networks(list: &[String]) -> Result<Vec<IpNet>> {
+    let mut ret = vec![];
+
+    for src in list {
+        if let Ok(net) = src.parse::<IpNet>() {
+            ret.push(net);
+        } else if let Ok(addr) = src.parse::<IpAddr>() {
+            ret.push(IpNet::from(addr));
+        } else {
+            return Err(anyhow!("access: invalid network: {}", src));
+        }
+    }
+
+    Ok(ret)
+}
+
+///
+/// 待ち受け毎の受信の制限の設定を展開した構造体
+///
+pub(crate) struct AccessPolicies {
+    /// TCPの待ち受けの制限
+    tcp: AccessPolicy,
+
+    /// UDPの待ち受けの制限
+    udp: AccessPolicy,
+
+    /// CoAPの待ち受けの制限
+    coap: AccessPolicy,
+
+    /// ラインプロトコルの待ち受けの制限
+    influx: AccessPolicy,
+
+    /// 書き込みAPIの待ち受けの制限
+    influx_http: AccessPolicy,
+
+    /// MQTTによる受信の制限
+    mqtt: AccessPolicy,
+}
+
+impl AccessPolicies {
+    ///
+    /// 設定の展開
+    ///
+    /// # 引数
+    /// * `section` - 待ち受け毎の制限の設定
+    ///
+    /// # 戻り値
+    /// 展開に成功した場合はオブジェクトを`Ok()`でラップして返す。設定に不備
+    /// があった場合はエラー情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn new(section: &AccessSection) -> Result<Self> {
+        Ok(Self {
+            tcp: AccessPolicy::new(&section.tcp)?,
+            udp: AccessPolicy::new(&section.udp)?,
+            coap: AccessPolicy::new(&section.coap)?,
+            influx: AccessPolicy::new(&section.influx)?,
+            influx_http: AccessPolicy::new(&section.influx_http)?,
+            mqtt: AccessPolicy::for_devices("mqtt", &section.mqtt)?,
+        })
+    }
+}
+
+///
+/// キー毎のトークンバケットをまとめた構造体
+///
+/// # 注記
+/// 保持するバケットの数は`MAX_BUCKETS`を上限とする。上限に達した場合は、
+/// 満杯まで補充されるだけの時間受信の無かったバケット(新規のものと同じ状
+/// 態)を破棄する。それでも空きが無い場合、新しいキーは全て共用のバケット
+/// で流量を制限する。
+///
+struct Buckets<K> {
+    /// キー毎のバケット
+    map: HashMap<K, Bucket>,
+
+    /// 上限を超えたキーで共用するバケット
+    overflow: Option<Bucket>,
+
+    /// 最後に破棄を行った時刻
+    last_sweep: Option<Instant>,
+}
+
+impl<K: Eq + Hash> Buckets<K> {
+    ///
+    /// オブジェクトの生成
+    ///
+    fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+            overflow: None,
+            last_sweep: None,
+        }
+    }
+
+    ///
+    /// トークンの取り出し
+    ///
+    /// # 戻り値
+    /// トークンを取り出せた場合は`true`を、バケットが空の場合は`false`を返
+    /// す。
+    ///
+    fn take(&mut self, key: K, rate: &Rate) -> bool {
+        let now = Instant::now();
+
+        if self.map.len() >= MAX_BUCKETS && !self.map.contains_key(&key) {
+            self.sweep(rate, now);
+        }
+
+        let bucket = if self.map.len() < MAX_BUCKETS
+            || self.map.contains_key(&key)
+        {
+            self.map.entry(key).or_insert_with(|| Bucket::new(rate, now))
+
+        } else {
+            debug!("token buckets are full, use overflow bucket");
+            self.overflow.get_or_insert_with(|| Bucket::new(rate, now))
+        };
+
+        bucket.refill(rate, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    ///
+    /// 受信の途絶えたバケットの破棄
+    ///
+    /// # 注記
+    /// 走査の負荷を抑えるため、破棄は`SWEEP_INTERVAL`毎に高々1回行う。
+    ///
+    fn sweep(&mut self, rate: &Rate, now: Instant) {
+        let swept = self.last_sweep.is_some_and(|tm| {
+            now.saturating_duration_since(tm) < SWEEP_INTERVAL
+        });
+
+        if swept {
+            return;
+        }
+
+        let idle = Duration::from_secs_f64(rate.burst / rate.rate);
+
+        self.map.retain(|_, bucket| {
+            now.saturating_duration_since(bucket.last) < idle
+        });
+        self.last_sweep = Some(now);
+
+        if self.map.len() < MAX_BUCKETS {
+            self.overflow = None;
+        }
+    }
+}
+
+///
+/// 待ち受け毎の受信の可否を判定する構造体
+///
+/// # 注記
+/// 待ち受けの受信処理と設定の再読み込み処理で共有し、再読み込み時には
+/// `update()`で設定を入れ替える(トークンバケットの状態は引き継ぐ)。
+///
+pub(crate) struct AccessControl {
+    /// 受信の制限の設定
+    policy: RwLock<AccessPolicy>,
+
+    /// 送信元アドレス毎のトークンバケット
+    sources: Mutex<Buckets<IpAddr>>,
+
+    /// デバイスID毎のトークンバケット
+    devices: Mutex<Buckets<String>>,
+
+    /// 受信を拒否した件数の集計
+    stats: Arc<ReceiverStats>,
+}
+
+impl AccessControl {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `name` - 待ち受けの名前(集計の表示に用いる)
+    /// * `policy` - 展開済みの受信の制限の設定
+    ///
+    pub(crate) fn new(name: &str, policy: AccessPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            policy: RwLock::new(policy),
+            sources: Mutex::new(Buckets::new()),
+            devices: Mutex::new(Buckets::new()),
+            stats: ReceiverStats::new(name),
+        })
+    }
+
+    ///
+    /// 設定の入れ替え
+    ///
+    /// # 引数
+    /// * `policy` - 展開済みの新しい受信の制限の設定
+    ///
+    pub(crate) fn update(&self, policy: AccessPolicy) {
+        match self.policy.write() {
+            Ok(mut current) => *current = policy,
+            Err(err) => *err.into_inner() = policy,
+        }
+    }
+
+    ///
+    /// 受信を拒否した件数の集計へのアクセサ
+    ///
+    pub(crate) fn stats(&self) -> Arc<ReceiverStats> {
+        self.stats.clone()
+    }
+
+    ///
+    /// 送信元の許可リストと拒否リストによる可否の判定
+    ///
+    /// # 引数
+    /// * `addr` - 送信元アドレス
+    ///
+    /// # 戻り値
+    /// 受信を許可する場合は`true`を返す。
+    ///
+    /// # 注記
+    /// 接続を維持したまま受信を続ける待ち受け(ラインプロトコルと書き込み
+    /// API)で、接続の受け付け時に呼び出す。流量の制限は受信の都度
+    /// `admit_source()`で行う。
+    ///
+    pub(crate) fn permits_source(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+
+        let Ok(policy) = self.policy.read() else {
+            return false;
+        };
+
+        if !policy.permits(&addr) {
+            debug!("source denied: {}", addr);
+            self.stats.count("denied");
+            return false;
+        }
+
+        true
+    }
+
+    ///
+    /// 送信元による受信の可否の判定
+    ///
+    /// # 引数
+    /// * `addr` - 送信元アドレス
+    ///
+    /// # 戻り値
+    /// 受信を許可する場合は`true`を返す。
+    ///
+    /// # 注記
+    /// データを読み込む前(TCPでは接続の受け付け時)に呼び出す。
+    ///
+    pub(crate) fn admit_source(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+
+        let Ok(policy) = self.policy.read() else {
+            return false;
+        };
+
+        if !policy.permits(&addr) {
+            debug!("source denied: {}", addr);
+            self.stats.count("denied");
+            return false;
+        }
+
+        let Some(rate) = &policy.source else {
+            return true;
+        };
+
+        let admitted = match self.sources.lock() {
+            Ok(mut sources) => sources.take(addr, rate),
+            Err(_) => false,
+        };
+
+        if !admitted {
+            debug!("source rate limited: {}", addr);
+            self.stats.count("source_limited");
+        }
+
+        admitted
+    }
+
+    ///
+    /// デバイスによる受信の可否の判定
+    ///
+    /// # 引数
+    /// * `record` - 受信したレコード
+    ///
+    /// # 戻り値
+    /// 受信を許可する場合は`true`を返す。デバイスIDを持たないレコードは流量
+    /// を制限しない。
+    ///
+    pub(crate) fn admit_device(&self, record: &SensorRecord) -> bool {
+        let Some(device_id) = record.device_id() else {
+            return true;
+        };
+
+        let Ok(policy) = self.policy.read() else {
+            return false;
+        };
+
+        let Some(rate) = &policy.device else {
+            return true;
+        };
+
+        let admitted = match self.devices.lock() {
+            Ok(mut devices) => devices.take(device_id.clone(), rate),
+
+            Err(_) => false,
+        };
+
+        if !admitted {
+            debug!("device rate limited: {}", device_id);
+            self.stats.count("device_limited");
+        }
+
+        admitted
+    }
+}
+
+///
+/// 待ち受け毎の受信の可否の判定をまとめた構造体
+///
+pub(crate) struct AccessControls {
+    /// TCPの待ち受け
+    tcp: Arc<AccessControl>,
+
+    /// UDPの待ち受け
+    udp: Arc<AccessControl>,
+
+    /// CoAPの待ち受け
+    coap: Arc<AccessControl>,
+
+    /// ラインプロトコルの待ち受け
+    influx: Arc<AccessControl>,
+
+    /// 書き込みAPIの待ち受け
+    influx_http: Arc<AccessControl>,
+
+    /// MQTTによる受信
+    mqtt: Arc<AccessControl>,
+}
+
+impl AccessControls {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `policies` - 展開済みの待ち受け毎の受信の制限の設定
+    ///
+    pub(crate) fn new(policies: AccessPolicies) -> Self {
+        Self {
+            tcp: AccessControl::new("tcp", policies.tcp),
+            udp: AccessControl::new("udp", policies.udp),
+            coap: AccessControl::new("coap", policies.coap),
+            influx: AccessControl::new("influx", policies.influx),
+            influx_http: AccessControl::new(
+                "influx_http",
+                policies.influx_http
+            ),
+            mqtt: AccessControl::new("mqtt", policies.mqtt),
+        }
+    }
+
+    ///
+    /// 設定の入れ替え
+    ///
+    /// # 引数
+    /// * `policies` - 展開済みの新しい待ち受け毎の受信の制限の設定
+    ///
+    pub(crate) fn update(&self, policies: AccessPolicies) {
+        self.tcp.update(policies.tcp);
+        self.udp.update(policies.udp);
+        self.coap.update(policies.coap);
+        self.influx.update(policies.influx);
+        self.influx_http.update(policies.influx_http);
+        self.mqtt.update(policies.mqtt);
+    }
+
+    ///
+    /// 受信を拒否した件数の集計のリストの取得
+    ///
+    pub(crate) fn stats(&self) -> Vec<Arc<ReceiverStats>> {
+        vec![
+            self.tcp.stats(),
+            self.udp.stats(),
+            self.coap.stats(),
+            self.influx.stats(),
+            self.influx_http.stats(),
+            self.mqtt.stats(),
+        ]
+    }
+
+    ///
+    /// TCPの待ち受けへのアクセサ
+    ///
+    pub(crate) fn tcp(&self) -> Arc<AccessControl> {
+        self.tcp.clone()
+    }
+
+    ///
+    /// UDPの待ち受けへのアクセサ
+    ///
+    pub(crate) fn udp(&self) -> Arc<AccessControl> {
+        self.udp.clone()
+    }
+
+    ///
+    /// CoAPの待ち受けへのアクセサ
+    ///
+    pub(crate) fn coap(&self) -> Arc<AccessControl> {
+        self.coap.clone()
+    }
+
+    ///
+    /// ラインプロトコルの待ち受けへのアクセサ
+    ///
+    pub(crate) fn influx(&self) -> Arc<AccessControl> {
+        self.influx.clone()
+    }
+
+    ///
+    /// 書き込みAPIの待ち受けへのアクセサ
+    ///
+    pub(crate) fn influx_http(&self) -> Arc<AccessControl> {
+        self.influx_http.clone()
+    }
+
+    ///
+    /// MQTTによる受信へのアクセサ
+    ///
+    pub(crate) fn mqtt(&self) -> Arc<AccessControl> {
+        self.mqtt.clone()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn unrestricted() -> Arc<AccessControl> {
+        let policy = AccessPolicy::new(&AccessConfig::default()).unwrap();
+        AccessControl::new("test", policy)
+    }
+
+    #[test]
+    fn limit_rate_per_key() {
+        let rate = Rate::new("source", Some(1.0), Some(2)).unwrap().unwrap();
+        let mut buckets = Buckets::new();
+
+        assert!(buckets.take(1, &rate));
+        assert!(buckets.take(1, &rate));
+        assert!(!buckets.take(1, &rate));
+        assert!(buckets.take(2, &rate));
+    }
+
+    #[test]
+    fn share_overflow_bucket_at_cap() {
+        let rate = Rate::new("source", Some(0.001), Some(1)).unwrap().unwrap();
+        let mut buckets = Buckets::new();
+
+        for key in 0..MAX_BUCKETS {
+            assert!(buckets.take(key, &rate));
+        }
+
+        // 上限を超えたキーは共用のバケットで制限する
+        assert!(buckets.take(MAX_BUCKETS, &rate));
+        assert!(!buckets.take(MAX_BUCKETS + 1, &rate));
+        assert_eq!(buckets.map.len(), MAX_BUCKETS);
+
+        // 既存のキーは引き続き自身のバケットを使う
+        assert!(!buckets.take(0, &rate));
+    }
+
+    #[test]
+    fn evict_idle_buckets_at_cap() {
+        let rate = Rate::new("source", Some(1000.0), Some(1)).unwrap().unwrap();
+        let mut buckets = Buckets::new();
+
+        for key in 0..MAX_BUCKETS {
+            assert!(buckets.take(key, &rate));
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+
+        assert!(buckets.take(MAX_BUCKETS, &rate));
+        assert!(buckets.overflow.is_none());
+        assert_eq!(buckets.map.len(), 1);
+    }
+
+    #[test]
+    fn limit_only_devices_for_mqtt() {
+        let parse = |src: &str| {
+            let section: AccessSection = toml::from_str(src).unwrap();
+            AccessPolicies::new(&section)
+        };
+
+        assert!(parse("[mqtt]\ndevice_rate = 1.0\n").is_ok());
+        assert!(parse("[mqtt]\nsource_rate = 1.0\n").is_err());
+        assert!(parse("[mqtt]\ndeny = [\"127.0.0.1\"]\n").is_err());
+        assert!(parse("[coap]\nsource_rate = 1.0\n").is_ok());
+    }
+}
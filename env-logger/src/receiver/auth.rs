@@ -0,0 +1,1045 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 受信レコードの認証処理をまとめたモジュール
+//!
+//! デバイス毎に共有鍵を設定すると、そのデバイスからのレコードは以下の形式の
+//! 署名付きエンベロープで送る必要がある(1行のJSON)。
+//!
+//! ```text
+//! {"device_id":"ID", "signed_at":MS, "nonce":"N",
+//!  "payload":"JSON", "signature":"HEX"}
+//! ```
+//!
+//! * `signed_at` - 署名を行った時刻(ミリ秒単位のUNIX時刻)
+//! * `nonce` - 署名毎に異なる任意の文字列(64文字以内)
+//! * `payload` - レコードのJSONを文字列としたもの
+//! * `signature` - "device_id\nsigned_at\nnonce\npayload"に対する
+//!   HMAC-SHA256の値(16進文字列)
+//!
+//! 検証に成功したレコードは`payload`に含まれるタイムスタンプをそのまま採用
+//! する(それ以外のレコードのタイムスタンプは受信時刻の前後に丸める)。
+//!
+//! 署名の検証に失敗したレコード、署名時刻が許容範囲外のレコード、使用済み
+//! のnonceを持つレコード(再送攻撃)、共有鍵を設定したデバイスからの署名の無
+//! いレコードは、品質フラグを付けて隔離する。
+//!
+//! JSONを受け付ける待ち受け(TCP、UDP、CoAP、MQTT)は署名付きエンベロープを
+//! 受け付ける。エンベロープを運べない形式(CoAPのCBORとラインプロトコル)
+//! では、共有鍵を設定したデバイスからのレコードを隔離し、全てのデバイスに
+//! 署名を要求する設定(`require`)の場合はレコードを受け付けない。
+//!
+//! 中継元のenv-loggerは、中継元毎の共有鍵で署名した以下の形式のエンベロー
+//! プでレコードを転送する。
+//!
+//! ```text
+//! {"relay":"NAME", "signed_at":MS, "nonce":"N",
+//!  "payload":"JSON", "signature":"HEX"}
+//! ```
+//!
+//! 署名の対象は"relay\nNAME\nsigned_at\nnonce\npayload"で、`payload`は
+//! `SensorRecord::to_relay_json()`の形式となる。中継元で認証と較正を済ませ
+//! ているので、検証に成功したレコードにはデバイス毎の認証を行わず、中継済
+//! みのレコードとして扱う(上流では較正と処理ステージを適用しない)。
+//!
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::ReceiverStats;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 署名時刻の許容範囲のデフォルト値(秒)
+const DEFAULT_REPLAY_WINDOW: u64 = 300;
+
+/// nonceの最大長
+const MAX_NONCE_LENGTH: usize = 64;
+
+///
+/// 認証の設定を表す構造体
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AuthConfig {
+    /// 共有鍵を設定していないデバイスにも署名を要求するか否か
+    #[serde(default)]
+    require: bool,
+
+    /// 署名時刻と受信時刻の差の許容範囲(秒)
+    #[serde(default = "default_replay_window")]
+    replay_window: u64,
+
+    /// デバイス毎の共有鍵
+    #[serde(default, rename = "key")]
+    keys: Vec<KeyConfig>,
+
+    /// 中継元毎の共有鍵
+    #[serde(default, rename = "relay")]
+    relays: Vec<RelayConfig>,
+}
+
+// Defaultトレイトの実装
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            require: false,
+            replay_window: DEFAULT_REPLAY_WINDOW,
+            keys: vec![],
+            relays: vec![],
+        }
+    }
+}
+
+///
+/// 署名時刻の許容範囲のデフォルト値の取得(serde用)
+///
+fn default_replay_window() -> u64 {
+    DEFAULT_REPLAY_WINDOW
+}
+
+///
+/// デバイス毎の共有鍵の定義を表す構造体
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyConfig {
+    /// デバイスID
+    device_id: String,
+
+    /// 共有鍵
+    secret: String,
+}
+
+///
+/// 中継元毎の共有鍵の定義を表す構造体
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RelayConfig {
+    /// 中継元の名前
+    name: String,
+
+    /// 共有鍵
+    secret: String,
+}
+
+///
+/// 署名者を表す列挙子(使用済みのnonceの管理に用いる)
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Signer {
+    /// デバイス(デバイスID)
+    Device(String),
+
+    /// 中継元(中継元の名前)
+    Relay(String),
+}
+
+///
+/// 検証に必要なエンベロープの内容をまとめた構造体
+///
+struct Seal<'a> {
+    /// 署名者
+    signer: Signer,
+
+    /// 署名を行った時刻(ミリ秒単位のUNIX時刻)
+    signed_at: u64,
+
+    /// 署名毎に異なる文字列
+    nonce: &'a str,
+
+    /// 署名(16進文字列)
+    signature: &'a str,
+
+    /// 署名対象のデータ
+    message: String,
+}
+
+///
+/// 署名付きエンベロープを表す構造体
+///
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    /// デバイスID
+    device_id: String,
+
+    /// 署名を行った時刻(ミリ秒単位のUNIX時刻)
+    signed_at: u64,
+
+    /// 署名毎に異なる文字列
+    nonce: String,
+
+    /// レコードのJSON
+    payload: String,
+
+    /// 署名(16進文字列)
+    signature: String,
+}
+
+impl Envelope {
+    ///
+    /// 検証に必要な内容の取り出し
+    ///
+    fn seal(&self) -> Seal<'_> {
+        Seal {
+            signer: Signer::Device(self.device_id.clone()),
+            signed_at: self.signed_at,
+            nonce: &self.nonce,
+            signature: &self.signature,
+            message: format!(
+                "{}\n{}\n{}\n{}",
+                self.device_id,
+                self.signed_at,
+                self.nonce,
+                self.payload
+            ),
+        }
+    }
+}
+
+///
+/// 中継元の署名付きエンベロープを表す構造体
+///
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RelayEnvelope {
+    /// 中継元の名前
+    relay: String,
+
+    /// 署名を行った時刻(ミリ秒単位のUNIX時刻)
+    signed_at: u64,
+
+    /// 署名毎に異なる文字列
+    nonce: String,
+
+    /// 中継するレコードのJSON(`SensorRecord::to_relay_json()`の形式)
+    payload: String,
+
+    /// 署名(16進文字列)
+    signature: String,
+}
+
+impl RelayEnvelope {
+    ///
+    /// 検証に必要な内容の取り出し
+    ///
+    fn seal(&self) -> Seal<'_> {
+        Seal {
+            signer: Signer::Relay(self.relay.clone()),
+            signed_at: self.signed_at,
+            nonce: &self.nonce,
+            signature: &self.signature,
+            message: format!(
+                "relay\n{}\n{}\n{}\n{}",
+                self.relay,
+                self.signed_at,
+                self.nonce,
+                self.payload
+            ),
+        }
+    }
+}
+
+///
+/// 認証の設定を展開した構造体
+///
+/// # 注記
+/// 設定の再読み込み時は、他の設定と共に全ての検証を終えてから
+/// `Authenticator::update()`で入れ替える。
+///
+pub(crate) struct AuthPolicy {
+    /// 共有鍵を設定していないデバイスにも署名を要求するか否か
+    require: bool,
+
+    /// 署名時刻と受信時刻の差の許容範囲(ミリ秒)
+    window: u64,
+
+    /// デバイスID毎の検証用の鍵
+    keys: HashMap<String, hmac::Key>,
+
+    /// 中継元の名前毎の検証用の鍵
+    relays: HashMap<String, hmac::Key>,
+}
+
+impl AuthPolicy {
+    ///
+    /// 設定の展開
+    ///
+    /// # 引数
+    /// * `config` - 認証の設定
+    ///
+    /// # 戻り値
+    /// 展開に成功した場合はオブジェクトを`Ok()`でラップして返す。設定に不備
+    /// があった場合はエラー情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn new(config: &AuthConfig) -> Result<Self> {
+        if config.replay_window == 0 {
+            return Err(anyhow!("auth: replay_window must be positive"));
+        }
+
+        let mut keys = HashMap::new();
+
+        for key in &config.keys {
+            if key.secret.is_empty() {
+                return Err(anyhow!("auth: empty secret for {}", key.device_id));
+            }
+
+            let secret = key.secret.as_bytes();
+            let value = hmac::Key::new(hmac::HMAC_SHA256, secret);
+
+            if keys.insert(key.device_id.clone(), value).is_some() {
+                return Err(anyhow!("auth: duplicate key: {}", key.device_id));
+            }
+        }
+
+        let mut relays = HashMap::new();
+
+        for relay in &config.relays {
+            if relay.secret.is_empty() {
+                return Err(anyhow!("auth: empty secret for {}", relay.name));
+            }
+
+            let secret = relay.secret.as_bytes();
+            let value = hmac::Key::new(hmac::HMAC_SHA256, secret);
+
+            if relays.insert(relay.name.clone(), value).is_some() {
+                return Err(anyhow!("auth: duplicate relay: {}", relay.name));
+            }
+        }
+
+        Ok(Self {
+            require: config.require,
+            window: config.replay_window * 1000,
+            keys,
+            relays,
+        })
+    }
+}
+
+///
+/// 受信レコードの認証を行う構造体
+///
+/// # 注記
+/// 全ての待ち受けの受信処理で共有し、設定の再読み込み時には`update()`で設
+/// 定を入れ替える。使用済みのnonceは許容範囲の間だけ保持する。
+///
+pub(crate) struct Authenticator {
+    /// 認証の設定
+    policy: RwLock<AuthPolicy>,
+
+    /// 使用済みのnonce((署名者, nonce)と署名時刻の組)
+    nonces: Mutex<HashMap<(Signer, String), u64>>,
+
+    /// 認証結果の集計
+    stats: Arc<ReceiverStats>,
+}
+
+impl Authenticator {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `policy` - 展開済みの認証の設定
+    ///
+    pub(crate) fn new(policy: AuthPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            policy: RwLock::new(policy),
+            nonces: Mutex::new(HashMap::new()),
+            stats: ReceiverStats::new("auth"),
+        })
+    }
+
+    ///
+    /// 設定の入れ替え
+    ///
+    /// # 引数
+    /// * `policy` - 展開済みの新しい認証の設定
+    ///
+    pub(crate) fn update(&self, policy: AuthPolicy) {
+        match self.policy.write() {
+            Ok(mut current) => *current = policy,
+            Err(err) => *err.into_inner() = policy,
+        }
+    }
+
+    ///
+    /// 認証結果の集計へのアクセサ
+    ///
+    pub(crate) fn stats(&self) -> Arc<ReceiverStats> {
+        self.stats.clone()
+    }
+
+    ///
+    /// 受信データからのレコードの生成
+    ///
+    /// # 引数
+    /// * `data` - 受信したJSON文字列(署名付きエンベロープまたはレコード)
+    ///
+    /// # 戻り値
+    /// レコードを`Ok()`でラップして返す。認証に失敗したレコードは隔離対象と
+    /// して返す。JSONとして解釈できない場合はエラー情報を`Err()`でラップして
+    /// 返す。
+    ///
+    /// # 注記
+    /// 中継元のエンベロープも受け付ける。
+    ///
+    pub(crate) fn open(&self, data: &str) -> Result<SensorRecord> {
+        self.unseal(data, None, None)
+    }
+
+    ///
+    /// 補完情報を伴う受信データからのレコードの生成
+    ///
+    /// # 引数
+    /// * `data` - 受信したJSON文字列(署名付きエンベロープまたはレコード)
+    /// * `location` - レコードに設置場所が含まれていない場合に補う設置場所
+    /// * `device_id` - レコードにデバイスIDが含まれていない場合に補うデバイ
+    ///   スID
+    ///
+    /// # 戻り値
+    /// `open()`と同じ。
+    ///
+    /// # 注記
+    /// MQTTのトピック名から取り出した情報の補完に用いる。デバイスIDの補完は
+    /// 署名の無いレコードのみに行う(署名付きエンベロープのデバイスIDを優先す
+    /// る)ので、補完したデバイスIDに共有鍵が設定されていればそのレコードは
+    /// 隔離される。
+    ///
+    pub(crate) fn open_with_hint(
+        &self,
+        data: &str,
+        location: Option<&str>,
+        device_id: Option<&str>,
+    ) -> Result<SensorRecord>
+    {
+        self.unseal(data, location, device_id)
+    }
+
+    ///
+    /// 署名を運べない形式で受信したレコードの認証
+    ///
+    /// # 引数
+    /// * `record` - 受信したレコード
+    ///
+    /// # 戻り値
+    /// レコードを受け付ける場合は`Ok(())`を返す(共有鍵を設定したデバイスか
+    /// らのレコードは隔離対象とする)。全てのデバイスに署名を要求する設定の
+    /// 場合はエラー情報を`Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// CoAPのCBORペイロードとラインプロトコルで受信したレコードに用いる。
+    ///
+    pub(crate) fn check_unsigned(&self, record: &mut SensorRecord)
+        -> Result<()>
+    {
+        let Ok(policy) = self.policy.read() else {
+            return Err(anyhow!("auth: policy lock poisoned"));
+        };
+
+        if policy.require {
+            warn!("authentication failed (unsigned): {}", record);
+            self.stats.count("unsigned");
+            return Err(anyhow!("signature required"));
+        }
+
+        let has_key = record.device_id()
+            .is_some_and(|id| policy.keys.contains_key(&id));
+
+        if has_key {
+            self.reject(record, "unsigned");
+        } else {
+            self.stats.count("plain");
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// 全てのデバイスに署名を要求する設定か否か
+    ///
+    /// # 注記
+    /// 署名を運べない形式の待ち受けで、リクエストを処理する前に拒否するため
+    /// に用いる。
+    ///
+    pub(crate) fn requires_signature(&self) -> bool {
+        self.policy.read().map_or(true, |policy| policy.require)
+    }
+
+    ///
+    /// 受信データからのレコードの生成(`open()`と`open_with_hint()`の本体)
+    ///
+    fn unseal(
+        &self,
+        data: &str,
+        location: Option<&str>,
+        device_id: Option<&str>,
+    ) -> Result<SensorRecord>
+    {
+        let value = match serde_json::from_str::<Value>(data) {
+            Ok(value) => value,
+            Err(err) => return Err(anyhow!("{}", err)),
+        };
+
+        let (signed, relayed) = match value.as_object() {
+            Some(map) => {
+                (map.contains_key("signature"), map.contains_key("relay"))
+            }
+
+            None => (false, false),
+        };
+
+        let Ok(policy) = self.policy.read() else {
+            return Err(anyhow!("auth: policy lock poisoned"));
+        };
+
+        /*
+         * 中継元のエンベロープ
+         */
+        if relayed {
+            return self.unseal_relay(&policy, value);
+        }
+
+        /*
+         * 署名の無いレコード
+         */
+        if !signed {
+            let mut record =
+                SensorRecord::from_json_with_hint(data, location, device_id)?;
+
+            let has_key = record.device_id()
+                .is_some_and(|id| policy.keys.contains_key(&id));
+
+            if has_key || policy.require {
+                self.reject(&mut record, "unsigned");
+            } else {
+                self.stats.count("plain");
+            }
+
+            return Ok(record);
+        }
+
+        /*
+         * 署名付きエンベロープ
+         */
+        let envelope = match serde_json::from_value::<Envelope>(value) {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                self.stats.count("malformed");
+                return Err(anyhow!("invalid envelope: {}", err));
+            }
+        };
+
+        let key = policy.keys.get(&envelope.device_id);
+        let verified = self.verify(key, policy.window, envelope.seal());
+
+        let payload = if verified.is_ok() {
+            SensorRecord::from_trusted_json(&envelope.payload)
+        } else {
+            SensorRecord::from_json(&envelope.payload)
+        };
+
+        let mut record = match payload {
+            Ok(record) => record,
+            Err(err) => {
+                self.stats.count("malformed");
+                return Err(anyhow!("invalid payload: {}", err));
+            }
+        };
+
+        if let Some(location) = location {
+            if record.location().is_empty() {
+                record.set_location(location.to_string());
+            }
+        }
+
+        match verified {
+            Ok(()) => match record.device_id() {
+                Some(id) if id != envelope.device_id => {
+                    self.reject(&mut record, "device_mismatch");
+                }
+
+                _ => {
+                    record.set_device_id(envelope.device_id);
+                    self.stats.count("verified");
+                }
+            },
+
+            Err(reason) => {
+                if record.device_id().is_none() {
+                    record.set_device_id(envelope.device_id);
+                }
+
+                self.reject(&mut record, reason);
+            }
+        }
+
+        Ok(record)
+    }
+
+    ///
+    /// 中継元のエンベロープからのレコードの生成
+    ///
+    /// # 注記
+    /// 検証に成功したレコードは中継済みのレコードとし、タイムスタンプをその
+    /// まま採用する。検証に失敗したレコードは隔離対象とする。
+    ///
+    fn unseal_relay(&self, policy: &AuthPolicy, value: Value)
+        -> Result<SensorRecord>
+    {
+        let envelope = match serde_json::from_value::<RelayEnvelope>(value) {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                self.stats.count("malformed");
+                return Err(anyhow!("invalid relay envelope: {}", err));
+            }
+        };
+
+        let key = policy.relays.get(&envelope.relay);
+        let verified = self.verify(key, policy.window, envelope.seal());
+
+        let payload = SensorRecord::from_relay_json(
+            &envelope.payload,
+            verified.is_ok()
+        );
+
+        let mut record = match payload {
+            Ok(record) => record,
+            Err(err) => {
+                self.stats.count("malformed");
+                return Err(anyhow!("invalid payload: {}", err));
+            }
+        };
+
+        match verified {
+            Ok(()) => {
+                record.mark_relayed();
+                self.stats.count("relayed");
+            }
+
+            Err(reason) => {
+                warn!("relay authentication failed: {}", envelope.relay);
+                self.reject(&mut record, reason);
+            }
+        }
+
+        Ok(record)
+    }
+
+    ///
+    /// 署名付きエンベロープの検証
+    ///
+    /// # 引数
+    /// * `key` - 署名者の検証用の鍵(鍵が設定されていない場合はNone)
+    /// * `window` - 署名時刻と受信時刻の差の許容範囲(ミリ秒)
+    /// * `seal` - 検証に必要なエンベロープの内容
+    ///
+    /// # 戻り値
+    /// 検証に成功した場合は`Ok(())`を返す。失敗した場合は理由を`Err()`でラッ
+    /// プして返す。
+    ///
+    fn verify(&self, key: Option<&hmac::Key>, window: u64, seal: Seal<'_>)
+        -> std::result::Result<(), &'static str>
+    {
+        let Some(key) = key else {
+            return Err("unknown_key");
+        };
+
+        let Ok(signature) = hex::decode(seal.signature) else {
+            return Err("signature");
+        };
+
+        if hmac::verify(key, seal.message.as_bytes(), &signature).is_err() {
+            return Err("signature");
+        }
+
+        let now = Utc::now().timestamp_millis() as u64;
+
+        if now.abs_diff(seal.signed_at) > window {
+            return Err("expired");
+        }
+
+        let nonce_len = seal.nonce.len();
+
+        if nonce_len == 0 || nonce_len > MAX_NONCE_LENGTH {
+            return Err("nonce");
+        }
+
+        /*
+         * 使用済みのnonceとの照合(許容範囲外となったものは破棄する)
+         */
+        let Ok(mut nonces) = self.nonces.lock() else {
+            return Err("nonce");
+        };
+
+        let since = now.saturating_sub(window);
+        nonces.retain(|_, signed_at| *signed_at >= since);
+
+        let entry = (seal.signer, seal.nonce.to_string());
+
+        if nonces.contains_key(&entry) {
+            return Err("replay");
+        }
+
+        nonces.insert(entry, seal.signed_at);
+
+        Ok(())
+    }
+
+    ///
+    /// 認証に失敗したレコードの隔離
+    ///
+    fn reject(&self, record: &mut SensorRecord, reason: &'static str) {
+        warn!("authentication failed ({}): {}", reason, record);
+
+        record.add_quality_flag(format!(
+            "auth:{}:{}",
+            reason,
+            record.device_id().unwrap_or_default()
+        ));
+
+        record.quarantine_unauthenticated();
+        self.stats.count(reason);
+    }
+}
+
+///
+/// 中継元として上流へ転送するレコードの署名を行う構造体
+///
+pub(crate) struct RelaySigner {
+    /// 中継元の名前
+    name: String,
+
+    /// 署名用の鍵
+    key: hmac::Key,
+
+    /// nonceの生成に用いる乱数生成器
+    rng: SystemRandom,
+}
+
+impl RelaySigner {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `name` - 中継元の名前(上流の設定の`[[auth.relay]]`の`name`)
+    /// * `secret` - 共有鍵
+    ///
+    pub(crate) fn new(name: &str, secret: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    ///
+    /// 中継元のエンベロープの生成
+    ///
+    /// # 引数
+    /// * `payload` - 転送するレコードのJSON(`to_relay_json()`の形式)
+    ///
+    /// # 戻り値
+    /// エンベロープのJSON文字列(改行を含まない)を`Ok()`でラップして返す。
+    /// nonceの生成に失敗した場合はエラー情報を`Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// 署名時刻は呼び出した時点の時刻とする。再送時は改めて署名すること。
+    ///
+    pub(crate) fn seal(&self, payload: String) -> Result<String> {
+        let mut nonce = [0u8; 16];
+
+        if self.rng.fill(&mut nonce).is_err() {
+            return Err(anyhow!("nonce generation failed"));
+        }
+
+        let mut envelope = RelayEnvelope {
+            relay: self.name.clone(),
+            signed_at: Utc::now().timestamp_millis() as u64,
+            nonce: hex::encode(nonce),
+            payload,
+            signature: String::new(),
+        };
+
+        let message = envelope.seal().message;
+        let signature = hmac::sign(&self.key, message.as_bytes());
+        envelope.signature = hex::encode(signature);
+
+        Ok(serde_json::to_string(&envelope)?)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::record::RawValues;
+    use crate::telemetry::Telemetry;
+
+    const CONFIG: &str = r#"
+        [[key]]
+        device_id = "dev1"
+        secret = "secret"
+
+        [[relay]]
+        name = "cabin"
+        secret = "relay-secret"
+    "#;
+
+    pub(crate) fn authenticator() -> Arc<Authenticator> {
+        let config: AuthConfig = toml::from_str(CONFIG).unwrap();
+        Authenticator::new(AuthPolicy::new(&config).unwrap())
+    }
+
+    pub(crate) fn now() -> u64 {
+        Utc::now().timestamp_millis() as u64
+    }
+
+    pub(crate) fn envelope(
+        device_id: &str,
+        signed_at: u64,
+        nonce: &str,
+        payload: &str,
+        secret: &str,
+    ) -> String {
+        let message = format!(
+            "{}\n{}\n{}\n{}",
+            device_id,
+            signed_at,
+            nonce,
+            payload
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let signature = hex::encode(hmac::sign(&key, message.as_bytes()));
+
+        json!({
+            "device_id": device_id,
+            "signed_at": signed_at,
+            "nonce": nonce,
+            "payload": payload,
+            "signature": signature,
+        }).to_string()
+    }
+
+    pub(crate) fn require_signature(auth: &Authenticator) {
+        let config = format!("require = true\n{}", CONFIG);
+        let config: AuthConfig = toml::from_str(&config).unwrap();
+        auth.update(AuthPolicy::new(&config).unwrap());
+    }
+
+    fn relayed_record() -> SensorRecord {
+        let mut record = SensorRecord::new(
+            "cabin".into(),
+            Some("dev1".into()),
+            1000,
+            Some(20.5),
+            None,
+            None
+        );
+
+        record.set_raw(RawValues {
+            temperature: Some(20.0),
+            ..Default::default()
+        });
+        record.add_quality_flag("humidity:missing:".into());
+        record.set_telemetry(Telemetry {
+            battery: Some(80.0),
+            ..Default::default()
+        });
+
+        record
+    }
+
+    pub(crate) fn assert_rejected(record: &SensorRecord, flag: &str) {
+        assert!(record.is_quarantined());
+        assert!(record.is_unauthenticated());
+        assert_eq!(record.quality().as_deref(), Some(flag));
+    }
+
+    #[test]
+    fn accept_valid_envelope() {
+        let auth = authenticator();
+        let payload = r#"{"location":"room","timestamp":1000}"#;
+        let data = envelope("dev1", now(), "n1", payload, "secret");
+
+        let record = auth.open(&data).unwrap();
+
+        assert!(!record.is_quarantined());
+        assert_eq!(record.quality(), None);
+        assert_eq!(record.device_id().as_deref(), Some("dev1"));
+        assert_eq!(record.timestamp(), 1000);
+    }
+
+    #[test]
+    fn reject_bad_signature() {
+        let auth = authenticator();
+        let payload = r#"{"location":"room","temperature":20}"#;
+        let data = envelope("dev1", now(), "n1", payload, "wrong");
+
+        let record = auth.open(&data).unwrap();
+        assert_rejected(&record, "auth:signature:dev1");
+    }
+
+    #[test]
+    fn accept_relayed_record() {
+        let auth = authenticator();
+        require_signature(&auth);
+
+        let signer = RelaySigner::new("cabin", "relay-secret");
+        let data = signer.seal(relayed_record().to_relay_json()).unwrap();
+
+        // 中継元で認証済みなのでデバイス毎の認証は行わない
+        let mut record = auth.open(&data).unwrap();
+        assert!(record.is_relayed());
+        assert!(!record.is_quarantined());
+        assert_eq!(record.timestamp(), 1000);
+        assert_eq!(record.temperature(), Some(20.5));
+        assert_eq!(record.raw().temperature, Some(20.0));
+        assert_eq!(record.quality().as_deref(), Some("humidity:missing:"));
+
+        record.split_telemetry();
+        assert_eq!(record.telemetry().battery, Some(80.0));
+
+        // 同じエンベロープの再送は隔離する
+        let record = auth.open(&data).unwrap();
+        assert!(!record.is_relayed());
+        assert!(record.is_quarantined());
+        assert!(record.is_unauthenticated());
+    }
+
+    #[test]
+    fn reject_unknown_relay() {
+        let auth = authenticator();
+        let payload = relayed_record().to_relay_json();
+
+        for signer in [
+            RelaySigner::new("cabin", "wrong"),
+            RelaySigner::new("barn", "relay-secret"),
+        ] {
+            let record = auth.open(&signer.seal(payload.clone()).unwrap())
+                .unwrap();
+            assert!(!record.is_relayed());
+            assert!(record.is_quarantined());
+            assert!(record.is_unauthenticated());
+            assert_ne!(record.timestamp(), 1000);
+        }
+    }
+
+    #[test]
+    fn reject_expired_envelope() {
+        let auth = authenticator();
+        let payload = r#"{"location":"room","temperature":20}"#;
+        let window = DEFAULT_REPLAY_WINDOW * 1000;
+
+        let signed_at = now() - window - 1000;
+        let data = envelope("dev1", signed_at, "n1", payload, "secret");
+        let record = auth.open(&data).unwrap();
+        assert_rejected(&record, "auth:expired:dev1");
+
+        let signed_at = now() + window + 1000;
+        let data = envelope("dev1", signed_at, "n2", payload, "secret");
+        let record = auth.open(&data).unwrap();
+        assert_rejected(&record, "auth:expired:dev1");
+    }
+
+    #[test]
+    fn reject_replayed_nonce() {
+        let auth = authenticator();
+        let payload = r#"{"location":"room","temperature":20}"#;
+        let data = envelope("dev1", now(), "n1", payload, "secret");
+
+        let record = auth.open(&data).unwrap();
+        assert!(!record.is_quarantined());
+
+        let record = auth.open(&data).unwrap();
+        assert_rejected(&record, "auth:replay:dev1");
+    }
+
+    #[test]
+    fn reject_device_mismatch() {
+        let auth = authenticator();
+        let payload = r#"{"location":"room","device_id":"dev2"}"#;
+        let data = envelope("dev1", now(), "n1", payload, "secret");
+
+        let record = auth.open(&data).unwrap();
+        assert_rejected(&record, "auth:device_mismatch:dev2");
+    }
+
+    #[test]
+    fn quarantine_unsigned_record() {
+        let auth = authenticator();
+
+        let data = r#"{"location":"room","device_id":"dev1","temperature":20}"#;
+        let record = auth.open(data).unwrap();
+        assert_rejected(&record, "auth:unsigned:dev1");
+
+        // 共有鍵を設定していないデバイスは署名が無くても受け付ける
+        let data = r#"{"location":"room","device_id":"dev3","temperature":20}"#;
+        let record = auth.open(data).unwrap();
+        assert!(!record.is_quarantined());
+    }
+
+    #[test]
+    fn apply_hint_before_key_check() {
+        let auth = authenticator();
+
+        // 補完したデバイスIDに共有鍵が設定されていれば隔離する
+        let data = r#"{"temperature":20}"#;
+        let record = auth.open_with_hint(data, Some("room"), Some("dev1"))
+            .unwrap();
+        assert_rejected(&record, "auth:unsigned:dev1");
+        assert_eq!(record.location(), "room");
+
+        // 署名付きエンベロープのデバイスIDは補完で上書きしない
+        let payload = r#"{"temperature":20}"#;
+        let data = envelope("dev1", now(), "n1", payload, "secret");
+        let record = auth.open_with_hint(&data, Some("room"), Some("dev2"))
+            .unwrap();
+        assert!(!record.is_quarantined());
+        assert_eq!(record.location(), "room");
+        assert_eq!(record.device_id().as_deref(), Some("dev1"));
+    }
+
+    #[test]
+    fn check_record_without_envelope() {
+        let auth = authenticator();
+        let record = |device_id: &str| {
+            SensorRecord::new(
+                "room".into(),
+                Some(device_id.into()),
+                now(),
+                Some(20.0),
+                None,
+                None
+            )
+        };
+
+        let mut keyed = record("dev1");
+        assert!(auth.check_unsigned(&mut keyed).is_ok());
+        assert_rejected(&keyed, "auth:unsigned:dev1");
+
+        let mut plain = record("dev3");
+        assert!(auth.check_unsigned(&mut plain).is_ok());
+        assert!(!plain.is_quarantined());
+
+        // 全てのデバイスに署名を要求する場合は受け付けない
+        require_signature(&auth);
+
+        assert!(auth.requires_signature());
+        assert!(auth.check_unsigned(&mut record("dev3")).is_err());
+    }
+}
@@ -0,0 +1,845 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! CoAP受信処理をまとめたモジュール
+//!
+//! `/records`へのPOSTリクエストのみを受け付け、ペイロード(JSONまたはCBOR)を
+//! 受信レコードとしてパイプラインに投入する。Confirmableメッセージに対する
+//! ACKは、パイプラインへの投入が完了した後にピギーバックレスポンスとして返
+//! す。
+//!
+//! 受信したメッセージは固定数のワーカー(`--udp-workers`で指定した数)で処理
+//! する。ワーカーのキューが満杯の場合は応答せずに破棄する(Confirmableメッ
+//! セージはクライアントが再送する)。
+//!
+//! 設定ファイルの`[access.coap]`で受信を許可しない送信元からのメッセージは
+//! 応答せずに破棄し、デバイスの流量の制限を超えたリクエストには4.29(Too
+//! Many Requests)を返す。
+//!
+//! JSONのペイロードは署名付きエンベロープも受け付ける。CBORのペイロードは
+//! 署名を運べないので、全てのデバイスに署名を要求する設定の場合は4.01
+//! (Unauthorized)を返す。
+//!
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use coap_lite::{
+    CoapOption, CoapResponse, ContentFormat, MessageClass, MessageType, Packet,
+    RequestType, ResponseType,
+};
+use rhexdump::rhexdumps;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+
+use super::access::AccessControl;
+use super::auth::Authenticator;
+use super::pool::WorkerPool;
+use crate::record::SensorRecord;
+use crate::cmd_args::Options;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 受け付けるリソースのパス
+const RECORDS_PATH: &str = "records";
+
+/// 受信バッファのサイズ
+const BUFFER_SIZE: usize = 1280;
+
+/// ワーカーのキューの長さ
+const QUEUE_SIZE: usize = 64;
+
+/// 重複検出のためにメッセージIDを保持する時間(秒)
+///
+/// # 注記
+/// RFC 7252で定義されているEXCHANGE_LIFETIMEのデフォルト値。
+const EXCHANGE_LIFETIME: u64 = 247;
+
+/// 重複検出のために保持するメッセージ交換の上限数
+const MAX_EXCHANGES: usize = 4096;
+
+/// 期限切れのメッセージ交換を破棄する間隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+///
+/// タスクに対するリクエスト
+///
+/// # 注記
+/// 現時点ではシャットダウンしかないが、将来の拡張用にenumで定義しておく。
+///
+enum TaskRequest {
+    /// シャットダウン要求
+    Shutdown,
+}
+
+///
+/// 受信処理タスクをラップする構造体
+///
+pub(crate) struct CoapReceiveTask {
+    /// タスクのジョインハンドル
+    handle: JoinHandle<()>,
+
+    /// タスクへのリクエスト通知用のチャネル
+    request_tx: Sender<TaskRequest>,
+}
+
+impl CoapReceiveTask {
+    ///
+    /// タスクの開始
+    ///
+    /// # 引数
+    /// * `opts` - オプション情報をまとめたオブジェクト
+    /// * `auth` - 受信レコードの認証を行うオブジェクト
+    /// * `access` - 受信の可否を判定するオブジェクト
+    ///
+    /// # 戻り値
+    /// タスクの開始に成功した場合は、タスクにバインドされたCoapReceiveTaskの
+    /// オブジェクト(Futureトレイトを実装)と、受信レコードの受信用のチャネル
+    /// オブジェクトをパックしたタプルを`Ok()`でラップして返す。
+    /// 失敗した場合はエラー情報を `Err()`でラップして返す。
+    ///
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        auth: Arc<Authenticator>,
+        access: Arc<AccessControl>,
+    ) -> Result<(Self, Receiver<SensorRecord>)>
+    {
+        let endpoint = match opts.coap_endpoint() {
+            Some(endpoint) => endpoint,
+            None => return Err(anyhow!("CoAP port is not specified")),
+        };
+
+        /*
+         * ソケットオブジェクトの生成(UDPポートのバインド)
+         */
+        let sock = match UdpSocket::bind(&endpoint).await {
+            Ok(sock) => sock,
+            Err(err) => return Err(anyhow!("bind failed: {}", err)),
+        };
+
+        info!("success bind to {} for CoAP", endpoint);
+
+        /*
+         * チャネルオブジェクトの生成
+         */
+        let (pipeline_tx, pipeline_rx) = tokio::sync::mpsc::channel(10);
+        let (request_tx, request_rx) = tokio::sync::mpsc::channel(5);
+
+        /*
+         * リスナータスクの起動
+         */
+        let shared = Shared {
+            sock,
+            auth,
+            access,
+            exchanges: Mutex::new(Exchanges::default()),
+            pipeline_tx,
+        };
+
+        let handle = tokio::spawn(listener_task(
+            Arc::new(shared),
+            opts.udp_workers(),
+            request_rx,
+        ));
+
+        /*
+         * 戻り値の生成
+         */
+        Ok((Self {handle, request_tx}, pipeline_rx))
+    }
+
+    ///
+    /// 制御用ハンドルの取得
+    ///
+    /// # 戻り値
+    /// 制御用ハンドルオブジェクトを返す。
+    ///
+    pub(crate) fn handle(&self) -> CoapReceiverHandle {
+        CoapReceiverHandle {request_tx: self.request_tx.clone()}
+    }
+}
+
+// Futureトレイトの実装
+impl Future for CoapReceiveTask {
+    type Output = std::result::Result<(), tokio::task::JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().handle).poll(cx)
+    }
+}
+
+///
+/// レシーバタスク制御用のハンドル構造体
+///
+pub(crate) struct CoapReceiverHandle {
+    /// シャットダウン要求送信用オブジェクト
+    request_tx: Sender<TaskRequest>,
+}
+
+impl CoapReceiverHandle {
+    ///
+    /// タスクの終了要求の発行
+    ///
+    pub(crate) async fn shutdown(&self) {
+        let _ = self.request_tx.send(TaskRequest::Shutdown).await;
+    }
+}
+
+///
+/// メッセージ交換の状態
+///
+#[derive(Clone)]
+enum Exchange {
+    /// 処理中(レスポンス未送信)
+    InFlight,
+
+    /// 処理済み(送信したレスポンスを保持する)
+    Done(Vec<u8>),
+}
+
+///
+/// Confirmableメッセージの重複検出を行うための構造体
+///
+/// # 注記
+/// ACKを取りこぼしたクライアントは同じメッセージIDで再送してくるので、処理
+/// 済みのメッセージには保持しているレスポンスを再送し、レコードの二重登録を
+/// 防ぐ。
+///
+/// 保持する交換の数は`MAX_EXCHANGES`を上限とし、上限に達した場合は最も古い
+/// 交換を破棄する。期限切れの交換の破棄は`SWEEP_INTERVAL`毎に登録順のキュー
+/// の先頭から行うので、メッセージ毎にテーブル全体を走査することはない。
+///
+#[derive(Default)]
+struct Exchanges {
+    /// 送信元とメッセージIDをキーにした交換状態のテーブル(値は開始時刻、通
+    /// 番、交換状態の組)
+    entries: HashMap<(SocketAddr, u16), (Instant, u64, Exchange)>,
+
+    /// 開始順に並べた通番とキーの組
+    ///
+    /// # 注記
+    /// 取り消しや再登録によってテーブルから消えた交換も残るため、テーブル側
+    /// の通番と一致するもののみを有効とする。
+    order: VecDeque<(u64, (SocketAddr, u16))>,
+
+    /// 次に割り当てる通番
+    serial: u64,
+
+    /// 最後に期限切れの交換を破棄した時刻
+    last_sweep: Option<Instant>,
+
+    /// 上限に達したことで期限前に破棄した交換の数(次回の破棄時に報告する)
+    evicted: usize,
+}
+
+impl Exchanges {
+    ///
+    /// メッセージ交換の開始
+    ///
+    /// # 引数
+    /// * `key` - 送信元とメッセージIDの組
+    ///
+    /// # 戻り値
+    /// 新規のメッセージであれば`None`を返す。重複したメッセージであればその
+    /// 交換状態を`Some()`でラップして返す。
+    ///
+    fn begin(&mut self, key: (SocketAddr, u16)) -> Option<Exchange> {
+        let now = Instant::now();
+        let lifetime = Duration::from_secs(EXCHANGE_LIFETIME);
+
+        self.sweep(now);
+
+        match self.entries.get(&key) {
+            // 破棄前の期限切れの交換は新規のメッセージとして扱う
+            Some((tm, _, exchange)) => {
+                if now.saturating_duration_since(*tm) < lifetime {
+                    return Some(exchange.clone());
+                }
+            }
+
+            None => {
+                if self.entries.len() >= MAX_EXCHANGES {
+                    self.evict();
+                }
+            }
+        }
+
+        let serial = self.serial;
+
+        self.serial += 1;
+        self.entries.insert(key, (now, serial, Exchange::InFlight));
+        self.order.push_back((serial, key));
+
+        None
+    }
+
+    ///
+    /// メッセージ交換の完了
+    ///
+    /// # 引数
+    /// * `key` - 送信元とメッセージIDの組
+    /// * `response` - 送信したレスポンス
+    ///
+    fn finish(&mut self, key: (SocketAddr, u16), response: Vec<u8>) {
+        if let Some((_, _, exchange)) = self.entries.get_mut(&key) {
+            *exchange = Exchange::Done(response);
+        }
+    }
+
+    ///
+    /// メッセージ交換の取り消し
+    ///
+    /// # 引数
+    /// * `key` - 送信元とメッセージIDの組
+    ///
+    /// # 注記
+    /// レスポンスを生成できなかった場合に呼び出し、再送されたメッセージを新
+    /// 規のメッセージとして処理できるようにする。
+    ///
+    fn cancel(&mut self, key: (SocketAddr, u16)) {
+        self.entries.remove(&key);
+    }
+
+    ///
+    /// 期限切れのメッセージ交換の破棄
+    ///
+    /// # 引数
+    /// * `now` - 現在時刻
+    ///
+    /// # 注記
+    /// 走査の負荷を抑えるため、破棄は`SWEEP_INTERVAL`毎に高々1回行う。
+    ///
+    fn sweep(&mut self, now: Instant) {
+        let swept = self.last_sweep.is_some_and(|tm| {
+            now.saturating_duration_since(tm) < SWEEP_INTERVAL
+        });
+
+        if swept {
+            return;
+        }
+
+        let lifetime = Duration::from_secs(EXCHANGE_LIFETIME);
+
+        while let Some((serial, key)) = self.order.front() {
+            if let Some((tm, current, _)) = self.entries.get(key) {
+                if current == serial {
+                    if now.saturating_duration_since(*tm) < lifetime {
+                        break;
+                    }
+
+                    self.entries.remove(key);
+                }
+            }
+
+            self.order.pop_front();
+        }
+
+        if self.evicted > 0 {
+            warn!(
+                "CoAP exchange table is full, {} exchange(s) evicted",
+                self.evicted
+            );
+
+            self.evicted = 0;
+        }
+
+        self.last_sweep = Some(now);
+    }
+
+    ///
+    /// 最も古いメッセージ交換の破棄
+    ///
+    fn evict(&mut self) {
+        while let Some((serial, key)) = self.order.pop_front() {
+            let valid = self.entries.get(&key)
+                .is_some_and(|(_, current, _)| *current == serial);
+
+            if valid {
+                self.entries.remove(&key);
+                self.evicted += 1;
+                return;
+            }
+        }
+    }
+}
+
+///
+/// リスナーとワーカーで共有する情報をまとめた構造体
+///
+struct Shared {
+    /// UDPポートにバインドされたソケットオブジェクト(レスポンスの送信にも
+    /// 用いる)
+    sock: UdpSocket,
+
+    /// 受信レコードの認証を行うオブジェクト
+    auth: Arc<Authenticator>,
+
+    /// 受信の可否を判定するオブジェクト
+    access: Arc<AccessControl>,
+
+    /// 重複検出用のテーブル
+    exchanges: Mutex<Exchanges>,
+
+    /// 受信レコード送信用チャネルオブジェクト
+    pipeline_tx: Sender<SensorRecord>,
+}
+
+///
+/// CoAPリスナー処理を行うタスク
+///
+/// # 引数
+/// * `shared` - リスナーとワーカーで共有する情報
+/// * `workers` - ワーカーの数
+/// * `request_rx` - リクエスト受信用チャネルオブジェクト
+///
+/// # 注記
+/// 終了時はキューに残ったメッセージを処理し終えるまで待つ。
+///
+async fn listener_task(
+    shared: Arc<Shared>,
+    workers: usize,
+    mut request_rx: Receiver<TaskRequest>,
+)
+{
+    info!("start CoAP receiver task");
+
+    /*
+     * ワーカーの起動
+     */
+    let pool = {
+        let shared = shared.clone();
+
+        WorkerPool::start("CoAP", workers, QUEUE_SIZE, move |(addr, data)| {
+            request_task(shared.clone(), addr, data)
+        })
+    };
+
+    /*
+     * 受信ループ
+     */
+    let mut buff = vec![0; BUFFER_SIZE];
+
+    loop {
+        tokio::select! {
+            // データグラムを受信した場合
+            result = shared.sock.recv_from(&mut buff) => {
+                let (len, addr) = match result {
+                    Ok(result) => result,
+                    Err(err) => {
+                        error!("receive failed: {}", err);
+                        continue;
+                    }
+                };
+
+                // 受信を許可しない送信元のメッセージは応答せずに破棄する
+                if !shared.access.admit_source(addr.ip()) {
+                    continue;
+                }
+
+                debug!("receive CoAP message from: {:?}", addr);
+
+                match pool.submit((addr, buff[..len].to_vec())) {
+                    Ok(()) => {}
+
+                    Err(TrySendError::Full(_)) => {
+                        warn!("CoAP worker queue is full, message dropped");
+                    }
+
+                    Err(TrySendError::Closed(_)) => break,
+                }
+            }
+
+            // 制御チャネルにリクエストが届いた場合
+            request = request_rx.recv() => {
+                match request {
+                    Some(TaskRequest::Shutdown) => break,
+                    None => { /* ignore */ }
+                }
+            }
+        }
+    }
+
+    /*
+     * ワーカーの終了待ち
+     */
+    pool.join().await;
+
+    info!("shutdown CoAP receiver task");
+}
+
+///
+/// CoAPリクエストの処理を行うタスク
+///
+/// # 引数
+/// * `shared` - リスナーとワーカーで共有する情報
+/// * `addr` - 送信元のアドレス
+/// * `data` - 受信したデータグラム
+///
+async fn request_task(shared: Arc<Shared>, addr: SocketAddr, data: Vec<u8>) {
+    let sock = &shared.sock;
+
+    debug!("received data:\n{}", rhexdumps!(&data));
+
+    let packet = match Packet::from_bytes(&data) {
+        Ok(packet) => packet,
+        Err(err) => {
+            error!("invalid CoAP message from {}: {}", addr, err);
+            return;
+        }
+    };
+
+    /*
+     * 空メッセージ(CoAP ping)にはRSTを返す
+     */
+    if packet.header.code == MessageClass::Empty {
+        if packet.header.get_type() == MessageType::Confirmable {
+            let mut reset = Packet::new();
+
+            reset.header.set_type(MessageType::Reset);
+            reset.header.code = MessageClass::Empty;
+            reset.header.message_id = packet.header.message_id;
+
+            send_response(sock, addr, &reset).await;
+        }
+
+        return;
+    }
+
+    /*
+     * ACK/RSTに対してはレスポンスを生成できないので破棄する
+     */
+    let mut response = match CoapResponse::new(&packet) {
+        Some(response) => response,
+        None => return,
+    };
+
+    /*
+     * 重複メッセージの確認
+     */
+    let key = (addr, packet.header.message_id);
+    let confirmable = packet.header.get_type() == MessageType::Confirmable;
+
+    if confirmable {
+        let duplicate = shared.exchanges.lock().unwrap().begin(key);
+
+        match duplicate {
+            None => { /* new message */ }
+
+            Some(Exchange::InFlight) => {
+                debug!("duplicate message from {} (in flight)", addr);
+                return;
+            }
+
+            Some(Exchange::Done(bytes)) => {
+                debug!("duplicate message from {} (resend response)", addr);
+
+                if let Err(err) = sock.send_to(&bytes, addr).await {
+                    error!("send CoAP response failed: {}", err);
+                }
+
+                return;
+            }
+        }
+    }
+
+    /*
+     * リクエストの処理とレスポンスの送信
+     *
+     * レスポンスは送信の成否に関わらず保持し、再送されたメッセージには同じ
+     * レスポンスを返す(レコードは投入済みのため)。レスポンスを生成できな
+     * かった場合は交換を取り消す。
+     */
+    let status = handle_request(
+        &packet,
+        &shared.auth,
+        &shared.access,
+        &shared.pipeline_tx
+    ).await;
+
+    response.set_status(status);
+
+    let bytes = encode_packet(&response.message);
+
+    if confirmable {
+        let mut exchanges = shared.exchanges.lock().unwrap();
+
+        match &bytes {
+            Some(bytes) => exchanges.finish(key, bytes.clone()),
+            None => exchanges.cancel(key),
+        }
+    }
+
+    if let Some(bytes) = bytes {
+        if let Err(err) = sock.send_to(&bytes, addr).await {
+            error!("send CoAP response failed: {}", err);
+        }
+    }
+}
+
+///
+/// リクエストの処理
+///
+/// # 引数
+/// * `packet` - 受信したリクエスト
+/// * `auth` - 受信レコードの認証を行うオブジェクト
+/// * `access` - 受信の可否を判定するオブジェクト
+/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+///
+/// # 戻り値
+/// レスポンスとして返すステータスコード
+///
+async fn handle_request(
+    packet: &Packet,
+    auth: &Authenticator,
+    access: &AccessControl,
+    pipeline_tx: &Sender<SensorRecord>,
+) -> ResponseType
+{
+    /*
+     * リソースとメソッドの確認
+     */
+    let path = packet.get_option(CoapOption::UriPath)
+        .map(|list| {
+            list.iter()
+                .map(|seg| String::from_utf8_lossy(seg).to_string())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .unwrap_or_default();
+
+    if path != RECORDS_PATH {
+        return ResponseType::NotFound;
+    }
+
+    if packet.header.code != MessageClass::Request(RequestType::Post) {
+        return ResponseType::MethodNotAllowed;
+    }
+
+    /*
+     * ペイロードのパース
+     *
+     * Content-Formatが省略されている場合はJSONとして扱う。
+     */
+    let specified = packet.get_first_option(CoapOption::ContentFormat)
+        .is_some();
+
+    let format = if specified {
+        packet.get_content_format()
+    } else {
+        Some(ContentFormat::ApplicationJSON)
+    };
+
+    let result = match format {
+        Some(ContentFormat::ApplicationJSON) => {
+            match std::str::from_utf8(&packet.payload) {
+                Ok(json) => auth.open(json),
+                Err(err) => Err(anyhow!("{}", err)),
+            }
+        }
+
+        Some(ContentFormat::ApplicationCBOR) => {
+            SensorRecord::from_cbor(&packet.payload)
+        }
+
+        _ => return ResponseType::UnsupportedContentFormat,
+    };
+
+    let mut record = match result {
+        Ok(record) => record,
+        Err(err) => {
+            error!("invalid payload received: {}", err);
+            return ResponseType::BadRequest;
+        }
+    };
+
+    /*
+     * 署名を運べないCBORのペイロードの認証
+     */
+    if format == Some(ContentFormat::ApplicationCBOR)
+        && auth.check_unsigned(&mut record).is_err()
+    {
+        return ResponseType::Unauthorized;
+    }
+
+    if !access.admit_device(&record) {
+        return ResponseType::TooManyRequests;
+    }
+
+    /*
+     * パイプラインへの投入
+     */
+    match pipeline_tx.send(record).await {
+        Ok(()) => ResponseType::Created,
+        Err(err) => {
+            error!("send sensor result failed: {}", err);
+            ResponseType::ServiceUnavailable
+        }
+    }
+}
+
+///
+/// パケットのエンコード
+///
+/// # 戻り値
+/// エンコードに成功した場合はバイト列を`Some()`でラップして返す。
+///
+fn encode_packet(packet: &Packet) -> Option<Vec<u8>> {
+    match packet.to_bytes() {
+        Ok(bytes) => Some(bytes),
+        Err(err) => {
+            error!("encode CoAP response failed: {}", err);
+            None
+        }
+    }
+}
+
+///
+/// レスポンスの送信
+///
+/// # 引数
+/// * `sock` - レスポンス送信用のソケットオブジェクト
+/// * `addr` - 送信先のアドレス
+/// * `packet` - 送信するパケット
+///
+async fn send_response(sock: &UdpSocket, addr: SocketAddr, packet: &Packet) {
+    let Some(bytes) = encode_packet(packet) else {
+        return;
+    };
+
+    if let Err(err) = sock.send_to(&bytes, addr).await {
+        error!("send CoAP response failed: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::access::tests::unrestricted;
+    use crate::receiver::auth::tests::{
+        assert_rejected, authenticator, envelope, now, require_signature
+    };
+    use serde_json::json;
+
+    fn key(id: usize) -> (SocketAddr, u16) {
+        let port = 10000 + (id / 0x10000) as u16;
+        (SocketAddr::from(([127, 0, 0, 1], port)), id as u16)
+    }
+
+    #[test]
+    fn detect_duplicate() {
+        let mut exchanges = Exchanges::default();
+
+        assert!(exchanges.begin(key(0)).is_none());
+        assert!(matches!(exchanges.begin(key(0)), Some(Exchange::InFlight)));
+
+        exchanges.finish(key(0), vec![1, 2, 3]);
+
+        match exchanges.begin(key(0)) {
+            Some(Exchange::Done(bytes)) => {
+                assert_eq!(bytes, vec![1, 2, 3]);
+            }
+
+            _ => panic!("response is not kept"),
+        }
+
+        // 取り消した交換は新規のメッセージとして扱う
+        exchanges.cancel(key(0));
+        assert!(exchanges.begin(key(0)).is_none());
+    }
+
+    #[test]
+    fn evict_oldest_at_cap() {
+        let mut exchanges = Exchanges::default();
+
+        for id in 0..MAX_EXCHANGES {
+            assert!(exchanges.begin(key(id)).is_none());
+        }
+
+        // 取り消し後に再登録した交換は登録し直した位置で扱う
+        exchanges.cancel(key(0));
+        assert!(exchanges.begin(key(0)).is_none());
+
+        assert!(exchanges.begin(key(MAX_EXCHANGES)).is_none());
+        assert_eq!(exchanges.entries.len(), MAX_EXCHANGES);
+        assert_eq!(exchanges.evicted, 1);
+
+        // 最も古い交換が破棄され、再登録したものは残る
+        assert!(!exchanges.entries.contains_key(&key(1)));
+        assert!(exchanges.entries.contains_key(&key(0)));
+        assert!(exchanges.entries.contains_key(&key(MAX_EXCHANGES)));
+    }
+
+    fn request(format: ContentFormat, payload: Vec<u8>) -> Packet {
+        let mut packet = Packet::new();
+
+        packet.header.code = MessageClass::Request(RequestType::Post);
+        packet.add_option(CoapOption::UriPath, RECORDS_PATH.into());
+        packet.set_content_format(format);
+        packet.payload = payload;
+
+        packet
+    }
+
+    fn cbor(value: serde_json::Value) -> Vec<u8> {
+        let mut bytes = vec![];
+        ciborium::into_writer(&value, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn authenticate_payloads() {
+        let auth = authenticator();
+        let access = unrestricted();
+        let (pipeline_tx, mut pipeline_rx) = tokio::sync::mpsc::channel(1);
+
+        let mut post = async |packet: Packet| {
+            let status = handle_request(&packet, &auth, &access, &pipeline_tx)
+                .await;
+
+            (status, pipeline_rx.try_recv().ok())
+        };
+
+        // 署名の無いJSON
+        let json = r#"{"location":"room","device_id":"dev1"}"#;
+        let packet = request(ContentFormat::ApplicationJSON, json.into());
+        let (status, record) = post(packet).await;
+        assert_eq!(status, ResponseType::Created);
+        assert_rejected(&record.unwrap(), "auth:unsigned:dev1");
+
+        // 署名付きエンベロープ
+        let payload = r#"{"location":"room","temperature":20}"#;
+        let data = envelope("dev1", now(), "n1", payload, "secret");
+        let packet = request(ContentFormat::ApplicationJSON, data.into());
+        let (status, record) = post(packet).await;
+        assert_eq!(status, ResponseType::Created);
+        assert!(!record.unwrap().is_quarantined());
+
+        // CBORは署名を運べない
+        let value = json!({"location": "room", "device_id": "dev1"});
+        let packet = request(ContentFormat::ApplicationCBOR, cbor(value));
+        let (status, record) = post(packet).await;
+        assert_eq!(status, ResponseType::Created);
+        assert_rejected(&record.unwrap(), "auth:unsigned:dev1");
+
+        require_signature(&auth);
+
+        let value = json!({"location": "room", "device_id": "dev3"});
+        let packet = request(ContentFormat::ApplicationCBOR, cbor(value));
+        let (status, record) = post(packet).await;
+        assert_eq!(status, ResponseType::Unauthorized);
+        assert!(record.is_none());
+    }
+}
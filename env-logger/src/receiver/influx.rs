@@ -0,0 +1,1156 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! InfluxDBラインプロトコルの受信処理をまとめたモジュール
+//!
+//! TCP/UDPによる行単位の受信と、InfluxDB v2の書き込みAPI(`/api/v2/write`)
+//! 互換のHTTPエンドポイントを提供する。
+//!
+//! 終了時は処理中のセッションに終了を通知し、`--shutdown-timeout`の期限ま
+//! で完了を待つ(期限を過ぎたセッションは中断する)。行単位の受信は受信中の
+//! 行を、書き込みAPIは処理中のリクエストを処理し終えてから切断する。
+//!
+//! 受信の制限は、行単位の受信には設定ファイルの`[access.influx]`を、書き込
+//! みAPIには`[access.influx_http]`を適用する。許可しない送信元からの接続は
+//! 受け付け時に切断する。送信元の流量は行単位の受信では行(UDPではデータグ
+//! ラム)毎に、書き込みAPIではリクエスト毎に制限し、制限を超えた場合はTCP
+//! では切断、書き込みAPIでは429を返す。デバイスの流量の制限を超えたレコー
+//! ドは破棄する。
+//!
+//! ラインプロトコルは署名を運べないので、共有鍵を設定したデバイスからのレ
+//! コードは隔離する。全てのデバイスに署名を要求する設定の場合はレコードを
+//! 受け付けない(書き込みAPIでは401を返す)。
+//!
+//! ポイントのタイムスタンプは既定ではそのまま採用する。
+//! `--influx-clamp-timestamps`を指定した場合は、JSONで受信したレコードと同
+//! 様に受信時刻の前後に丸める。
+//!
+
+use std::future::Future;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Body, Bytes};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rhexdump::rhexdumps;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{timeout, timeout_at, Duration};
+
+use super::access::AccessControl;
+use super::auth::Authenticator;
+use super::pool::WorkerPool;
+use crate::cmd_args::Options;
+use crate::line_protocol::{Point, Precision};
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 書き込みAPIのパス
+const WRITE_PATH: &str = "/api/v2/write";
+
+/// 書き込みAPIで受け付けるボディの最大サイズ(展開後)
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// UDPの受信バッファのサイズ
+const BUFFER_SIZE: usize = 65536;
+
+/// UDPのワーカーのキューの長さ
+const QUEUE_SIZE: usize = 64;
+
+///
+/// タスクに対するリクエスト
+///
+/// # 注記
+/// 現時点ではシャットダウンしかないが、将来の拡張用にenumで定義しておく。
+///
+enum TaskRequest {
+    /// シャットダウン要求
+    Shutdown,
+}
+
+///
+/// 受信処理タスクをラップする構造体
+///
+pub(crate) struct InfluxReceiveTask {
+    /// タスクのジョインハンドル
+    handle: JoinHandle<()>,
+
+    /// タスクへのリクエスト通知用のチャネル
+    request_tx: Sender<TaskRequest>,
+}
+
+impl InfluxReceiveTask {
+    ///
+    /// タスクの開始
+    ///
+    /// # 引数
+    /// * `opts` - オプション情報をまとめたオブジェクト
+    /// * `auth` - 受信レコードの認証を行うオブジェクト
+    /// * `access` - 行単位の受信の可否を判定するオブジェクト
+    /// * `http_access` - 書き込みAPIの受信の可否を判定するオブジェクト
+    ///
+    /// # 戻り値
+    /// タスクの開始に成功した場合は、タスクにバインドされたInfluxReceiveTask
+    /// のオブジェクト(Futureトレイトを実装)と、受信レコードの受信用のチャネ
+    /// ルオブジェクトをパックしたタプルを`Ok()`でラップして返す。
+    /// 失敗した場合はエラー情報を `Err()`でラップして返す。
+    ///
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        auth: Arc<Authenticator>,
+        access: Arc<AccessControl>,
+        http_access: Arc<AccessControl>,
+    ) -> Result<(Self, Receiver<SensorRecord>)>
+    {
+        let mut listeners = Listeners::default();
+
+        /*
+         * 行単位受信用のポートのバインド
+         */
+        if let Some(endpoint) = opts.influx_endpoint() {
+            listeners.tcp = match TcpListener::bind(&endpoint).await {
+                Ok(sock) => Some(sock),
+                Err(err) => return Err(anyhow!("bind failed: {}", err)),
+            };
+
+            listeners.udp = match UdpSocket::bind(&endpoint).await {
+                Ok(sock) => Some(sock),
+                Err(err) => return Err(anyhow!("bind failed: {}", err)),
+            };
+
+            info!("success bind to {} for line protocol", endpoint);
+        }
+
+        /*
+         * 書き込みAPI用のポートのバインド
+         */
+        if let Some(endpoint) = opts.influx_http_endpoint() {
+            listeners.http = match TcpListener::bind(&endpoint).await {
+                Ok(sock) => Some(sock),
+                Err(err) => return Err(anyhow!("bind failed: {}", err)),
+            };
+
+            info!("success bind to {} for write API", endpoint);
+        }
+
+        if listeners.is_empty() {
+            return Err(anyhow!("line protocol port is not specified"));
+        }
+
+        let mapping = Mapping {
+            location_tag: opts.influx_location_tag(),
+            device_tag: opts.influx_device_tag(),
+            precision: opts.influx_precision(),
+            clamp_timestamps: opts.influx_clamp_timestamps(),
+        };
+
+        let limits = Limits {
+            workers: opts.udp_workers(),
+            max_line_length: opts.tcp_max_line_length(),
+            read_timeout: Duration::from_secs(opts.tcp_read_timeout()),
+            shutdown_timeout: Duration::from_secs(opts.shutdown_timeout()),
+        };
+
+        /*
+         * チャネルオブジェクトの生成
+         */
+        let (pipeline_tx, pipeline_rx) = tokio::sync::mpsc::channel(10);
+        let (request_tx, request_rx) = tokio::sync::mpsc::channel(5);
+
+        /*
+         * リスナータスクの起動
+         */
+        let handle = tokio::spawn(listener_task(
+            listeners,
+            mapping,
+            limits,
+            Controls {auth, line: access, http: http_access},
+            pipeline_tx,
+            request_rx,
+        ));
+
+        /*
+         * 戻り値の生成
+         */
+        Ok((Self {handle, request_tx}, pipeline_rx))
+    }
+
+    ///
+    /// 制御用ハンドルの取得
+    ///
+    /// # 戻り値
+    /// 制御用ハンドルオブジェクトを返す。
+    ///
+    pub(crate) fn handle(&self) -> InfluxReceiverHandle {
+        InfluxReceiverHandle {request_tx: self.request_tx.clone()}
+    }
+}
+
+// Futureトレイトの実装
+impl Future for InfluxReceiveTask {
+    type Output = std::result::Result<(), tokio::task::JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().handle).poll(cx)
+    }
+}
+
+///
+/// レシーバタスク制御用のハンドル構造体
+///
+pub(crate) struct InfluxReceiverHandle {
+    /// シャットダウン要求送信用オブジェクト
+    request_tx: Sender<TaskRequest>,
+}
+
+impl InfluxReceiverHandle {
+    ///
+    /// タスクの終了要求の発行
+    ///
+    pub(crate) async fn shutdown(&self) {
+        let _ = self.request_tx.send(TaskRequest::Shutdown).await;
+    }
+}
+
+///
+/// バインド済みのソケットをまとめた構造体
+///
+#[derive(Default)]
+struct Listeners {
+    /// 行単位受信用のTCPリスナー
+    tcp: Option<TcpListener>,
+
+    /// 行単位受信用のUDPソケット
+    udp: Option<UdpSocket>,
+
+    /// 書き込みAPI用のTCPリスナー
+    http: Option<TcpListener>,
+}
+
+impl Listeners {
+    ///
+    /// 待ち受けを行うソケットが無いか否か
+    ///
+    fn is_empty(&self) -> bool {
+        self.tcp.is_none() && self.udp.is_none() && self.http.is_none()
+    }
+}
+
+///
+/// 受信レコードの認証と受信の可否の判定を行うオブジェクトをまとめた構造体
+///
+struct Controls {
+    /// 受信レコードの認証を行うオブジェクト
+    auth: Arc<Authenticator>,
+
+    /// 行単位の受信(TCP/UDP)
+    line: Arc<AccessControl>,
+
+    /// 書き込みAPI
+    http: Arc<AccessControl>,
+}
+
+///
+/// 受信処理の制限をまとめた構造体
+///
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// UDPで受信したデータを処理するワーカーの数
+    workers: usize,
+
+    /// TCPで受信する1行の長さの上限
+    max_line_length: usize,
+
+    /// TCPで1行を受信し終えるまでの期限
+    read_timeout: Duration,
+
+    /// 終了時に処理中のセッションの完了を待つ期限
+    shutdown_timeout: Duration,
+}
+
+///
+/// ラインプロトコルからレコードへの対応付けをまとめた構造体
+///
+#[derive(Debug, Clone)]
+struct Mapping {
+    /// 設置場所として扱うタグのキー
+    location_tag: String,
+
+    /// デバイスIDとして扱うタグのキー
+    device_tag: String,
+
+    /// デフォルトのタイムスタンプの精度
+    precision: Precision,
+
+    /// タイムスタンプを受信時刻の前後に丸めるか否か
+    clamp_timestamps: bool,
+}
+
+impl Mapping {
+    ///
+    /// ポイントからレコードへの変換
+    ///
+    /// # 引数
+    /// * `point` - パース済みのポイント
+    /// * `precision` - タイムスタンプの精度
+    ///
+    /// # 戻り値
+    /// 変換に成功した場合はレコードを`Ok(Some())`でラップして返す。対象の
+    /// フィールドを一つも含まないポイントの場合は`Ok(None)`を返す。
+    ///
+    fn to_record(&self, point: &Point, precision: Precision)
+        -> Result<Option<SensorRecord>>
+    {
+        let value = |key: &str| {
+            point.field(key)
+                .and_then(|val| val.as_f64())
+                .map(|val| val as f32)
+        };
+
+        let temperature = value("temperature");
+        let humidity = value("humidity");
+        let air_pressure = value("air_pressure");
+
+        // 気温、湿度、気圧以外の数値のフィールド(計測種別の登録簿に従って後
+        // 段で取り込む)
+        let others = point.fields()
+            .filter(|(key, _)| {
+                !["temperature", "humidity", "air_pressure"].contains(key)
+            })
+            .filter_map(|(key, val)| Some((key, val.as_f64()?)))
+            .collect::<Vec<_>>();
+
+        if temperature.is_none()
+            && humidity.is_none()
+            && air_pressure.is_none()
+            && others.is_empty()
+        {
+            debug!("skip measurement {}", point.measurement());
+            return Ok(None);
+        }
+
+        let location = match point.tag(&self.location_tag) {
+            Some(location) => location.to_string(),
+            None => return Err(anyhow!("missing tag {}", self.location_tag)),
+        };
+
+        let device_id = point.tag(&self.device_tag).map(|s| s.to_string());
+
+        let timestamp = match point.timestamp() {
+            Some(tm) => {
+                let tm = precision.to_millis(tm);
+
+                if tm < 0 {
+                    return Err(anyhow!("negative timestamp"));
+                }
+
+                tm as u64
+            }
+
+            None => chrono::Utc::now().timestamp_millis() as u64,
+        };
+
+        let mut record = SensorRecord::new(
+            location,
+            device_id,
+            timestamp,
+            temperature,
+            humidity,
+            air_pressure,
+        );
+
+        for (key, val) in others {
+            record.set_extra(key, val.into());
+        }
+
+        if self.clamp_timestamps {
+            record.clamp_timestamp();
+        }
+
+        Ok(Some(record))
+    }
+}
+
+///
+/// ラインプロトコルのテキストの取り込み
+///
+/// # 引数
+/// * `text` - 改行区切りのラインプロトコル
+/// * `mapping` - レコードへの対応付け
+/// * `precision` - タイムスタンプの精度
+/// * `auth` - 受信レコードの認証を行うオブジェクト
+/// * `access` - 受信の可否を判定するオブジェクト
+/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+///
+/// # 戻り値
+/// 全ての行の処理に成功した場合は`Ok(())`を返す。パースに失敗した行(署名を
+/// 要求されて受け付けなかった行を含む)があった場合は、最初のエラーを
+/// `Err()`でラップして返す(失敗した行以外は取り込まれる)。デバイスの流量
+/// の制限を超えたレコードは破棄し、エラーとしては扱わない。
+///
+async fn ingest(
+    text: &str,
+    mapping: &Mapping,
+    precision: Precision,
+    auth: &Authenticator,
+    access: &AccessControl,
+    pipeline_tx: &Sender<SensorRecord>,
+) -> Result<()>
+{
+    let mut first_error = None;
+
+    for line in text.lines() {
+        let result = Point::parse(line)
+            .and_then(|point| match point {
+                Some(point) => mapping.to_record(&point, precision),
+                None => Ok(None),
+            })
+            .and_then(|record| match record {
+                Some(mut record) => {
+                    auth.check_unsigned(&mut record)?;
+                    Ok(Some(record))
+                }
+
+                None => Ok(None),
+            });
+
+        match result {
+            Ok(Some(record)) => {
+                if !access.admit_device(&record) {
+                    continue;
+                }
+
+                if let Err(err) = pipeline_tx.send(record).await {
+                    return Err(anyhow!("send sensor result failed: {}", err));
+                }
+            }
+
+            Ok(None) => { /* ignore */ }
+
+            Err(err) => {
+                error!("invalid line protocol: {}", err);
+
+                if first_error.is_none() {
+                    first_error = Some(err);
+                }
+            }
+        }
+    }
+
+    match first_error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+///
+/// 省略可能なTCPリスナーでの接続受付
+///
+/// # 注記
+/// リスナーが存在しない場合は永久に完了しない。
+///
+async fn accept(sock: &Option<TcpListener>)
+    -> std::io::Result<(TcpStream, SocketAddr)>
+{
+    match sock {
+        Some(sock) => sock.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+///
+/// 省略可能なUDPソケットでの受信
+///
+/// # 注記
+/// ソケットが存在しない場合は永久に完了しない。
+///
+async fn recv_from(sock: &Option<UdpSocket>, buff: &mut [u8])
+    -> std::io::Result<(usize, SocketAddr)>
+{
+    match sock {
+        Some(sock) => sock.recv_from(buff).await,
+        None => std::future::pending().await,
+    }
+}
+
+///
+/// ラインプロトコルのリスナー処理を行うタスク
+///
+/// # 引数
+/// * `listeners` - バインド済みのソケット
+/// * `mapping` - レコードへの対応付け
+/// * `limits` - 受信処理の制限
+/// * `controls` - 受信の可否を判定するオブジェクト
+/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+/// * `request_rx` - リクエスト受信用チャネルオブジェクト
+///
+async fn listener_task(
+    listeners: Listeners,
+    mapping: Mapping,
+    limits: Limits,
+    controls: Controls,
+    pipeline_tx: Sender<SensorRecord>,
+    mut request_rx: Receiver<TaskRequest>,
+)
+{
+    info!("start line protocol receiver task");
+
+    // セッションタスクと共有できるようにArcでラップ
+    let mapping = Arc::new(mapping);
+    let controls = Arc::new(controls);
+    let pipeline_tx = Arc::new(pipeline_tx);
+    let mut buff = vec![0; BUFFER_SIZE];
+
+    // TCPのセッションは終了時に完了を待てるようにまとめて管理する
+    let mut sessions = JoinSet::new();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    // UDPで受信したデータグラムはワーカープールで処理する
+    let pool = {
+        let mapping = mapping.clone();
+        let auth = controls.auth.clone();
+        let access = controls.line.clone();
+        let pipeline_tx = pipeline_tx.clone();
+
+        WorkerPool::start(
+            "line protocol",
+            limits.workers,
+            QUEUE_SIZE,
+            move |text: String| {
+                let mapping = mapping.clone();
+                let auth = auth.clone();
+                let access = access.clone();
+                let pipeline_tx = pipeline_tx.clone();
+
+                async move {
+                    let precision = mapping.precision;
+                    let _ = ingest(
+                        &text,
+                        &mapping,
+                        precision,
+                        &auth,
+                        &access,
+                        &pipeline_tx
+                    ).await;
+                }
+            }
+        )
+    };
+
+    loop {
+        tokio::select! {
+            // 行単位受信用のポートへの接続があった場合
+            result = accept(&listeners.tcp) => {
+                match result {
+                    Ok((sock, addr)) => {
+                        // 受信を許可しない送信元は即座に切断する
+                        if !controls.line.permits_source(addr.ip()) {
+                            continue;
+                        }
+
+                        info!("line protocol connection from: {:?}", addr);
+
+                        sessions.spawn(line_session_task(
+                            sock,
+                            addr.ip(),
+                            mapping.clone(),
+                            limits,
+                            controls.clone(),
+                            pipeline_tx.clone(),
+                            shutdown_rx.clone(),
+                        ));
+                    }
+
+                    Err(err) => error!("accept failed: {}", err),
+                }
+            }
+
+            // 行単位受信用のポートでデータグラムを受信した場合
+            result = recv_from(&listeners.udp, &mut buff) => {
+                match result {
+                    Ok((len, addr)) => {
+                        debug!("receive line protocol from: {:?}", addr);
+
+                        // 受信を許可しない送信元のデータは読み捨てる
+                        if !controls.line.admit_source(addr.ip()) {
+                            continue;
+                        }
+
+                        let text = String::from_utf8_lossy(&buff[..len])
+                            .to_string();
+
+                        match pool.submit(text) {
+                            Ok(()) => {}
+
+                            Err(TrySendError::Full(_)) => {
+                                warn!(
+                                    "line protocol worker queue is full, \
+                                     datagram dropped"
+                                );
+                            }
+
+                            Err(TrySendError::Closed(_)) => break,
+                        }
+                    }
+
+                    Err(err) => error!("receive failed: {}", err),
+                }
+            }
+
+            // 書き込みAPI用のポートへの接続があった場合
+            result = accept(&listeners.http) => {
+                match result {
+                    Ok((sock, addr)) => {
+                        // 受信を許可しない送信元は即座に切断する
+                        if !controls.http.permits_source(addr.ip()) {
+                            continue;
+                        }
+
+                        debug!("write API connection from: {:?}", addr);
+
+                        sessions.spawn(http_session_task(
+                            sock,
+                            addr.ip(),
+                            mapping.clone(),
+                            controls.clone(),
+                            pipeline_tx.clone(),
+                            shutdown_rx.clone(),
+                        ));
+                    }
+
+                    Err(err) => error!("accept failed: {}", err),
+                }
+            }
+
+            // 終了したセッションの回収
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+
+            // 制御チャネルにリクエストが届いた場合
+            request = request_rx.recv() => {
+                match request {
+                    Some(TaskRequest::Shutdown) => break,
+                    None => { /* ignore */ }
+                }
+            }
+        }
+    }
+
+    /*
+     * 新たな接続の受け付けを止め、処理中のセッションの完了を待つ(期限を過
+     * ぎたセッションは中断する)
+     */
+    drop(listeners);
+    let _ = shutdown_tx.send(true);
+
+    if !sessions.is_empty() {
+        info!("waiting for {} line protocol session(s)", sessions.len());
+    }
+
+    let result = timeout(limits.shutdown_timeout, async {
+        while sessions.join_next().await.is_some() {}
+    }).await;
+
+    if result.is_err() {
+        warn!("{} line protocol session(s) aborted", sessions.len());
+        sessions.shutdown().await;
+    }
+
+    pool.join().await;
+
+    info!("shutdown line protocol receiver task");
+}
+
+///
+/// 行単位受信のセッション処理を行うタスク
+///
+/// # 引数
+/// * `sock` - TCPセッションのソケット
+/// * `source` - 送信元のアドレス
+/// * `mapping` - レコードへの対応付け
+/// * `limits` - 受信処理の制限
+/// * `controls` - 受信レコードの認証と受信の可否の判定を行うオブジェクト
+/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+/// * `shutdown_rx` - 終了の通知を受け取るチャネルオブジェクト
+///
+/// # 注記
+/// Telegrafのsocket_writer等は接続を維持したまま送信を続けるので、クライア
+/// ントが切断するか終了が通知されるまで受信を継続する。行の間の待機には期限
+/// を設けないが、行の先頭を受信してから受信し終えるまでには期限を設け、行の
+/// 長さが上限を超えた場合と共に接続を打ち切る。送信元の流量の制限を超えた
+/// 場合も接続を打ち切る(応答を返す手段が無いため)。
+///
+async fn line_session_task(
+    sock: TcpStream,
+    source: IpAddr,
+    mapping: Arc<Mapping>,
+    limits: Limits,
+    controls: Arc<Controls>,
+    pipeline_tx: Arc<Sender<SensorRecord>>,
+    mut shutdown_rx: watch::Receiver<bool>,
+)
+{
+    let mut reader = BufReader::new(sock);
+    let max_length = limits.max_line_length;
+
+    loop {
+        /*
+         * 次の行の先頭が届くまで待つ(終了が通知された場合は切断する)
+         */
+        let result = tokio::select! {
+            result = reader.fill_buf() => result.map(|buf| buf.is_empty()),
+            _ = wait_shutdown(&mut shutdown_rx) => break,
+        };
+
+        match result {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(err) => {
+                error!("TCP receive failed: {}", err);
+                break;
+            }
+        }
+
+        /*
+         * 1行分のデータを受信
+         */
+        let deadline = Instant::now() + limits.read_timeout;
+        let mut line = vec![];
+
+        let result = timeout_at(
+            deadline.into(),
+            (&mut reader)
+                .take(max_length as u64 + 1)
+                .read_until(b'\n', &mut line)
+        ).await;
+
+        match result {
+            Ok(Ok(_)) => {}
+
+            Ok(Err(err)) => {
+                error!("TCP receive failed: {}", err);
+                break;
+            }
+
+            Err(_) => {
+                warn!("line protocol receive timed out");
+                break;
+            }
+        }
+
+        if line.ends_with(b"\n") {
+            line.pop();
+
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+
+        } else if line.len() > max_length {
+            warn!("receive data exceeds {} bytes", max_length);
+            break;
+        }
+
+        debug!("received data:\n{}", rhexdumps!(&line));
+
+        if !controls.line.admit_source(source) {
+            break;
+        }
+
+        /*
+         * 取り込み
+         */
+        let line = String::from_utf8_lossy(&line);
+        let precision = mapping.precision;
+
+        if let Err(err) = ingest(
+            &line,
+            &mapping,
+            precision,
+            &controls.auth,
+            &controls.line,
+            &pipeline_tx
+        ).await {
+            debug!("{}", err);
+        }
+    }
+}
+
+///
+/// 書き込みAPIのセッション処理を行うタスク
+///
+/// # 引数
+/// * `sock` - TCPセッションのソケット
+/// * `source` - 送信元のアドレス
+/// * `mapping` - レコードへの対応付け
+/// * `controls` - 受信レコードの認証と受信の可否の判定を行うオブジェクト
+/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+/// * `shutdown_rx` - 終了の通知を受け取るチャネルオブジェクト
+///
+/// # 注記
+/// 終了が通知された場合は、処理中のリクエストに応答してから切断する。
+///
+async fn http_session_task(
+    sock: TcpStream,
+    source: IpAddr,
+    mapping: Arc<Mapping>,
+    controls: Arc<Controls>,
+    pipeline_tx: Arc<Sender<SensorRecord>>,
+    mut shutdown_rx: watch::Receiver<bool>,
+)
+{
+    let service = service_fn(move |req| {
+        let mapping = mapping.clone();
+        let controls = controls.clone();
+        let pipeline_tx = pipeline_tx.clone();
+
+        async move {
+            Ok::<_, hyper::Error>(handle_write(
+                req,
+                source,
+                &mapping,
+                &controls.auth,
+                &controls.http,
+                &pipeline_tx
+            ).await)
+        }
+    });
+
+    let conn = http1::Builder::new()
+        .serve_connection(TokioIo::new(sock), service);
+    tokio::pin!(conn);
+
+    let result = tokio::select! {
+        result = conn.as_mut() => Some(result),
+        _ = wait_shutdown(&mut shutdown_rx) => None,
+    };
+
+    let result = match result {
+        Some(result) => result,
+        None => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+
+    if let Err(err) = result {
+        debug!("write API connection error: {}", err);
+    }
+}
+
+///
+/// 終了の通知の待ち合わせ
+///
+/// # 注記
+/// 通知元が破棄された場合も終了が通知されたものとして扱う。
+///
+async fn wait_shutdown(shutdown_rx: &mut watch::Receiver<bool>) {
+    let _ = shutdown_rx.wait_for(|shutdown| *shutdown).await;
+}
+
+///
+/// 書き込みAPIのリクエスト処理
+///
+/// # 引数
+/// * `req` - HTTPリクエスト
+/// * `source` - 送信元のアドレス
+/// * `mapping` - レコードへの対応付け
+/// * `auth` - 受信レコードの認証を行うオブジェクト
+/// * `access` - 受信の可否を判定するオブジェクト
+/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+///
+/// # 戻り値
+/// HTTPレスポンスを返す。全ての行の取り込みに成功した場合は204を、失敗した
+/// 行があった場合はInfluxDBと同様のエラー形式で400を返す。送信元の流量の制
+/// 限を超えた場合は429を、全てのデバイスに署名を要求する設定の場合は401を
+/// ボディを読まずに返す。
+///
+async fn handle_write<B>(
+    req: Request<B>,
+    source: IpAddr,
+    mapping: &Mapping,
+    auth: &Authenticator,
+    access: &AccessControl,
+    pipeline_tx: &Sender<SensorRecord>,
+) -> Response<Full<Bytes>>
+where
+    B: Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    if req.uri().path() != WRITE_PATH {
+        return error_response(StatusCode::NOT_FOUND, "not found", "not found");
+    }
+
+    if req.method() != Method::POST {
+        return error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method not allowed",
+            "method not allowed"
+        );
+    }
+
+    if !access.admit_source(source) {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many requests",
+            "too many requests"
+        );
+    }
+
+    if auth.requires_signature() {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "signature required"
+        );
+    }
+
+    /*
+     * タイムスタンプの精度の決定
+     */
+    let precision = match query_param(&req, "precision") {
+        Some(value) => match Precision::parse(&value) {
+            Some(precision) => precision,
+            None => return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid",
+                &format!("invalid precision {}", value)
+            ),
+        },
+
+        None => mapping.precision,
+    };
+
+    let gzipped = req.headers()
+        .get(hyper::header::CONTENT_ENCODING)
+        .is_some_and(|val| val.as_bytes().eq_ignore_ascii_case(b"gzip"));
+
+    /*
+     * ボディの読み出し
+     */
+    let body = match Limited::new(req.into_body(), MAX_BODY_SIZE)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(err) => return error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "too large",
+            &err.to_string()
+        ),
+    };
+
+    let text = if gzipped {
+        let mut text = String::new();
+        let decoder = GzDecoder::new(&body[..]);
+
+        match decoder.take(MAX_BODY_SIZE as u64 + 1).read_to_string(&mut text) {
+            Ok(len) if len <= MAX_BODY_SIZE => text,
+
+            Ok(_) => return error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "too large",
+                "request body is too large"
+            ),
+
+            Err(err) => return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid",
+                &format!("gzip decode failed: {}", err)
+            ),
+        }
+
+    } else {
+        match String::from_utf8(body.to_vec()) {
+            Ok(text) => text,
+            Err(err) => return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid",
+                &err.to_string()
+            ),
+        }
+    };
+
+    /*
+     * 取り込み
+     */
+    match ingest(&text, mapping, precision, auth, access, pipeline_tx).await {
+        Ok(()) => {
+            let mut resp = Response::new(Full::new(Bytes::new()));
+            *resp.status_mut() = StatusCode::NO_CONTENT;
+            resp
+        }
+
+        Err(err) => {
+            error_response(StatusCode::BAD_REQUEST, "invalid", &err.to_string())
+        }
+    }
+}
+
+///
+/// クエリーパラメータの取得
+///
+/// # 引数
+/// * `req` - HTTPリクエスト
+/// * `key` - パラメータ名
+///
+fn query_param<B>(req: &Request<B>, key: &str) -> Option<String> {
+    req.uri().query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v.to_string())
+}
+
+///
+/// エラーレスポンスの生成
+///
+/// # 引数
+/// * `status` - HTTPステータスコード
+/// * `code` - InfluxDB形式のエラーコード
+/// * `message` - エラーメッセージ
+///
+fn error_response(status: StatusCode, code: &str, message: &str)
+    -> Response<Full<Bytes>>
+{
+    let body = json!({"code": code, "message": message}).to_string();
+    let mut resp = Response::new(Full::new(Bytes::from(body)));
+
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json")
+    );
+
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::access::tests::unrestricted;
+    use crate::receiver::auth::tests::{
+        assert_rejected, authenticator, require_signature
+    };
+
+    fn mapping() -> Mapping {
+        Mapping {
+            location_tag: "location".into(),
+            device_tag: "device_id".into(),
+            precision: Precision::Ns,
+            clamp_timestamps: false,
+        }
+    }
+
+    #[test]
+    fn clamp_timestamps_if_requested() {
+        let point = Point::parse("env,location=room temperature=20 1000")
+            .unwrap()
+            .unwrap();
+
+        let record = mapping().to_record(&point, Precision::Ms)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.timestamp(), 1000);
+
+        let mapping = Mapping {clamp_timestamps: true, ..mapping()};
+        let record = mapping.to_record(&point, Precision::Ms)
+            .unwrap()
+            .unwrap();
+        assert_ne!(record.timestamp(), 1000);
+    }
+
+    #[tokio::test]
+    async fn authenticate_lines() {
+        let auth = authenticator();
+        let access = unrestricted();
+        let (pipeline_tx, mut pipeline_rx) = tokio::sync::mpsc::channel(2);
+
+        let text = "env,location=room,device_id=dev1 temperature=20\n\
+                    env,location=room,device_id=dev3 temperature=20";
+
+        ingest(text, &mapping(), Precision::Ns, &auth, &access, &pipeline_tx)
+            .await
+            .unwrap();
+
+        assert_rejected(&pipeline_rx.try_recv().unwrap(), "auth:unsigned:dev1");
+        assert!(!pipeline_rx.try_recv().unwrap().is_quarantined());
+
+        // 全てのデバイスに署名を要求する場合は受け付けない
+        require_signature(&auth);
+
+        let text = "env,location=room,device_id=dev3 temperature=20";
+        let result = ingest(
+            text,
+            &mapping(),
+            Precision::Ns,
+            &auth,
+            &access,
+            &pipeline_tx
+        ).await;
+
+        assert!(result.is_err());
+        assert!(pipeline_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn reject_write_without_signature() {
+        let auth = authenticator();
+        let access = unrestricted();
+        let (pipeline_tx, mut pipeline_rx) = tokio::sync::mpsc::channel(1);
+        let source = IpAddr::from([127, 0, 0, 1]);
+
+        let request = || {
+            let body = "env,location=room,device_id=dev1 temperature=20";
+
+            Request::post(WRITE_PATH)
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+
+        let resp = handle_write(
+            request(),
+            source,
+            &mapping(),
+            &auth,
+            &access,
+            &pipeline_tx
+        ).await;
+
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_rejected(&pipeline_rx.try_recv().unwrap(), "auth:unsigned:dev1");
+
+        require_signature(&auth);
+
+        let resp = handle_write(
+            request(),
+            source,
+            &mapping(),
+            &auth,
+            &access,
+            &pipeline_tx
+        ).await;
+
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(pipeline_rx.try_recv().is_err());
+    }
+}
@@ -8,5 +8,198 @@
 //! 受信処理をまとめたモジュール
 //!
 
+pub(crate) mod access;
+pub(crate) mod auth;
+pub(crate) mod coap;
+pub(crate) mod influx;
+pub(crate) mod mqtt;
+pub(crate) mod pool;
 pub(crate) mod tcp;
+pub(crate) mod tls;
 pub(crate) mod udp;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::Receiver;
+
+use crate::record::SensorRecord;
+use coap::CoapReceiverHandle;
+use influx::InfluxReceiverHandle;
+use mqtt::MqttReceiverHandle;
+use tcp::TcpReceiverHandle;
+use udp::UdpReceiverHandle;
+
+///
+/// 各レシーバタスクの制御用ハンドルをまとめる列挙子
+///
+pub(crate) enum ReceiverHandle {
+    /// TCPレシーバタスクのハンドル
+    Tcp(TcpReceiverHandle),
+
+    /// UDPレシーバタスクのハンドル
+    Udp(UdpReceiverHandle),
+
+    /// MQTTレシーバタスクのハンドル
+    Mqtt(MqttReceiverHandle),
+
+    /// CoAPレシーバタスクのハンドル
+    Coap(CoapReceiverHandle),
+
+    /// ラインプロトコルレシーバタスクのハンドル
+    Influx(InfluxReceiverHandle),
+}
+
+impl ReceiverHandle {
+    ///
+    /// タスクの終了要求の発行
+    ///
+    pub(crate) async fn shutdown(&self) {
+        match self {
+            Self::Tcp(handle) => handle.shutdown().await,
+            Self::Udp(handle) => handle.shutdown().await,
+            Self::Mqtt(handle) => handle.shutdown().await,
+            Self::Coap(handle) => handle.shutdown().await,
+            Self::Influx(handle) => handle.shutdown().await,
+        }
+    }
+}
+
+///
+/// 起動が任意のレシーバタスクの受信チャネルをラップする構造体
+///
+/// # 注記
+/// タスクが起動されていない場合と、チャネルが閉じられた後は、受信が永久に
+/// 完了しないチャネルとして振る舞う。複数のチャネルから同時に受信する際、閉
+/// じられたチャネルが他のチャネルの受信を妨げないようにするためのもの。
+///
+pub(crate) struct OptionalReceiver(Option<Receiver<SensorRecord>>);
+
+impl OptionalReceiver {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `rx` - ラップする受信チャネル(タスク未起動の場合はNone)
+    ///
+    pub(crate) fn new(rx: Option<Receiver<SensorRecord>>) -> Self {
+        Self(rx)
+    }
+
+    ///
+    /// レコードの受信
+    ///
+    /// # 戻り値
+    /// 受信したレコードを`Some()`でラップして返す。チャネルが閉じられた場合は
+    /// `None`を返す(以降の呼び出しは完了しない)。
+    ///
+    pub(crate) async fn recv(&mut self) -> Option<SensorRecord> {
+        let Some(rx) = &mut self.0 else {
+            return std::future::pending().await;
+        };
+
+        let result = rx.recv().await;
+
+        if result.is_none() {
+            self.0 = None;
+        }
+
+        result
+    }
+
+    ///
+    /// チャネルが閉じられているか否かの判定
+    ///
+    /// # 戻り値
+    /// タスクが起動されていない場合と、チャネルが閉じられた(残っていたレコー
+    /// ドを全て受信し終えた)場合は`true`を返す。
+    ///
+    pub(crate) fn is_closed(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+///
+/// 受信側の稼働状況を集計する構造体
+///
+/// # 注記
+/// 認証の失敗や受信の拒否など、事象の種別毎の発生回数を保持する。事象の種
+/// 別は呼び出し側が任意に決める。接続数のような現在値も項目毎に保持する。
+///
+pub(crate) struct ReceiverStats {
+    /// 集計対象の名前
+    name: String,
+
+    /// 事象の種別毎の発生回数
+    counters: Mutex<BTreeMap<&'static str, u64>>,
+
+    /// 項目毎の現在値
+    gauges: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl ReceiverStats {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `name` - 集計対象の名前
+    ///
+    pub(crate) fn new(name: &str) -> Arc<Self> {
+        Arc::new(Self {
+            name: name.to_string(),
+            counters: Mutex::new(BTreeMap::new()),
+            gauges: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    ///
+    /// 事象の発生の計上
+    ///
+    /// # 引数
+    /// * `kind` - 事象の種別
+    ///
+    pub(crate) fn count(&self, kind: &'static str) {
+        if let Ok(mut counters) = self.counters.lock() {
+            *counters.entry(kind).or_insert(0) += 1;
+        }
+    }
+
+    ///
+    /// 現在値の設定
+    ///
+    /// # 引数
+    /// * `kind` - 項目の名前
+    /// * `value` - 現在値
+    ///
+    pub(crate) fn set(&self, kind: &'static str, value: u64) {
+        if let Ok(mut gauges) = self.gauges.lock() {
+            gauges.insert(kind, value);
+        }
+    }
+}
+
+// Displayトレイトの実装(ログへの出力用)
+impl fmt::Display for ReceiverStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.name)?;
+
+        let Ok(gauges) = self.gauges.lock() else {
+            return Ok(());
+        };
+
+        let Ok(counters) = self.counters.lock() else {
+            return Ok(());
+        };
+
+        if gauges.is_empty() && counters.is_empty() {
+            write!(f, " (no event)")?;
+        }
+
+        for (kind, n) in gauges.iter().chain(counters.iter()) {
+            write!(f, " {}={}", kind, n)?;
+        }
+
+        Ok(())
+    }
+}
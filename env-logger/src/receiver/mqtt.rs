@@ -0,0 +1,582 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! MQTT受信処理をまとめたモジュール
+//!
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use rhexdump::rhexdumps;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+use super::access::AccessControl;
+use super::auth::Authenticator;
+use crate::record::SensorRecord;
+use crate::cmd_args::Options;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// キープアライブの間隔(秒)
+const KEEP_ALIVE: u64 = 30;
+
+/// 再接続を試みるまでの待ち時間(秒)
+const RECONNECT_INTERVAL: u64 = 5;
+
+/// クライアントからイベントループへのリクエストキューの長さ
+const REQUEST_CAPACITY: usize = 32;
+
+///
+/// タスクに対するリクエスト
+///
+/// # 注記
+/// 現時点ではシャットダウンしかないが、将来の拡張用にenumで定義しておく。
+///
+enum TaskRequest {
+    /// シャットダウン要求
+    Shutdown,
+}
+
+///
+/// 受信処理タスクをラップする構造体
+///
+pub(crate) struct MqttReceiveTask {
+    /// タスクのジョインハンドル
+    handle: JoinHandle<()>,
+
+    /// タスクへのリクエスト通知用のチャネル
+    request_tx: Sender<TaskRequest>,
+}
+
+impl MqttReceiveTask {
+    ///
+    /// タスクの開始
+    ///
+    /// # 引数
+    /// * `opts` - オプション情報をまとめたオブジェクト
+    /// * `auth` - 受信レコードの認証を行うオブジェクト
+    /// * `access` - 受信の可否を判定するオブジェクト
+    ///
+    /// # 戻り値
+    /// タスクの開始に成功した場合は、タスクにバインドされたMqttReceiveTaskの
+    /// オブジェクト(Futureトレイトを実装)と、受信レコードの受信用のチャネル
+    /// オブジェクトをパックしたタプルを`Ok()`でラップして返す。
+    /// 失敗した場合はエラー情報を `Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// ブローカーへの接続はタスク内で行うため、起動時にブローカーが停止してい
+    /// てもエラーにはならない(接続できるまで再接続を繰り返す)。
+    ///
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        auth: Arc<Authenticator>,
+        access: Arc<AccessControl>,
+    ) -> Result<(Self, Receiver<SensorRecord>)>
+    {
+        let (host, port) = match opts.mqtt_broker() {
+            Some(broker) => broker,
+            None => return Err(anyhow!("MQTT broker is not specified")),
+        };
+
+        /*
+         * トピックフィルタのパース
+         */
+        let mut patterns = vec![];
+
+        for filter in opts.mqtt_topics() {
+            patterns.push(TopicPattern::parse(&filter)?);
+        }
+
+        /*
+         * 接続オプションの設定
+         *
+         * 切断中に配送されたQoS1のメッセージをブローカー側で保持させるため、
+         * クリーンセッションは無効にしておく。また、パイプラインへの投入が完
+         * 了するまでPUBACKを返さないように手動ACKを有効にしておく。
+         */
+        let mut mqtt_opts = MqttOptions::new(opts.mqtt_client_id(), &host, port);
+
+        mqtt_opts
+            .set_keep_alive(Duration::from_secs(KEEP_ALIVE))
+            .set_clean_session(false)
+            .set_manual_acks(true);
+
+        if let Some((user, password)) = opts.mqtt_credentials() {
+            mqtt_opts.set_credentials(user, password);
+        }
+
+        let (client, eventloop) = AsyncClient::new(mqtt_opts, REQUEST_CAPACITY);
+
+        info!("MQTT broker is {}:{}", host, port);
+
+        /*
+         * チャネルオブジェクトの生成
+         */
+        let (pipeline_tx, pipeline_rx) = tokio::sync::mpsc::channel(10);
+        let (request_tx, request_rx) = tokio::sync::mpsc::channel(5);
+
+        /*
+         * リスナータスクの起動
+         */
+        let handle = tokio::spawn(listener_task(
+            client,
+            eventloop,
+            patterns,
+            auth,
+            access,
+            pipeline_tx,
+            request_rx,
+        ));
+
+        /*
+         * 戻り値の生成
+         */
+        Ok((Self {handle, request_tx}, pipeline_rx))
+    }
+
+    ///
+    /// 制御用ハンドルの取得
+    ///
+    /// # 戻り値
+    /// 制御用ハンドルオブジェクトを返す。
+    ///
+    pub(crate) fn handle(&self) -> MqttReceiverHandle {
+        MqttReceiverHandle {request_tx: self.request_tx.clone()}
+    }
+}
+
+// Futureトレイトの実装
+impl Future for MqttReceiveTask {
+    type Output = std::result::Result<(), tokio::task::JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().handle).poll(cx)
+    }
+}
+
+///
+/// レシーバタスク制御用のハンドル構造体
+///
+pub(crate) struct MqttReceiverHandle {
+    /// シャットダウン要求送信用オブジェクト
+    request_tx: Sender<TaskRequest>,
+}
+
+impl MqttReceiverHandle {
+    ///
+    /// タスクの終了要求の発行
+    ///
+    pub(crate) async fn shutdown(&self) {
+        let _ = self.request_tx.send(TaskRequest::Shutdown).await;
+    }
+}
+
+///
+/// トピックフィルタの1セグメントを表す列挙子
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// 固定文字列
+    Literal(String),
+
+    /// 単一レベルのワイルドカード("+")
+    SingleLevel,
+
+    /// 複数レベルのワイルドカード("#")
+    MultiLevel,
+
+    /// 設置場所を取り出すワイルドカード("{location}")
+    Location,
+
+    /// デバイスIDを取り出すワイルドカード("{device_id}")
+    DeviceId,
+}
+
+///
+/// 購読するトピックフィルタを表す構造体
+///
+#[derive(Debug, Clone)]
+struct TopicPattern {
+    /// ブローカーに登録するトピックフィルタ
+    filter: String,
+
+    /// セグメント毎のマッチング条件
+    segments: Vec<Segment>,
+}
+
+impl TopicPattern {
+    ///
+    /// トピックフィルタのパース
+    ///
+    /// # 引数
+    /// * `src` - "{location}"/"{device_id}"を含み得るトピックフィルタ
+    ///
+    /// # 戻り値
+    /// パースに成功した場合は、TopicPatternオブジェクトを`Ok()`でラップして
+    /// 返す。失敗した場合はエラー情報を`Err()`でラップして返す。
+    ///
+    fn parse(src: &str) -> Result<Self> {
+        if src.is_empty() {
+            return Err(anyhow!("empty MQTT topic filter"));
+        }
+
+        let items: Vec<&str> = src.split('/').collect();
+        let mut segments = vec![];
+
+        for (i, item) in items.iter().enumerate() {
+            let segment = match *item {
+                "+" => Segment::SingleLevel,
+                "#" => Segment::MultiLevel,
+                "{location}" => Segment::Location,
+                "{device_id}" => Segment::DeviceId,
+                _ => Segment::Literal(item.to_string()),
+            };
+
+            if let Segment::Literal(s) = &segment {
+                if s.contains(['+', '#', '{', '}']) {
+                    return Err(anyhow!("invalid MQTT topic filter: {}", src));
+                }
+            }
+
+            if segment == Segment::MultiLevel && i != items.len() - 1 {
+                return Err(anyhow!("'#' must be the last level: {}", src));
+            }
+
+            if segments.contains(&segment)
+                && matches!(segment, Segment::Location | Segment::DeviceId)
+            {
+                return Err(anyhow!("duplicate placeholder: {}", src));
+            }
+
+            segments.push(segment);
+        }
+
+        let filter = segments.iter()
+            .map(|segment| match segment {
+                Segment::Literal(s) => s.as_str(),
+                Segment::MultiLevel => "#",
+                _ => "+",
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Ok(Self {filter, segments})
+    }
+
+    ///
+    /// トピック名とのマッチング
+    ///
+    /// # 引数
+    /// * `topic` - メッセージのトピック名
+    ///
+    /// # 戻り値
+    /// マッチした場合は、トピック名から取り出した設置場所とデバイスIDのタプル
+    /// を`Some()`でラップして返す。
+    ///
+    fn capture(&self, topic: &str)
+        -> Option<(Option<String>, Option<String>)>
+    {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let mut location = None;
+        let mut device_id = None;
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            if *segment == Segment::MultiLevel {
+                return Some((location, device_id));
+            }
+
+            let level = levels.get(i)?;
+
+            match segment {
+                Segment::Literal(s) if s != level => return None,
+                Segment::Location => location = Some(level.to_string()),
+                Segment::DeviceId => device_id = Some(level.to_string()),
+                _ => {}
+            }
+        }
+
+        if levels.len() == self.segments.len() {
+            Some((location, device_id))
+        } else {
+            None
+        }
+    }
+}
+
+///
+/// イベントループへの送信待ちのリクエストを表す列挙子
+///
+/// # 注記
+/// イベントループはポーリング時に受信済みのイベントを返し終えるまでリクエ
+/// ストキューを処理しない。ポーリングを行うタスク自身がキューの空きを待つ
+/// とデッドロックするので、キューが満杯の場合はリクエストを保持しておき、
+/// 次のポーリングの後に送り直す。
+///
+enum PendingRequest {
+    /// トピックフィルタの購読登録
+    Subscribe(String),
+
+    /// 受信したメッセージに対するPUBACK
+    Ack(Publish),
+}
+
+///
+/// MQTT受信処理を行うタスク
+///
+/// # 引数
+/// * `client` - MQTTクライアントオブジェクト
+/// * `eventloop` - MQTTクライアントのイベントループ
+/// * `patterns` - 購読するトピックフィルタのリスト
+/// * `auth` - 受信レコードの認証を行うオブジェクト
+/// * `access` - 受信の可否を判定するオブジェクト
+/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+/// * `request_rx` - リクエスト受信用チャネルオブジェクト
+///
+/// # 注記
+/// 接続エラー発生時はRECONNECT_INTERVAL秒待ってから再接続を試みる。再接続は
+/// イベントループのポーリングを継続することでrumqttcによって行われる。
+/// イベントループへのリクエストはキューの空きを待たずに送り、送れなかった
+/// ものはポーリングの度に送り直す。
+///
+async fn listener_task(
+    client: AsyncClient,
+    mut eventloop: EventLoop,
+    patterns: Vec<TopicPattern>,
+    auth: Arc<Authenticator>,
+    access: Arc<AccessControl>,
+    pipeline_tx: Sender<SensorRecord>,
+    mut request_rx: Receiver<TaskRequest>,
+)
+{
+    info!("start MQTT receiver task");
+
+    let mut pending = VecDeque::new();
+
+    loop {
+        tokio::select! {
+            // イベントループにイベントが届いた場合
+            event = eventloop.poll() => {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                        info!("connected to MQTT broker");
+
+                        // ブローカー側にセッションが残っていない場合のみ購読
+                        // 手続きを行う
+                        if !ack.session_present {
+                            for pattern in &patterns {
+                                pending.push_back(PendingRequest::Subscribe(
+                                    pattern.filter.clone()
+                                ));
+                            }
+                        }
+                    }
+
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        handle_publish(
+                            &publish,
+                            &patterns,
+                            &auth,
+                            &access,
+                            &pipeline_tx
+                        ).await;
+
+                        // パイプラインへの投入(あるいは破棄)が完了してから
+                        // PUBACKを返す
+                        pending.push_back(PendingRequest::Ack(publish));
+                    }
+
+                    Ok(_) => { /* ignore */ }
+
+                    Err(err) => {
+                        error!("MQTT connection error: {}", err);
+
+                        tokio::select! {
+                            _ = sleep(Duration::from_secs(RECONNECT_INTERVAL))
+                                => {}
+
+                            request = request_rx.recv() => {
+                                match request {
+                                    Some(TaskRequest::Shutdown) => break,
+                                    None => { /* ignore */ }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                send_requests(&client, &mut pending);
+            }
+
+            // 制御チャネルにリクエストが届いた場合
+            request = request_rx.recv() => {
+                match request {
+                    Some(TaskRequest::Shutdown) => break,
+                    None => { /* ignore */ }
+                }
+            }
+        }
+    }
+
+    /*
+     * ブローカーからの切断
+     */
+    if client.try_disconnect().is_ok() {
+        let _ = tokio::time::timeout(
+            Duration::from_secs(1),
+            eventloop.poll()
+        ).await;
+    }
+
+    info!("shutdown MQTT receiver task");
+}
+
+///
+/// 送信待ちのリクエストのイベントループへの送信
+///
+/// # 引数
+/// * `client` - MQTTクライアントオブジェクト
+/// * `pending` - 送信待ちのリクエストのキュー
+///
+/// # 注記
+/// リクエストキューが満杯になった時点で送信を止め、残りは次回に送る(PUBACK
+/// は受信した順に送る)。
+///
+fn send_requests(client: &AsyncClient, pending: &mut VecDeque<PendingRequest>) {
+    while let Some(request) = pending.front() {
+        let result = match request {
+            PendingRequest::Subscribe(filter) => {
+                client.try_subscribe(filter.as_str(), QoS::AtLeastOnce)
+            }
+
+            PendingRequest::Ack(publish) => client.try_ack(publish),
+        };
+
+        if let Err(err) = result {
+            debug!("{} ({} request(s) pending)", err, pending.len());
+            break;
+        }
+
+        if let PendingRequest::Subscribe(filter) = request {
+            info!("subscribe {}", filter);
+        }
+
+        pending.pop_front();
+    }
+}
+
+///
+/// 受信メッセージの処理
+///
+/// # 引数
+/// * `publish` - 受信したPUBLISHパケット
+/// * `patterns` - 購読しているトピックフィルタのリスト
+/// * `auth` - 受信レコードの認証を行うオブジェクト
+/// * `access` - 受信の可否を判定するオブジェクト
+/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+///
+/// # 注記
+/// ペイロードは署名付きエンベロープも受け付ける。トピック名から取り出した
+/// 設置場所とデバイスIDは、ペイロードに該当するプロパティが含まれていない
+/// 場合の補完に用いる。デバイスの流量の制限を超えた
+/// メッセージは破棄する(再送させても流量は下がらないので、PUBACKは返す)。
+///
+async fn handle_publish(
+    publish: &Publish,
+    patterns: &[TopicPattern],
+    auth: &Authenticator,
+    access: &AccessControl,
+    pipeline_tx: &Sender<SensorRecord>,
+)
+{
+    debug!(
+        "received data from {}:\n{}",
+        publish.topic,
+        rhexdumps!(&publish.payload)
+    );
+
+    let (location, device_id) = patterns.iter()
+        .find_map(|pattern| pattern.capture(&publish.topic))
+        .unwrap_or_default();
+
+    let json = match std::str::from_utf8(&publish.payload) {
+        Ok(json) => json,
+        Err(err) => {
+            error!("invalid JSON received: {}", err);
+            return;
+        }
+    };
+
+    let record = match auth.open_with_hint(
+        json,
+        location.as_deref(),
+        device_id.as_deref(),
+    ) {
+        Ok(record) => record,
+        Err(err) => {
+            error!("invalid JSON received on {}: {}", publish.topic, err);
+            return;
+        }
+    };
+
+    if !access.admit_device(&record) {
+        return;
+    }
+
+    if let Err(err) = pipeline_tx.send(record).await {
+        error!("send sensor result failed: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::access::tests::unrestricted;
+    use crate::receiver::auth::tests::{
+        assert_rejected, authenticator, envelope, now
+    };
+
+    #[tokio::test]
+    async fn authenticate_messages() {
+        let auth = authenticator();
+        let access = unrestricted();
+        let filter = "envlog/{location}/{device_id}";
+        let patterns = [TopicPattern::parse(filter).unwrap()];
+        let (pipeline_tx, mut pipeline_rx) = tokio::sync::mpsc::channel(1);
+
+        let mut publish = async |payload: &str| {
+            let publish = Publish::new(
+                "envlog/room/dev1",
+                QoS::AtLeastOnce,
+                payload
+            );
+
+            handle_publish(&publish, &patterns, &auth, &access, &pipeline_tx)
+                .await;
+
+            pipeline_rx.try_recv().unwrap()
+        };
+
+        // トピック名から補完したデバイスIDにも署名を要求する
+        let record = publish(r#"{"temperature":20}"#).await;
+        assert_rejected(&record, "auth:unsigned:dev1");
+        assert_eq!(record.location(), "room");
+
+        let payload = r#"{"temperature":20}"#;
+        let data = envelope("dev1", now(), "n1", payload, "secret");
+        let record = publish(&data).await;
+        assert!(!record.is_quarantined());
+        assert_eq!(record.location(), "room");
+    }
+}
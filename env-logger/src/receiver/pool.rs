@@ -0,0 +1,112 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! データグラム処理のワーカープールをまとめたモジュール
+//!
+//! データグラム型の待ち受けは、受信したデータグラムを有限長のキューを介して
+//! 固定数のワーカーで処理する。受信毎にタスクを生成しないので、大量のデータ
+//! グラムを受けても処理中のタスク数とメモリの使用量は一定に保たれる。キュー
+//! が満杯の場合は投入を拒否するので、呼び出し側で破棄して計上する。
+//!
+
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+///
+/// ワーカープールを表す構造体
+///
+pub(crate) struct WorkerPool<T> {
+    /// プールの名前(ログ出力用)
+    name: &'static str,
+
+    /// ワーカーのキューへの投入用チャネル
+    queue_tx: Sender<T>,
+
+    /// ワーカーのジョインハンドル
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    ///
+    /// ワーカーの起動
+    ///
+    /// # 引数
+    /// * `name` - プールの名前(ログ出力用)
+    /// * `workers` - ワーカーの数
+    /// * `queue_size` - キューの長さ
+    /// * `worker` - キューから取り出した要素を処理する関数
+    ///
+    pub(crate) fn start<F, Fut>(
+        name: &'static str,
+        workers: usize,
+        queue_size: usize,
+        worker: F,
+    ) -> Self
+    where
+        F: Fn(T) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (queue_tx, queue_rx) = mpsc::channel(queue_size);
+        let queue_rx = Arc::new(Mutex::new(queue_rx));
+
+        let handles = (0..workers)
+            .map(|_| {
+                let queue_rx = queue_rx.clone();
+                let worker = worker.clone();
+
+                tokio::spawn(async move {
+                    loop {
+                        let Some(item) = queue_rx.lock().await.recv().await else {
+                            break;
+                        };
+
+                        worker(item).await;
+                    }
+                })
+            })
+            .collect();
+
+        Self {name, queue_tx, handles}
+    }
+
+    ///
+    /// キューへの投入
+    ///
+    /// # 戻り値
+    /// キューが満杯の場合は投入しようとした要素をエラー情報と共に`Err()`で
+    /// ラップして返す。
+    ///
+    pub(crate) fn submit(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.queue_tx.try_send(item)
+    }
+
+    ///
+    /// ワーカーの終了待ち
+    ///
+    /// # 注記
+    /// キューに残っている要素を処理し終えてからワーカーが終了する。
+    ///
+    pub(crate) async fn join(self) {
+        let Self {name, queue_tx, handles} = self;
+
+        drop(queue_tx);
+
+        for handle in handles {
+            if let Err(err) = handle.await {
+                warn!("{} worker has been troubled: {}", name, err);
+            }
+        }
+    }
+}
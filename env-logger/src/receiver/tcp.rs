@@ -7,28 +7,123 @@
 //!
 //! TCP受信処理をまとめたモジュール
 //!
+//! TLSを使用する場合のハンドシェイクは`tls`モジュールで行う。
+//!
+//! 1接続で1行(1レコード)を受信し、受け付けた場合は"OK"を、受け付けなかっ
+//! た場合は"NG"に続けて以下の理由コードを1行で応答する。
+//!
+//! | 理由コード     | 内容                                           |
+//! |----------------|------------------------------------------------|
+//! | `busy`         | 同時接続数が上限に達している                   |
+//! | `too_long`     | 行の長さが上限を超えた                         |
+//! | `timeout`      | 受信期限までに1行を受信できなかった            |
+//! | `rate_limited` | デバイスの流量の制限を超えた                   |
+//! | `invalid`      | レコードとして解釈できない                     |
+//!
+//! TLSを使用している場合、`busy`はハンドシェイク前に判定するため応答せずに
+//! 切断する。拒否した件数は理由コード毎に稼働状況として計上する。
+//!
 
 use std::future::Future;
+use std::io::Write;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
 use rhexdump::rhexdumps;
-use tokio::io::{AsyncWriteExt, AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::task::JoinHandle;
-use tokio::time::{timeout, Duration};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{timeout, timeout_at, Duration};
 
+use super::access::AccessControl;
+use super::auth::Authenticator;
+use super::tls::TlsServer;
 use crate::record::SensorRecord;
 use crate::cmd_args::Options;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 
-/// データ受信タイムアウト(秒)
-const DATA_TIMEOUT: u64 = 10;
+/// レコードを受け付けた場合の応答
+const ACK_OK: &[u8] = b"OK\n";
+
+///
+/// レコードを受け付けなかった理由
+///
+#[derive(Debug, Clone, Copy)]
+enum Reject {
+    /// 同時接続数が上限に達している
+    Busy,
+
+    /// 行の長さが上限を超えた
+    TooLong,
+
+    /// 受信期限までに1行を受信できなかった
+    Timeout,
+
+    /// デバイスの流量の制限を超えた
+    RateLimited,
+
+    /// レコードとして解釈できない
+    Invalid,
+}
+
+impl Reject {
+    ///
+    /// 理由コードの取得
+    ///
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Busy => "busy",
+            Self::TooLong => "too_long",
+            Self::Timeout => "timeout",
+            Self::RateLimited => "rate_limited",
+            Self::Invalid => "invalid",
+        }
+    }
+
+    ///
+    /// 応答の生成
+    ///
+    fn reply(&self) -> Vec<u8> {
+        format!("NG {}\n", self.code()).into_bytes()
+    }
+}
+
+///
+/// 各セッションで共有する情報をまとめた構造体
+///
+struct Shared {
+    /// 受信レコードの認証を行うオブジェクト
+    auth: Arc<Authenticator>,
+
+    /// TLSの接続を受け付けるオブジェクト(TLS未使用時はNone)
+    tls: Option<Arc<TlsServer>>,
+
+    /// 受信の可否を判定するオブジェクト
+    access: Arc<AccessControl>,
+
+    /// 同時接続数を制限するセマフォ
+    connections: Arc<Semaphore>,
+
+    /// 1行の長さの上限(バイト)
+    max_line_length: usize,
+
+    /// 接続の受け付けから1行を受信し終えるまでの期限
+    read_timeout: Duration,
+
+    /// 終了時に処理中のセッションの完了を待つ期限
+    shutdown_timeout: Duration,
+
+    /// 受信レコード送信用チャネルオブジェクト
+    pipeline_tx: Sender<SensorRecord>,
+}
 
 ///
 /// タスクに対するリクエスト
@@ -58,6 +153,10 @@ impl TcpReceiveTask {
     ///
     /// # 引数
     /// * `opts` - オプション情報をまとめたオブジェクト
+    /// * `auth` - 受信レコードの認証を行うオブジェクト
+    /// * `tls` - TLSの接続を受け付けるオブジェクト(TLS未使用時はNone)
+    /// * `access` - 受信の可否を判定するオブジェクト
+    /// * `listener` - systemdから渡された待ち受けソケット(無い場合はNone)
     ///
     /// # 戻り値
     /// タスクの開始に成功した場合は、タスクにバインドされたTcpReceiveTaskのオ
@@ -65,18 +164,42 @@ impl TcpReceiveTask {
     /// ジェクトをパックしたタプルを`Ok()`でラップして返す。
     /// 失敗した場合はエラー情報を `Err()`でラップして返す。
     ///
-    pub(crate) async fn start(opts: Arc<Options>)
-        -> Result<(Self, Receiver<SensorRecord>)>
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        auth: Arc<Authenticator>,
+        tls: Option<Arc<TlsServer>>,
+        access: Arc<AccessControl>,
+        listener: Option<std::net::TcpListener>,
+    ) -> Result<(Self, Receiver<SensorRecord>)>
     {
         /*
          * リスナーオブジェクトの生成(TCPポートのバインド)
+         *
+         * systemdからソケットが渡されている場合はそれを用いる。
          */
-        let sock = match TcpListener::bind(opts.endpoint()).await {
-            Ok(sock) => sock,
-            Err(err) => return Err(anyhow!("bind failed: {}", err)),
+        let sock = match listener {
+            Some(listener) => {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)?
+            }
+
+            None => match TcpListener::bind(opts.endpoint()).await {
+                Ok(sock) => sock,
+                Err(err) => return Err(anyhow!("bind failed: {}", err)),
+            },
         };
 
-        info!("success bind to {}", opts.endpoint());
+        info!("success bind to {}", sock.local_addr()?);
+
+        info!(
+            concat!(
+                "TCP limits: max_connections={} max_line_length={} ",
+                "read_timeout={}s"
+            ),
+            opts.tcp_max_connections(),
+            opts.tcp_max_line_length(),
+            opts.tcp_read_timeout(),
+        );
 
         /*
          * チャネルオブジェクトの生成
@@ -87,11 +210,18 @@ impl TcpReceiveTask {
         /*
          * リスナータスクの起動
          */
-        let handle =tokio::spawn(listener_task(
-            sock,
+        let shared = Arc::new(Shared {
+            auth,
+            tls,
+            access,
+            connections: Arc::new(Semaphore::new(opts.tcp_max_connections())),
+            max_line_length: opts.tcp_max_line_length(),
+            read_timeout: Duration::from_secs(opts.tcp_read_timeout()),
+            shutdown_timeout: Duration::from_secs(opts.shutdown_timeout()),
             pipeline_tx,
-            request_rx,
-        ));
+        });
+
+        let handle = tokio::spawn(listener_task(sock, shared, request_rx));
 
         /*
          * 戻り値の生成
@@ -141,31 +271,53 @@ impl TcpReceiverHandle {
 ///
 /// # 引数
 /// * `sock` - TCPポートにバインドされたリスナーソケットオブジェクト
-/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+/// * `shared` - 各セッションで共有する情報
 /// * `shutdown_rx` - シャットダウン要求受信用チャネルオブジェクト
 ///
+/// # 注記
+/// シャットダウン要求を受けた場合は新たな接続の受け付けを止め、処理中のセッ
+/// ションの完了を期限まで待つ。期限までに完了しなかったセッションは打ち切
+/// る。
+///
 async fn listener_task(
     sock: TcpListener,
-    pipeline_tx: Sender<SensorRecord>,
+    shared: Arc<Shared>,
     mut request_rx: Receiver<TaskRequest>,
 )
 {
     info!("start TCP receiver task");
 
-    // レコード送信チャネルを複製できるようにArcでラップ
-    let pipeline_tx = Arc::new(pipeline_tx);
+    let mut sessions = JoinSet::new();
+    let stats = shared.access.stats();
 
     loop {
+        // 現在の接続数を稼働状況に反映する
+        stats.set("connections", sessions.len() as u64);
+
         tokio::select! {
             // バインドポートへの接続があった場合
             result = sock.accept() => {
                 match result {
                     Ok((sock, addr)) => {
+                        // 受信を許可しない送信元は応答せずに切断する
+                        if !shared.access.admit_source(addr.ip()) {
+                            continue;
+                        }
+
+                        // 同時接続数が上限に達している場合は即座に拒否する
+                        let connections = shared.connections.clone();
+                        let Ok(permit) = connections.try_acquire_owned() else {
+                            debug!("connection from {:?} rejected: busy", addr);
+                            reject_busy(sock, &shared);
+                            continue;
+                        };
+
                         info!("connection from: {:?}", addr);
 
-                        tokio::spawn(session_task(
+                        sessions.spawn(accept_task(
                             sock,
-                            pipeline_tx.clone()
+                            permit,
+                            shared.clone()
                         ));
                     }
 
@@ -173,6 +325,9 @@ async fn listener_task(
                 }
             }
 
+            // 終了したセッションの回収
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+
             // 制御チャネルにリクエストが届いた場合
             request = request_rx.recv() => {
                 match request {
@@ -183,44 +338,186 @@ async fn listener_task(
         }
     }
 
+    /*
+     * 処理中のセッションの完了待ち
+     */
+    drop(sock);
+
+    if !sessions.is_empty() {
+        info!("waiting for {} TCP session(s)", sessions.len());
+    }
+
+    let result = timeout(shared.shutdown_timeout, async {
+        while sessions.join_next().await.is_some() {}
+    }).await;
+
+    if result.is_err() {
+        warn!("{} TCP session(s) aborted", sessions.len());
+        sessions.shutdown().await;
+    }
+
+    stats.set("connections", sessions.len() as u64);
+
     info!("shutdown TCP receiver task");
 }
 
 ///
-/// セッション処理を行うタスク
+/// 同時接続数の上限に達している場合の接続の拒否
+///
+/// # 注記
+/// 応答はソケットの送信バッファに書き込めた場合のみ行う(書き込みを待たな
+/// い)。TLSを使用している場合は応答せずに切断する。
+///
+fn reject_busy(sock: TcpStream, shared: &Shared) {
+    shared.access.stats().count(Reject::Busy.code());
+
+    if shared.tls.is_none() {
+        if let Ok(mut sock) = sock.into_std() {
+            let _ = sock.write(&Reject::Busy.reply());
+        }
+    }
+}
+
+///
+/// 接続の受け付けを行うタスク
 ///
 /// # 引数
-/// * `sock` - TCPセッションタスク
-/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+/// * `sock` - 接続を受け付けたソケットオブジェクト
+/// * `permit` - 同時接続数の枠(セッションの終了まで保持する)
+/// * `shared` - 各セッションで共有する情報
 ///
-async fn session_task(
+/// # 注記
+/// TLSを使用する場合はハンドシェイクを行ってからセッション処理に移る。ハン
+/// ドシェイクに失敗した場合は応答せずに切断する。受信期限はハンドシェイク
+/// を含めて接続の受け付け時点から数える。
+///
+async fn accept_task(
     sock: TcpStream,
-    pipeline_tx: Arc<Sender<SensorRecord>>
-) 
+    permit: OwnedSemaphorePermit,
+    shared: Arc<Shared>,
+)
+{
+    let deadline = Instant::now() + shared.read_timeout;
+
+    let Some(tls) = shared.tls.clone() else {
+        session_task(sock, None, &shared, deadline).await;
+        drop(permit);
+        return;
+    };
+
+    match timeout_at(deadline.into(), tls.accept(sock)).await {
+        Ok(Ok((stream, device_id))) => {
+            if let Some(id) = &device_id {
+                debug!("client certificate for {}", id);
+            }
+
+            session_task(stream, device_id, &shared, deadline).await;
+        }
+
+        Ok(Err(err)) => error!("{}", err),
+
+        Err(err) => {
+            error!("TLS handshake timeout: {}", err);
+            shared.access.stats().count(Reject::Timeout.code());
+        }
+    }
+
+    drop(permit);
+}
+
+///
+/// セッション処理
+///
+/// # 引数
+/// * `sock` - TCPセッションのストリーム(TLS使用時はTLSのストリーム)
+/// * `device_id` - クライアント証明書から得たデバイスID
+/// * `shared` - 各セッションで共有する情報
+/// * `deadline` - 受信期限
+///
+/// # 注記
+/// レコードをパイプラインに引き渡した時点で"OK"を、受け付けなかった場合は
+/// "NG"と理由コードを1行で応答してからセッションを切断する(パイプラインへ
+/// の引き渡しに失敗した場合は応答しない)。デバイスは応答を読まずに切断し
+/// ても構わない。中継モードのenv-loggerは応答を転送完了の確認に用いる。
+///
+async fn session_task<S>(
+    mut sock: S,
+    device_id: Option<String>,
+    shared: &Shared,
+    deadline: Instant,
+)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
 {
     /*
-     * クライアントからのデータを受信
+     * クライアントからのデータを受信し、パイプラインへ引き渡す
      */
-    let duration = Duration::from_secs(DATA_TIMEOUT);
-    let record = match timeout(duration, receive_record(sock)).await {
-        Ok(Ok(record)) => record,
+    let result = timeout_at(
+        deadline.into(),
+        receive_record(&mut sock, &shared.auth, shared.max_line_length)
+    ).await;
+
+    let result = match result {
+        Ok(Ok(mut record)) => {
+            /*
+             * クライアント証明書のデバイスIDを優先する(中継済みのレコード
+             * は中継元で認証済みなので除く)
+             */
+            if let Some(id) = device_id.filter(|_| !record.is_relayed()) {
+                if record.device_id().is_some_and(|claimed| claimed != id) {
+                    warn!("device ID overridden by client certificate: {}", id);
+                }
+
+                record.set_device_id(id);
+            }
 
-        Ok(Err(err)) => {
-            error!("{}", err);
-            return;
+            if shared.access.admit_device(&record) {
+                Ok(record)
+            } else {
+                Err(Reject::RateLimited)
+            }
         }
 
+        Ok(Err(reject)) => Err(reject),
+
         Err(err) => {
             error!("data receive timeout: {}", err);
-            return;
+            Err(Reject::Timeout)
+        }
+    };
+
+    let reply = match result {
+        Ok(record) => {
+            if let Err(err) = shared.pipeline_tx.send(record).await {
+                error!("send sensor result failed: {}", err);
+                return;
+            }
+
+            ACK_OK.to_vec()
+        }
+
+        Err(reject) => {
+            // 流量の制限は判定時に計上済み
+            if !matches!(reject, Reject::RateLimited) {
+                shared.access.stats().count(reject.code());
+            }
+
+            reject.reply()
         }
     };
 
     /*
-     * 受信したレコードの送信
+     * 応答の送信とセッションの切断
      */
-    if let Err(err) = pipeline_tx.send(record).await {
-        error!("send sensor result failed: {}", err);
+    let result = timeout(shared.read_timeout, async {
+        sock.write_all(&reply).await?;
+        sock.shutdown().await
+    }).await;
+
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => debug!("TCP reply failed: {}", err),
+        Err(err) => debug!("TCP reply timeout: {}", err),
     }
 }
 
@@ -229,48 +526,102 @@ async fn session_task(
 ///
 /// # 引数
 /// * `sock` - ソケットオブジェクト
+/// * `auth` - 受信レコードの認証を行うオブジェクト
+/// * `max_length` - 1行の長さの上限(バイト)
 ///
 /// # 戻り値
-/// 受信に成功した場合は受信したJSONを`Ok()`でラップして返す。
+/// 受信に成功した場合は受信したレコードを`Ok()`でラップして返す。失敗した
+/// 場合は理由を`Err()`でラップして返す。
 ///
-/// # 概要、
+/// # 注記
+/// 行の長さが上限を超えた場合は、それ以上読み込まずに受信を打ち切る。
 ///
-async fn receive_record(mut sock: TcpStream) -> Result<SensorRecord> {
-    let reader = BufReader::new(&mut sock);
+async fn receive_record<S>(
+    sock: &mut S,
+    auth: &Authenticator,
+    max_length: usize,
+) -> std::result::Result<SensorRecord, Reject>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut reader = BufReader::new(sock).take(max_length as u64 + 1);
+    let mut line = vec![];
 
     /*
      * 1行分のデータを受信
      */
-    let ret = match reader.lines().next_line().await {
-        Ok(result) => {
-            if let Some(json) = result {
-                /*
-                 * データが受信できたらJSONとしてパース
-                 */
-                debug!("received data:\n{}", rhexdumps!(&json));
-
-                match SensorRecord::from_json(&json) {
-                    Ok(record) => Ok(record),
-                    Err(err) => Err(anyhow!("parse JSON failed: {}", err)),
-                }
+    if let Err(err) = reader.read_until(b'\n', &mut line).await {
+        error!("TCP receive failed: {}", err);
+        return Err(Reject::Invalid);
+    }
 
-            } else {
-                /*
-                 * データが受信できていなかったらエラー
-                 */
-                Err(anyhow!("receive data is empty"))
-            }
-        } 
+    if line.ends_with(b"\n") {
+        line.pop();
 
-        Err(err) => Err(anyhow!("TCP receive failed: {}", err)),
-    };
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+
+    } else if line.len() > max_length {
+        warn!("receive data exceeds {} bytes", max_length);
+        return Err(Reject::TooLong);
+    }
+
+    if line.is_empty() {
+        error!("receive data is empty");
+        return Err(Reject::Invalid);
+    }
 
     /*
-     * 後始末としてセッションを切断
+     * データが受信できたらJSONとしてパース
      */
-    if let Err(err) = sock.shutdown().await {
-        return Err(anyhow!("TCP socket shutdown failed: {}", err));
+    debug!("received data:\n{}", rhexdumps!(&line));
+
+    let json = match String::from_utf8(line) {
+        Ok(json) => json,
+        Err(err) => {
+            error!("parse JSON failed: {}", err);
+            return Err(Reject::Invalid);
+        }
+    };
+
+    match auth.open(&json) {
+        Ok(record) => Ok(record),
+        Err(err) => {
+            error!("parse JSON failed: {}", err);
+            Err(Reject::Invalid)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::auth::tests::{
+        assert_rejected, authenticator, envelope, now
+    };
+
+    async fn receive(auth: &Authenticator, data: &str) -> SensorRecord {
+        let line = format!("{}\n", data);
+
+        match receive_record(&mut line.as_bytes(), auth, 1024).await {
+            Ok(record) => record,
+            Err(_) => panic!("record rejected: {}", data),
+        }
     }
 
-    ret
+    #[tokio::test]
+    async fn authenticate_records() {
+        let auth = authenticator();
+
+        let data = r#"{"location":"room","device_id":"dev1"}"#;
+        let record = receive(&auth, data).await;
+        assert_rejected(&record, "auth:unsigned:dev1");
+
+        let payload = r#"{"location":"room","temperature":20}"#;
+        let data = envelope("dev1", now(), "n1", payload, "secret");
+        let record = receive(&auth, &data).await;
+        assert!(!record.is_quarantined());
+        assert_eq!(record.device_id().as_deref(), Some("dev1"));
+    }
 }
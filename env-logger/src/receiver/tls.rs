@@ -0,0 +1,248 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! TCP受信のTLS処理をまとめたモジュール
+//!
+//! `--tls-cert`と`--tls-key`を指定すると、TCPの待ち受けはTLSでのみ接続を受け
+//! 付ける。さらに`--tls-client-ca`を指定した場合は、そのCAが発行したクライ
+//! アント証明書を要求する(相互TLS認証)。
+//!
+//! 相互TLS認証では、クライアント証明書のSAN(最初のDNS名)またはCNをデバイス
+//! IDとみなし、レコードの`device_id`を上書きする。
+//!
+//! 証明書と鍵はSIGHUPで読み込み直す。読み込みに失敗した場合は従前のものを
+//! 使い続ける(確立済みのセッションには影響しない)。
+//!
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+use rustls::{RootCertStore, ServerConfig};
+use rustls::crypto::ring::default_provider;
+use rustls::server::WebPkiClientVerifier;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_pki_types::pem::PemObject;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use x509_parser::extensions::GeneralName;
+use x509_parser::parse_x509_certificate;
+
+use crate::cmd_args::Options;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+///
+/// TLSの接続を受け付ける構造体
+///
+/// # 注記
+/// TCPの受信処理と設定の再読み込み処理で共有し、再読み込み時には`reload()`
+/// でアクセプタを入れ替える。
+///
+pub(crate) struct TlsServer {
+    /// サーバ証明書のパス
+    cert: PathBuf,
+
+    /// 秘密鍵のパス
+    key: PathBuf,
+
+    /// クライアント証明書の検証に用いるCA証明書のパス
+    client_ca: Option<PathBuf>,
+
+    /// 現在のアクセプタ
+    acceptor: RwLock<TlsAcceptor>,
+}
+
+impl TlsServer {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `opts` - オプション情報をまとめたオブジェクト
+    ///
+    /// # 戻り値
+    /// TLSを使用する場合はオブジェクトを`Ok(Some())`でラップして返す。使用
+    /// しない場合は`Ok(None)`を返す。証明書や鍵の読み込みに失敗した場合はエ
+    /// ラー情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn new(opts: &Options) -> Result<Option<Arc<Self>>> {
+        let Some((cert, key)) = opts.tls_identity() else {
+            return Ok(None);
+        };
+
+        let client_ca = opts.tls_client_ca();
+        let acceptor = load_acceptor(&cert, &key, client_ca.as_deref())?;
+
+        info!(
+            "TLS enabled (client authentication: {})",
+            if client_ca.is_some() {"required"} else {"none"}
+        );
+
+        Ok(Some(Arc::new(Self {
+            cert,
+            key,
+            client_ca,
+            acceptor: RwLock::new(acceptor),
+        })))
+    }
+
+    ///
+    /// 証明書と鍵の再読み込み
+    ///
+    /// # 戻り値
+    /// 読み込みに失敗した場合はエラー情報を`Err()`でラップして返す(その場合
+    /// は従前の証明書と鍵を使い続ける)。
+    ///
+    pub(crate) fn reload(&self) -> Result<()> {
+        let acceptor = load_acceptor(
+            &self.cert,
+            &self.key,
+            self.client_ca.as_deref()
+        )?;
+
+        match self.acceptor.write() {
+            Ok(mut current) => *current = acceptor,
+            Err(_) => return Err(anyhow!("tls: acceptor lock poisoned")),
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// TLSのハンドシェイク
+    ///
+    /// # 引数
+    /// * `sock` - 接続を受け付けたソケットオブジェクト
+    ///
+    /// # 戻り値
+    /// ハンドシェイクに成功した場合は、TLSのストリームとクライアント証明書か
+    /// ら得たデバイスID(相互TLS認証時のみ)をパックしたタプルを`Ok()`でラッ
+    /// プして返す。
+    ///
+    pub(crate) async fn accept(&self, sock: TcpStream)
+        -> Result<(TlsStream<TcpStream>, Option<String>)>
+    {
+        let acceptor = match self.acceptor.read() {
+            Ok(acceptor) => acceptor.clone(),
+            Err(_) => return Err(anyhow!("tls: acceptor lock poisoned")),
+        };
+
+        let stream = match acceptor.accept(sock).await {
+            Ok(stream) => stream,
+            Err(err) => return Err(anyhow!("TLS handshake failed: {}", err)),
+        };
+
+        let device_id = match stream.get_ref().1.peer_certificates() {
+            Some([cert, ..]) => Some(peer_device_id(cert)?),
+            _ => None,
+        };
+
+        Ok((stream, device_id))
+    }
+}
+
+///
+/// アクセプタの生成
+///
+/// # 引数
+/// * `cert` - サーバ証明書のパス
+/// * `key` - 秘密鍵のパス
+/// * `client_ca` - クライアント証明書の検証に用いるCA証明書のパス
+///
+fn load_acceptor(cert: &Path, key: &Path, client_ca: Option<&Path>)
+    -> Result<TlsAcceptor>
+{
+    let provider = Arc::new(default_provider());
+
+    /*
+     * 証明書と鍵の読み込み
+     */
+    let certs = load_certs(cert)?;
+    let key = match PrivateKeyDer::from_pem_file(key) {
+        Ok(key) => key,
+        Err(err) => {
+            return Err(anyhow!("read {} failed: {}", key.display(), err));
+        }
+    };
+
+    /*
+     * 設定の生成
+     */
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+
+            for cert in load_certs(path)? {
+                roots.add(cert)?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(roots),
+                provider
+            ).build()?;
+
+            builder.with_client_cert_verifier(verifier)
+        }
+
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = match builder.with_single_cert(certs, key) {
+        Ok(config) => config,
+        Err(err) => return Err(anyhow!("invalid TLS certificate: {}", err)),
+    };
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+///
+/// PEMファイルからの証明書の読み込み
+///
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>());
+
+    match certs {
+        Ok(certs) if !certs.is_empty() => Ok(certs),
+        Ok(_) => Err(anyhow!("{} contains no certificate", path.display())),
+        Err(err) => Err(anyhow!("read {} failed: {}", path.display(), err)),
+    }
+}
+
+///
+/// クライアント証明書からのデバイスIDの取得
+///
+/// # 注記
+/// SANのDNS名を優先し、無い場合はCNを用いる。
+///
+fn peer_device_id(cert: &CertificateDer) -> Result<String> {
+    let cert = match parse_x509_certificate(cert) {
+        Ok((_, cert)) => cert,
+        Err(err) => return Err(anyhow!("invalid client certificate: {}", err)),
+    };
+
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            if let GeneralName::DNSName(name) = name {
+                return Ok(name.to_string());
+            }
+        }
+    }
+
+    let cn = cert.subject().iter_common_name().next();
+
+    match cn.map(|cn| cn.as_str()) {
+        Some(Ok(cn)) if !cn.is_empty() => Ok(cn.to_string()),
+        Some(_) => Err(anyhow!("invalid common name in client certificate")),
+        None => Err(anyhow!("no device ID in client certificate")),
+    }
+}
@@ -7,23 +7,34 @@
 //!
 //! UDP受信処理をまとめたモジュール
 //!
+//! 1データグラムを1レコードとして受信する。受信したデータグラムの解釈は固
+//! 定数のワーカーで行い、受信バッファはワーカーの処理後に再利用する。
+//!
 
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
 use anyhow::{anyhow, Result};
 use rhexdump::rhexdumps;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::task::JoinHandle;
 
+use super::access::AccessControl;
+use super::auth::Authenticator;
+use super::pool::WorkerPool;
 use crate::record::SensorRecord;
 use crate::cmd_args::Options;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
+
+/// ワーカーのキューの長さ
+const QUEUE_SIZE: usize = 64;
+
 ///
 /// 受信処理タスクをラップする構造体
 ///
@@ -41,6 +52,9 @@ impl UdpReceiveTask {
     ///
     /// # 引数
     /// * `opts` - オプション情報をまとめたオブジェクト
+    /// * `auth` - 受信レコードの認証を行うオブジェクト
+    /// * `access` - 受信の可否を判定するオブジェクト
+    /// * `socket` - systemdから渡された待ち受けソケット(無い場合はNone)
     ///
     /// # 戻り値
     /// タスクの開始に成功した場合は、タスクにバインドされたTcpReceiveTaskのオ
@@ -48,18 +62,36 @@ impl UdpReceiveTask {
     /// ジェクトをパックしたタプルを`Ok()`でラップして返す。
     /// 失敗した場合はエラー情報を `Err()`でラップして返す。
     ///
-    pub(crate) async fn start(opts: Arc<Options>)
-        -> Result<(Self, Receiver<SensorRecord>)>
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        auth: Arc<Authenticator>,
+        access: Arc<AccessControl>,
+        socket: Option<std::net::UdpSocket>,
+    ) -> Result<(Self, Receiver<SensorRecord>)>
     {
         /*
-         * リスナーオブジェクトの生成(TCPポートのバインド)
+         * ソケットオブジェクトの生成(UDPポートのバインド)
+         *
+         * systemdからソケットが渡されている場合はそれを用いる。
          */
-        let sock = match UdpSocket::bind(opts.endpoint()).await {
-            Ok(sock) => sock,
-            Err(err) => return Err(anyhow!("bind failed: {}", err)),
+        let sock = match socket {
+            Some(socket) => {
+                socket.set_nonblocking(true)?;
+                UdpSocket::from_std(socket)?
+            }
+
+            None => match UdpSocket::bind(opts.endpoint()).await {
+                Ok(sock) => sock,
+                Err(err) => return Err(anyhow!("bind failed: {}", err)),
+            },
         };
 
-        info!("success bind to {}", opts.endpoint());
+        info!("success bind to {}", sock.local_addr()?);
+        info!(
+            "UDP limits: workers={} max_datagram_size={}",
+            opts.udp_workers(),
+            opts.udp_max_datagram_size(),
+        );
 
         /*
          * チャネルオブジェクトの生成
@@ -69,10 +101,25 @@ impl UdpReceiveTask {
 
         /*
          * リスナータスクの起動
+         *
+         * 受信バッファは上限より1バイト大きく確保し、上限を超えるデータグラム
+         * (切り詰められたもの)を判別できるようにする。
          */
-        let handle =tokio::spawn(listener_task(
-            sock,
+        let shared = Arc::new(Shared {
+            auth,
+            access,
+            pool: BufferPool {
+                size: opts.udp_max_datagram_size() + 1,
+                limit: QUEUE_SIZE + opts.udp_workers() + 1,
+                buffers: Mutex::new(vec![]),
+            },
             pipeline_tx,
+        });
+
+        let handle = tokio::spawn(listener_task(
+            sock,
+            shared,
+            opts.udp_workers(),
             request_rx,
         ));
 
@@ -130,46 +177,162 @@ impl UdpReceiverHandle {
     }
 }
 
+///
+/// 受信バッファのプール
+///
+/// # 注記
+/// 受信毎のメモリ確保を避けるため、処理を終えたバッファを再利用する。保持
+/// するバッファの数には上限を設け、超えた分は解放する。
+///
+struct BufferPool {
+    /// バッファの大きさ
+    size: usize,
+
+    /// 保持するバッファの数の上限
+    limit: usize,
+
+    /// 再利用可能なバッファ
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    ///
+    /// バッファの取り出し(再利用可能なものが無い場合は新たに確保する)
+    ///
+    fn get(&self) -> Vec<u8> {
+        self.buffers.lock()
+            .ok()
+            .and_then(|mut buffers| buffers.pop())
+            .unwrap_or_else(|| vec![0; self.size])
+    }
+
+    ///
+    /// バッファの返却
+    ///
+    fn put(&self, buff: Vec<u8>) {
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < self.limit {
+                buffers.push(buff);
+            }
+        }
+    }
+}
+
+///
+/// ワーカーに引き渡すデータグラム
+///
+struct Datagram {
+    /// 受信バッファ
+    buff: Vec<u8>,
+
+    /// 受信したデータの長さ
+    len: usize,
+}
+
+///
+/// リスナーとワーカーで共有する情報をまとめた構造体
+///
+struct Shared {
+    /// 受信レコードの認証を行うオブジェクト
+    auth: Arc<Authenticator>,
+
+    /// 受信の可否を判定するオブジェクト
+    access: Arc<AccessControl>,
+
+    /// 受信バッファのプール
+    pool: BufferPool,
+
+    /// 受信レコード送信用チャネルオブジェクト
+    pipeline_tx: Sender<SensorRecord>,
+}
+
 ///
 /// UDPリスナー処理を行うタスク
 ///
 /// # 引数
-/// * `sock` - TCPポートにバインドされたリスナーソケットオブジェクト
-/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+/// * `sock` - UDPポートにバインドされたソケットオブジェクト
+/// * `shared` - リスナーとワーカーで共有する情報
+/// * `workers` - ワーカーの数
 /// * `shutdown_rx` - シャットダウン要求受信用チャネルオブジェクト
 ///
+/// # 注記
+/// 受信したデータグラムは固定数のワーカーに引き渡して処理する。ワーカーの
+/// キューが満杯の場合と、データグラムが上限を超えて切り詰められた場合は破
+/// 棄して稼働状況に計上する。終了時はキューに残ったデータグラムを処理し終
+/// えるまで待つ。
+///
 async fn listener_task(
     sock: UdpSocket,
-    pipeline_tx: Sender<SensorRecord>,
+    shared: Arc<Shared>,
+    workers: usize,
     mut request_rx: Receiver<TaskRequest>,
 ) {
     info!("start UDP receiver task");
 
-    // レコード送信チャネルを複製できるようにArcでラップ
-    let pipeline_tx = Arc::new(pipeline_tx);
+    /*
+     * ワーカーの起動
+     */
+    let pool = {
+        let shared = shared.clone();
+
+        WorkerPool::start("UDP", workers, QUEUE_SIZE, move |datagram| {
+            worker_task(datagram, shared.clone())
+        })
+    };
+
+    /*
+     * 受信ループ
+     */
+    let max_size = shared.pool.size - 1;
 
     loop {
-        let mut buff = vec![0; 1024];
+        let mut buff = shared.pool.get();
 
         tokio::select! {
             // バインドポートへの接続があった場合
             result = sock.recv_from(&mut buff) => {
-                match result {
-                    Ok((len, addr)) => {
-                        info!("receive from: {:?}", addr);
-
-                        tokio::spawn(receive_task(
-                            buff[..len].to_vec(),
-                            pipeline_tx.clone()
-                        ));
+                let (len, addr) = match result {
+                    Ok(result) => result,
+                    Err(err) => {
+                        error!("receive failed: {}", err);
+                        shared.pool.put(buff);
+                        continue;
+                    }
+                };
+
+                // 受信を許可しない送信元のデータは読み捨てる
+                if !shared.access.admit_source(addr.ip()) {
+                    shared.pool.put(buff);
+                    continue;
+                }
+
+                // 上限を超えたデータグラムは切り詰められているので破棄する
+                if len > max_size {
+                    warn!("datagram exceeds {} bytes: {:?}", max_size, addr);
+                    shared.access.stats().count("truncated");
+                    shared.pool.put(buff);
+                    continue;
+                }
+
+                info!("receive from: {:?}", addr);
+
+                match pool.submit(Datagram {buff, len}) {
+                    Ok(()) => {}
+
+                    Err(TrySendError::Full(datagram)) => {
+                        warn!("worker queue is full, datagram dropped");
+                        shared.access.stats().count("queue_full");
+                        shared.pool.put(datagram.buff);
                     }
 
-                    Err(err) => error!("accept failed: {}", err),
+                    Err(TrySendError::Closed(_)) => break,
                 }
             }
 
             // 制御チャネルにリクエストが届いた場合
             request = request_rx.recv() => {
+                shared.pool.put(buff);
+
                 match request {
                     Some(TaskRequest::Shutodwn) => break,
                     None => { /* ignore */ }
@@ -178,38 +341,101 @@ async fn listener_task(
         }
     }
 
+    /*
+     * ワーカーの終了待ち
+     */
+    pool.join().await;
+
     info!("shutdown UDP receiver task");
 }
 
 ///
-/// データ受信処理を行うタスク
+/// データグラムの処理を行うワーカー
 ///
 /// # 引数
-/// * `json` - TCPセッションタスク
-/// * `pipeline_tx` - 受信レコード送信用チャネルオブジェクト
+/// * `datagram` - 受信したデータグラム
+/// * `shared` - リスナーとワーカーで共有する情報
 ///
-async fn receive_task(
-    data: Vec<u8>,
-    pipeline_tx: Arc<Sender<SensorRecord>>
-) 
+async fn worker_task(datagram: Datagram, shared: Arc<Shared>) {
+    if let Some(record) = parse_datagram(&datagram, &shared) {
+        if let Err(err) = shared.pipeline_tx.send(record).await {
+            error!("send sensor result failed: {}", err);
+        }
+    }
+
+    shared.pool.put(datagram.buff);
+}
+
+///
+/// データグラムからのレコードの生成
+///
+/// # 戻り値
+/// 受け付けたレコードを`Some()`でラップして返す。レコードとして解釈できな
+/// い場合と、流量の制限により受け付けなかった場合は`None`を返す。
+///
+fn parse_datagram(datagram: &Datagram, shared: &Shared)
+    -> Option<SensorRecord>
 {
-    debug!("received data:\n{}", rhexdumps!(&data));
-
-    match String::from_utf8(data) {
-        Ok(json) => {
-            let record = match SensorRecord::from_json(&json) {
-                Ok(record) => record,
-                Err(err) => {
-                    error!("invalid JSON received: {}", err);
-                    return;
-                }
-            };
+    let data = &datagram.buff[..datagram.len];
 
-            if let Err(err) = pipeline_tx.send(record).await {
-                error!("send sensor result failed: {}", err);
-            }
+    debug!("received data:\n{}", rhexdumps!(data));
+
+    let json = match std::str::from_utf8(data) {
+        Ok(json) => json,
+        Err(err) => {
+            error!("invalid JSON received: {}", err);
+            shared.access.stats().count("invalid");
+            return None;
+        }
+    };
+
+    let record = match shared.auth.open(json) {
+        Ok(record) => record,
+        Err(err) => {
+            error!("invalid JSON received: {}", err);
+            shared.access.stats().count("invalid");
+            return None;
         }
+    };
+
+    shared.access.admit_device(&record).then_some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::access::tests::unrestricted;
+    use crate::receiver::auth::tests::{
+        assert_rejected, authenticator, envelope, now
+    };
+
+    fn parse(shared: &Shared, data: &str) -> Option<SensorRecord> {
+        let datagram = Datagram {
+            buff: data.as_bytes().to_vec(),
+            len: data.len(),
+        };
+
+        parse_datagram(&datagram, shared)
+    }
+
+    #[test]
+    fn authenticate_datagrams() {
+        let (pipeline_tx, _pipeline_rx) = tokio::sync::mpsc::channel(1);
+        let shared = Shared {
+            auth: authenticator(),
+            access: unrestricted(),
+            pool: BufferPool {size: 0, limit: 0, buffers: Mutex::new(vec![])},
+            pipeline_tx,
+        };
+
+        let data = r#"{"location":"room","device_id":"dev1"}"#;
+        let record = parse(&shared, data).unwrap();
+        assert_rejected(&record, "auth:unsigned:dev1");
 
-        Err(err) => error!("invalid JSON received: {}", err),
+        let payload = r#"{"location":"room","temperature":20}"#;
+        let data = envelope("dev1", now(), "n1", payload, "secret");
+        let record = parse(&shared, &data).unwrap();
+        assert!(!record.is_quarantined());
+        assert_eq!(record.device_id().as_deref(), Some("dev1"));
     }
 }
@@ -8,39 +8,184 @@
 //! レコード定義を行うモジュール
 //!
 
+use std::collections::BTreeMap;
+use std::fmt;
+
 use anyhow::{anyhow, Result};
-use chrono::{Local, TimeZone, Utc};
-use serde::Deserialize;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::barometer::Barometric;
+use crate::psychrometrics::Derived;
+use crate::telemetry::Telemetry;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 
+/// デバイスが申告したタイムスタンプと受信時刻の差の許容範囲(ミリ秒)
+const MAX_CLOCK_SKEW: u64 = 5 * 60 * 1000;
+
 ///
 /// センサーから受信したデータのレコードを投影する構造体
 ///
-#[derive(Debug, Deserialize)]
+/// # 注記
+/// タイムスタンプはJSON等に含まれていない場合(0の場合も含む)、変換時に受信
+/// 時刻を補う。含まれている場合も受信時刻との差が`MAX_CLOCK_SKEW`を超えない
+/// ように丸める。認証済みのレコードは`from_trusted_json()`で、中継元の
+/// env-loggerから転送されたレコードは`from_relay_json()`で変換して申告どお
+/// りのタイムスタンプを持つ。
+///
+/// `new()`で生成したレコード(ラインプロトコルで受信したレコードなど)は、
+/// 与えたタイムスタンプをそのまま持つ。丸める場合は生成後に
+/// `clamp_timestamp()`を呼び出すこと。
+///
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub(crate) struct SensorRecord {
-    /// 送信デバイスの設置場所
+    /// 送信デバイスの設置場所(省略時は登録簿から補う)
+    #[serde(default)]
     location: String,
 
     /// 送信デバイス固有のID
+    #[serde(skip_serializing_if = "Option::is_none")]
     device_id: Option<String>,
 
     /// タイムスタンプ
-    #[serde(skip)]
+    #[serde(default)]
     timestamp: u64, 
 
     /// 気温
+    #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
 
     /// 湿度
+    #[serde(skip_serializing_if = "Option::is_none")]
     humidity: Option<f32>,
 
     /// 気圧
+    #[serde(skip_serializing_if = "Option::is_none")]
     air_pressure: Option<f32>,
+
+    /// 較正前の計測値(較正を適用した計測値のみ値を持つ)
+    #[serde(skip)]
+    raw: RawValues,
+
+    /// 品質フラグのリスト(妥当性検査で問題が見つかった場合のみ値を持つ)
+    #[serde(skip)]
+    quality: Vec<String>,
+
+    /// 隔離対象か否か
+    #[serde(skip)]
+    quarantined: bool,
+
+    /// 認証に失敗したか否か(送信元を信用できないレコード)
+    #[serde(skip)]
+    unauthenticated: bool,
+
+    /// 中継元で較正と処理ステージを適用済みのレコードか否か
+    #[serde(skip)]
+    relayed: bool,
+
+    /// 気圧に関する派生値(気圧を持つレコードのみ)
+    #[serde(skip)]
+    barometric: Option<Barometric>,
+
+    /// デバイスの稼働状態(未知のプロパティから取り出したもの)
+    #[serde(flatten, skip_deserializing)]
+    telemetry: Telemetry,
+
+    /// 気温、湿度、気圧以外の計測値(計測種別の登録簿で受け付けたもの)
+    #[serde(flatten, skip_deserializing)]
+    metrics: BTreeMap<String, f32>,
+
+    /// 未知のプロパティ(計測値として取り込んでいないもの)
+    #[serde(flatten)]
+    extras: Map<String, Value>,
+}
+
+///
+/// 較正前の計測値を保持する構造体
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub(crate) struct RawValues {
+    /// 較正前の気温
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) temperature: Option<f32>,
+
+    /// 較正前の湿度
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) humidity: Option<f32>,
+
+    /// 較正前の気圧
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) air_pressure: Option<f32>,
+}
+
+///
+/// 中継時のレコードの形式を表す構造体
+///
+/// # 注記
+/// レコード本体はデバイスから受け取る場合と同じ形式とし、JSONに含まれない
+/// 較正前の計測値と品質フラグを別のプロパティで運ぶ。
+///
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RelayPayload {
+    /// レコード本体(計測値は較正と処理ステージの適用後の値)
+    record: SensorRecord,
+
+    /// 較正前の計測値
+    #[serde(default)]
+    raw: RawValues,
+
+    /// 品質フラグのリスト
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    quality: Vec<String>,
 }
 
 impl SensorRecord {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `location` - 送信デバイスの設置場所
+    /// * `device_id` - 送信デバイス固有のID
+    /// * `timestamp` - ミリ秒単位のUNIX時刻
+    /// * `temperature` - 気温
+    /// * `humidity` - 湿度
+    /// * `air_pressure` - 気圧
+    ///
+    /// # 注記
+    /// JSON以外の形式で受信したデータから生成する場合に使用する。
+    ///
+    pub(crate) fn new(
+        location: String,
+        device_id: Option<String>,
+        timestamp: u64,
+        temperature: Option<f32>,
+        humidity: Option<f32>,
+        air_pressure: Option<f32>,
+    ) -> Self
+    {
+        Self {
+            location,
+            device_id,
+            timestamp,
+            temperature,
+            humidity,
+            air_pressure,
+            raw: RawValues::default(),
+            quality: vec![],
+            quarantined: false,
+            unauthenticated: false,
+            relayed: false,
+            barometric: None,
+            telemetry: Telemetry::default(),
+            metrics: BTreeMap::new(),
+            extras: Map::new(),
+        }
+    }
+
     ///
     /// JSONからの変換関数
     ///
@@ -51,13 +196,60 @@ impl SensorRecord {
     /// JSONから変換したセンサーデータ
     ///
     /// # 注記
-    /// タイムスタンプは本関数で取得する (JSONには該当するプロパティは存在しな
-    /// い)。
+    /// JSONにタイムスタンプ(ミリ秒単位のUNIX時刻)が含まれていない場合は本関
+    /// 数で取得する。含まれている場合は受信時刻との差を許容範囲内に丸める。
     ///
-    pub(crate) fn from_json<'a>(json: &'a str) -> Result<Self> {
+    pub(crate) fn from_json(json: &str) -> Result<Self> {
         match serde_json::from_str::<SensorRecord>(json) {
-            Ok(mut value) => {
-                value.timestamp = Utc::now().timestamp_millis() as u64;
+            Ok(value) => Ok(value.fill_timestamp(false)),
+
+            Err(err) => Err(anyhow!("{}", err)),
+        }
+    }
+
+    ///
+    /// 信頼できる送信元から受け取ったJSONからの変換関数
+    ///
+    /// # 引数
+    /// * `json` - 中継元または認証済みのデバイスから受け取ったJSON文字列
+    ///
+    /// # 戻り値
+    /// JSONから変換したセンサーデータ
+    ///
+    /// # 注記
+    /// `from_json()`と異なり、JSONに含まれるタイムスタンプをそのまま採用す
+    /// る。
+    ///
+    pub(crate) fn from_trusted_json(json: &str) -> Result<Self> {
+        match serde_json::from_str::<SensorRecord>(json) {
+            Ok(value) => Ok(value.fill_timestamp(true)),
+
+            Err(err) => Err(anyhow!("{}", err)),
+        }
+    }
+
+    ///
+    /// 中継元から受け取ったJSONからの変換関数
+    ///
+    /// # 引数
+    /// * `json` - 中継元から受け取ったJSON文字列(`to_relay_json()`の形式)
+    /// * `trusted` - 含まれるタイムスタンプをそのまま採用するか否か
+    ///
+    /// # 戻り値
+    /// JSONから変換したセンサーデータ
+    ///
+    /// # 注記
+    /// 較正前の計測値と品質フラグも復元する。中継済みのレコードとしての扱い
+    /// は、中継元の認証に成功した場合に`mark_relayed()`で設定すること。
+    ///
+    pub(crate) fn from_relay_json(json: &str, trusted: bool) -> Result<Self> {
+        match serde_json::from_str::<RelayPayload>(json) {
+            Ok(payload) => {
+                let mut value = payload.record.fill_timestamp(trusted);
+
+                value.raw = payload.raw;
+                value.quality = payload.quality;
+
                 Ok(value)
             }
 
@@ -65,6 +257,143 @@ impl SensorRecord {
         }
     }
 
+    ///
+    /// CBORからの変換関数
+    ///
+    /// # 引数
+    /// * `cbor` - デバイスから受け取ったCBORのバイト列
+    ///
+    /// # 戻り値
+    /// CBORから変換したセンサーデータ
+    ///
+    /// # 注記
+    /// プロパティの構成はJSONの場合と同じ。タイムスタンプの扱いもJSONの場合と
+    /// 同じ。
+    ///
+    pub(crate) fn from_cbor(cbor: &[u8]) -> Result<Self> {
+        match ciborium::from_reader::<SensorRecord, _>(cbor) {
+            Ok(value) => Ok(value.fill_timestamp(false)),
+
+            Err(err) => Err(anyhow!("{}", err)),
+        }
+    }
+
+    ///
+    /// 補完情報付きでのJSONからの変換関数
+    ///
+    /// # 引数
+    /// * `json` - デバイスから受け取ったJSON文字列
+    /// * `location` - JSONに設置場所が含まれていない場合に補う設置場所
+    /// * `device_id` - JSONにデバイスIDが含まれていない場合に補うデバイスID
+    ///
+    /// # 戻り値
+    /// JSONから変換したセンサーデータ
+    ///
+    /// # 注記
+    /// JSON側に値が存在する場合はJSON側の値を優先する。
+    ///
+    pub(crate) fn from_json_with_hint(
+        json: &str,
+        location: Option<&str>,
+        device_id: Option<&str>,
+    ) -> Result<Self>
+    {
+        let mut value = match serde_json::from_str::<Value>(json) {
+            Ok(value) => value,
+            Err(err) => return Err(anyhow!("{}", err)),
+        };
+
+        /*
+         * 欠落しているプロパティの補完
+         */
+        if let Some(map) = value.as_object_mut() {
+            let hints = [("location", location), ("device_id", device_id)];
+
+            for (key, hint) in hints {
+                if let Some(hint) = hint {
+                    if map.get(key).is_none_or(|val| val.is_null()) {
+                        map.insert(key.to_string(), hint.into());
+                    }
+                }
+            }
+        }
+
+        match serde_json::from_value::<SensorRecord>(value) {
+            Ok(value) => Ok(value.fill_timestamp(false)),
+
+            Err(err) => Err(anyhow!("{}", err)),
+        }
+    }
+
+    ///
+    /// JSONへの変換関数
+    ///
+    /// # 戻り値
+    /// タイムスタンプを含めたJSON文字列(改行を含まない)
+    ///
+    pub(crate) fn to_json(&self) -> String {
+        // 文字列と数値のみで構成されるので失敗することはない
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    ///
+    /// 中継用のJSONへの変換関数
+    ///
+    /// # 戻り値
+    /// 較正前の計測値と品質フラグを含めたJSON文字列(改行を含まない)
+    ///
+    pub(crate) fn to_relay_json(&self) -> String {
+        let payload = RelayPayload {
+            record: self.clone(),
+            raw: self.raw,
+            quality: self.quality.clone(),
+        };
+
+        // 文字列と数値のみで構成されるので失敗することはない
+        serde_json::to_string(&payload).unwrap_or_default()
+    }
+
+    ///
+    /// タイムスタンプの補完
+    ///
+    /// # 引数
+    /// * `trusted` - 設定されているタイムスタンプをそのまま採用するか否か
+    ///
+    /// # 注記
+    /// タイムスタンプが設定されていない場合に現在時刻を設定する。信頼できな
+    /// い送信元のタイムスタンプは、現在時刻との差が許容範囲に収まるように丸
+    /// める。
+    ///
+    fn fill_timestamp(mut self, trusted: bool) -> Self {
+        if self.timestamp == 0 {
+            self.timestamp = Utc::now().timestamp_millis() as u64;
+
+        } else if !trusted {
+            self.clamp_timestamp();
+        }
+
+        self
+    }
+
+    ///
+    /// タイムスタンプの丸め
+    ///
+    /// # 注記
+    /// 現在時刻との差が許容範囲(`MAX_CLOCK_SKEW`)に収まるように丸める。
+    ///
+    pub(crate) fn clamp_timestamp(&mut self) {
+        let now = Utc::now().timestamp_millis() as u64;
+
+        if self.timestamp.abs_diff(now) > MAX_CLOCK_SKEW {
+            debug!("timestamp out of range: {}", self.timestamp);
+
+            self.timestamp = self.timestamp.clamp(
+                now.saturating_sub(MAX_CLOCK_SKEW),
+                now + MAX_CLOCK_SKEW
+            );
+        }
+    }
+
     ///
     /// デバイス設置場所へのアクセサ
     ///
@@ -102,7 +431,7 @@ impl SensorRecord {
     /// 気温データが取得できている場合は値を`Some()`でラップして返す
     ///
     pub(crate) fn temperature(&self) -> Option<f32> {
-        self.temperature.clone()
+        self.temperature
     }
 
     ///
@@ -112,7 +441,7 @@ impl SensorRecord {
     /// 湿度データが取得できている場合は値を`Some()`でラップして返す
     ///
     pub(crate) fn humidity(&self) -> Option<f32> {
-        self.humidity.clone()
+        self.humidity
     }
 
     ///
@@ -122,13 +451,293 @@ impl SensorRecord {
     /// 気圧データが取得できている場合は値を`Some()`でラップして返す
     ///
     pub(crate) fn air_pressure(&self) -> Option<f32> {
-        self.air_pressure.clone()
+        self.air_pressure
+    }
+
+    ///
+    /// 較正前の計測値へのアクセサ
+    ///
+    /// # 戻り値
+    /// 較正前の計測値を返す(較正を適用していない計測値はNoneとなる)
+    ///
+    pub(crate) fn raw(&self) -> RawValues {
+        self.raw
+    }
+
+    ///
+    /// 派生値の取得
+    ///
+    /// # 戻り値
+    /// 気温と湿度から派生値を計算できた場合は、その値を`Some()`でラップして
+    /// 返す。
+    ///
+    pub(crate) fn derived(&self) -> Option<Derived> {
+        Derived::compute(self.temperature, self.humidity)
+    }
+
+    ///
+    /// 気圧に関する派生値へのアクセサ
+    ///
+    /// # 戻り値
+    /// 気圧に関する派生値が設定されている場合は、その値を`Some()`でラップし
+    /// て返す。
+    ///
+    pub(crate) fn barometric(&self) -> Option<Barometric> {
+        self.barometric.clone()
+    }
+
+    ///
+    /// 気温、湿度、気圧以外の計測値へのアクセサ
+    ///
+    /// # 戻り値
+    /// 計測種別の名前と値のマップを返す
+    ///
+    pub(crate) fn metrics(&self) -> &BTreeMap<String, f32> {
+        &self.metrics
+    }
+
+    ///
+    /// デバイスの稼働状態へのアクセサ
+    ///
+    pub(crate) fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    ///
+    /// 未知のプロパティへのアクセサ
+    ///
+    /// # 戻り値
+    /// プロパティ名と値のマップを返す
+    ///
+    pub(crate) fn extras(&self) -> &Map<String, Value> {
+        &self.extras
+    }
+
+    ///
+    /// 未知のプロパティのJSON文字列の生成
+    ///
+    /// # 戻り値
+    /// 未知のプロパティを持つ場合は、JSONオブジェクトの文字列を`Some()`でラッ
+    /// プして返す。
+    ///
+    pub(crate) fn extras_json(&self) -> Option<String> {
+        (!self.extras.is_empty())
+            .then(|| Value::Object(self.extras.clone()).to_string())
+    }
+
+    ///
+    /// 品質フラグへのアクセサ
+    ///
+    /// # 戻り値
+    /// 品質フラグが設定されている場合は、カンマ区切りで連結した文字列を
+    /// `Some()`でラップして返す。
+    ///
+    pub(crate) fn quality(&self) -> Option<String> {
+        if self.quality.is_empty() {
+            None
+        } else {
+            Some(self.quality.join(","))
+        }
+    }
+
+    ///
+    /// 隔離対象か否かの判定
+    ///
+    /// # 戻り値
+    /// 隔離対象の場合は`true`を返す。
+    ///
+    pub(crate) fn is_quarantined(&self) -> bool {
+        self.quarantined
+    }
+
+    ///
+    /// 認証に失敗したレコードか否かの判定
+    ///
+    /// # 戻り値
+    /// 認証に失敗したレコードの場合は`true`を返す。
+    ///
+    pub(crate) fn is_unauthenticated(&self) -> bool {
+        self.unauthenticated
+    }
+
+    ///
+    /// 中継済みのレコードか否かの判定
+    ///
+    /// # 戻り値
+    /// 中継元で較正と処理ステージを適用済みのレコードの場合は`true`を返す。
+    ///
+    pub(crate) fn is_relayed(&self) -> bool {
+        self.relayed
+    }
+
+    ///
+    /// デバイス設置場所の設定
+    ///
+    /// # 引数
+    /// * `location` - 設置場所
+    ///
+    pub(crate) fn set_location(&mut self, location: String) {
+        self.location = location;
+    }
+
+    ///
+    /// デバイスIDの設定
+    ///
+    /// # 引数
+    /// * `device_id` - デバイスID
+    ///
+    pub(crate) fn set_device_id(&mut self, device_id: String) {
+        self.device_id = Some(device_id);
+    }
+
+    ///
+    /// 気温データの設定
+    ///
+    /// # 引数
+    /// * `val` - 気温データ(摂氏)
+    ///
+    pub(crate) fn set_temperature(&mut self, val: Option<f32>) {
+        self.temperature = val;
+    }
+
+    ///
+    /// 湿度データの設定
+    ///
+    /// # 引数
+    /// * `val` - 湿度データ(相対湿度%)
+    ///
+    pub(crate) fn set_humidity(&mut self, val: Option<f32>) {
+        self.humidity = val;
+    }
+
+    ///
+    /// 気圧データの設定
+    ///
+    /// # 引数
+    /// * `val` - 気圧データ(hPa)
+    ///
+    pub(crate) fn set_air_pressure(&mut self, val: Option<f32>) {
+        self.air_pressure = val;
+    }
+
+    ///
+    /// 較正前の計測値の設定
+    ///
+    /// # 引数
+    /// * `raw` - 較正前の計測値
+    ///
+    pub(crate) fn set_raw(&mut self, raw: RawValues) {
+        self.raw = raw;
+    }
+
+    ///
+    /// 気圧に関する派生値の設定
+    ///
+    /// # 引数
+    /// * `barometric` - 気圧に関する派生値
+    ///
+    pub(crate) fn set_barometric(&mut self, barometric: Barometric) {
+        self.barometric = Some(barometric);
+    }
+
+    ///
+    /// 計測値の設定
+    ///
+    /// # 引数
+    /// * `name` - 計測種別の名前
+    /// * `val` - 計測値
+    ///
+    pub(crate) fn set_metric(&mut self, name: &str, val: f32) {
+        self.metrics.insert(name.to_string(), val);
+    }
+
+
+    ///
+    /// デバイスの稼働状態の設定
+    ///
+    /// # 引数
+    /// * `telemetry` - デバイスの稼働状態
+    ///
+    pub(crate) fn set_telemetry(&mut self, telemetry: Telemetry) {
+        self.telemetry = telemetry;
+    }
+
+    ///
+    /// 未知のプロパティからの稼働状態の取り出し
+    ///
+    /// # 注記
+    /// 稼働状態のプロパティ(`telemetry`モジュールを参照)を未知のプロパティ
+    /// から取り除き、稼働状態として保持する。
+    ///
+    pub(crate) fn split_telemetry(&mut self) {
+        self.telemetry = Telemetry::take(&mut self.extras);
+    }
+
+    ///
+    /// 未知のプロパティの設定
+    ///
+    /// # 引数
+    /// * `key` - プロパティ名
+    /// * `val` - 値
+    ///
+    pub(crate) fn set_extra(&mut self, key: &str, val: Value) {
+        self.extras.insert(key.to_string(), val);
+    }
+
+    ///
+    /// 未知のプロパティの削除
+    ///
+    /// # 引数
+    /// * `key` - プロパティ名
+    ///
+    pub(crate) fn remove_extra(&mut self, key: &str) {
+        self.extras.remove(key);
+    }
+
+    ///
+    /// 品質フラグの追加
+    ///
+    /// # 引数
+    /// * `flag` - 品質フラグ("計測値名:理由:値"の形式)
+    ///
+    pub(crate) fn add_quality_flag(&mut self, flag: String) {
+        self.quality.push(flag);
+    }
+
+    ///
+    /// 隔離対象への設定
+    ///
+    pub(crate) fn quarantine(&mut self) {
+        self.quarantined = true;
+    }
+
+    ///
+    /// 認証に失敗したレコードとしての隔離
+    ///
+    /// # 注記
+    /// 内容を信用できないので、隔離テーブル以外(デバイスの稼働状態など)には
+    /// 記録しない。
+    ///
+    pub(crate) fn quarantine_unauthenticated(&mut self) {
+        self.quarantined = true;
+        self.unauthenticated = true;
+    }
+
+    ///
+    /// 中継済みのレコードへの設定
+    ///
+    /// # 注記
+    /// 中継元の認証に成功したレコードにのみ設定する。設定したレコードには
+    /// 較正と処理ステージを適用しない。
+    ///
+    pub(crate) fn mark_relayed(&mut self) {
+        self.relayed = true;
     }
 }
 
-// ToStringトレイトの実装
-impl ToString for SensorRecord {
-    fn to_string(&self) -> String {
+// Displayトレイトの実装
+impl fmt::Display for SensorRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut vals = vec![];
 
         if let Some(val) = &self.device_id {
@@ -147,7 +756,12 @@ impl ToString for SensorRecord {
             vals.push(format!("{:.1}hpa", val));
         }
 
-        format!(
+        for (name, val) in &self.metrics {
+            vals.push(format!("{}={}", name, val));
+        }
+
+        write!(
+            f,
             "\"{}\",{},{}",
             self.location,
             local_time_string(self.timestamp),
@@ -165,10 +779,69 @@ impl ToString for SensorRecord {
 /// # 戻り値
 /// ローカルタイムでの表記に変換した文字列
 ///
-fn local_time_string(tm: u64) -> String {
+pub(crate) fn local_time_string(tm: u64) -> String {
     Utc.timestamp_opt((tm / 1000) as i64, ((tm % 1000) * 1000000) as u32)
         .unwrap()
         .with_timezone(&Local)
         //.format("%Y/%m/%d %H:%M:%S").to_string()
         .to_string()
 }
+
+///
+/// 計測値の丸め
+///
+/// # 引数
+/// * `val` - 丸める値
+/// * `digits` - 小数点以下の桁数
+///
+/// # 戻り値
+/// 指定した桁数に丸めた値を返す。
+///
+/// # 注記
+/// f32からf64への変換で生じる端数が残らないように、f64で計算する。
+///
+pub(crate) fn round_value(val: f64, digits: u32) -> f64 {
+    let scale = 10f64.powi(digits as i32);
+    (val * scale).round() / scale
+}
+
+///
+/// 日時を表す文字列をミリ秒単位のUNIX時刻に変換する
+///
+/// # 引数
+/// * `s` - 変換対象の文字列
+///
+/// # 戻り値
+/// 変換に成功した場合はミリ秒単位のUNIX時刻を`Ok()`でラップして返す。
+///
+/// # 注記
+/// RFC3339形式の他、タイムゾーンを省略した"YYYY-MM-DDTHH:MM:SS"形式(区切り
+/// は空白も可)と"YYYY-MM-DD"形式を受け付ける。タイムゾーンを省略した場合は
+/// ローカルタイムとして扱い、時刻を省略した場合はその日の0時とする。
+///
+pub(crate) fn parse_time_string(s: &str) -> Result<u64> {
+    let s = s.trim();
+
+    let tm = if let Ok(tm) = DateTime::parse_from_rfc3339(s) {
+        tm.timestamp_millis()
+
+    } else {
+        let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+            .or_else(|_| {
+                NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                    .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+            });
+
+        match naive.ok().and_then(|tm| Local.from_local_datetime(&tm).earliest()) {
+            Some(tm) => tm.timestamp_millis(),
+            None => return Err(anyhow!("invalid date time: {}", s)),
+        }
+    };
+
+    if tm < 0 {
+        return Err(anyhow!("date time out of range: {}", s));
+    }
+
+    Ok(tm as u64)
+}
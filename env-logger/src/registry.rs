@@ -0,0 +1,557 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 設置場所とデバイスの登録簿をまとめたモジュール
+//!
+//! 登録簿はデータベースの以下のテーブルで管理する(編集はサブコマンドで行
+//! う)。
+//!
+//! * `LOCATION_TABLE` - 設置場所毎の表示名、階、部屋、屋内外の別、標高、タ
+//!   グ
+//! * `DEVICE_TABLE` - デバイス毎の説明、登録時刻、退役時刻
+//! * `DEVICE_ASSIGNMENT_TABLE` - デバイスの設置場所と、その有効期間
+//!
+//! デバイスIDのみを送信してきたレコードには、タイムスタンプ時点でのデバイス
+//! の設置場所を補う。
+//!
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use rusqlite::{named_params, Connection, OptionalExtension, Row};
+
+use crate::barometer::LocationConfig;
+use crate::database::open_database;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 設置場所の一覧の取得クエリー
+const SELECT_LOCATIONS_QUERY: &str =
+    include_str!("../data/select_locations.sql");
+
+/// 設置場所の登録クエリー
+const UPSERT_LOCATION_QUERY: &str = include_str!("../data/upsert_location.sql");
+
+/// デバイスの一覧の取得クエリー
+const SELECT_DEVICES_QUERY: &str = include_str!("../data/select_devices.sql");
+
+/// デバイスの登録クエリー
+const INSERT_DEVICE_QUERY: &str = include_str!("../data/insert_device.sql");
+
+/// デバイスの退役クエリー
+const RETIRE_DEVICE_QUERY: &str = include_str!("../data/retire_device.sql");
+
+/// 設置場所の割り当ての一覧の取得クエリー
+const SELECT_ASSIGNMENTS_QUERY: &str =
+    include_str!("../data/select_assignments.sql");
+
+/// 現在の設置場所の割り当ての取得クエリー
+const SELECT_CURRENT_ASSIGNMENT_QUERY: &str =
+    include_str!("../data/select_current_assignment.sql");
+
+/// 設置場所の割り当ての追加クエリー
+const INSERT_ASSIGNMENT_QUERY: &str =
+    include_str!("../data/insert_assignment.sql");
+
+/// 設置場所の割り当ての終了クエリー
+const CLOSE_ASSIGNMENT_QUERY: &str =
+    include_str!("../data/close_assignment.sql");
+
+///
+/// 設置場所の情報を表す構造体
+///
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LocationInfo {
+    /// 設置場所名(レコードのlocationと同じ表記)
+    pub(crate) name: String,
+
+    /// 表示名
+    pub(crate) display_name: Option<String>,
+
+    /// 階
+    pub(crate) floor: Option<String>,
+
+    /// 部屋
+    pub(crate) room: Option<String>,
+
+    /// 屋外か否か
+    pub(crate) outdoor: bool,
+
+    /// 標高(m)
+    pub(crate) altitude: Option<f32>,
+
+    /// タグのリスト
+    pub(crate) tags: Vec<String>,
+}
+
+///
+/// デバイスの情報を表す構造体
+///
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceInfo {
+    /// デバイス固有のID
+    pub(crate) device_id: String,
+
+    /// 説明
+    pub(crate) description: Option<String>,
+
+    /// 登録時刻(ミリ秒単位のUNIX時刻)
+    pub(crate) registered: u64,
+
+    /// 退役時刻(ミリ秒単位のUNIX時刻、稼働中はNone)
+    pub(crate) retired: Option<u64>,
+}
+
+///
+/// デバイスへの設置場所の割り当てを表す構造体
+///
+#[derive(Debug, Clone)]
+pub(crate) struct Assignment {
+    /// デバイス固有のID
+    pub(crate) device_id: String,
+
+    /// 設置場所名
+    pub(crate) location: String,
+
+    /// 有効期間の開始時刻(この時刻を含む)
+    pub(crate) valid_from: u64,
+
+    /// 有効期間の終了時刻(この時刻を含まない、現在も有効な場合はNone)
+    pub(crate) valid_until: Option<u64>,
+}
+
+impl Assignment {
+    ///
+    /// 有効期間の判定
+    ///
+    fn covers(&self, timestamp: u64) -> bool {
+        timestamp >= self.valid_from
+            && self.valid_until.is_none_or(|until| timestamp < until)
+    }
+}
+
+///
+/// 登録簿を表す構造体
+///
+/// # 注記
+/// 起動時と設定の再読み込み時にデータベースから読み込む。サブコマンドで登録
+/// 簿を編集した場合は、SIGHUPを送ることで稼働中のプロセスに反映できる。
+///
+pub(crate) struct Registry {
+    /// 設置場所名毎の設置場所の情報
+    locations: HashMap<String, LocationInfo>,
+
+    /// デバイスID毎の設置場所の割り当てのリスト
+    assignments: HashMap<String, Vec<Assignment>>,
+}
+
+impl Registry {
+    ///
+    /// 登録簿の読み込み
+    ///
+    /// # 引数
+    /// * `path` - データベースファイルへのパス
+    ///
+    /// # 戻り値
+    /// 読み込みに成功した場合はRegistryオブジェクトを`Ok()`でラップして返す。
+    ///
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = open_database(path)?;
+
+        let locations = select_locations(&conn)?
+            .into_iter()
+            .map(|info| (info.name.clone(), info))
+            .collect::<HashMap<_, _>>();
+
+        let mut assignments: HashMap<String, Vec<Assignment>> = HashMap::new();
+
+        for assignment in select_assignments(&conn)? {
+            assignments.entry(assignment.device_id.clone())
+                .or_default()
+                .push(assignment);
+        }
+
+        debug!(
+            "registry loaded: {} locations, {} devices",
+            locations.len(),
+            assignments.len()
+        );
+
+        Ok(Self {locations, assignments})
+    }
+
+    ///
+    /// 標高が登録されている設置場所の定義の取得
+    ///
+    /// # 戻り値
+    /// 気圧に関する派生値の計算に用いる設置場所の定義のリストを返す。
+    ///
+    pub(crate) fn altitudes(&self) -> Vec<LocationConfig> {
+        self.locations.values()
+            .filter_map(|info| {
+                info.altitude
+                    .map(|altitude| LocationConfig::new(&info.name, altitude))
+            })
+            .collect()
+    }
+
+    ///
+    /// 設置場所の補完
+    ///
+    /// # 引数
+    /// * `record` - 対象のレコード
+    ///
+    /// # 戻り値
+    /// レコードが設置場所を持つ場合、または補完できた場合は`true`を返す。
+    ///
+    /// # 注記
+    /// 設置場所を持たないレコードに対し、デバイスIDとタイムスタンプに合致す
+    /// る割り当てから設置場所を補う。設置場所を持つレコードはそのまま扱う。
+    ///
+    pub(crate) fn resolve(&self, record: &mut SensorRecord) -> bool {
+        if !record.location().is_empty() {
+            return true;
+        }
+
+        let location = record.device_id()
+            .and_then(|id| self.assignments.get(&id))
+            .and_then(|list| {
+                list.iter().find(|a| a.covers(record.timestamp()))
+            })
+            .map(|a| a.location.clone());
+
+        match location {
+            Some(location) => {
+                record.set_location(location);
+                true
+            }
+
+            None => false,
+        }
+    }
+}
+
+///
+/// 設置場所の一覧の読み出し
+///
+pub(crate) fn select_locations(conn: &Connection)
+    -> rusqlite::Result<Vec<LocationInfo>>
+{
+    let mut stmt = conn.prepare(SELECT_LOCATIONS_QUERY)?;
+    let rows = stmt.query_map([], |row| {
+        let tags: Option<String> = row.get(6)?;
+
+        Ok(LocationInfo {
+            name: row.get(0)?,
+            display_name: row.get(1)?,
+            floor: row.get(2)?,
+            room: row.get(3)?,
+            outdoor: row.get(4)?,
+            altitude: row.get(5)?,
+            tags: tags.map(|s| split_tags(&s)).unwrap_or_default(),
+        })
+    })?;
+
+    rows.collect()
+}
+
+///
+/// 設置場所の登録(登録済みの場合は内容を置き換える)
+///
+pub(crate) fn upsert_location(conn: &Connection, info: &LocationInfo)
+    -> rusqlite::Result<()>
+{
+    let tags = (!info.tags.is_empty()).then(|| info.tags.join(","));
+
+    conn.execute(
+        UPSERT_LOCATION_QUERY,
+        named_params! {
+            ":name" : info.name,
+            ":display_name" : info.display_name,
+            ":floor" : info.floor,
+            ":room" : info.room,
+            ":outdoor" : info.outdoor,
+            ":altitude" : info.altitude,
+            ":tags" : tags,
+        },
+    )?;
+
+    Ok(())
+}
+
+///
+/// デバイスの一覧の読み出し
+///
+pub(crate) fn select_devices(conn: &Connection)
+    -> rusqlite::Result<Vec<DeviceInfo>>
+{
+    let mut stmt = conn.prepare(SELECT_DEVICES_QUERY)?;
+    let rows = stmt.query_map([], |row| {
+        Ok(DeviceInfo {
+            device_id: row.get(0)?,
+            description: row.get(1)?,
+            registered: row.get(2)?,
+            retired: row.get(3)?,
+        })
+    })?;
+
+    rows.collect()
+}
+
+///
+/// 設置場所の割り当ての一覧の読み出し(デバイスIDと開始時刻の順)
+///
+pub(crate) fn select_assignments(conn: &Connection)
+    -> rusqlite::Result<Vec<Assignment>>
+{
+    let mut stmt = conn.prepare(SELECT_ASSIGNMENTS_QUERY)?;
+    let rows = stmt.query_map([], to_assignment)?;
+
+    rows.collect()
+}
+
+///
+/// 読み出した行からの割り当ての生成
+///
+fn to_assignment(row: &Row) -> rusqlite::Result<Assignment> {
+    Ok(Assignment {
+        device_id: row.get(0)?,
+        location: row.get(1)?,
+        valid_from: row.get(2)?,
+        valid_until: row.get(3)?,
+    })
+}
+
+///
+/// デバイスの登録
+///
+/// # 引数
+/// * `conn` - データベースへの接続
+/// * `device_id` - デバイスID
+/// * `location` - 設置場所名
+/// * `since` - 設置した時刻(ミリ秒単位のUNIX時刻)
+/// * `description` - 説明
+///
+/// # 戻り値
+/// 登録に成功した場合は`Ok(())`を返す。デバイスが登録済みの場合や設置場所が
+/// 未登録の場合はエラー情報を`Err()`でラップして返す。
+///
+pub(crate) fn add_device(
+    conn: &Connection,
+    device_id: &str,
+    location: &str,
+    since: u64,
+    description: Option<String>,
+) -> Result<()>
+{
+    ensure_location(conn, location)?;
+
+    if find_device(conn, device_id)?.is_some() {
+        return Err(anyhow!("device {} is already registered", device_id));
+    }
+
+    let tx = conn.unchecked_transaction()?;
+
+    tx.execute(
+        INSERT_DEVICE_QUERY,
+        named_params! {
+            ":device_id" : device_id,
+            ":description" : description,
+            ":registered" : since,
+        },
+    )?;
+
+    tx.execute(
+        INSERT_ASSIGNMENT_QUERY,
+        named_params! {
+            ":device_id" : device_id,
+            ":location" : location,
+            ":valid_from" : since,
+        },
+    )?;
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+///
+/// デバイスの移設
+///
+/// # 引数
+/// * `conn` - データベースへの接続
+/// * `device_id` - デバイスID
+/// * `location` - 移設先の設置場所名
+/// * `since` - 移設した時刻(ミリ秒単位のUNIX時刻)
+///
+/// # 戻り値
+/// 移設に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+/// # 注記
+/// 現在の割り当てを移設した時刻で終了し、移設先の割り当てを追加する。
+///
+pub(crate) fn move_device(
+    conn: &Connection,
+    device_id: &str,
+    location: &str,
+    since: u64,
+) -> Result<()>
+{
+    ensure_location(conn, location)?;
+    let current = ensure_active(conn, device_id, since)?;
+
+    if current.location == location {
+        return Err(anyhow!("device {} is already at {}", device_id, location));
+    }
+
+    let tx = conn.unchecked_transaction()?;
+
+    tx.execute(
+        CLOSE_ASSIGNMENT_QUERY,
+        named_params! {":device_id": device_id, ":valid_until": since},
+    )?;
+
+    tx.execute(
+        INSERT_ASSIGNMENT_QUERY,
+        named_params! {
+            ":device_id" : device_id,
+            ":location" : location,
+            ":valid_from" : since,
+        },
+    )?;
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+///
+/// デバイスの退役
+///
+/// # 引数
+/// * `conn` - データベースへの接続
+/// * `device_id` - デバイスID
+/// * `since` - 退役した時刻(ミリ秒単位のUNIX時刻)
+///
+/// # 戻り値
+/// 退役に成功した場合は`Ok(())`を返す。失敗した場合はエラー情報を`Err()`で
+/// ラップして返す。
+///
+/// # 注記
+/// 現在の割り当てを退役した時刻で終了する。割り当ての履歴は残すので、退役
+/// 前に記録したレコードの設置場所は引き続き参照できる。
+///
+pub(crate) fn retire_device(conn: &Connection, device_id: &str, since: u64)
+    -> Result<()>
+{
+    ensure_active(conn, device_id, since)?;
+
+    let tx = conn.unchecked_transaction()?;
+
+    tx.execute(
+        CLOSE_ASSIGNMENT_QUERY,
+        named_params! {":device_id": device_id, ":valid_until": since},
+    )?;
+
+    tx.execute(
+        RETIRE_DEVICE_QUERY,
+        named_params! {":device_id": device_id, ":retired": since},
+    )?;
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+///
+/// 設置場所が登録済みであることの確認
+///
+fn ensure_location(conn: &Connection, location: &str) -> Result<()> {
+    let exists = select_locations(conn)?
+        .iter()
+        .any(|info| info.name == location);
+
+    if !exists {
+        return Err(anyhow!("location {} is not registered", location));
+    }
+
+    Ok(())
+}
+
+///
+/// デバイスの検索
+///
+fn find_device(conn: &Connection, device_id: &str)
+    -> rusqlite::Result<Option<DeviceInfo>>
+{
+    Ok(select_devices(conn)?
+        .into_iter()
+        .find(|info| info.device_id == device_id))
+}
+
+///
+/// デバイスが稼働中であることの確認
+///
+/// # 引数
+/// * `conn` - データベースへの接続
+/// * `device_id` - デバイスID
+/// * `since` - 割り当てを終了する時刻
+///
+/// # 戻り値
+/// 稼働中であれば現在の割り当てを`Ok()`でラップして返す。未登録の場合、退
+/// 役済みの場合、指定時刻が現在の割り当ての開始時刻以前の場合はエラー情報
+/// を`Err()`でラップして返す。
+///
+fn ensure_active(conn: &Connection, device_id: &str, since: u64)
+    -> Result<Assignment>
+{
+    let device = match find_device(conn, device_id)? {
+        Some(device) => device,
+        None => return Err(anyhow!("device {} is not registered", device_id)),
+    };
+
+    if device.retired.is_some() {
+        return Err(anyhow!("device {} is already retired", device_id));
+    }
+
+    let current = conn.query_row(
+        SELECT_CURRENT_ASSIGNMENT_QUERY,
+        named_params! {":device_id": device_id},
+        to_assignment
+    ).optional()?;
+
+    let Some(current) = current else {
+        return Err(anyhow!("device {} has no location", device_id));
+    };
+
+    if since <= current.valid_from {
+        return Err(anyhow!(
+            "device {}: the time must be after the current assignment",
+            device_id
+        ));
+    }
+
+    Ok(current)
+}
+
+///
+/// タグ文字列の分割
+///
+/// # 注記
+/// カンマ区切りの文字列を分割し、前後の空白を取り除く(空の要素は捨てる)。
+///
+pub(crate) fn split_tags(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_string())
+        .collect()
+}
@@ -0,0 +1,249 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! CSVファイルへの出力処理をまとめたモジュール
+//!
+//! 受信したレコードを、指定されたディレクトリに日付毎("YYYY-MM-DD.csv")の
+//! CSVファイルとして追記する。日付はレコードのタイムスタンプのローカル時刻
+//! で決める。
+//!
+
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveDate, TimeZone};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+
+use super::SinkStats;
+use crate::cmd_args::Options;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// CSVファイルのヘッダ行
+///
+/// metricsには追加の計測値を"名前=値"のセミコロン区切りで、extrasには未知
+/// のプロパティをJSONオブジェクトで格納する。
+const HEADER: &str = concat!(
+    "timestamp,location,device_id,temperature,humidity,air_pressure,",
+    "dew_point,absolute_humidity,humidex,heat_index,vpd,",
+    "sea_level_pressure,pressure_tendency,pressure_trend,forecast,metrics,",
+    "extras\n"
+);
+
+///
+/// CSV出力タスクをラップする構造体
+///
+pub(crate) struct CsvWriteTask {
+    /// タスクのジョインハンドル
+    handle: JoinHandle<()>,
+}
+
+impl CsvWriteTask {
+    ///
+    /// タスクの開始
+    ///
+    /// # 引数
+    /// * `opts` - オプション情報をパックしたオブジェクト
+    /// * `record_rx` - シンクのキューの受信用チャネルオブジェクト
+    /// * `stats` - シンクの稼働状況を集計するオブジェクト
+    ///
+    /// # 戻り値
+    /// タスクの開始に成功した場合は、タスクにバインドされたCsvWriteTaskのオブ
+    /// ジェクト(Futureトレイトを実装)を`Ok()`でラップして返す。
+    /// 失敗した場合はエラー情報を `Err()`でラップして返す。
+    ///
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        record_rx: Receiver<SensorRecord>,
+        stats: Arc<SinkStats>,
+    ) -> Result<Self>
+    {
+        let dir = match opts.csv_dir() {
+            Some(dir) => dir,
+            None => return Err(anyhow!("CSV directory is not specified")),
+        };
+
+        /*
+         * 出力先ディレクトリの生成
+         */
+        if let Err(err) = fs::create_dir_all(&dir) {
+            return Err(anyhow!("create {} failed: {}", dir.display(), err));
+        }
+
+        /*
+         * 出力タスクの起動
+         */
+        let handle = tokio::spawn(write_task(dir, record_rx, stats));
+
+        /*
+         * 戻り値の生成
+         */
+        Ok(Self {handle})
+    }
+}
+
+// Futureトレイトの実装
+impl Future for CsvWriteTask {
+    type Output = std::result::Result<(), tokio::task::JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().handle).poll(cx)
+    }
+}
+
+///
+/// 出力中のCSVファイルを表す構造体
+///
+struct CsvFile {
+    /// 出力先ディレクトリ
+    dir: PathBuf,
+
+    /// オープン中のファイルの日付とファイルオブジェクト
+    current: Option<(NaiveDate, File)>,
+}
+
+impl CsvFile {
+    ///
+    /// レコードの追記
+    ///
+    /// # 引数
+    /// * `record` - 追記するレコード
+    ///
+    /// # 注記
+    /// レコードの日付がオープン中のファイルと異なる場合は、該当する日付のファ
+    /// イルを開き直す。新規に作成したファイルにはヘッダ行を書き込む。
+    ///
+    fn write(&mut self, record: &SensorRecord) -> Result<()> {
+        let tm = match Local.timestamp_millis_opt(record.timestamp() as i64) {
+            chrono::LocalResult::Single(tm) => tm,
+            _ => return Err(anyhow!("invalid timestamp: {}", record.timestamp())),
+        };
+
+        /*
+         * 出力先ファイルの切り替え
+         */
+        let date = tm.date_naive();
+
+        if self.current.as_ref().is_none_or(|(current, _)| *current != date) {
+            let path = self.dir.join(format!("{}.csv", date.format("%Y-%m-%d")));
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?;
+
+            if file.metadata()?.len() == 0 {
+                file.write_all(HEADER.as_bytes())?;
+            }
+
+            debug!("open CSV file {}", path.display());
+            self.current = Some((date, file));
+        }
+
+        /*
+         * レコードの書き込み
+         */
+        let derived = record.derived();
+        let barometric = record.barometric().unwrap_or_default();
+        let metrics = record.metrics()
+            .iter()
+            .map(|(name, val)| format!("{}={}", name, val))
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let line = format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            tm.format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+            quote(&record.location()),
+            quote(&record.device_id().unwrap_or_default()),
+            value_string(record.temperature()),
+            value_string(record.humidity()),
+            value_string(record.air_pressure()),
+            value_string(derived.map(|d| d.dew_point)),
+            value_string(derived.map(|d| d.absolute_humidity)),
+            value_string(derived.map(|d| d.humidex)),
+            value_string(derived.map(|d| d.heat_index)),
+            value_string(derived.map(|d| d.vpd)),
+            value_string(barometric.sea_level_pressure),
+            value_string(barometric.tendency),
+            barometric.trend.map(|t| t.to_string()).unwrap_or_default(),
+            quote(&barometric.forecast.unwrap_or_default()),
+            quote(&metrics),
+            quote(&record.extras_json().unwrap_or_default()),
+        );
+
+        if let Some((_, file)) = &mut self.current {
+            file.write_all(line.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// CSV出力を行うタスク
+///
+/// # 引数
+/// * `dir` - 出力先ディレクトリ
+/// * `record_rx` - シンクのキューの受信用チャネルオブジェクト
+/// * `stats` - シンクの稼働状況を集計するオブジェクト
+///
+async fn write_task(
+    dir: PathBuf,
+    mut record_rx: Receiver<SensorRecord>,
+    stats: Arc<SinkStats>,
+)
+{
+    info!("start CSV write task");
+
+    let mut file = CsvFile {dir, current: None};
+
+    while let Some(record) = record_rx.recv().await {
+        match file.write(&record) {
+            Ok(()) => stats.count_delivered(1),
+
+            Err(err) => {
+                error!("CSV write failed: {}", err);
+                stats.count_failed(1, err);
+
+                // 次のレコードでファイルを開き直す
+                file.current = None;
+            }
+        }
+    }
+
+    info!("shutdown CSV write task");
+}
+
+///
+/// CSVのフィールドのクォート
+///
+/// # 注記
+/// 区切り文字、ダブルクォート、改行を含む場合のみダブルクォートで囲む。
+///
+pub(crate) fn quote(src: &str) -> String {
+    if src.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", src.replace('"', "\"\""))
+    } else {
+        src.to_string()
+    }
+}
+
+///
+/// 計測値の文字列化(値が無い場合は空文字列)
+///
+pub(crate) fn value_string(val: Option<f32>) -> String {
+    val.map(|val| val.to_string()).unwrap_or_default()
+}
@@ -0,0 +1,377 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 受信レコードの出力先(シンク)の処理をまとめたモジュール
+//!
+//! 中継処理で集約した受信レコードは、ファンアウトを介してシンク毎に用意した
+//! 有限長のキューへ振り分ける。各シンクは独立したタスクでキューを処理するの
+//! で、特定のシンクの遅延や障害が他のシンクに波及することはない。
+//!
+
+pub(crate) mod csv;
+pub(crate) mod mqtt;
+pub(crate) mod queue;
+pub(crate) mod relay;
+pub(crate) mod tsdb;
+pub(crate) mod webhook;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::Local;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::receiver::ReceiverStats;
+use crate::record::{local_time_string, SensorRecord};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+///
+/// シンクのキューが満杯の場合の振る舞いを指し示す列挙子
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Overflow {
+    /// キューに空きができるまで待つ(取りこぼしが許されないシンク用)
+    Block,
+
+    /// レコードを破棄する
+    Drop,
+}
+
+///
+/// シンク毎の稼働状況を集計する構造体
+///
+/// # 注記
+/// 各カウンタはシンクのタスクと集計結果の出力処理から並行して参照されるた
+/// め、アトミック変数で保持する。
+///
+pub(crate) struct SinkStats {
+    /// シンクの名前
+    name: String,
+
+    /// キューへ投入したレコードの数
+    queued: AtomicU64,
+
+    /// キューが満杯で破棄したレコードの数
+    dropped: AtomicU64,
+
+    /// 出力に成功したレコードの数
+    delivered: AtomicU64,
+
+    /// 出力に失敗したレコードの数
+    failed: AtomicU64,
+
+    /// 最後に出力に成功した時刻(ミリ秒単位のUNIX時刻、未成功の場合は0)
+    last_success: AtomicU64,
+
+    /// 最後に発生したエラーの時刻と内容
+    last_error: Mutex<Option<(u64, String)>>,
+}
+
+impl SinkStats {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `name` - シンクの名前
+    ///
+    pub(crate) fn new(name: &str) -> Arc<Self> {
+        Arc::new(Self {
+            name: name.to_string(),
+            queued: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            delivered: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            last_success: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        })
+    }
+
+    ///
+    /// キューへの投入の計上
+    ///
+    /// # 引数
+    /// * `n` - 投入したレコードの数
+    ///
+    pub(crate) fn count_queued(&self, n: u64) {
+        self.queued.fetch_add(n, Ordering::Relaxed);
+    }
+
+    ///
+    /// レコードの破棄の計上
+    ///
+    pub(crate) fn count_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    ///
+    /// 出力成功の計上
+    ///
+    /// # 引数
+    /// * `n` - 出力に成功したレコードの数
+    ///
+    pub(crate) fn count_delivered(&self, n: u64) {
+        self.delivered.fetch_add(n, Ordering::Relaxed);
+        self.last_success.store(now_millis(), Ordering::Relaxed);
+    }
+
+    ///
+    /// 出力失敗の計上
+    ///
+    /// # 引数
+    /// * `n` - 出力に失敗したレコードの数
+    /// * `err` - 失敗の内容
+    ///
+    pub(crate) fn count_failed(&self, n: u64, err: impl fmt::Display) {
+        self.failed.fetch_add(n, Ordering::Relaxed);
+
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = Some((now_millis(), err.to_string()));
+        }
+    }
+
+    ///
+    /// 出力に成功したレコード数の取得
+    ///
+    pub(crate) fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::Relaxed)
+    }
+
+    ///
+    /// キューに滞留しているレコード数の取得
+    ///
+    /// # 戻り値
+    /// 投入したレコードの内、出力の成否が確定していないレコードの数を返す。
+    ///
+    pub(crate) fn pending(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+            .saturating_sub(self.delivered.load(Ordering::Relaxed))
+            .saturating_sub(self.failed.load(Ordering::Relaxed))
+    }
+}
+
+// Displayトレイトの実装(ログへの出力用)
+impl fmt::Display for SinkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: queued={} delivered={} failed={} dropped={} pending={}",
+            self.name,
+            self.queued.load(Ordering::Relaxed),
+            self.delivered.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.pending(),
+        )?;
+
+        let last_success = self.last_success.load(Ordering::Relaxed);
+
+        if last_success > 0 {
+            write!(f, " last_success={}", local_time_string(last_success))?;
+        }
+
+        if let Ok(last_error) = self.last_error.lock() {
+            if let Some((tm, msg)) = last_error.as_ref() {
+                write!(f, " last_error={} \"{}\"", local_time_string(*tm), msg)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// ファンアウトからシンクへの出口を表す構造体
+///
+struct SinkPort {
+    /// シンクのキューへの送信用チャネルオブジェクト
+    tx: Sender<SensorRecord>,
+
+    /// キューが満杯の場合の振る舞い
+    overflow: Overflow,
+
+    /// シンクの稼働状況
+    stats: Arc<SinkStats>,
+}
+
+impl SinkPort {
+    ///
+    /// シンクのキューへのレコードの送信
+    ///
+    /// # 引数
+    /// * `record` - 送信するレコード
+    ///
+    async fn send(&self, record: SensorRecord) {
+        let result = match self.overflow {
+            Overflow::Block => self.tx.send(record).await
+                .map_err(|err| TrySendError::Closed(err.0)),
+
+            Overflow::Drop => self.tx.try_send(record),
+        };
+
+        match result {
+            Ok(()) => self.stats.count_queued(1),
+
+            Err(TrySendError::Full(_)) => {
+                self.stats.count_dropped();
+                warn!("sink queue is full, drop record: {}", self.stats.name);
+            }
+
+            Err(TrySendError::Closed(_)) => {
+                self.stats.count_dropped();
+                error!("sink has been closed: {}", self.stats.name);
+            }
+        }
+    }
+}
+
+///
+/// 受信レコードを複数のシンクへ振り分ける構造体
+///
+pub(crate) struct FanOut {
+    /// 振り分け先のリスト
+    ports: Vec<SinkPort>,
+
+    /// 各シンクのキューの長さ
+    capacity: usize,
+}
+
+impl FanOut {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `capacity` - 各シンクのキューの長さ
+    ///
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {ports: vec![], capacity}
+    }
+
+    ///
+    /// シンクの追加
+    ///
+    /// # 引数
+    /// * `name` - シンクの名前
+    /// * `overflow` - キューが満杯の場合の振る舞い
+    ///
+    /// # 戻り値
+    /// シンクのキューの受信用チャネルオブジェクトと、シンクの稼働状況を集計す
+    /// るオブジェクトのタプルを返す。
+    ///
+    pub(crate) fn add(&mut self, name: &str, overflow: Overflow)
+        -> (Receiver<SensorRecord>, Arc<SinkStats>)
+    {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let stats = SinkStats::new(name);
+
+        self.ports.push(SinkPort {tx, overflow, stats: stats.clone()});
+
+        (rx, stats)
+    }
+
+    ///
+    /// レコードの振り分け
+    ///
+    /// # 引数
+    /// * `record` - 振り分けるレコード
+    ///
+    /// # 注記
+    /// `Overflow::Drop`のシンクはキューが満杯の場合にレコードを破棄するので、
+    /// 本関数がブロックするのは`Overflow::Block`のシンクが滞留した場合のみ。
+    ///
+    pub(crate) async fn dispatch(&self, record: SensorRecord) {
+        for port in &self.ports {
+            port.send(record.clone()).await;
+        }
+    }
+
+    ///
+    /// 特定のシンクへのレコードの振り分け
+    ///
+    /// # 引数
+    /// * `name` - 振り分け先のシンクの名前
+    /// * `record` - 振り分けるレコード
+    ///
+    /// # 注記
+    /// 隔離したレコードの様に、一部のシンクでのみ扱うレコードに使用する。
+    ///
+    pub(crate) async fn dispatch_to(&self, name: &str, record: SensorRecord) {
+        for port in self.ports.iter().filter(|port| port.stats.name == name) {
+            port.send(record.clone()).await;
+        }
+    }
+}
+
+///
+/// シンクと受信側の稼働状況を定期的にログへ出力するタスクの起動
+///
+/// # 引数
+/// * `stats` - 出力対象のシンクの稼働状況のリスト
+/// * `receivers` - 出力対象の受信側の稼働状況のリスト
+/// * `period` - 出力間隔(秒)
+///
+/// # 戻り値
+/// タスクのジョインハンドルを返す。
+///
+/// # 注記
+/// タスクは自身では終了しないので、不要になった時点でアボートすること。
+///
+pub(crate) fn spawn_stats_reporter(
+    stats: Vec<Arc<SinkStats>>,
+    receivers: Vec<Arc<ReceiverStats>>,
+    period: u64,
+) -> JoinHandle<()>
+{
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(period));
+
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // 初回のティックは即座に完了するので読み捨てる
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            log_stats(&stats, &receivers);
+        }
+    })
+}
+
+///
+/// シンクと受信側の稼働状況のログへの出力
+///
+/// # 引数
+/// * `stats` - 出力対象のシンクの稼働状況のリスト
+/// * `receivers` - 出力対象の受信側の稼働状況のリスト
+///
+pub(crate) fn log_stats(
+    stats: &[Arc<SinkStats>],
+    receivers: &[Arc<ReceiverStats>],
+) {
+    for stats in stats {
+        info!("sink stats {}", stats);
+    }
+
+    for stats in receivers {
+        info!("receiver stats {}", stats);
+    }
+}
+
+///
+/// 現在時刻の取得
+///
+/// # 戻り値
+/// 現在時刻をミリ秒単位のUNIX時刻で返す
+///
+fn now_millis() -> u64 {
+    Local::now().timestamp_millis() as u64
+}
@@ -0,0 +1,524 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! MQTTへの再配信処理をまとめたモジュール
+//!
+//! 受信したレコードをステートトピックへ配信すると共に、Home Assistantの
+//! MQTTディスカバリー用の設定を配信する。
+//!
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+use super::SinkStats;
+use crate::record::{round_value, SensorRecord};
+use crate::cmd_args::Options;
+use crate::config::Config;
+use crate::measurement::Measurements;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// キープアライブの間隔(秒)
+const KEEP_ALIVE: u64 = 30;
+
+/// 再接続を試みるまでの待ち時間(秒)
+const RECONNECT_INTERVAL: u64 = 5;
+
+/// クライアントからイベントループへのリクエストキューの長さ
+const REQUEST_CAPACITY: usize = 64;
+
+/// クライアントIDに付与するサフィックス
+const CLIENT_ID_SUFFIX: &str = "-publisher";
+
+///
+/// 再配信する計測値の定義
+///
+/// # 注記
+/// 各要素は(プロパティ名, 表示名, device_class, 単位)の組。後半は気温と湿度
+/// から計算した派生値と、気圧から計算した派生値。
+///
+const MEASUREMENTS: [(&str, &str, Option<&str>, &str); 10] = [
+    ("temperature", "Temperature", Some("temperature"), "°C"),
+    ("humidity", "Humidity", Some("humidity"), "%"),
+    ("air_pressure", "Air pressure", Some("atmospheric_pressure"), "hPa"),
+    ("dew_point", "Dew point", Some("temperature"), "°C"),
+    ("absolute_humidity", "Absolute humidity", None, "g/m³"),
+    ("humidex", "Humidex", None, "°C"),
+    ("heat_index", "Heat index", Some("temperature"), "°C"),
+    ("vpd", "Vapour pressure deficit", Some("pressure"), "kPa"),
+    (
+        "sea_level_pressure",
+        "Sea-level pressure",
+        Some("atmospheric_pressure"),
+        "hPa"
+    ),
+    ("pressure_tendency", "Pressure tendency", None, "hPa/3h"),
+];
+
+///
+/// 再配信する文字列値の定義
+///
+/// # 注記
+/// 各要素は(プロパティ名, 表示名)の組。
+///
+const TEXTS: [(&str, &str); 2] = [
+    ("pressure_trend", "Pressure trend"),
+    ("forecast", "Forecast"),
+];
+
+///
+/// 再配信処理タスクをラップする構造体
+///
+pub(crate) struct MqttPublishTask {
+    /// タスクのジョインハンドル
+    handle: JoinHandle<()>,
+}
+
+impl MqttPublishTask {
+    ///
+    /// タスクの開始
+    ///
+    /// # 引数
+    /// * `opts` - オプション情報をパックしたオブジェクト
+    /// * `record_rx` - シンクのキューの受信用チャネルオブジェクト
+    /// * `stats` - シンクの稼働状況を集計するオブジェクト
+    ///
+    /// # 戻り値
+    /// タスクの開始に成功した場合は、タスクにバインドされたMqttPublishTaskの
+    /// オブジェクト(Futureトレイトを実装)を`Ok()`でラップして返す。
+    /// 失敗した場合はエラー情報を `Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// タスクはシンクのキューの送信側が閉じられた時点で終了する。
+    ///
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        record_rx: Receiver<SensorRecord>,
+        stats: Arc<SinkStats>,
+    ) -> Result<Self>
+    {
+        let (host, port) = match opts.mqtt_broker() {
+            Some(broker) => broker,
+            None => return Err(anyhow!("MQTT broker is not specified")),
+        };
+
+        let topics = Topics::new(
+            opts.mqtt_state_prefix(),
+            opts.mqtt_discovery_prefix()
+        );
+
+        /*
+         * 接続オプションの設定
+         *
+         * 異常切断時にHome Assistant側でエンティティを利用不可として扱えるよ
+         * う、ラストウィルでアベイラビリティトピックに"offline"を設定する。
+         */
+        let client_id = format!("{}{}", opts.mqtt_client_id(), CLIENT_ID_SUFFIX);
+        let mut mqtt_opts = MqttOptions::new(client_id, &host, port);
+
+        mqtt_opts
+            .set_keep_alive(Duration::from_secs(KEEP_ALIVE))
+            .set_last_will(LastWill::new(
+                topics.availability(),
+                "offline",
+                QoS::AtLeastOnce,
+                true
+            ));
+
+        if let Some((user, password)) = opts.mqtt_credentials() {
+            mqtt_opts.set_credentials(user, password);
+        }
+
+        let (client, eventloop) = AsyncClient::new(mqtt_opts, REQUEST_CAPACITY);
+
+        /*
+         * 追加の計測値のディスカバリー設定に用いる計測種別の読み込み
+         */
+        let config = Config::load(opts.config_file())?;
+        let measurements = Measurements::new(&config.measurements())?;
+
+        /*
+         * 再配信タスクの起動
+         */
+        let handle = tokio::spawn(publish_task(
+            client,
+            eventloop,
+            topics,
+            measurements,
+            record_rx,
+            stats
+        ));
+
+        /*
+         * 戻り値の生成
+         */
+        Ok(Self {handle})
+    }
+}
+
+// Futureトレイトの実装
+impl Future for MqttPublishTask {
+    type Output = std::result::Result<(), tokio::task::JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().handle).poll(cx)
+    }
+}
+
+///
+/// トピック名の生成規則をまとめた構造体
+///
+struct Topics {
+    /// ステートトピックのプレフィックス
+    state_prefix: String,
+
+    /// ディスカバリートピックのプレフィックス
+    discovery_prefix: String,
+}
+
+impl Topics {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `state_prefix` - ステートトピックのプレフィックス
+    /// * `discovery_prefix` - ディスカバリートピックのプレフィックス
+    ///
+    fn new(state_prefix: String, discovery_prefix: String) -> Self {
+        Self {state_prefix, discovery_prefix}
+    }
+
+    ///
+    /// アベイラビリティトピック名の取得
+    ///
+    fn availability(&self) -> String {
+        format!("{}/status", self.state_prefix)
+    }
+
+    ///
+    /// ステートトピック名の取得
+    ///
+    /// # 引数
+    /// * `node_id` - デバイスを識別するノードID
+    ///
+    fn state(&self, node_id: &str) -> String {
+        format!("{}/{}/state", self.state_prefix, node_id)
+    }
+
+    ///
+    /// ディスカバリー設定トピック名の取得
+    ///
+    /// # 引数
+    /// * `node_id` - デバイスを識別するノードID
+    /// * `key` - 計測値のプロパティ名
+    ///
+    fn config(&self, node_id: &str, key: &str) -> String {
+        format!("{}/sensor/{}/{}/config", self.discovery_prefix, node_id, key)
+    }
+}
+
+///
+/// 再配信処理を行うタスク
+///
+/// # 引数
+/// * `client` - MQTTクライアントオブジェクト
+/// * `eventloop` - MQTTクライアントのイベントループ
+/// * `topics` - トピック名の生成規則
+/// * `measurements` - 計測種別の登録簿
+/// * `record_rx` - シンクのキューの受信用チャネルオブジェクト
+/// * `stats` - シンクの稼働状況を集計するオブジェクト
+///
+/// # 注記
+/// ブローカーとの接続が切れている間もレコードの受信は継続し、リクエストキュー
+/// に空きが無い場合はそのレコードの配信を諦める(ステートはリテインされるた
+/// め、次のレコードの配信で最新の状態に復帰する)。
+///
+async fn publish_task(
+    client: AsyncClient,
+    mut eventloop: EventLoop,
+    topics: Topics,
+    measurements: Measurements,
+    mut record_rx: Receiver<SensorRecord>,
+    stats: Arc<SinkStats>,
+)
+{
+    info!("start MQTT publish task");
+
+    // ディスカバリー設定を配信済みのノードIDと計測値の組
+    let mut announced = HashSet::new();
+
+    loop {
+        tokio::select! {
+            // イベントループにイベントが届いた場合
+            event = eventloop.poll() => {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("connected to MQTT broker for publishing");
+
+                        // ブローカーが再起動している可能性があるので、
+                        // ディスカバリー設定は再度配信する
+                        announced.clear();
+
+                        if let Err(err) = client.try_publish(
+                            topics.availability(),
+                            QoS::AtLeastOnce,
+                            true,
+                            "online"
+                        ) {
+                            error!("MQTT publish failed: {}", err);
+                        }
+                    }
+
+                    Ok(_) => { /* ignore */ }
+
+                    Err(err) => {
+                        error!("MQTT connection error: {}", err);
+                        sleep(Duration::from_secs(RECONNECT_INTERVAL)).await;
+                    }
+                }
+            }
+
+            // キューにレコードが届いた場合
+            record = record_rx.recv() => {
+                match record {
+                    Some(record) => {
+                        match publish_record(
+                            &client,
+                            &topics,
+                            &measurements,
+                            &mut announced,
+                            &record
+                        ) {
+                            Ok(()) => stats.count_delivered(1),
+                            Err(err) => stats.count_failed(1, err),
+                        }
+                    }
+
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /*
+     * ブローカーからの切断
+     */
+    let _ = client.try_publish(
+        topics.availability(),
+        QoS::AtLeastOnce,
+        true,
+        "offline"
+    );
+
+    if client.try_disconnect().is_ok() {
+        let _ = tokio::time::timeout(Duration::from_secs(1), async {
+            while eventloop.poll().await.is_ok() {}
+        }).await;
+    }
+
+    info!("shutdown MQTT publish task");
+}
+
+///
+/// レコードの配信
+///
+/// # 引数
+/// * `client` - MQTTクライアントオブジェクト
+/// * `topics` - トピック名の生成規則
+/// * `measurements` - 計測種別の登録簿
+/// * `announced` - ディスカバリー設定を配信済みのノードIDと計測値の組の集合
+/// * `record` - 配信するレコード
+///
+/// # 戻り値
+/// ステートの配信要求に成功した場合は`Ok(())`を返す。失敗した場合はエラー情
+/// 報を`Err()`でラップして返す。
+///
+fn publish_record(
+    client: &AsyncClient,
+    topics: &Topics,
+    measurements: &Measurements,
+    announced: &mut HashSet<(String, String)>,
+    record: &SensorRecord,
+) -> Result<()>
+{
+    let node_id = node_id(record);
+    let derived = record.derived();
+    let barometric = record.barometric().unwrap_or_default();
+    let values = [
+        record.temperature(),
+        record.humidity(),
+        record.air_pressure(),
+        derived.map(|d| d.dew_point),
+        derived.map(|d| d.absolute_humidity),
+        derived.map(|d| d.humidex),
+        derived.map(|d| d.heat_index),
+        derived.map(|d| d.vpd),
+        barometric.sea_level_pressure,
+        barometric.tendency,
+    ];
+    let texts = [
+        barometric.trend.map(|t| t.to_string()),
+        barometric.forecast,
+    ];
+
+    /*
+     * 未配信の計測値や文字列値があればディスカバリー設定を配信
+     */
+    let mut pending = vec![];
+
+    for ((key, name, class, unit), value) in MEASUREMENTS.iter().zip(values) {
+        if value.is_some() {
+            pending.push((key.to_string(), json!({
+                "name": name,
+                "device_class": class,
+                "unit_of_measurement": unit,
+                "state_class": "measurement",
+            })));
+        }
+    }
+
+    for ((key, name), value) in TEXTS.iter().zip(&texts) {
+        if value.is_some() {
+            pending.push((key.to_string(), json!({"name": name})));
+        }
+    }
+
+    for key in record.metrics().keys() {
+        let Some(mtype) = measurements.get(key) else {
+            continue;
+        };
+
+        let unit = (!mtype.unit.is_empty()).then_some(&mtype.unit);
+
+        pending.push((key.clone(), json!({
+            "name": key,
+            "device_class": mtype.device_class,
+            "unit_of_measurement": unit,
+            "state_class": "measurement",
+        })));
+    }
+
+    for (key, mut config) in pending {
+        if announced.contains(&(node_id.clone(), key.clone())) {
+            continue;
+        }
+
+        if let Value::Object(config) = &mut config {
+            config.extend([
+                ("unique_id".into(), format!("{}_{}", node_id, key).into()),
+                ("object_id".into(), format!("{}_{}", node_id, key).into()),
+                ("state_topic".into(), topics.state(&node_id).into()),
+                (
+                    "value_template".into(),
+                    format!("{{{{ value_json.{} }}}}", key).into()
+                ),
+                ("availability_topic".into(), topics.availability().into()),
+                ("device".into(), json!({
+                    "identifiers": [node_id.clone()],
+                    "name": record.location(),
+                    "suggested_area": record.location(),
+                    "manufacturer": "envlog2",
+                    "model": "env-logger",
+                })),
+            ]);
+        }
+
+        match client.try_publish(
+            topics.config(&node_id, &key),
+            QoS::AtLeastOnce,
+            true,
+            config.to_string()
+        ) {
+            Ok(()) => {
+                debug!("announce {} {} to Home Assistant", node_id, key);
+                announced.insert((node_id.clone(), key));
+            }
+
+            Err(err) => error!("MQTT publish failed: {}", err),
+        }
+    }
+
+    /*
+     * ステートの配信
+     */
+    let mut state = Map::new();
+
+    state.insert("location".into(), record.location().into());
+    state.insert("device_id".into(), record.device_id().into());
+    state.insert("timestamp".into(), record.timestamp().into());
+
+    for ((key, _, _, _), value) in MEASUREMENTS.iter().zip(values) {
+        let value = value.map(|v| round_value(v.into(), 2));
+        state.insert(key.to_string(), value.into());
+    }
+
+    for ((key, _), value) in TEXTS.iter().zip(texts) {
+        state.insert(key.to_string(), value.into());
+    }
+
+    for (key, value) in record.metrics() {
+        state.insert(key.clone(), round_value((*value).into(), 2).into());
+    }
+
+    if let Err(err) = client.try_publish(
+        topics.state(&node_id),
+        QoS::AtLeastOnce,
+        true,
+        Value::Object(state).to_string()
+    ) {
+        error!("MQTT publish failed: {}", err);
+        return Err(anyhow!("MQTT publish failed: {}", err));
+    }
+
+    Ok(())
+}
+
+///
+/// レコードに対応するノードIDの生成
+///
+/// # 引数
+/// * `record` - 対象のレコード
+///
+/// # 戻り値
+/// デバイスIDが設定されている場合はデバイスIDから、設定されていない場合は設
+/// 置場所から生成したノードIDを返す。
+///
+/// # 注記
+/// Home AssistantのノードIDに使用できる文字は英数字とアンダースコア、ハイフ
+/// ンのみなので、ASCIIの記号は取り除き、非ASCII文字はUTF-8のバイト列を16進
+/// 表記に置き換える。
+///
+fn node_id(record: &SensorRecord) -> String {
+    let src = record.device_id().unwrap_or_else(|| record.location());
+    let mut ret = String::from("envlog_");
+
+    for ch in src.chars() {
+        if ch.is_ascii_alphanumeric() {
+            ret.push(ch.to_ascii_lowercase());
+
+        } else if !ch.is_ascii() {
+            let mut buf = [0u8; 4];
+
+            for byte in ch.encode_utf8(&mut buf).bytes() {
+                ret.push_str(&format!("{:02x}", byte));
+            }
+        }
+    }
+
+    ret
+}
@@ -0,0 +1,340 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 転送キューの操作と転送タスクの共通処理をまとめたモジュール
+//!
+//! 転送キューは記録済みレコードのうち、転送先毎に転送が完了していないもの
+//! をデータベース上で管理するテーブル。レコードの記録時にデータベースタスク
+//! が登録し、各転送タスクが転送完了時に取り除く。
+//!
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rusqlite::{named_params, Connection};
+use serde_json::Map;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::{sleep_until, Duration, Instant};
+
+use super::SinkStats;
+use crate::barometer::{Barometric, Trend};
+use crate::record::{RawValues, SensorRecord};
+use crate::telemetry::Telemetry;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 転送対象レコード取得クエリー
+const SELECT_BATCH_QUERY: &str =
+    include_str!("../../data/select_forward_batch.sql");
+
+/// 転送キューからの削除クエリー
+const DEQUEUE_QUERY: &str = include_str!("../../data/dequeue_forward.sql");
+
+/// 追加の計測値の取得クエリー
+const SELECT_MEASUREMENTS_QUERY: &str =
+    include_str!("../../data/select_measurements.sql");
+
+/// 通知が無い場合に転送キューを確認する間隔(秒)
+const POLL_INTERVAL: u64 = 60;
+
+/// 転送失敗時の再送待ち時間の初期値(秒)
+const RETRY_INTERVAL_MIN: u64 = 1;
+
+/// 転送失敗時の再送待ち時間の上限(秒)
+const RETRY_INTERVAL_MAX: u64 = 300;
+
+///
+/// 転送キューから読み出したレコードを表す構造体
+///
+pub(crate) struct QueuedRecord {
+    /// 設置場所名
+    location: String,
+
+    /// 登録時刻(ミリ秒単位のUNIX時刻)
+    timestamp: i64,
+
+    /// 記録済みレコードの内容(レコードが削除されている場合はNone)
+    record: Option<SensorRecord>,
+}
+
+impl QueuedRecord {
+    ///
+    /// 記録済みレコードへのアクセサ
+    ///
+    /// # 戻り値
+    /// 記録済みレコードが存在する場合は、レコードを`Some()`でラップして返す。
+    ///
+    pub(crate) fn record(&self) -> Option<&SensorRecord> {
+        self.record.as_ref()
+    }
+}
+
+///
+/// 転送先毎の転送キューを表す構造体
+///
+pub(crate) struct ForwardQueue {
+    /// データベース接続オブジェクト
+    conn: Connection,
+
+    /// 転送先の識別名
+    sink: &'static str,
+}
+
+impl ForwardQueue {
+    ///
+    /// 転送キューのオープン
+    ///
+    /// # 引数
+    /// * `path` - データベースファイルへのパス
+    /// * `sink` - 転送先の識別名
+    ///
+    /// # 戻り値
+    /// オープンに成功した場合はForwardQueueオブジェクトを`Ok()`でラップして返
+    /// す。
+    ///
+    /// # 注記
+    /// テーブルはデータベースタスクが生成するので、データベースタスクの起動後
+    /// に呼び出すこと。
+    ///
+    pub(crate) fn open(path: impl AsRef<Path>, sink: &'static str)
+        -> Result<Self>
+    {
+        match Connection::open(path) {
+            Ok(conn) => Ok(Self {conn, sink}),
+            Err(err) => Err(anyhow!("databse open failed: {}", err)),
+        }
+    }
+
+    ///
+    /// 転送キューからのレコードの読み出し
+    ///
+    /// # 引数
+    /// * `limit` - 読み出すレコードの最大数
+    ///
+    /// # 戻り値
+    /// 登録時刻の古い順に最大で`limit`件のレコードを`Ok()`でラップして返す。
+    ///
+    pub(crate) fn fetch(&self, limit: usize) -> Result<Vec<QueuedRecord>> {
+        let mut stmt = self.conn.prepare_cached(SELECT_BATCH_QUERY)?;
+        let rows = stmt.query_map(
+            named_params! {
+                ":sink": self.sink,
+                ":limit": limit as i64,
+            },
+            |row| {
+                let location: String = row.get(0)?;
+                let timestamp: i64 = row.get(1)?;
+                let exists: bool = row.get(6)?;
+
+                let record = if exists {
+                    let mut record = SensorRecord::new(
+                        location.clone(),
+                        row.get(2)?,
+                        timestamp as u64,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    );
+
+                    if record.air_pressure().is_some() {
+                        let tendency: Option<f32> = row.get(8)?;
+
+                        record.set_barometric(Barometric {
+                            sea_level_pressure: row.get(7)?,
+                            tendency,
+                            trend: tendency.map(Trend::from_tendency),
+                            forecast: row.get(9)?,
+                        });
+                    }
+
+                    let extras: Option<String> = row.get(10)?;
+                    let extras = extras.as_deref()
+                        .and_then(|s| serde_json::from_str::<Map<_, _>>(s).ok())
+                        .unwrap_or_default();
+
+                    for (key, val) in extras {
+                        record.set_extra(&key, val);
+                    }
+
+                    record.set_raw(RawValues {
+                        temperature: row.get(11)?,
+                        humidity: row.get(12)?,
+                        air_pressure: row.get(13)?,
+                    });
+
+                    let quality: Option<String> = row.get(14)?;
+
+                    for flag in quality.iter().flat_map(|s| s.split(',')) {
+                        record.add_quality_flag(flag.to_string());
+                    }
+
+                    record.set_telemetry(Telemetry {
+                        firmware: row.get(15)?,
+                        rssi: row.get(16)?,
+                        battery: row.get(17)?,
+                        uptime: row.get(18)?,
+                        reset_reason: row.get(19)?,
+                    });
+
+                    Some(record)
+                } else {
+                    None
+                };
+
+                Ok(QueuedRecord {location, timestamp, record})
+            }
+        )?;
+
+        let mut items = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        /*
+         * 追加の計測値の読み出し
+         */
+        let mut stmt = self.conn.prepare_cached(SELECT_MEASUREMENTS_QUERY)?;
+
+        for item in &mut items {
+            let Some(record) = &mut item.record else {
+                continue;
+            };
+
+            let rows = stmt.query_map(
+                named_params! {
+                    ":location": item.location,
+                    ":timestamp": item.timestamp,
+                },
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, f32>(1)?))
+            )?;
+
+            for row in rows {
+                let (name, value) = row?;
+                record.set_metric(&name, value);
+            }
+        }
+
+        Ok(items)
+    }
+
+    ///
+    /// 転送キューからのレコードの削除
+    ///
+    /// # 引数
+    /// * `items` - 削除するレコードのリスト
+    ///
+    pub(crate) fn dequeue(&self, items: &[QueuedRecord]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        for item in items {
+            tx.execute(
+                DEQUEUE_QUERY,
+                named_params! {
+                    ":sink": self.sink,
+                    ":location": item.location,
+                    ":timestamp": item.timestamp,
+                },
+            )?;
+        }
+
+        Ok(tx.commit()?)
+    }
+}
+
+///
+/// 転送キューの送出処理を表すトレイト
+///
+pub(crate) trait Flush {
+    ///
+    /// 転送キューに溜まっているレコードの転送
+    ///
+    /// # 戻り値
+    /// 転送キューが空になった場合は`Ok(())`を返す。転送を中断した場合はエラー
+    /// 情報を`Err()`でラップして返す。
+    ///
+    fn flush(&mut self) -> impl Future<Output = Result<()>> + Send;
+}
+
+///
+/// 転送タスクの共通処理
+///
+/// # 引数
+/// * `name` - ログに出力する転送先の名前
+/// * `forwarder` - 転送キューの送出処理を行うオブジェクト
+/// * `stored_rx` - 記録済みレコードの受信用チャネルオブジェクト
+/// * `stats` - シンクの稼働状況を集計するオブジェクト
+///
+/// # 注記
+/// 記録済みレコードの通知は転送開始のきっかけとしてのみ使用し、転送する内容
+/// は常に転送キューから読み出す。転送に失敗した場合は、待ち時間を倍々に伸ば
+/// しながら再送を試みる(待ち時間の間に届いた通知では転送を行わない)。記録
+/// 済みレコードの送信側が全て閉じられた時点で最後の転送を試みて終了する。
+///
+pub(crate) async fn forward_loop(
+    name: &str,
+    mut forwarder: impl Flush,
+    mut stored_rx: Receiver<SensorRecord>,
+    stats: Arc<SinkStats>,
+)
+{
+    let mut retry_interval = Duration::from_secs(RETRY_INTERVAL_MIN);
+    let mut next_try = Instant::now();
+    let mut closed = false;
+
+    loop {
+        /*
+         * 転送キューの送出
+         */
+        let wakeup = if Instant::now() >= next_try {
+            match forwarder.flush().await {
+                Ok(()) => {
+                    retry_interval = Duration::from_secs(RETRY_INTERVAL_MIN);
+                    Instant::now() + Duration::from_secs(POLL_INTERVAL)
+                }
+
+                Err(err) => {
+                    // 再送するのでレコード数としては計上しない
+                    stats.count_failed(0, &err);
+
+                    warn!(
+                        "{} forward failed: {} (retry in {}s)",
+                        name,
+                        err,
+                        retry_interval.as_secs()
+                    );
+
+                    next_try = Instant::now() + retry_interval;
+                    retry_interval = (retry_interval * 2)
+                        .min(Duration::from_secs(RETRY_INTERVAL_MAX));
+                    next_try
+                }
+            }
+        } else {
+            next_try
+        };
+
+        if closed {
+            break;
+        }
+
+        /*
+         * 次の転送契機を待つ
+         */
+        tokio::select! {
+            result = stored_rx.recv() => {
+                match result {
+                    Ok(_) => stats.count_queued(1),
+                    Err(RecvError::Lagged(n)) => stats.count_queued(n),
+                    Err(RecvError::Closed) => closed = true,
+                }
+            }
+
+            _ = sleep_until(wakeup) => {}
+        }
+    }
+}
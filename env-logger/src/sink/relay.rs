@@ -0,0 +1,290 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 上流のenv-loggerへの中継処理をまとめたモジュール
+//!
+//! 記録済みのレコードを、中継元の共有鍵で署名したエンベロープ(形式は
+//! `receiver::auth`を参照)に収めて、改行区切りのJSONで上流のenv-loggerの
+//! TCPポートへ転送する。上流からの"OK"応答を受け取った時点で転送キューか
+//! ら取り除くので、回線断の間に溜まったレコードは復旧後に順次転送される。
+//!
+//! 転送する計測値は較正と処理ステージの適用後の値で、較正前の計測値、品質
+//! フラグ、デバイスの稼働状態も併せて転送する。上流では較正と処理ステージ
+//! を適用せずに記録する。
+//!
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+
+use super::SinkStats;
+use super::queue::{self, Flush, ForwardQueue};
+use crate::cmd_args::Options;
+use crate::receiver::auth::RelaySigner;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 転送キュー上での転送先の識別名
+pub(crate) const SINK_NAME: &str = "relay";
+
+/// 転送キューから一度に読み出すレコードの数
+const BATCH_SIZE: usize = 100;
+
+/// 上流への接続のタイムアウト時間(秒)
+const CONNECT_TIMEOUT: u64 = 10;
+
+/// 上流からの応答のタイムアウト時間(秒)
+const ACK_TIMEOUT: u64 = 10;
+
+///
+/// 中継処理タスクをラップする構造体
+///
+pub(crate) struct RelayForwardTask {
+    /// タスクのジョインハンドル
+    handle: JoinHandle<()>,
+}
+
+impl RelayForwardTask {
+    ///
+    /// タスクの開始
+    ///
+    /// # 引数
+    /// * `opts` - オプション情報をパックしたオブジェクト
+    /// * `stored_rx` - 記録済みレコードの受信用チャネルオブジェクト
+    /// * `stats` - シンクの稼働状況を集計するオブジェクト
+    ///
+    /// # 戻り値
+    /// タスクの開始に成功した場合は、タスクにバインドされたRelayForwardTaskの
+    /// オブジェクト(Futureトレイトを実装)を`Ok()`でラップして返す。
+    /// 失敗した場合はエラー情報を `Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// 転送キューを参照するため、データベースタスクの起動後に呼び出すこと。
+    ///
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        stored_rx: Receiver<SensorRecord>,
+        stats: Arc<SinkStats>,
+    ) -> Result<Self>
+    {
+        let (host, port) = match opts.relay_upstream() {
+            Some(upstream) => upstream,
+            None => return Err(anyhow!("relay upstream is not specified")),
+        };
+
+        let signer = match opts.relay_key() {
+            Some((name, secret)) => RelaySigner::new(&name, &secret),
+            None => return Err(anyhow!("relay key is not specified")),
+        };
+
+        /*
+         * 転送キューのオープン
+         */
+        let queue = ForwardQueue::open(opts.db_file(), SINK_NAME)?;
+
+        /*
+         * 中継タスクの起動
+         */
+        let upstream = Upstream {host, port, signer};
+        let handle = tokio::spawn(forward_task(
+            Forwarder {queue, stats, upstream},
+            stored_rx
+        ));
+
+        /*
+         * 戻り値の生成
+         */
+        Ok(Self {handle})
+    }
+}
+
+// Futureトレイトの実装
+impl Future for RelayForwardTask {
+    type Output = std::result::Result<(), tokio::task::JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().handle).poll(cx)
+    }
+}
+
+///
+/// 転送の失敗理由を表す列挙子
+///
+enum SendError {
+    /// 時間をおいて再送すべき失敗(通信エラーや応答が得られなかった場合)
+    Retry(String),
+
+    /// 上流がレコードを受け付けなかった場合
+    Reject,
+}
+
+///
+/// 上流のenv-loggerを表す構造体
+///
+struct Upstream {
+    /// 上流のenv-loggerのホスト名
+    host: String,
+
+    /// 上流のenv-loggerのポート番号
+    port: u16,
+
+    /// 転送するレコードの署名を行うオブジェクト
+    signer: RelaySigner,
+}
+
+///
+/// 中継処理に必要な情報をまとめた構造体
+///
+struct Forwarder {
+    /// 転送キュー
+    queue: ForwardQueue,
+
+    /// シンクの稼働状況
+    stats: Arc<SinkStats>,
+
+    /// 上流のenv-logger
+    upstream: Upstream,
+}
+
+// Flushトレイトの実装
+//
+// レコードは登録時刻の古い順に1件ずつ転送し、転送に失敗した時点でそれまでに
+// 転送できた分を転送キューから取り除いて中断する。
+impl Flush for Forwarder {
+    async fn flush(&mut self) -> Result<()> {
+        loop {
+            let batch = self.queue.fetch(BATCH_SIZE)?;
+
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            for (i, item) in batch.iter().enumerate() {
+                // レコードが削除されている場合は転送済みとして扱う
+                let Some(record) = item.record() else {
+                    continue;
+                };
+
+                match self.upstream.send(record).await {
+                    Ok(()) => {
+                        debug!("relay record: {}", record);
+                        self.stats.count_delivered(1);
+                    }
+
+                    Err(SendError::Reject) => {
+                        error!("upstream rejected record: {}", record);
+                        self.stats.count_failed(1, "rejected by upstream");
+                    }
+
+                    Err(SendError::Retry(msg)) => {
+                        self.queue.dequeue(&batch[..i])?;
+                        return Err(anyhow!(msg));
+                    }
+                }
+            }
+
+            self.queue.dequeue(&batch)?;
+        }
+    }
+}
+
+impl Upstream {
+    ///
+    /// 上流へのレコードの送信
+    ///
+    /// # 引数
+    /// * `record` - 送信するレコード
+    ///
+    /// # 戻り値
+    /// 上流がレコードを受け付けた場合は`Ok(())`を返す。失敗した場合は失敗理由
+    /// を`Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// 上流のTCPレシーバは1接続につき1レコードを受け付けるので、レコード毎に
+    /// 接続を行う。署名は再送時も含めて送信の都度行う。
+    ///
+    async fn send(&self, record: &SensorRecord)
+        -> std::result::Result<(), SendError>
+    {
+        /*
+         * 上流への接続
+         */
+        let duration = Duration::from_secs(CONNECT_TIMEOUT);
+        let addr = (self.host.as_str(), self.port);
+        let mut sock = match timeout(duration, TcpStream::connect(addr)).await {
+            Ok(Ok(sock)) => sock,
+            Ok(Err(err)) => return Err(SendError::Retry(err.to_string())),
+            Err(_) => return Err(SendError::Retry("connect timeout".into())),
+        };
+
+        /*
+         * レコードの送信と応答の受信
+         */
+        let line = match self.signer.seal(record.to_relay_json()) {
+            Ok(envelope) => format!("{}\n", envelope),
+            Err(err) => return Err(SendError::Retry(err.to_string())),
+        };
+
+        let duration = Duration::from_secs(ACK_TIMEOUT);
+        let result = timeout(duration, async {
+            let mut reply = String::new();
+
+            sock.write_all(line.as_bytes()).await?;
+            BufReader::new(&mut sock).read_line(&mut reply).await?;
+
+            Ok::<_, std::io::Error>(reply)
+        }).await;
+
+        /*
+         * 応答の評価
+         */
+        match result {
+            Ok(Ok(reply)) => match reply.trim_end() {
+                "OK" => Ok(()),
+                "NG" => Err(SendError::Reject),
+
+                // 上流の混雑による拒否は再送する
+                "NG busy" | "NG timeout" | "NG rate_limited" => {
+                    Err(SendError::Retry(reply.trim_end().into()))
+                }
+
+                s if s.starts_with("NG ") => Err(SendError::Reject),
+                _ => Err(SendError::Retry("no acknowledgement".into())),
+            },
+
+            Ok(Err(err)) => Err(SendError::Retry(err.to_string())),
+            Err(_) => Err(SendError::Retry("acknowledgement timeout".into())),
+        }
+    }
+}
+
+///
+/// 中継処理を行うタスク
+///
+/// # 引数
+/// * `forwarder` - 中継処理に必要な情報をまとめたオブジェクト
+/// * `stored_rx` - 記録済みレコードの受信用チャネルオブジェクト
+///
+async fn forward_task(forwarder: Forwarder, stored_rx: Receiver<SensorRecord>) {
+    info!("start relay task");
+
+    let stats = forwarder.stats.clone();
+
+    queue::forward_loop("relay", forwarder, stored_rx, stats).await;
+
+    info!("shutdown relay task");
+}
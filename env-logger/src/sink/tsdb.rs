@@ -0,0 +1,379 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 時系列データベースへの転送処理をまとめたモジュール
+//!
+//! 記録済みのレコードをラインプロトコルに変換し、InfluxDBやVictoriaMetrics
+//! の書き込みAPIへ転送する。転送待ちのレコードはデータベース上の転送キュー
+//! で管理し、転送に成功した時点でキューから取り除く。
+//!
+
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{Client, StatusCode};
+use tokio::sync::broadcast::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+use super::SinkStats;
+use super::queue::{self, Flush, ForwardQueue, QueuedRecord};
+use crate::cmd_args::Options;
+use crate::line_protocol::{FieldValue, Point};
+use crate::record::{round_value, SensorRecord};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 転送キュー上での転送先の識別名
+pub(crate) const SINK_NAME: &str = "tsdb";
+
+/// リクエストのタイムアウト時間(秒)
+const REQUEST_TIMEOUT: u64 = 30;
+
+///
+/// 転送処理タスクをラップする構造体
+///
+pub(crate) struct TsdbForwardTask {
+    /// タスクのジョインハンドル
+    handle: JoinHandle<()>,
+}
+
+impl TsdbForwardTask {
+    ///
+    /// タスクの開始
+    ///
+    /// # 引数
+    /// * `opts` - オプション情報をパックしたオブジェクト
+    /// * `stored_rx` - 記録済みレコードの受信用チャネルオブジェクト
+    /// * `stats` - シンクの稼働状況を集計するオブジェクト
+    ///
+    /// # 戻り値
+    /// タスクの開始に成功した場合は、タスクにバインドされたTsdbForwardTaskの
+    /// オブジェクト(Futureトレイトを実装)を`Ok()`でラップして返す。
+    /// 失敗した場合はエラー情報を `Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// 転送キューを参照するため、データベースタスクの起動後に呼び出すこと。
+    ///
+    pub(crate) async fn start(
+        opts: Arc<Options>,
+        stored_rx: Receiver<SensorRecord>,
+        stats: Arc<SinkStats>,
+    ) -> Result<Self>
+    {
+        let url = match opts.tsdb_url() {
+            Some(url) => url,
+            None => return Err(anyhow!("TSDB URL is not specified")),
+        };
+
+        /*
+         * 転送キューのオープン
+         */
+        let queue = ForwardQueue::open(opts.db_file(), SINK_NAME)?;
+
+        /*
+         * HTTPクライアントの生成
+         */
+        let client = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .user_agent(concat!("env-logger/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+
+        let forwarder = Forwarder {
+            queue,
+            stats,
+            api: WriteApi {client, url, token: opts.tsdb_token()},
+            measurement: opts.tsdb_measurement(),
+            batch_size: opts.tsdb_batch_size(),
+        };
+
+        /*
+         * 転送タスクの起動
+         */
+        let handle = tokio::spawn(forward_task(forwarder, stored_rx));
+
+        /*
+         * 戻り値の生成
+         */
+        Ok(Self {handle})
+    }
+}
+
+// Futureトレイトの実装
+impl Future for TsdbForwardTask {
+    type Output = std::result::Result<(), tokio::task::JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().handle).poll(cx)
+    }
+}
+
+///
+/// 転送の失敗理由を表す列挙子
+///
+enum SendError {
+    /// 時間をおいて再送すべき失敗(通信エラーやサーバ側のエラー)
+    Retry(String),
+
+    /// 再送しても受け付けられない失敗(データ自体の不備)
+    Reject(String),
+}
+
+///
+/// 書き込みAPIへの送信に必要な情報をまとめた構造体
+///
+struct WriteApi {
+    /// HTTPクライアント
+    client: Client,
+
+    /// 書き込みAPIのURL
+    url: String,
+
+    /// 認証トークン
+    token: Option<String>,
+}
+
+///
+/// 転送処理に必要な情報をまとめた構造体
+///
+struct Forwarder {
+    /// 転送キュー
+    queue: ForwardQueue,
+
+    /// シンクの稼働状況
+    stats: Arc<SinkStats>,
+
+    /// 書き込みAPI
+    api: WriteApi,
+
+    /// メジャーメント名
+    measurement: String,
+
+    /// 1回のリクエストで転送するレコードの最大数
+    batch_size: usize,
+}
+
+// Flushトレイトの実装
+//
+// データの不備で受け付けられなかったバッチは、再送しても結果が変わらないの
+// でエラーをログに記録した上で転送キューから取り除く。
+impl Flush for Forwarder {
+    async fn flush(&mut self) -> Result<()> {
+        loop {
+            let batch = self.queue.fetch(self.batch_size)?;
+
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            /*
+             * 送信データの生成
+             */
+            let mut lines = String::new();
+            let mut count = 0;
+
+            for item in &batch {
+                if let Some(point) = self.to_point(item) {
+                    lines.push_str(&point.to_string());
+                    lines.push('\n');
+                    count += 1;
+                }
+            }
+
+            /*
+             * 送信
+             */
+            if count > 0 {
+                match self.api.send(lines).await {
+                    Ok(()) => {
+                        debug!("forward {} records to TSDB", count);
+                        self.stats.count_delivered(count);
+                    }
+
+                    Err(SendError::Reject(msg)) => {
+                        error!("TSDB rejected {} records: {}", count, msg);
+                        self.stats.count_failed(count, msg);
+                    }
+
+                    Err(SendError::Retry(msg)) => return Err(anyhow!(msg)),
+                }
+            }
+
+            /*
+             * 転送キューからの削除
+             */
+            self.queue.dequeue(&batch)?;
+        }
+    }
+}
+
+impl Forwarder {
+    ///
+    /// レコードのラインプロトコルへの変換
+    ///
+    /// # 引数
+    /// * `item` - 転送キューから読み出したレコード
+    ///
+    /// # 戻り値
+    /// 変換に成功した場合はPointオブジェクトを`Some()`でラップして返す。レコ
+    /// ードが削除されている場合や計測値を1つも持たない場合はNoneを返す。
+    ///
+    /// # 注記
+    /// タイムスタンプはナノ秒精度で出力する。
+    ///
+    fn to_point(&self, item: &QueuedRecord) -> Option<Point> {
+        let record = item.record()?;
+        let timestamp = (record.timestamp() as i64).checked_mul(1_000_000)?;
+        let mut point = Point::new(&self.measurement, Some(timestamp));
+
+        point.add_tag("location", &record.location());
+
+        if let Some(device_id) = record.device_id() {
+            point.add_tag("device_id", &device_id);
+        }
+
+        let values = [
+            ("temperature", record.temperature()),
+            ("humidity", record.humidity()),
+            ("air_pressure", record.air_pressure()),
+        ];
+
+        for (key, value) in values {
+            if let Some(value) = value {
+                let value = round_value(value.into(), 2);
+                point.add_field(key, FieldValue::Float(value));
+            }
+        }
+
+        if let Some(derived) = record.derived() {
+            for (key, value) in derived.fields() {
+                let value = round_value(value.into(), 2);
+                point.add_field(key, FieldValue::Float(value));
+            }
+        }
+
+        for (key, value) in record.metrics() {
+            let value = round_value((*value).into(), 2);
+            point.add_field(key, FieldValue::Float(value));
+        }
+
+        if let Some(barometric) = record.barometric() {
+            let values = [
+                ("sea_level_pressure", barometric.sea_level_pressure),
+                ("pressure_tendency", barometric.tendency),
+            ];
+
+            for (key, value) in values {
+                if let Some(value) = value {
+                    let value = round_value(value.into(), 2);
+                    point.add_field(key, FieldValue::Float(value));
+                }
+            }
+
+            if let Some(trend) = barometric.trend {
+                point.add_field(
+                    "pressure_trend",
+                    FieldValue::String(trend.to_string())
+                );
+            }
+
+            if let Some(forecast) = barometric.forecast {
+                point.add_field("forecast", FieldValue::String(forecast));
+            }
+        }
+
+        point.has_fields().then_some(point)
+    }
+}
+
+impl WriteApi {
+    ///
+    /// 書き込みAPIへの送信
+    ///
+    /// # 引数
+    /// * `lines` - 送信するラインプロトコル
+    ///
+    /// # 戻り値
+    /// 書き込みAPIが受け付けた場合は`Ok(())`を返す。失敗した場合は失敗理由を
+    /// `Err()`でラップして返す。
+    ///
+    async fn send(&self, lines: String) -> std::result::Result<(), SendError> {
+        /*
+         * ボディの圧縮
+         */
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+
+        let body = match encoder.write_all(lines.as_bytes())
+            .and_then(|_| encoder.finish())
+        {
+            Ok(body) => body,
+            Err(err) => return Err(SendError::Retry(err.to_string())),
+        };
+
+        /*
+         * リクエストの送信
+         */
+        let mut req = self.client.post(&self.url)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(body);
+
+        if let Some(token) = &self.token {
+            req = req.header(AUTHORIZATION, format!("Token {}", token));
+        }
+
+        let resp = match req.send().await {
+            Ok(resp) => resp,
+            Err(err) => return Err(SendError::Retry(err.to_string())),
+        };
+
+        /*
+         * 応答の評価
+         */
+        let status = resp.status();
+
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let text = resp.text().await.unwrap_or_default();
+        let msg = format!("{} {}", status, text.trim()).trim_end().to_string();
+
+        match status {
+            StatusCode::BAD_REQUEST
+                | StatusCode::PAYLOAD_TOO_LARGE
+                | StatusCode::UNPROCESSABLE_ENTITY => Err(SendError::Reject(msg)),
+
+            _ => Err(SendError::Retry(msg)),
+        }
+    }
+}
+
+///
+/// 転送処理を行うタスク
+///
+/// # 引数
+/// * `forwarder` - 転送処理に必要な情報をまとめたオブジェクト
+/// * `stored_rx` - 記録済みレコードの受信用チャネルオブジェクト
+///
+async fn forward_task(forwarder: Forwarder, stored_rx: Receiver<SensorRecord>) {
+    info!("start TSDB forward task");
+
+    let stats = forwarder.stats.clone();
+
+    queue::forward_loop("TSDB", forwarder, stored_rx, stats).await;
+
+    info!("shutdown TSDB forward task");
+}
@@ -0,0 +1,155 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! Webhookへの通知処理をまとめたモジュール
+//!
+//! 受信したレコードを1件ずつJSONで指定されたURLへPOSTする。通知に失敗した
+//! レコードは再送しない(取りこぼしが許されない用途には転送キューを使用する
+//! TSDBへの転送や中継を用いる)。
+//!
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Result};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+use super::SinkStats;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// リクエストのタイムアウト時間(秒)
+const REQUEST_TIMEOUT: u64 = 10;
+
+///
+/// Webhook通知タスクをラップする構造体
+///
+pub(crate) struct WebhookTask {
+    /// タスクのジョインハンドル
+    handle: JoinHandle<()>,
+}
+
+impl WebhookTask {
+    ///
+    /// タスクの開始
+    ///
+    /// # 引数
+    /// * `url` - 通知先のURL
+    /// * `record_rx` - シンクのキューの受信用チャネルオブジェクト
+    /// * `stats` - シンクの稼働状況を集計するオブジェクト
+    ///
+    /// # 戻り値
+    /// タスクの開始に成功した場合は、タスクにバインドされたWebhookTaskのオブ
+    /// ジェクト(Futureトレイトを実装)を`Ok()`でラップして返す。
+    /// 失敗した場合はエラー情報を `Err()`でラップして返す。
+    ///
+    /// # 注記
+    /// 通知先は複数指定できるため、オプション情報ではなく通知先のURLを受け取
+    /// る。
+    ///
+    pub(crate) async fn start(
+        url: String,
+        record_rx: Receiver<SensorRecord>,
+        stats: Arc<SinkStats>,
+    ) -> Result<Self>
+    {
+        /*
+         * HTTPクライアントの生成
+         */
+        let client = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .user_agent(concat!("env-logger/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+
+        /*
+         * 通知タスクの起動
+         */
+        let handle = tokio::spawn(notify_task(client, url, record_rx, stats));
+
+        /*
+         * 戻り値の生成
+         */
+        Ok(Self {handle})
+    }
+}
+
+// Futureトレイトの実装
+impl Future for WebhookTask {
+    type Output = std::result::Result<(), tokio::task::JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().handle).poll(cx)
+    }
+}
+
+///
+/// Webhook通知を行うタスク
+///
+/// # 引数
+/// * `client` - HTTPクライアント
+/// * `url` - 通知先のURL
+/// * `record_rx` - シンクのキューの受信用チャネルオブジェクト
+/// * `stats` - シンクの稼働状況を集計するオブジェクト
+///
+async fn notify_task(
+    client: Client,
+    url: String,
+    mut record_rx: Receiver<SensorRecord>,
+    stats: Arc<SinkStats>,
+)
+{
+    info!("start webhook task for {}", url);
+
+    while let Some(record) = record_rx.recv().await {
+        match post(&client, &url, &record).await {
+            Ok(()) => stats.count_delivered(1),
+
+            Err(err) => {
+                error!("webhook {} failed: {}", url, err);
+                stats.count_failed(1, err);
+            }
+        }
+    }
+
+    info!("shutdown webhook task for {}", url);
+}
+
+///
+/// レコードのPOST
+///
+/// # 引数
+/// * `client` - HTTPクライアント
+/// * `url` - 通知先のURL
+/// * `record` - 通知するレコード
+///
+/// # 戻り値
+/// 通知先が2xxで応答した場合は`Ok(())`を返す。それ以外の場合はエラー情報を
+/// `Err()`でラップして返す。
+///
+async fn post(client: &Client, url: &str, record: &SensorRecord) -> Result<()> {
+    let resp = client.post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(record.to_json())
+        .send()
+        .await?;
+
+    let status = resp.status();
+
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{}", status))
+    }
+}
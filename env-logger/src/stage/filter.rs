@@ -0,0 +1,121 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! レコードの取捨を行う処理ステージをまとめたモジュール
+//!
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+
+use super::Stage;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+///
+/// 登録されていないデバイスからのレコードを破棄するステージ
+///
+pub(crate) struct AllowDevices {
+    /// 受け付けるデバイスIDの集合
+    devices: HashSet<String>,
+
+    /// デバイスIDを持たないレコードを受け付けるか否か
+    allow_anonymous: bool,
+}
+
+impl AllowDevices {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `devices` - 受け付けるデバイスIDのリスト
+    /// * `allow_anonymous` - デバイスIDを持たないレコードを受け付けるか否か
+    ///
+    pub(crate) fn new(devices: Vec<String>, allow_anonymous: bool) -> Self {
+        Self {devices: devices.into_iter().collect(), allow_anonymous}
+    }
+}
+
+// Stageトレイトの実装
+impl Stage for AllowDevices {
+    fn name(&self) -> &str {
+        "allow_devices"
+    }
+
+    fn process(&mut self, record: SensorRecord) -> Vec<SensorRecord> {
+        let allowed = match record.device_id() {
+            Some(device_id) => self.devices.contains(&device_id),
+            None => self.allow_anonymous,
+        };
+
+        if allowed {
+            vec![record]
+        } else {
+            warn!("record from unknown device: {}", record);
+            vec![]
+        }
+    }
+}
+
+///
+/// 重複して届いたレコードを破棄するステージ
+///
+/// # 注記
+/// 同一の設置場所とデバイスIDの組から、直前に通過したレコードのタイムスタン
+/// プから時間幅以内に届いたレコードを重複とみなす。同じ計測結果が複数の経路
+/// (UDPとMQTTなど)で届いた場合や、再送された場合の除去を想定している。
+///
+pub(crate) struct Dedup {
+    /// 重複とみなす時間幅(ミリ秒)
+    window: u64,
+
+    /// 設置場所とデバイスIDの組毎の直前に通過したレコードのタイムスタンプ
+    last: HashMap<(String, Option<String>), u64>,
+}
+
+impl Dedup {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `window` - 重複とみなす時間幅(秒)
+    ///
+    /// # 戻り値
+    /// 生成に成功した場合はDedupオブジェクトを`Ok()`でラップして返す。
+    ///
+    pub(crate) fn new(window: f64) -> Result<Self> {
+        if !window.is_finite() || window <= 0.0 {
+            return Err(anyhow!("dedup: invalid window {}", window));
+        }
+
+        Ok(Self {window: (window * 1000.0) as u64, last: HashMap::new()})
+    }
+}
+
+// Stageトレイトの実装
+impl Stage for Dedup {
+    fn name(&self) -> &str {
+        "dedup"
+    }
+
+    fn process(&mut self, record: SensorRecord) -> Vec<SensorRecord> {
+        let key = (record.location(), record.device_id());
+        let tm = record.timestamp();
+
+        if let Some(last) = self.last.get(&key) {
+            if tm.abs_diff(*last) < self.window {
+                return vec![];
+            }
+        }
+
+        self.last.insert(key, tm);
+
+        vec![record]
+    }
+}
@@ -0,0 +1,296 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 受信レコードの処理ステージをまとめたモジュール
+//!
+//! 処理ステージは中継処理とファンアウトの間に置かれ、受信レコードのフィル
+//! タリングや変換を行う。ステージは設定ファイルの記述順に連結され、前段の
+//! 出力が後段の入力となる。
+//!
+
+pub(crate) mod filter;
+pub(crate) mod plausibility;
+pub(crate) mod script;
+pub(crate) mod transform;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::record::SensorRecord;
+use filter::{AllowDevices, Dedup};
+use plausibility::{Action, Limit, Plausibility};
+use script::ScriptStage;
+use transform::{
+    ConvertUnit, HumidityUnit, PressureUnit, RenameLocation, Round,
+    TemperatureUnit
+};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// スクリプトの演算回数の上限のデフォルト値
+const DEFAULT_SCRIPT_MAX_OPERATIONS: u64 = 100_000;
+
+/// スクリプトの実行時間の上限のデフォルト値(ミリ秒)
+const DEFAULT_SCRIPT_TIMEOUT: u64 = 100;
+
+///
+/// 処理ステージを表すトレイト
+///
+pub(crate) trait Stage: Send {
+    ///
+    /// ステージ名の取得
+    ///
+    /// # 戻り値
+    /// ログ出力に用いるステージ名を返す
+    ///
+    fn name(&self) -> &str;
+
+    ///
+    /// レコードの処理
+    ///
+    /// # 引数
+    /// * `record` - 処理対象のレコード
+    ///
+    /// # 戻り値
+    /// 後段に渡すレコードのリストを返す。空のリストを返した場合はレコードを破
+    /// 棄したものとして扱う。複数のレコードを返すこともできる。
+    ///
+    fn process(&mut self, record: SensorRecord) -> Vec<SensorRecord>;
+}
+
+///
+/// 設定ファイル上の処理ステージの定義を表す列挙子
+///
+/// # 注記
+/// 設定ファイルでは`[[stage]]`テーブルの`type`キーでステージの種別を指定す
+/// る。
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum StageConfig {
+    /// 登録されていないデバイスからのレコードを破棄する
+    AllowDevices {
+        /// 受け付けるデバイスIDのリスト
+        devices: Vec<String>,
+
+        /// デバイスIDを持たないレコードを受け付けるか否か
+        #[serde(default)]
+        allow_anonymous: bool,
+    },
+
+    /// 設置場所名を付け替える
+    RenameLocation {
+        /// 変更前の設置場所名と変更後の設置場所名の対応
+        map: BTreeMap<String, String>,
+    },
+
+    /// 計測値の単位を変換する
+    ConvertUnit {
+        /// 変換対象のデバイスIDのリスト(空の場合は全てのデバイスが対象)
+        #[serde(default)]
+        devices: Vec<String>,
+
+        /// 気温の変換元の単位
+        temperature: Option<TemperatureUnit>,
+
+        /// 湿度の変換元の単位
+        humidity: Option<HumidityUnit>,
+
+        /// 気圧の変換元の単位
+        air_pressure: Option<PressureUnit>,
+    },
+
+    /// 計測値を指定した小数点以下の桁数に丸める
+    Round {
+        /// 気温の桁数
+        temperature: Option<u32>,
+
+        /// 湿度の桁数
+        humidity: Option<u32>,
+
+        /// 気圧の桁数
+        air_pressure: Option<u32>,
+    },
+
+    /// 重複して届いたレコードを破棄する
+    Dedup {
+        /// 同一デバイスからのレコードを重複とみなす時間幅(秒)
+        window: f64,
+    },
+
+    /// 計測値の妥当性を検査する
+    Plausibility {
+        /// 異常値を検出した場合の振る舞い
+        #[serde(default)]
+        action: Action,
+
+        /// 気温の条件
+        temperature: Option<Limit>,
+
+        /// 湿度の条件
+        humidity: Option<Limit>,
+
+        /// 気圧の条件
+        air_pressure: Option<Limit>,
+    },
+
+    /// スクリプトでレコードを処理する
+    Script {
+        /// スクリプトファイルのパス
+        path: PathBuf,
+
+        /// 1レコードあたりの演算回数の上限
+        #[serde(default = "default_script_max_operations")]
+        max_operations: u64,
+
+        /// 1レコードあたりの実行時間の上限(ミリ秒)
+        #[serde(default = "default_script_timeout")]
+        timeout: u64,
+    },
+}
+
+///
+/// スクリプトの演算回数の上限のデフォルト値の取得(serde用)
+///
+fn default_script_max_operations() -> u64 {
+    DEFAULT_SCRIPT_MAX_OPERATIONS
+}
+
+///
+/// スクリプトの実行時間の上限のデフォルト値の取得(serde用)
+///
+fn default_script_timeout() -> u64 {
+    DEFAULT_SCRIPT_TIMEOUT
+}
+
+///
+/// 処理ステージの連結を表す構造体
+///
+pub(crate) struct StageChain {
+    /// 処理ステージのリスト(適用順)
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl StageChain {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `configs` - 処理ステージの定義のリスト
+    ///
+    /// # 戻り値
+    /// 生成に成功した場合はStageChainオブジェクトを`Ok()`でラップして返す。
+    /// 定義に不備があった場合はエラー情報を`Err()`でラップして返す。
+    ///
+    pub(crate) fn new(configs: &[StageConfig]) -> Result<Self> {
+        let mut stages: Vec<Box<dyn Stage>> = vec![];
+
+        for config in configs {
+            let stage: Box<dyn Stage> = match config.clone() {
+                StageConfig::AllowDevices {devices, allow_anonymous} => {
+                    Box::new(AllowDevices::new(devices, allow_anonymous))
+                }
+
+                StageConfig::RenameLocation {map} => {
+                    Box::new(RenameLocation::new(map))
+                }
+
+                StageConfig::ConvertUnit {
+                    devices,
+                    temperature,
+                    humidity,
+                    air_pressure
+                } => {
+                    Box::new(ConvertUnit::new(
+                        devices,
+                        temperature,
+                        humidity,
+                        air_pressure
+                    ))
+                }
+
+                StageConfig::Round {temperature, humidity, air_pressure} => {
+                    Box::new(Round::new(temperature, humidity, air_pressure)?)
+                }
+
+                StageConfig::Dedup {window} => {
+                    Box::new(Dedup::new(window)?)
+                }
+
+                StageConfig::Plausibility {
+                    action,
+                    temperature,
+                    humidity,
+                    air_pressure
+                } => {
+                    Box::new(Plausibility::new(
+                        action,
+                        temperature,
+                        humidity,
+                        air_pressure
+                    )?)
+                }
+
+                StageConfig::Script {path, max_operations, timeout} => {
+                    Box::new(ScriptStage::new(path, max_operations, timeout)?)
+                }
+            };
+
+            info!("stage enabled: {}", stage.name());
+            stages.push(stage);
+        }
+
+        Ok(Self {stages})
+    }
+
+    ///
+    /// レコードの処理
+    ///
+    /// # 引数
+    /// * `record` - 処理対象のレコード
+    ///
+    /// # 戻り値
+    /// 全てのステージを通過したレコードのリストを返す。
+    ///
+    /// # 注記
+    /// 隔離対象となったレコードは以降のステージを通過させずにそのまま返す。
+    ///
+    pub(crate) fn process(&mut self, record: SensorRecord) -> Vec<SensorRecord> {
+        let mut records = vec![record];
+
+        for stage in &mut self.stages {
+            let mut outputs = vec![];
+
+            for record in records {
+                if record.is_quarantined() {
+                    outputs.push(record);
+                    continue;
+                }
+
+                let result = stage.process(record.clone());
+
+                if result.is_empty() {
+                    debug!("record dropped by {}: {}", stage.name(), record);
+                }
+
+                outputs.extend(result);
+            }
+
+            records = outputs;
+
+            if records.is_empty() {
+                break;
+            }
+        }
+
+        records
+    }
+}
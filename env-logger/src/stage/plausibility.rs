@@ -0,0 +1,293 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! 計測値の妥当性検査を行う処理ステージをまとめたモジュール
+//!
+//! センサーの不調(I2Cの通信異常など)で生じる異常値を、物理的にありえる範囲
+//! と単位時間あたりの変化量で検出する。検出した計測値は品質フラグとして
+//! "計測値名:理由:値"の形式で記録する。理由は以下の通り。
+//!
+//! * `range` - 範囲外の値
+//! * `spike` - 直前の値からの変化量が上限を超えた値
+//!
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use super::Stage;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 変化量の評価に用いる最小の時間幅(ミリ秒)
+const MIN_RATE_PERIOD: u64 = 60_000;
+
+/// 計測値毎の直前に受け付けた値(タイムスタンプと値の組)
+type LastValues = [Option<(u64, f32)>; 3];
+
+///
+/// 異常値を検出した場合の振る舞いを指し示す列挙子
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Action {
+    /// 異常値のみを欠損値(NULL)に置き換えて記録する
+    #[default]
+    Null,
+
+    /// レコード全体を隔離する(隔離テーブルに記録し、他の出力先には渡さない)
+    Quarantine,
+}
+
+///
+/// 計測値の妥当性の条件を表す構造体
+///
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Limit {
+    /// 下限値
+    min: Option<f32>,
+
+    /// 上限値
+    max: Option<f32>,
+
+    /// 1分あたりの変化量の上限
+    max_rate: Option<f32>,
+}
+
+impl Limit {
+    ///
+    /// 条件の確認
+    ///
+    fn validate(&self, name: &str) -> Result<()> {
+        let vals = [self.min, self.max, self.max_rate];
+
+        if vals.iter().flatten().any(|v| !v.is_finite()) {
+            return Err(anyhow!("plausibility: invalid limit for {}", name));
+        }
+
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(anyhow!("plausibility: min > max for {}", name));
+            }
+        }
+
+        if self.max_rate.is_some_and(|rate| rate <= 0.0) {
+            return Err(anyhow!("plausibility: invalid max_rate for {}", name));
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// 計測値の評価
+    ///
+    /// # 引数
+    /// * `val` - 評価対象の計測値
+    /// * `timestamp` - 評価対象のレコードのタイムスタンプ
+    /// * `last` - 直前に受け付けた計測値とそのタイムスタンプ
+    ///
+    /// # 戻り値
+    /// 条件を満たさない場合は理由を`Some()`でラップして返す。
+    ///
+    /// # 注記
+    /// 変化量の上限は経過時間に比例して緩める。ただし経過時間が1分未満の場合
+    /// は1分として扱う。
+    ///
+    fn check(&self, val: f32, timestamp: u64, last: Option<(u64, f32)>)
+        -> Option<&'static str>
+    {
+        if self.min.is_some_and(|min| val < min)
+            || self.max.is_some_and(|max| val > max)
+        {
+            return Some("range");
+        }
+
+        if let (Some(rate), Some((tm, prev))) = (self.max_rate, last) {
+            let period = timestamp.abs_diff(tm).max(MIN_RATE_PERIOD);
+            let allowed = rate * (period as f32 / 60_000.0);
+
+            if (val - prev).abs() > allowed {
+                return Some("spike");
+            }
+        }
+
+        None
+    }
+}
+
+///
+/// 検査対象の計測値を指し示す列挙子
+///
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// 気温
+    Temperature,
+
+    /// 湿度
+    Humidity,
+
+    /// 気圧
+    AirPressure,
+}
+
+impl Kind {
+    ///
+    /// 計測値名の取得
+    ///
+    fn name(self) -> &'static str {
+        match self {
+            Self::Temperature => "temperature",
+            Self::Humidity => "humidity",
+            Self::AirPressure => "air_pressure",
+        }
+    }
+
+    ///
+    /// レコードからの計測値の取得
+    ///
+    fn get(self, record: &SensorRecord) -> Option<f32> {
+        match self {
+            Self::Temperature => record.temperature(),
+            Self::Humidity => record.humidity(),
+            Self::AirPressure => record.air_pressure(),
+        }
+    }
+
+    ///
+    /// レコードの計測値の消去
+    ///
+    fn clear(self, record: &mut SensorRecord) {
+        match self {
+            Self::Temperature => record.set_temperature(None),
+            Self::Humidity => record.set_humidity(None),
+            Self::AirPressure => record.set_air_pressure(None),
+        }
+    }
+}
+
+///
+/// 計測値の妥当性検査を行うステージ
+///
+/// # 注記
+/// 変化量の評価は設置場所とデバイスIDの組毎に、直前に受け付けた計測値を基準
+/// に行う。異常と判定した計測値は基準として用いない。
+///
+pub(crate) struct Plausibility {
+    /// 異常値を検出した場合の振る舞い
+    action: Action,
+
+    /// 検査対象の計測値とその条件のリスト
+    limits: Vec<(Kind, Limit)>,
+
+    /// 設置場所とデバイスIDの組毎の直前に受け付けた計測値
+    last: HashMap<(String, Option<String>), LastValues>,
+}
+
+impl Plausibility {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `action` - 異常値を検出した場合の振る舞い
+    /// * `temperature` - 気温の条件
+    /// * `humidity` - 湿度の条件
+    /// * `air_pressure` - 気圧の条件
+    ///
+    /// # 戻り値
+    /// 生成に成功した場合はPlausibilityオブジェクトを`Ok()`でラップして返す。
+    ///
+    pub(crate) fn new(
+        action: Action,
+        temperature: Option<Limit>,
+        humidity: Option<Limit>,
+        air_pressure: Option<Limit>,
+    ) -> Result<Self>
+    {
+        let candidates = [
+            (Kind::Temperature, temperature),
+            (Kind::Humidity, humidity),
+            (Kind::AirPressure, air_pressure),
+        ];
+
+        let mut limits = vec![];
+
+        for (kind, limit) in candidates {
+            if let Some(limit) = limit {
+                limit.validate(kind.name())?;
+                limits.push((kind, limit));
+            }
+        }
+
+        Ok(Self {action, limits, last: HashMap::new()})
+    }
+}
+
+// Stageトレイトの実装
+impl Stage for Plausibility {
+    fn name(&self) -> &str {
+        "plausibility"
+    }
+
+    fn process(&mut self, mut record: SensorRecord) -> Vec<SensorRecord> {
+        let key = (record.location(), record.device_id());
+        let tm = record.timestamp();
+        let last = self.last.get(&key).copied().unwrap_or_default();
+
+        let mut accepted = last;
+        let mut failed = false;
+
+        /*
+         * 計測値毎の評価
+         */
+        for (kind, limit) in &self.limits {
+            let Some(val) = kind.get(&record) else {
+                continue;
+            };
+
+            match limit.check(val, tm, last[*kind as usize]) {
+                None => {
+                    accepted[*kind as usize] = Some((tm, val));
+                }
+
+                Some(reason) => {
+                    record.add_quality_flag(
+                        format!("{}:{}:{}", kind.name(), reason, val)
+                    );
+
+                    if self.action == Action::Null {
+                        kind.clear(&mut record);
+                    }
+
+                    failed = true;
+                }
+            }
+        }
+
+        /*
+         * 評価結果の反映
+         */
+        if failed {
+            let quality = record.quality().unwrap_or_default();
+
+            if self.action == Action::Quarantine {
+                warn!("quarantine record ({}): {}", quality, record);
+                record.quarantine();
+                return vec![record];
+            }
+
+            warn!("implausible value nulled ({}): {}", quality, record);
+        }
+
+        self.last.insert(key, accepted);
+
+        vec![record]
+    }
+}
@@ -0,0 +1,370 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! スクリプト(Rhai)によるレコード処理ステージをまとめたモジュール
+//!
+//! スクリプトには`process(record)`関数を定義する。引数にはレコードの各フィ
+//! ールドを格納したオブジェクトマップが渡され、戻り値によってレコードの扱い
+//! が決まる。
+//!
+//! * オブジェクトマップ - そのレコードで置き換える
+//! * 配列(オブジェクトマップの配列) - 複数のレコードに分割する
+//! * `()` - レコードを破棄する
+//!
+//! 値を持たない計測値やデバイスIDは`()`で表す。登録簿に定義された計測種別
+//! の計測値(CO2濃度など)は`metrics`フィールドに、デバイスの稼働状態(電池残
+//! 量など)は`telemetry`フィールドにオブジェクトマップとして格納する。稼働状
+//! 態は参照のみで、スクリプトでの変更はレコードに反映しない。
+//!
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use super::Stage;
+use crate::record::SensorRecord;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// スクリプトから呼び出す関数の名前
+const ENTRY_POINT: &str = "process";
+
+/// 関数呼び出しのネストの上限
+const MAX_CALL_LEVELS: usize = 32;
+
+/// 文字列長の上限
+const MAX_STRING_SIZE: usize = 4096;
+
+/// 配列の要素数の上限
+const MAX_ARRAY_SIZE: usize = 1024;
+
+/// オブジェクトマップの要素数の上限
+const MAX_MAP_SIZE: usize = 256;
+
+///
+/// スクリプトでレコードを処理するステージ
+///
+/// # 注記
+/// スクリプトの実行は演算回数と実行時間で制限し、上限を超えた場合やスクリプ
+/// トがエラーになった場合は元のレコードをそのまま後段に渡す。
+///
+pub(crate) struct ScriptStage {
+    /// ステージ名(スクリプトのパスを含む)
+    name: String,
+
+    /// スクリプトエンジン
+    engine: Engine,
+
+    /// コンパイル済みのスクリプト
+    ast: AST,
+
+    /// 実行開始時刻(実行時間の監視用にエンジンと共有する)
+    started: Arc<Mutex<Instant>>,
+}
+
+impl ScriptStage {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `path` - スクリプトファイルのパス
+    /// * `max_operations` - 1レコードあたりの演算回数の上限
+    /// * `timeout` - 1レコードあたりの実行時間の上限(ミリ秒)
+    ///
+    /// # 戻り値
+    /// 生成に成功した場合はScriptStageオブジェクトを`Ok()`でラップして返す。
+    /// スクリプトの読み込みやコンパイルに失敗した場合はエラー情報を`Err()`で
+    /// ラップして返す。
+    ///
+    pub(crate) fn new(path: PathBuf, max_operations: u64, timeout: u64)
+        -> Result<Self>
+    {
+        if timeout == 0 {
+            return Err(anyhow!("script: timeout must be greater than 0"));
+        }
+
+        let timeout = Duration::from_millis(timeout);
+        let started = Arc::new(Mutex::new(Instant::now()));
+
+        /*
+         * エンジンの生成とサンドボックスの設定
+         *
+         * ファイルを読み込むimportとevalは使用できないようにする。
+         */
+        let mut engine = Engine::new();
+
+        engine
+            .set_max_operations(max_operations)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_MAP_SIZE)
+            .set_module_resolver(DummyModuleResolver::new())
+            .disable_symbol("eval")
+            .on_print(|s| info!("script: {}", s))
+            .on_debug(|s, _, pos| debug!("script: {} ({})", s, pos));
+
+        let monitor = started.clone();
+
+        engine.on_progress(move |_| {
+            match monitor.lock() {
+                Ok(started) if started.elapsed() > timeout => {
+                    Some(format!("timeout ({}ms)", timeout.as_millis()).into())
+                }
+
+                _ => None,
+            }
+        });
+
+        /*
+         * スクリプトのコンパイル
+         */
+        let ast = match engine.compile_file(path.clone()) {
+            Ok(ast) => ast,
+            Err(err) => return Err(anyhow!(
+                "compile {} failed: {}",
+                path.display(),
+                err
+            )),
+        };
+
+        let defined = ast.iter_functions()
+            .any(|f| f.name == ENTRY_POINT && f.params.len() == 1);
+
+        if !defined {
+            return Err(anyhow!(
+                "{}: function {}(record) is not defined",
+                path.display(),
+                ENTRY_POINT
+            ));
+        }
+
+        Ok(Self {
+            name: format!("script({})", path.display()),
+            engine,
+            ast,
+            started,
+        })
+    }
+
+    ///
+    /// スクリプトの実行
+    ///
+    /// # 引数
+    /// * `record` - 処理対象のレコード
+    ///
+    /// # 戻り値
+    /// 実行に成功した場合は後段に渡すレコードのリストを`Ok()`でラップして返す。
+    ///
+    fn run(&self, record: &SensorRecord) -> Result<Vec<SensorRecord>> {
+        if let Ok(mut started) = self.started.lock() {
+            *started = Instant::now();
+        }
+
+        let mut scope = Scope::new();
+        let result = self.engine.call_fn::<Dynamic>(
+            &mut scope,
+            &self.ast,
+            ENTRY_POINT,
+            (to_map(record),)
+        );
+
+        let value = match result {
+            Ok(value) => value,
+            Err(err) => match *err {
+                EvalAltResult::ErrorTerminated(reason, _) => {
+                    return Err(anyhow!("terminated by {}", reason));
+                }
+
+                err => return Err(anyhow!("{}", err)),
+            },
+        };
+
+        /*
+         * 戻り値の評価
+         */
+        if value.is_unit() {
+            Ok(vec![])
+
+        } else if value.is_map() {
+            Ok(vec![from_map(value.cast::<Map>(), record)?])
+
+        } else if value.is_array() {
+            value.cast::<Array>()
+                .into_iter()
+                .map(|item| match item.try_cast::<Map>() {
+                    Some(map) => from_map(map, record),
+                    None => Err(anyhow!("array element is not a map")),
+                })
+                .collect()
+
+        } else {
+            Err(anyhow!("unexpected return type: {}", value.type_name()))
+        }
+    }
+}
+
+// Stageトレイトの実装
+impl Stage for ScriptStage {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process(&mut self, record: SensorRecord) -> Vec<SensorRecord> {
+        match self.run(&record) {
+            Ok(records) => records,
+
+            Err(err) => {
+                error!("{} failed: {}", self.name, err);
+
+                vec![record]
+            }
+        }
+    }
+}
+
+///
+/// レコードのオブジェクトマップへの変換
+///
+fn to_map(record: &SensorRecord) -> Map {
+    let mut map = Map::new();
+
+    map.insert("location".into(), record.location().into());
+    map.insert("device_id".into(), opt(record.device_id()));
+    map.insert("timestamp".into(), (record.timestamp() as i64).into());
+    map.insert("temperature".into(), opt(record.temperature().map(f64::from)));
+    map.insert("humidity".into(), opt(record.humidity().map(f64::from)));
+    map.insert("air_pressure".into(), opt(record.air_pressure().map(f64::from)));
+
+    let metrics = record.metrics()
+        .iter()
+        .map(|(name, val)| (name.as_str().into(), Dynamic::from(*val as f64)))
+        .collect::<Map>();
+
+    map.insert("metrics".into(), metrics.into());
+
+    let telemetry = record.telemetry();
+    let mut status = Map::new();
+
+    status.insert("firmware".into(), opt(telemetry.firmware.clone()));
+    status.insert("rssi".into(), opt(telemetry.rssi.map(i64::from)));
+    status.insert("battery".into(), opt(telemetry.battery.map(f64::from)));
+    status.insert("uptime".into(), opt(telemetry.uptime.map(|v| v as i64)));
+    status.insert("reset_reason".into(), opt(telemetry.reset_reason.clone()));
+
+    map.insert("telemetry".into(), status.into());
+
+    map
+}
+
+///
+/// オブジェクトマップからのレコードの生成
+///
+/// # 引数
+/// * `map` - スクリプトが返したオブジェクトマップ
+/// * `orig` - 元のレコード
+///
+/// # 注記
+/// オブジェクトマップに存在しないフィールドは元のレコードの値を引き継ぐ。
+/// 稼働状態と未知のプロパティは常に元のレコードの値を引き継ぐ。
+///
+fn from_map(map: Map, orig: &SensorRecord) -> Result<SensorRecord> {
+    let location = match map.get("location") {
+        Some(val) => match val.clone().into_string() {
+            Ok(val) => val,
+            Err(_) => return Err(anyhow!("location must be a string")),
+        },
+
+        None => orig.location(),
+    };
+
+    let device_id = match map.get("device_id") {
+        Some(val) if val.is_unit() => None,
+        Some(val) => match val.clone().into_string() {
+            Ok(val) => Some(val),
+            Err(_) => return Err(anyhow!("device_id must be a string or ()")),
+        },
+
+        None => orig.device_id(),
+    };
+
+    let timestamp = match map.get("timestamp") {
+        Some(val) => match val.as_int() {
+            Ok(val) if val >= 0 => val as u64,
+            _ => return Err(anyhow!("timestamp must be a positive integer")),
+        },
+
+        None => orig.timestamp(),
+    };
+
+    let mut record = SensorRecord::new(
+        location,
+        device_id,
+        timestamp,
+        number(&map, "temperature", orig.temperature())?,
+        number(&map, "humidity", orig.humidity())?,
+        number(&map, "air_pressure", orig.air_pressure())?,
+    );
+
+    match map.get("metrics") {
+        Some(val) => {
+            let Some(metrics) = val.clone().try_cast::<Map>() else {
+                return Err(anyhow!("metrics must be an object map"));
+            };
+
+            for name in metrics.keys() {
+                if let Some(val) = number(&metrics, name, None)? {
+                    record.set_metric(name, val);
+                }
+            }
+        }
+
+        None => {
+            for (name, val) in orig.metrics() {
+                record.set_metric(name, *val);
+            }
+        }
+    }
+
+    record.set_telemetry(orig.telemetry().clone());
+
+    for (key, val) in orig.extras() {
+        record.set_extra(key, val.clone());
+    }
+
+    Ok(record)
+}
+
+///
+/// オブジェクトマップからの計測値の取り出し
+///
+fn number(map: &Map, key: &str, orig: Option<f32>) -> Result<Option<f32>> {
+    match map.get(key) {
+        Some(val) if val.is_unit() => Ok(None),
+        Some(val) => match val.as_float().or(val.as_int().map(|v| v as f64)) {
+            Ok(val) if val.is_finite() => Ok(Some(val as f32)),
+            _ => Err(anyhow!("{} must be a number or ()", key)),
+        },
+
+        None => Ok(orig),
+    }
+}
+
+///
+/// Option値のスクリプト上の値への変換(Noneは`()`とする)
+///
+fn opt<T>(val: Option<T>) -> Dynamic
+where
+    T: Into<Dynamic>
+{
+    val.map_or(Dynamic::UNIT, |val| val.into())
+}
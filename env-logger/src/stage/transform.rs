@@ -0,0 +1,294 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! レコードの内容を変換する処理ステージをまとめたモジュール
+//!
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use super::Stage;
+use crate::record::{round_value, SensorRecord};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 丸めで指定できる小数点以下の桁数の上限
+const MAX_DIGITS: u32 = 6;
+
+///
+/// 気温の単位を指し示す列挙子
+///
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TemperatureUnit {
+    /// 摂氏
+    Celsius,
+
+    /// 華氏
+    Fahrenheit,
+
+    /// ケルビン
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    ///
+    /// 摂氏への変換
+    ///
+    fn to_celsius(self, val: f32) -> f32 {
+        match self {
+            Self::Celsius => val,
+            Self::Fahrenheit => (val - 32.0) * 5.0 / 9.0,
+            Self::Kelvin => val - 273.15,
+        }
+    }
+}
+
+///
+/// 湿度の単位を指し示す列挙子
+///
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HumidityUnit {
+    /// 百分率
+    Percent,
+
+    /// 比率(0.0〜1.0)
+    Ratio,
+}
+
+impl HumidityUnit {
+    ///
+    /// 百分率への変換
+    ///
+    fn to_percent(self, val: f32) -> f32 {
+        match self {
+            Self::Percent => val,
+            Self::Ratio => val * 100.0,
+        }
+    }
+}
+
+///
+/// 気圧の単位を指し示す列挙子
+///
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PressureUnit {
+    /// ヘクトパスカル
+    Hpa,
+
+    /// パスカル
+    Pa,
+
+    /// キロパスカル
+    Kpa,
+
+    /// 水銀柱インチ
+    Inhg,
+
+    /// 水銀柱ミリメートル
+    Mmhg,
+}
+
+impl PressureUnit {
+    ///
+    /// ヘクトパスカルへの変換
+    ///
+    fn to_hpa(self, val: f32) -> f32 {
+        match self {
+            Self::Hpa => val,
+            Self::Pa => val / 100.0,
+            Self::Kpa => val * 10.0,
+            Self::Inhg => val * 33.863_89,
+            Self::Mmhg => val * 1.333_224,
+        }
+    }
+}
+
+///
+/// 設置場所名を付け替えるステージ
+///
+pub(crate) struct RenameLocation {
+    /// 変更前の設置場所名と変更後の設置場所名の対応
+    map: BTreeMap<String, String>,
+}
+
+impl RenameLocation {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `map` - 変更前の設置場所名と変更後の設置場所名の対応
+    ///
+    pub(crate) fn new(map: BTreeMap<String, String>) -> Self {
+        Self {map}
+    }
+}
+
+// Stageトレイトの実装
+impl Stage for RenameLocation {
+    fn name(&self) -> &str {
+        "rename_location"
+    }
+
+    fn process(&mut self, mut record: SensorRecord) -> Vec<SensorRecord> {
+        if let Some(location) = self.map.get(&record.location()) {
+            record.set_location(location.clone());
+        }
+
+        vec![record]
+    }
+}
+
+///
+/// 計測値の単位を変換するステージ
+///
+/// # 注記
+/// 指定された単位で送信してくるデバイスの計測値を、本プログラムで扱う単位
+/// (摂氏、百分率、ヘクトパスカル)に変換する。
+///
+pub(crate) struct ConvertUnit {
+    /// 変換対象のデバイスIDのリスト(空の場合は全てのデバイスが対象)
+    devices: Vec<String>,
+
+    /// 気温の変換元の単位
+    temperature: Option<TemperatureUnit>,
+
+    /// 湿度の変換元の単位
+    humidity: Option<HumidityUnit>,
+
+    /// 気圧の変換元の単位
+    air_pressure: Option<PressureUnit>,
+}
+
+impl ConvertUnit {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `devices` - 変換対象のデバイスIDのリスト
+    /// * `temperature` - 気温の変換元の単位
+    /// * `humidity` - 湿度の変換元の単位
+    /// * `air_pressure` - 気圧の変換元の単位
+    ///
+    pub(crate) fn new(
+        devices: Vec<String>,
+        temperature: Option<TemperatureUnit>,
+        humidity: Option<HumidityUnit>,
+        air_pressure: Option<PressureUnit>,
+    ) -> Self
+    {
+        Self {devices, temperature, humidity, air_pressure}
+    }
+}
+
+// Stageトレイトの実装
+impl Stage for ConvertUnit {
+    fn name(&self) -> &str {
+        "convert_unit"
+    }
+
+    fn process(&mut self, mut record: SensorRecord) -> Vec<SensorRecord> {
+        if !self.devices.is_empty() {
+            let matched = record.device_id()
+                .is_some_and(|id| self.devices.contains(&id));
+
+            if !matched {
+                return vec![record];
+            }
+        }
+
+        if let Some(unit) = self.temperature {
+            record.set_temperature(record.temperature().map(|v| unit.to_celsius(v)));
+        }
+
+        if let Some(unit) = self.humidity {
+            record.set_humidity(record.humidity().map(|v| unit.to_percent(v)));
+        }
+
+        if let Some(unit) = self.air_pressure {
+            record.set_air_pressure(record.air_pressure().map(|v| unit.to_hpa(v)));
+        }
+
+        vec![record]
+    }
+}
+
+///
+/// 計測値を丸めるステージ
+///
+pub(crate) struct Round {
+    /// 気温の桁数
+    temperature: Option<u32>,
+
+    /// 湿度の桁数
+    humidity: Option<u32>,
+
+    /// 気圧の桁数
+    air_pressure: Option<u32>,
+}
+
+impl Round {
+    ///
+    /// オブジェクトの生成
+    ///
+    /// # 引数
+    /// * `temperature` - 気温の小数点以下の桁数
+    /// * `humidity` - 湿度の小数点以下の桁数
+    /// * `air_pressure` - 気圧の小数点以下の桁数
+    ///
+    /// # 戻り値
+    /// 生成に成功した場合はRoundオブジェクトを`Ok()`でラップして返す。
+    ///
+    pub(crate) fn new(
+        temperature: Option<u32>,
+        humidity: Option<u32>,
+        air_pressure: Option<u32>,
+    ) -> Result<Self>
+    {
+        let digits = [temperature, humidity, air_pressure];
+
+        if digits.iter().flatten().any(|n| *n > MAX_DIGITS) {
+            return Err(anyhow!("round: digits must be {} or less", MAX_DIGITS));
+        }
+
+        Ok(Self {temperature, humidity, air_pressure})
+    }
+}
+
+// Stageトレイトの実装
+impl Stage for Round {
+    fn name(&self) -> &str {
+        "round"
+    }
+
+    fn process(&mut self, mut record: SensorRecord) -> Vec<SensorRecord> {
+        if let Some(n) = self.temperature {
+            record.set_temperature(
+                record.temperature().map(|v| round_value(v.into(), n) as f32)
+            );
+        }
+
+        if let Some(n) = self.humidity {
+            record.set_humidity(
+                record.humidity().map(|v| round_value(v.into(), n) as f32)
+            );
+        }
+
+        if let Some(n) = self.air_pressure {
+            record.set_air_pressure(
+                record.air_pressure().map(|v| round_value(v.into(), n) as f32)
+            );
+        }
+
+        vec![record]
+    }
+}
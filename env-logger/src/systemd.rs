@@ -0,0 +1,259 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! systemdとの連携処理をまとめたモジュール
+//!
+//! * 起動完了(`READY=1`)と終了開始(`STOPPING=1`)の通知
+//! * 稼働状況の通知(`STATUS=`)
+//! * ウォッチドッグ(`WATCHDOG=1`)の通知
+//! * ソケットアクティベーション(`LISTEN_FDS`)で渡されたソケットの受け取り
+//!
+//! ウォッチドッグは中継処理タスクに確認要求を送り、応答があった場合にのみ
+//! 通知する。中継処理タスクが停止している場合(データベースへの書き込みが滞
+//! りファンアウトで待たされ続けている場合を含む)は通知が途絶え、systemdに
+//! よってサービスが再起動される。
+//!
+//! `NOTIFY_SOCKET`が設定されていない場合(systemd配下で無い場合)は何も通知
+//! しない。手元での確認には`misc/fake-systemd.py`を用いる。
+//!
+
+use std::net::{TcpListener, UdpSocket};
+use std::os::fd::{FromRawFd, RawFd};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use sd_notify::NotifyState;
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, timeout, Duration, MissedTickBehavior};
+
+use crate::sink::SinkStats;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// ウォッチドッグが無効な場合の稼働状況の通知間隔
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 中継処理タスクへの確認要求の送信用チャネル
+pub(crate) type ProbeSender = mpsc::Sender<oneshot::Sender<()>>;
+
+///
+/// systemd配下で起動されているか否か
+///
+fn enabled() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+///
+/// systemdへの通知
+///
+/// # 注記
+/// 通知に失敗してもサービスの動作には影響しないので、ログに残すのみとする。
+///
+fn notify(state: &[NotifyState]) {
+    if let Err(err) = sd_notify::notify(false, state) {
+        debug!("sd_notify failed: {}", err);
+    }
+}
+
+///
+/// 起動完了の通知
+///
+pub(crate) fn notify_ready() {
+    notify(&[NotifyState::Ready, NotifyState::Status("running")]);
+}
+
+///
+/// 終了開始の通知
+///
+pub(crate) fn notify_stopping() {
+    notify(&[NotifyState::Stopping, NotifyState::Status("shutting down")]);
+}
+
+///
+/// ソケットアクティベーションで渡されたソケットをまとめた構造体
+///
+#[derive(Default)]
+pub(crate) struct ListenSockets {
+    /// TCPの待ち受けソケット
+    tcp: Option<TcpListener>,
+
+    /// UDPの待ち受けソケット
+    udp: Option<UdpSocket>,
+}
+
+impl ListenSockets {
+    ///
+    /// 渡されたソケットの受け取り
+    ///
+    /// # 戻り値
+    /// 受け取ったソケットをまとめたオブジェクトを`Ok()`でラップして返す。
+    /// ソケットが渡されていない場合は空のオブジェクトを返す。
+    ///
+    /// # 注記
+    /// ソケットの種別(ストリームかデータグラムか)で振り分け、それぞれ最初の
+    /// ものを用いる。残りのソケットは閉じる。関連する環境変数はこの呼び出し
+    /// で削除されるので、プロセス中で一度だけ呼び出すこと。
+    ///
+    pub(crate) fn take() -> Result<Self> {
+        let fds = match sd_notify::listen_fds() {
+            Ok(fds) => fds,
+            Err(err) => return Err(anyhow!("invalid LISTEN_FDS: {}", err)),
+        };
+
+        let mut ret = Self::default();
+
+        for fd in fds {
+            match socket_type(fd)? {
+                libc::SOCK_STREAM if ret.tcp.is_none() => {
+                    info!("TCP socket passed by systemd (fd={})", fd);
+                    ret.tcp = Some(unsafe {TcpListener::from_raw_fd(fd)});
+                }
+
+                libc::SOCK_DGRAM if ret.udp.is_none() => {
+                    info!("UDP socket passed by systemd (fd={})", fd);
+                    ret.udp = Some(unsafe {UdpSocket::from_raw_fd(fd)});
+                }
+
+                _ => {
+                    warn!("unused socket passed by systemd (fd={})", fd);
+                    unsafe {libc::close(fd)};
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    ///
+    /// TCPの待ち受けソケットの取り出し
+    ///
+    pub(crate) fn take_tcp(&mut self) -> Option<TcpListener> {
+        self.tcp.take()
+    }
+
+    ///
+    /// UDPの待ち受けソケットの取り出し
+    ///
+    pub(crate) fn take_udp(&mut self) -> Option<UdpSocket> {
+        self.udp.take()
+    }
+}
+
+///
+/// ソケットの種別の取得
+///
+fn socket_type(fd: RawFd) -> Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if res < 0 {
+        return Err(anyhow!(
+            "passed fd {} is not a socket: {}",
+            fd,
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(value)
+}
+
+///
+/// ウォッチドッグタスクの起動
+///
+/// # 引数
+/// * `probe_tx` - 中継処理タスクへの確認要求の送信用チャネル
+/// * `database` - データベースシンクの稼働状況(稼働状況の通知に用いる)
+///
+/// # 戻り値
+/// systemd配下で起動されている場合はタスクのジョインハンドルを`Some()`でラ
+/// ップして返す。そうでない場合は`None`を返す。
+///
+/// # 注記
+/// ウォッチドッグが有効な場合は`WATCHDOG_USEC`の半分の間隔で中継処理タスク
+/// に確認要求を送り、間隔内に応答があった場合に`WATCHDOG=1`を通知する。無
+/// 効な場合は稼働状況の通知のみを行う。中継処理タスクの終了(終了処理の開
+/// 始)をもってタスクを終える(終了処理中はsystemdがウォッチドッグを停止す
+/// る)。
+///
+pub(crate) fn spawn_watchdog(probe_tx: ProbeSender, database: Arc<SinkStats>)
+    -> Option<JoinHandle<()>>
+{
+    if !enabled() {
+        return None;
+    }
+
+    let mut usec = 0;
+    let watchdog = sd_notify::watchdog_enabled(false, &mut usec);
+
+    let period = if watchdog {
+        info!("systemd watchdog enabled ({}us)", usec);
+        Duration::from_micros(usec / 2).max(Duration::from_millis(100))
+    } else {
+        STATUS_INTERVAL
+    };
+
+    Some(tokio::spawn(async move {
+        let mut ticker = interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            let status = format!(
+                "stored {} records, {} pending",
+                database.delivered(),
+                database.pending()
+            );
+
+            if !watchdog {
+                notify(&[NotifyState::Status(&status)]);
+                continue;
+            }
+
+            /*
+             * 中継処理タスクの応答の確認
+             */
+            let (reply_tx, reply_rx) = oneshot::channel();
+
+            match probe_tx.try_send(reply_tx) {
+                Ok(()) => {}
+
+                // 中継処理タスクが終了した(終了処理中)
+                Err(TrySendError::Closed(_)) => break,
+
+                Err(TrySendError::Full(_)) => {
+                    warn!("relay task not responding, watchdog not notified");
+                    continue;
+                }
+            }
+
+            match timeout(period, reply_rx).await {
+                Ok(Ok(())) => notify(&[
+                    NotifyState::Watchdog,
+                    NotifyState::Status(&status),
+                ]),
+
+                _ => {
+                    warn!("relay task not responding, watchdog not notified");
+                }
+            }
+        }
+    }))
+}
@@ -0,0 +1,203 @@
+//
+// Logger for environment sensors
+//
+//  Copyright 2025 (C) Hiroshi KUWAGATA <kgt9221@gmail.com>
+//
+
+//!
+//! デバイスの稼働状態(テレメトリー)の処理をまとめたモジュール
+//!
+//! レコードには計測値とは別に、デバイスの稼働状態を表す以下のプロパティを
+//! 任意で含めることができる。これらは計測値とは別に`DEVICE_STATUS_TABLE`に
+//! 記録する。
+//!
+//! | プロパティ     | 型     | 内容                          |
+//! |----------------|--------|-------------------------------|
+//! | `firmware`     | 文字列 | ファームウェアのバージョン    |
+//! | `rssi`         | 整数   | 受信信号強度(dBm)             |
+//! | `battery`      | 数値   | 電池残量(%)                   |
+//! | `uptime`       | 整数   | 起動からの経過時間(秒)        |
+//! | `reset_reason` | 文字列 | 直前のリセットの理由          |
+//!
+//! 再起動の回数は、経過時間が前回の報告よりも小さくなった回数として数える。
+//!
+
+use anyhow::{anyhow, Result};
+use rusqlite::{named_params, Connection};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// 稼働状態の要約の取得クエリー
+const SELECT_DEVICE_STATUS_QUERY: &str =
+    include_str!("../data/select_device_status.sql");
+
+///
+/// デバイスの稼働状態を表す構造体
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Telemetry {
+    /// ファームウェアのバージョン
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) firmware: Option<String>,
+
+    /// 受信信号強度(dBm)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rssi: Option<i32>,
+
+    /// 電池残量(%)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) battery: Option<f32>,
+
+    /// 起動からの経過時間(秒)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) uptime: Option<u64>,
+
+    /// 直前のリセットの理由
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reset_reason: Option<String>,
+}
+
+impl Telemetry {
+    ///
+    /// 未知のプロパティからの稼働状態の取り出し
+    ///
+    /// # 引数
+    /// * `extras` - レコードの未知のプロパティ
+    ///
+    /// # 注記
+    /// 型の合致したプロパティのみを取り出し、`extras`からは削除する。型の
+    /// 合致しないものは未知のプロパティのまま残す。
+    ///
+    pub(crate) fn take(extras: &mut Map<String, Value>) -> Self {
+        fn take<T>(
+            extras: &mut Map<String, Value>,
+            key: &str,
+            conv: impl Fn(&Value) -> Option<T>,
+        ) -> Option<T> {
+            let val = extras.get(key).and_then(conv)?;
+            extras.remove(key);
+            Some(val)
+        }
+
+        Self {
+            firmware: take(extras, "firmware", |v| {
+                v.as_str().map(|s| s.to_string())
+            }),
+            rssi: take(extras, "rssi", |v| {
+                v.as_i64().and_then(|v| i32::try_from(v).ok())
+            }),
+            battery: take(extras, "battery", |v| {
+                v.as_f64().filter(|v| v.is_finite()).map(|v| v as f32)
+            }),
+            uptime: take(extras, "uptime", |v| v.as_u64()),
+            reset_reason: take(extras, "reset_reason", |v| {
+                v.as_str().map(|s| s.to_string())
+            }),
+        }
+    }
+
+    ///
+    /// 稼働状態を持たないか否かの判定
+    ///
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+///
+/// デバイス毎の稼働状態の要約を表す構造体
+///
+pub(crate) struct StatusSummary {
+    /// デバイスID
+    pub(crate) device_id: String,
+
+    /// 最後に稼働状態を受信した時刻(ミリ秒単位のUNIX時刻)
+    pub(crate) last_seen: u64,
+
+    /// 最後に報告されたファームウェアのバージョン
+    pub(crate) firmware: Option<String>,
+
+    /// 最後に報告された受信信号強度(dBm)
+    pub(crate) rssi: Option<i32>,
+
+    /// 直近1時間の受信信号強度の平均(dBm)
+    pub(crate) rssi_1h: Option<f64>,
+
+    /// 直近24時間の受信信号強度の平均(dBm)
+    pub(crate) rssi_24h: Option<f64>,
+
+    /// 最後に報告された電池残量(%)
+    pub(crate) battery: Option<f32>,
+
+    /// 最後に報告された起動からの経過時間(秒)
+    pub(crate) uptime: Option<u64>,
+
+    /// 最後に報告されたリセットの理由
+    pub(crate) reset_reason: Option<String>,
+
+    /// 再起動の回数
+    pub(crate) reboot_count: u64,
+}
+
+impl StatusSummary {
+    ///
+    /// 受信信号強度の傾向の取得
+    ///
+    /// # 戻り値
+    /// 直近1時間の平均と直近24時間の平均を比較し、"rising"、"falling"、
+    /// "steady"のいずれかを返す。判定できない場合は`None`を返す。
+    ///
+    pub(crate) fn rssi_trend(&self) -> Option<&'static str> {
+        /// 変化とみなす差(dB)
+        const THRESHOLD: f64 = 3.0;
+
+        let diff = self.rssi_1h? - self.rssi_24h?;
+
+        Some(if diff >= THRESHOLD {
+            "rising"
+        } else if diff <= -THRESHOLD {
+            "falling"
+        } else {
+            "steady"
+        })
+    }
+}
+
+///
+/// デバイス毎の稼働状態の要約の読み出し
+///
+/// # 引数
+/// * `conn` - データベース接続オブジェクト
+/// * `device_id` - 対象のデバイスID(未指定時は全てのデバイス)
+///
+/// # 戻り値
+/// デバイスID順の要約のリストを`Ok()`でラップして返す。
+///
+pub(crate) fn select_status(conn: &Connection, device_id: Option<&str>)
+    -> Result<Vec<StatusSummary>>
+{
+    let mut stmt = conn.prepare(SELECT_DEVICE_STATUS_QUERY)?;
+    let rows = stmt.query_map(
+        named_params! {":device_id": device_id},
+        |row| Ok(StatusSummary {
+            device_id: row.get(0)?,
+            last_seen: row.get(1)?,
+            firmware: row.get(2)?,
+            rssi: row.get(3)?,
+            rssi_1h: row.get(4)?,
+            rssi_24h: row.get(5)?,
+            battery: row.get(6)?,
+            uptime: row.get(7)?,
+            reset_reason: row.get(8)?,
+            reboot_count: row.get(9)?,
+        })
+    )?;
+
+    match rows.collect::<rusqlite::Result<Vec<_>>>() {
+        Ok(list) => Ok(list),
+        Err(err) => Err(anyhow!("select device status failed: {}", err)),
+    }
+}
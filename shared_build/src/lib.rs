@@ -6,7 +6,7 @@ pub fn set_env() {
 }
 
 fn git_hash() -> String {
-    match Command::new("git").args(&["rev-parse", "--short", "HEAD"]).output() {
+    match Command::new("git").args(["rev-parse", "--short", "HEAD"]).output() {
         Ok(output) => {
             let hash = output.stdout;
             eprintln!("come {}:{}", file!(), line!());